@@ -0,0 +1,40 @@
+//! Embeds what `--version-json` reports about the build itself: the cargo
+//! features it was built with, and the git commit if it was built from a
+//! checkout with git available.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=PNGME_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-changed=build.rs");
+    // A commit moves the branch HEAD names, so watch both
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(branch) = std::fs::read_to_string(head)
+            .ok()
+            .and_then(|head| Some(head.strip_prefix("ref: ")?.trim().to_string()))
+        {
+            println!("cargo:rerun-if-changed=.git/{branch}");
+        }
+    }
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(hash) = hash {
+        println!("cargo:rustc-env=PNGME_GIT_HASH={}", hash.trim());
+    }
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::chunk::{Chunk, ChunkRef};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(chunk) = Chunk::try_from(data) {
+        let _ = chunk.to_string();
+        let _ = chunk.data_as_string();
+        assert_eq!(chunk.as_bytes(), data);
+    }
+    let _ = ChunkRef::parse(data, 0).map(|chunk| chunk.data_as_str().is_ok());
+});
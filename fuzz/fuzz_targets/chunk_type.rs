@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::chunk_type::ChunkType;
+use std::str::FromStr;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(bytes) = <[u8; 4]>::try_from(data) {
+        if let Ok(chunk_type) = ChunkType::try_from(bytes) {
+            assert_eq!(chunk_type.bytes(), bytes);
+            let _ = chunk_type.to_string();
+        }
+    }
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = ChunkType::from_str(s).map(|chunk_type| chunk_type.to_string());
+    }
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::png::{ParseOptions, Png};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(png) = Png::try_from(data) {
+        let _ = png.to_string();
+    }
+    let _ = Png::from_reader(data);
+    let _ = Png::parse_borrowed(data);
+    let lenient = ParseOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    if let Ok(png) = Png::parse_with(data, &lenient) {
+        assert_eq!(png.as_bytes(), data);
+    }
+});
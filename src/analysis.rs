@@ -0,0 +1,362 @@
+use crate::format::format_size;
+use crate::png::Png;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::fmt::{self, Display};
+use std::io::Read;
+
+/// File formats recognised by their leading magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Magic {
+    Zlib,
+    Gzip,
+    Zip,
+    Png,
+    Jpeg,
+}
+
+impl Magic {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Magic::Zlib => "zlib stream",
+            Magic::Gzip => "gzip",
+            Magic::Zip => "zip archive",
+            Magic::Png => "PNG image",
+            Magic::Jpeg => "JPEG image",
+        }
+    }
+    pub fn detect(data: &[u8]) -> Option<Magic> {
+        match data {
+            [0x1f, 0x8b, ..] => Some(Magic::Gzip),
+            [b'P', b'K', 3, 4, ..] => Some(Magic::Zip),
+            [0xff, 0xd8, 0xff, ..] => Some(Magic::Jpeg),
+            _ if data.starts_with(&Png::STANDARD_HEADER) => Some(Magic::Png),
+            // Deflate method with a header checksum that's a multiple of 31
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 =>
+            {
+                Some(Magic::Zlib)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InflateError {
+    /// The stream inflates to more than this many bytes.
+    TooLarge(u64),
+    /// It starts like a stream but isn't one.
+    Corrupt(String),
+}
+
+impl Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InflateError::TooLarge(max) => {
+                write!(f, "it inflates to more than {}", format_size(*max, false))
+            }
+            InflateError::Corrupt(reason) => write!(f, "it doesn't inflate: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+/// Inflates `data` if it starts like a zlib or gzip stream, reading at
+/// most `max` bytes of output so a small payload can't expand without
+/// bound. `None` if it starts like neither.
+pub fn inflate(data: &[u8], max: u64) -> Option<(Magic, Result<Vec<u8>, InflateError>)> {
+    let magic = Magic::detect(data)?;
+    let decoder: Box<dyn Read + '_> = match magic {
+        Magic::Zlib => Box::new(ZlibDecoder::new(data)),
+        Magic::Gzip => Box::new(GzDecoder::new(data)),
+        _ => return None,
+    };
+    let mut inflated = Vec::new();
+    let result = match decoder
+        .take(max.saturating_add(1))
+        .read_to_end(&mut inflated)
+    {
+        Err(e) => Err(InflateError::Corrupt(e.to_string())),
+        Ok(_) if inflated.len() as u64 > max => Err(InflateError::TooLarge(max)),
+        Ok(_) => Ok(inflated),
+    };
+    Some((magic, result))
+}
+
+/// Image and document formats a file given in place of a PNG is often
+/// one of, recognised so the error can say what the file is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownFormat {
+    Jpeg,
+    Gif,
+    WebP,
+    Bmp,
+    Tiff,
+    Pdf,
+    Zip,
+}
+
+impl KnownFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            KnownFormat::Jpeg => "JPEG",
+            KnownFormat::Gif => "GIF",
+            KnownFormat::WebP => "WebP",
+            KnownFormat::Bmp => "BMP",
+            KnownFormat::Tiff => "TIFF",
+            KnownFormat::Pdf => "PDF",
+            KnownFormat::Zip => "ZIP",
+        }
+    }
+    /// How many leading bytes identify the format.
+    pub fn magic_len(&self) -> usize {
+        match self {
+            KnownFormat::Bmp => 2,
+            KnownFormat::Jpeg => 3,
+            KnownFormat::WebP | KnownFormat::Tiff | KnownFormat::Zip => 4,
+            KnownFormat::Pdf => 5,
+            KnownFormat::Gif => 6,
+        }
+    }
+    /// A WebP is told apart from other RIFF files by bytes 8 to 12, so it
+    /// needs at least 12 bytes; the rest need only their magic.
+    pub fn detect(data: &[u8]) -> Option<KnownFormat> {
+        match data {
+            [0xff, 0xd8, 0xff, ..] => Some(KnownFormat::Jpeg),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(KnownFormat::Gif),
+            [
+                b'R',
+                b'I',
+                b'F',
+                b'F',
+                _,
+                _,
+                _,
+                _,
+                b'W',
+                b'E',
+                b'B',
+                b'P',
+                ..,
+            ] => Some(KnownFormat::WebP),
+            [b'B', b'M', ..] => Some(KnownFormat::Bmp),
+            [b'I', b'I', b'*', 0, ..] | [b'M', b'M', 0, b'*', ..] => Some(KnownFormat::Tiff),
+            [b'%', b'P', b'D', b'F', b'-', ..] => Some(KnownFormat::Pdf),
+            [b'P', b'K', 3, 4, ..] => Some(KnownFormat::Zip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Guess {
+    Empty,
+    Text,
+    Format(Magic),
+    /// Close to 8 bits of entropy per byte with no recognisable header.
+    HighEntropy,
+    Binary,
+}
+
+impl std::fmt::Display for Guess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Guess::Empty => write!(f, "empty"),
+            Guess::Text => write!(f, "looks like text"),
+            Guess::Format(magic) => write!(f, "looks like a {}", magic.name()),
+            Guess::HighEntropy => write!(f, "looks compressed or encrypted"),
+            Guess::Binary => write!(f, "looks like structured binary data"),
+        }
+    }
+}
+
+/// A statistical summary of a payload, for telling text from compressed or
+/// encrypted data at a glance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub len: usize,
+    /// Shannon entropy in bits per byte, from 0 to 8.
+    pub entropy: f64,
+    /// Share of bytes that are printable ASCII or common whitespace.
+    pub printable: f64,
+    /// Byte counts in 16 equal buckets: 0x00-0x0f, 0x10-0x1f and so on.
+    pub histogram: [usize; 16],
+    pub magic: Option<Magic>,
+    pub guess: Guess,
+}
+
+/// Entropy above which data without a known header is assumed random.
+const HIGH_ENTROPY: f64 = 7.5;
+const TEXT_PRINTABLE: f64 = 0.95;
+
+pub fn analyze(data: &[u8]) -> Analysis {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let len = data.len();
+    let entropy = counts
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / len as f64;
+            -p * p.log2()
+        })
+        .sum::<f64>();
+    let printable = data
+        .iter()
+        .filter(|b| b.is_ascii_graphic() || matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+        .count();
+    let printable = if len == 0 {
+        0.0
+    } else {
+        printable as f64 / len as f64
+    };
+    let mut histogram = [0; 16];
+    for (byte, n) in counts.iter().enumerate() {
+        histogram[byte / 16] += n;
+    }
+    let magic = Magic::detect(data);
+    // Short text can masquerade as a zlib header, so text wins over zlib
+    let guess = if len == 0 {
+        Guess::Empty
+    } else if printable >= TEXT_PRINTABLE && magic != Some(Magic::Gzip) {
+        Guess::Text
+    } else if let Some(magic) = magic {
+        Guess::Format(magic)
+    } else if entropy >= HIGH_ENTROPY {
+        Guess::HighEntropy
+    } else {
+        Guess::Binary
+    };
+    Analysis {
+        len,
+        entropy,
+        printable,
+        histogram,
+        magic,
+        guess,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    /// Deterministic stand-in for random bytes
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x1234_5678;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_text() {
+        let analysis = analyze(b"The quick brown fox jumps over the lazy dog\n");
+        assert_eq!(analysis.guess, Guess::Text);
+        assert_eq!(analysis.printable, 1.0);
+        assert!(analysis.entropy > 3.0 && analysis.entropy < 5.0);
+    }
+
+    #[test]
+    fn test_entropy_bounds() {
+        assert_eq!(analyze(&[7; 100]).entropy, 0.0);
+        let all: Vec<u8> = (0..=255).collect();
+        let analysis = analyze(&all);
+        assert!((analysis.entropy - 8.0).abs() < 1e-9);
+        assert_eq!(analysis.histogram, [16; 16]);
+    }
+
+    #[test]
+    fn test_random_is_high_entropy() {
+        let mut data = noise(4096);
+        // Make sure it doesn't start with anything zlib-shaped
+        data[0] = 0;
+        assert_eq!(analyze(&data).guess, Guess::HighEntropy);
+    }
+
+    #[test]
+    fn test_magic_numbers() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&noise(1000)).unwrap();
+        let zlib = encoder.finish().unwrap();
+        assert_eq!(&zlib[..2], [0x78, 0x9c]);
+        assert_eq!(analyze(&zlib).guess, Guess::Format(Magic::Zlib));
+
+        assert_eq!(Magic::detect(&[0x1f, 0x8b, 8, 0]), Some(Magic::Gzip));
+        assert_eq!(Magic::detect(b"PK\x03\x04rest"), Some(Magic::Zip));
+        assert_eq!(Magic::detect(&[0xff, 0xd8, 0xff, 0xe0]), Some(Magic::Jpeg));
+        assert_eq!(Magic::detect(&Png::STANDARD_HEADER), Some(Magic::Png));
+        assert_eq!(Magic::detect(b"plain"), None);
+    }
+
+    #[test]
+    fn test_inflate() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 10_000]).unwrap();
+        let zlib = encoder.finish().unwrap();
+        assert_eq!(
+            inflate(&zlib, 10_000),
+            Some((Magic::Zlib, Ok(vec![0; 10_000])))
+        );
+        assert_eq!(
+            inflate(&zlib, 9_999),
+            Some((Magic::Zlib, Err(InflateError::TooLarge(9_999))))
+        );
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"gzipped").unwrap();
+        let gzip = encoder.finish().unwrap();
+        assert_eq!(
+            inflate(&gzip, 100),
+            Some((Magic::Gzip, Ok(b"gzipped".to_vec())))
+        );
+        // A valid zlib header, but only by chance
+        assert!(matches!(
+            inflate(b"x^ marks the spot", 100),
+            Some((Magic::Zlib, Err(InflateError::Corrupt(_))))
+        ));
+        assert_eq!(inflate(b"plain", 100), None);
+        assert_eq!(inflate(b"PK\x03\x04", 100), None);
+    }
+
+    #[test]
+    fn test_known_formats() {
+        let cases: [(&[u8], KnownFormat); 8] = [
+            (&[0xff, 0xd8, 0xff, 0xe0, 0, 0x10], KnownFormat::Jpeg),
+            (b"GIF89a\x01\x00", KnownFormat::Gif),
+            (b"GIF87a", KnownFormat::Gif),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", KnownFormat::WebP),
+            (b"BM\x3a\x00\x00\x00", KnownFormat::Bmp),
+            (b"MM\x00\x2a\x00\x00\x00\x08", KnownFormat::Tiff),
+            (b"%PDF-1.7\n", KnownFormat::Pdf),
+            (b"PK\x03\x04\x14\x00", KnownFormat::Zip),
+        ];
+        for (data, format) in cases {
+            assert_eq!(KnownFormat::detect(data), Some(format), "{data:?}");
+        }
+        assert_eq!(KnownFormat::detect(b"II*\x00"), Some(KnownFormat::Tiff));
+        // A RIFF that isn't a WebP, and one too short to tell
+        assert_eq!(KnownFormat::detect(b"RIFF\x24\x00\x00\x00WAVE"), None);
+        assert_eq!(KnownFormat::detect(b"RIFF\x24\x00\x00\x00"), None);
+        assert_eq!(KnownFormat::detect(&Png::STANDARD_HEADER), None);
+        assert_eq!(KnownFormat::detect(b"GIF8"), None);
+    }
+
+    #[test]
+    fn test_empty() {
+        let analysis = analyze(&[]);
+        assert_eq!(analysis.guess, Guess::Empty);
+        assert_eq!(analysis.entropy, 0.0);
+        assert_eq!(analysis.printable, 0.0);
+    }
+}
@@ -0,0 +1,296 @@
+//! Animated PNG frames. An APNG is a PNG with an acTL saying how many
+//! frames there are, and for each frame an fcTL giving its size and place
+//! followed by its image data: IDAT for a frame that is also the default
+//! image, fdAT (a sequence number, then the same data IDAT would hold) for
+//! the rest.
+//!
+//! `frames` pairs each fcTL with its data, and `Frame::to_png` makes a
+//! standalone file of a frame as it's stored: its own rectangle, not
+//! composited onto the frames before it.
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::Ihdr;
+use crate::png::Png;
+use std::fmt::{self, Display};
+
+pub const ACTL: ChunkType = ChunkType::literal(*b"acTL");
+pub const FCTL: ChunkType = ChunkType::literal(*b"fcTL");
+pub const FDAT: ChunkType = ChunkType::literal(*b"fdAT");
+
+/// Chunks that change how the pixels look, copied into every frame.
+const COLOR_CHUNKS: [&[u8; 4]; 8] = [
+    b"PLTE", b"tRNS", b"gAMA", b"cHRM", b"cICP", b"iCCP", b"sBIT", b"sRGB",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApngError {
+    /// There's no acTL, so the file isn't animated.
+    NotAnimated,
+    /// A chunk at this index is the wrong size for its type.
+    BadLength {
+        index: usize,
+        chunk_type: ChunkType,
+        len: usize,
+    },
+    /// A frame has no IDAT or fdAT after its fcTL.
+    NoData { frame: usize },
+    /// Image data that no fcTL comes before.
+    Orphan { index: usize },
+    /// The sequence numbers across fcTL and fdAT aren't 0, 1, 2...
+    Sequence {
+        index: usize,
+        expected: u32,
+        found: u32,
+    },
+}
+
+impl Display for ApngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApngError::NotAnimated => write!(f, "There is no acTL chunk, so it isn't animated"),
+            ApngError::BadLength {
+                index,
+                chunk_type,
+                len,
+            } => write!(f, "{chunk_type} at chunk {index} is {len} bytes, too short"),
+            ApngError::NoData { frame } => {
+                write!(f, "Frame {frame} has no image data after its fcTL")
+            }
+            ApngError::Orphan { index } => {
+                write!(f, "The fdAT at chunk {index} has no fcTL before it")
+            }
+            ApngError::Sequence {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Chunk {index} has sequence number {found} where {expected} comes next"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ApngError {}
+
+/// An fcTL: where a frame goes and how long it shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    /// The delay is `delay_num / delay_den` seconds; a denominator of 0
+    /// means 100.
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: u8,
+    pub blend_op: u8,
+}
+
+impl FrameControl {
+    pub fn parse(data: &[u8]) -> Option<FrameControl> {
+        let data: &[u8; 26] = data.try_into().ok()?;
+        let u32_at =
+            |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        Some(FrameControl {
+            sequence: u32_at(0),
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16_at(20),
+            delay_den: u16_at(22),
+            dispose_op: data[24],
+            blend_op: data[25],
+        })
+    }
+}
+
+/// One frame: its fcTL and the zlib stream of its pixels, in the parts it
+/// was stored in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub control: FrameControl,
+    pub data: Vec<Vec<u8>>,
+    /// Whether the frame is the default image, stored in IDAT.
+    pub is_default_image: bool,
+}
+
+impl Frame {
+    /// A PNG of just this frame: IHDR with the frame's size and the rest of
+    /// `ihdr`, the chunks of `png` that affect how pixels look, and the
+    /// frame's data as IDAT.
+    pub fn to_png(&self, ihdr: &Ihdr, png: &Png) -> Png {
+        let ihdr = Ihdr {
+            width: self.control.width,
+            height: self.control.height,
+            ..*ihdr
+        };
+        let mut chunks = vec![ihdr.to_chunk()];
+        let first_idat = png
+            .chunks()
+            .iter()
+            .position(|c| *c.chunk_type() == ChunkType::IDAT)
+            .unwrap_or(png.chunks().len());
+        chunks.extend(
+            png.chunks()[..first_idat]
+                .iter()
+                .filter(|c| COLOR_CHUNKS.contains(&&c.chunk_type().bytes()))
+                .cloned(),
+        );
+        chunks.extend(
+            self.data
+                .iter()
+                .map(|part| Chunk::new(ChunkType::IDAT, part.clone())),
+        );
+        chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+        Png::from_chunks(chunks)
+    }
+}
+
+/// Every frame of an animated PNG, in order. The sequence numbers must run
+/// 0, 1, 2... across fcTL and fdAT, as decoders rely on them.
+pub fn frames(png: &Png) -> Result<Vec<Frame>, ApngError> {
+    if !png.chunks().iter().any(|c| *c.chunk_type() == ACTL) {
+        return Err(ApngError::NotAnimated);
+    }
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut next_sequence = 0;
+    let mut check_sequence = |index: usize, found: u32| {
+        if found != next_sequence {
+            return Err(ApngError::Sequence {
+                index,
+                expected: next_sequence,
+                found,
+            });
+        }
+        next_sequence += 1;
+        Ok(())
+    };
+    // Whether the chunks just seen are the data of the last frame
+    let mut in_frame = false;
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_type = *chunk.chunk_type();
+        let bad_length = || ApngError::BadLength {
+            index,
+            chunk_type,
+            len: chunk.data().len(),
+        };
+        if chunk_type == FCTL {
+            if let Some(last) = frames.last()
+                && last.data.is_empty()
+            {
+                return Err(ApngError::NoData {
+                    frame: frames.len() - 1,
+                });
+            }
+            let control = FrameControl::parse(chunk.data()).ok_or_else(bad_length)?;
+            check_sequence(index, control.sequence)?;
+            frames.push(Frame {
+                control,
+                data: Vec::new(),
+                is_default_image: false,
+            });
+            in_frame = true;
+        } else if chunk_type == ChunkType::IDAT {
+            // Only part of the animation if an fcTL comes first
+            if let Some(frame) = frames.last_mut().filter(|_| in_frame) {
+                frame.is_default_image = true;
+                frame.data.push(chunk.data().to_vec());
+            }
+        } else if chunk_type == FDAT {
+            let frame = frames
+                .last_mut()
+                .filter(|frame| in_frame && !frame.is_default_image)
+                .ok_or(ApngError::Orphan { index })?;
+            let (sequence, data) = chunk
+                .data()
+                .split_first_chunk::<4>()
+                .ok_or_else(bad_length)?;
+            check_sequence(index, u32::from_be_bytes(*sequence))?;
+            frame.data.push(data.to_vec());
+        } else {
+            in_frame = in_frame && chunk_type != ChunkType::IEND;
+        }
+    }
+    if let Some(last) = frames.last()
+        && last.data.is_empty()
+    {
+        return Err(ApngError::NoData {
+            frame: frames.len() - 1,
+        });
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::verify;
+
+    /// A 3-frame APNG of 4x3 RGB, the first frame also the default image
+    /// and the others 2x2 at (1, 1), the last split across two fdATs.
+    fn animation() -> Png {
+        let mut png = Png::try_from(crate::testutil::apng(3).as_slice()).unwrap();
+        // The last chunk before IEND is the last frame's only fdAT
+        let last = png.chunks().len() - 2;
+        let fdat = png.remove_chunk_at(last).unwrap();
+        let (sequence, data) = fdat.data().split_at(4);
+        let sequence = u32::from_be_bytes(sequence.try_into().unwrap());
+        let (head, tail) = data.split_at(data.len() / 2);
+        for (n, part) in [head, tail].into_iter().enumerate().rev() {
+            let data = [&(sequence + n as u32).to_be_bytes(), part].concat();
+            png.insert_chunk(last, Chunk::new(FDAT, data));
+        }
+        png
+    }
+
+    #[test]
+    fn test_frames_become_standalone_pngs() {
+        let png = animation();
+        let frames = frames(&png).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].is_default_image);
+        assert!(!frames[1].is_default_image);
+        assert!(frames[2].data.len() > 1);
+        let ihdr = Ihdr::try_from(&png.chunks()[0]).unwrap();
+        for (frame, (size, value)) in frames
+            .iter()
+            .zip([((4, 3), 10), ((2, 2), 20), ((2, 2), 30)])
+        {
+            let bytes = frame.to_png(&ihdr, &png).as_bytes();
+            assert_eq!(verify(&bytes, &Default::default()), []);
+            let mut reader = png::Decoder::new(std::io::Cursor::new(&bytes))
+                .read_info()
+                .unwrap();
+            let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+            let info = reader.next_frame(&mut pixels).unwrap();
+            assert_eq!((info.width, info.height), size);
+            assert!(pixels.iter().all(|&p| p == value));
+        }
+    }
+
+    #[test]
+    fn test_malformed_animations() {
+        let still = crate::builder::PngBuilder::new(1, 1).build().unwrap();
+        assert_eq!(frames(&still), Err(ApngError::NotAnimated));
+
+        let mut png = animation();
+        let second_fctl = png
+            .chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| *c.chunk_type() == FCTL)
+            .nth(1)
+            .unwrap()
+            .0;
+        let fdat = png.remove_chunk_at(second_fctl + 1).unwrap();
+        assert_eq!(frames(&png), Err(ApngError::NoData { frame: 1 }));
+        png.insert_chunk(second_fctl, fdat);
+        assert_eq!(frames(&png), Err(ApngError::Orphan { index: second_fctl }));
+    }
+}
@@ -0,0 +1,408 @@
+use crate::platform;
+use pngme::base64;
+use pngme::builder::ColorType;
+use pngme::chunk_type::ChunkType;
+use pngme::format::Delimited;
+use pngme::hex;
+use pngme::survival::Position;
+use pngme::template::Template;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Splits a chunk type from its message on the first `separator`, keeping
+/// the rest verbatim.
+fn split_chunk_spec(s: &str, separator: char) -> Result<(ChunkType, String), String> {
+    let (chunk_type, message) = s
+        .split_once(separator)
+        .ok_or_else(|| format!("expected TYPE{separator}MESSAGE, got '{s}'"))?;
+    let chunk_type = ChunkType::from_str(chunk_type).map_err(|e| format!("'{chunk_type}': {e}"))?;
+    Ok((chunk_type, message.to_string()))
+}
+
+/// Parses a `TYPE=MESSAGE` pair, splitting on the first `=`.
+pub fn parse_chunk_spec(s: &str) -> Result<(ChunkType, String), String> {
+    split_chunk_spec(s, '=')
+}
+
+/// Parses a `TYPE:MESSAGE` pair, splitting on the first `:`.
+pub fn parse_kv(s: &str) -> Result<(ChunkType, String), String> {
+    split_chunk_spec(s, ':')
+}
+
+pub fn parse_delimited(s: &str) -> Result<Delimited, String> {
+    match s {
+        "csv" => Ok(Delimited::Csv),
+        "tsv" => Ok(Delimited::Tsv),
+        _ => Err(format!("expected csv or tsv, got '{s}'")),
+    }
+}
+
+/// What `list --format` prints each chunk as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListFormat {
+    Delimited(Delimited),
+    Template(Template),
+}
+
+/// `csv` or `tsv`, or else a template.
+pub fn parse_list_format(s: &str) -> Result<ListFormat, String> {
+    match parse_delimited(s) {
+        Ok(delimited) => Ok(ListFormat::Delimited(delimited)),
+        Err(_) => Template::from_str(s)
+            .map(ListFormat::Template)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+pub fn parse_color_type(s: &str) -> Result<ColorType, String> {
+    match s {
+        "8bit-rgb" => Ok(ColorType::Rgb),
+        "8bit-rgba" => Ok(ColorType::Rgba),
+        _ => Err(format!("expected 8bit-rgb or 8bit-rgba, got '{s}'")),
+    }
+}
+
+/// Parses a hex color such as `ff8800` or `ff880080`, one byte per channel.
+pub fn parse_fill(s: &str) -> Result<Vec<u8>, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.is_empty() {
+        return Err(format!("expected hex bytes such as ff8800, got '{s}'"));
+    }
+    parse_hex(hex).map_err(|_| format!("expected hex bytes such as ff8800, got '{s}'"))
+}
+
+/// Parses a byte in decimal, or in hex after `0x`: `255` or `0xff`.
+pub fn parse_byte(s: &str) -> Result<u8, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("expected a byte such as 0 or 0xff, got '{s}'"))
+}
+
+/// Parses a non-empty string of hex digit pairs to repeat, such as
+/// `5245444143544544` for "REDACTED".
+pub fn parse_pattern(s: &str) -> Result<Vec<u8>, String> {
+    match parse_hex(s)? {
+        pattern if pattern.is_empty() => Err("expected at least one byte of hex".to_string()),
+        pattern => Ok(pattern),
+    }
+}
+
+/// Parses a string of hex digit pairs such as `00ff7f`, which may be empty.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(format!(
+            "expected pairs of hex digits such as 00ff7f, got '{s}'"
+        ));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| format!("'{s}': {e}")))
+        .collect()
+}
+
+/// How a payload is written as text: `encode --input-encoding` reads it
+/// that way and `decode --output-encoding` prints it that way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PayloadEncoding {
+    #[default]
+    Raw,
+    Base64,
+    Hex,
+}
+
+impl PayloadEncoding {
+    /// The bytes `text` stands for.
+    pub fn decode(self, text: String) -> Result<Vec<u8>, String> {
+        match self {
+            PayloadEncoding::Raw => Ok(text.into_bytes()),
+            PayloadEncoding::Base64 => base64::decode(&text).map_err(|e| e.to_string()),
+            PayloadEncoding::Hex => hex::decode(&text).map_err(|e| e.to_string()),
+        }
+    }
+    /// `data` as text; the same bytes for `Raw`.
+    pub fn encode(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            PayloadEncoding::Raw => data,
+            PayloadEncoding::Base64 => base64::encode(&data).into_bytes(),
+            PayloadEncoding::Hex => hex::encode(&data).into_bytes(),
+        }
+    }
+}
+
+pub fn parse_payload_encoding(s: &str) -> Result<PayloadEncoding, String> {
+    match s {
+        "raw" => Ok(PayloadEncoding::Raw),
+        "base64" => Ok(PayloadEncoding::Base64),
+        "hex" => Ok(PayloadEncoding::Hex),
+        _ => Err(format!("expected raw, base64 or hex, got '{s}'")),
+    }
+}
+
+/// How a whole png is read or written: as it is, or as text, a data URL or
+/// bare base64 (`--input-format`, `--output-format`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileFormat {
+    #[default]
+    Png,
+    Base64,
+}
+
+pub fn parse_file_format(s: &str) -> Result<FileFormat, String> {
+    match s {
+        "png" => Ok(FileFormat::Png),
+        "base64" => Ok(FileFormat::Base64),
+        _ => Err(format!("expected png or base64, got '{s}'")),
+    }
+}
+
+/// Parses a chunk type given as its four byte values, `0xab424344` or
+/// `ab:42:43:44`, which needn't be letters.
+pub fn parse_type_hex(s: &str) -> Result<ChunkType, String> {
+    let hex = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => hex.to_string(),
+        None if s.len() == 11 && s.split(':').all(|b| b.len() == 2) => s.replace(':', ""),
+        None => String::new(),
+    };
+    match parse_hex(&hex)
+        .ok()
+        .and_then(|b| <[u8; 4]>::try_from(b).ok())
+    {
+        Some(bytes) => Ok(ChunkType::from_bytes_unchecked(bytes)),
+        None => Err(format!(
+            "expected four bytes such as 0xab424344 or ab:42:43:44, got '{s}'"
+        )),
+    }
+}
+
+/// Splits a list of paths, one per line or with `null` one per
+/// NUL-terminated entry. Blank entries are skipped. Lines may end in CRLF,
+/// and those starting with `#` are comments; with `null` every entry is a
+/// path as it stands.
+pub fn parse_file_list(bytes: &[u8], null: bool) -> Vec<PathBuf> {
+    let separator = if null { 0 } else { b'\n' };
+    bytes
+        .split(|&b| b == separator)
+        .map(|entry| match entry {
+            [line @ .., b'\r'] if !null => line,
+            entry => entry,
+        })
+        .filter(|entry| !entry.is_empty() && (null || !entry.starts_with(b"#")))
+        .map(platform::path_from_bytes)
+        .collect()
+}
+
+pub fn parse_position(s: &str) -> Result<Position, String> {
+    match s {
+        "before-idat" => Ok(Position::BeforeIdat),
+        "after-idat" => Ok(Position::AfterIdat),
+        "after-iend" => Ok(Position::AfterIend),
+        _ => Err(format!(
+            "expected before-idat, after-idat or after-iend, got '{s}'"
+        )),
+    }
+}
+
+/// Parses a list of chunk types, one per line. Blank lines and anything
+/// after a `#` are ignored, and a type listed twice counts once. Every line
+/// is checked, so all the bad ones are reported together, by line number.
+pub fn parse_type_list(text: &str) -> Result<Vec<ChunkType>, Vec<String>> {
+    let mut types = Vec::new();
+    let mut errors = Vec::new();
+    for (number, line) in (1..).zip(text.lines()) {
+        let name = line.split('#').next().unwrap_or("").trim();
+        if name.is_empty() {
+            continue;
+        }
+        match ChunkType::from_str(name) {
+            Ok(chunk_type) if !types.contains(&chunk_type) => types.push(chunk_type),
+            Ok(_) => {}
+            Err(e) => errors.push(format!("line {number}: '{name}': {e}")),
+        }
+    }
+    if errors.is_empty() {
+        Ok(types)
+    } else {
+        Err(errors)
+    }
+}
+
+/// How `encode --corrupt-crc` should make the stored CRC wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcCorruption {
+    /// Added to the correct CRC, wrapping around.
+    Delta(i64),
+    /// Stored as is.
+    Value(u32),
+}
+
+impl CrcCorruption {
+    pub fn apply(&self, crc: u32) -> u32 {
+        match *self {
+            CrcCorruption::Delta(delta) => (i64::from(crc) + delta) as u32,
+            CrcCorruption::Value(value) => value,
+        }
+    }
+}
+
+/// Parses `+N`/`-N` as a delta from the correct CRC, or `0xXXXXXXXX` as the
+/// exact value to store.
+pub fn parse_crc_corruption(s: &str) -> Result<CrcCorruption, String> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16)
+            .map(CrcCorruption::Value)
+            .map_err(|e| format!("'{s}': {e}"));
+    }
+    if !s.starts_with(['+', '-']) {
+        return Err(format!(
+            "expected a delta such as +1 or a value such as 0xdeadbeef, got '{s}'"
+        ));
+    }
+    match s.parse::<i64>() {
+        Ok(0) => Err("a delta of 0 would leave the CRC correct".to_string()),
+        Ok(delta) => Ok(CrcCorruption::Delta(delta)),
+        Err(e) => Err(format!("'{s}': {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chunk_spec() {
+        let (chunk_type, message) = parse_chunk_spec("ruSt=a=b").unwrap();
+        assert_eq!(chunk_type.to_string(), "ruSt");
+        assert_eq!(message, "a=b");
+    }
+
+    #[test]
+    fn test_parse_chunk_spec_empty_message() {
+        let (_, message) = parse_chunk_spec("ruSt=").unwrap();
+        assert_eq!(message, "");
+    }
+
+    #[test]
+    fn test_parse_chunk_spec_invalid() {
+        assert!(parse_chunk_spec("ruSt").is_err());
+        assert!(parse_chunk_spec("ru5t=hello").is_err());
+    }
+
+    #[test]
+    fn test_parse_kv() {
+        let (chunk_type, message) = parse_kv("ruSt:at 12:30: lunch").unwrap();
+        assert_eq!(chunk_type.to_string(), "ruSt");
+        assert_eq!(message, "at 12:30: lunch");
+        assert_eq!(
+            parse_kv("ruSt=hello").unwrap_err(),
+            "expected TYPE:MESSAGE, got 'ruSt=hello'"
+        );
+        assert!(parse_kv("ru5t:hi").is_err());
+    }
+
+    #[test]
+    fn test_parse_file_list() {
+        let paths = |bytes: &[u8], null| {
+            let list = parse_file_list(bytes, null);
+            list.into_iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(b"a.png\n\n# skipped\nshots/b c.png\n", false),
+            ["a.png", "shots/b c.png"]
+        );
+        assert_eq!(paths(b"a.png\r\nb.png\r\n", false), ["a.png", "b.png"]);
+        assert_eq!(paths(b"a\nb.png\0#c.png\0\0", true), ["a\nb.png", "#c.png"]);
+        assert!(paths(b"", false).is_empty());
+    }
+
+    #[test]
+    fn test_parse_list_format() {
+        assert_eq!(
+            parse_list_format("csv"),
+            Ok(ListFormat::Delimited(Delimited::Csv))
+        );
+        assert_eq!(
+            parse_list_format("tsv"),
+            Ok(ListFormat::Delimited(Delimited::Tsv))
+        );
+        assert!(matches!(
+            parse_list_format("{index},{type}"),
+            Ok(ListFormat::Template(_))
+        ));
+        assert!(parse_list_format("{name}").is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_and_pattern() {
+        assert_eq!(parse_byte("0"), Ok(0));
+        assert_eq!(parse_byte("255"), Ok(255));
+        assert_eq!(parse_byte("0xff"), Ok(255));
+        assert_eq!(parse_byte("0X2a"), Ok(42));
+        assert!(parse_byte("256").is_err());
+        assert!(parse_byte("ff").is_err());
+        assert!(parse_byte("0x").is_err());
+        assert_eq!(parse_pattern("52454441").unwrap(), b"REDA");
+        assert!(parse_pattern("").is_err());
+        assert!(parse_pattern("abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_fill() {
+        assert_eq!(parse_fill("ff0080").unwrap(), [255, 0, 128]);
+        assert_eq!(parse_fill("#00ff0040").unwrap(), [0, 255, 0, 64]);
+        assert!(parse_fill("fff").is_err());
+        assert!(parse_fill("zz0000").is_err());
+        assert!(parse_fill("").is_err());
+        assert!(parse_hex("").unwrap().is_empty());
+        assert_eq!(parse_hex("00fF7f").unwrap(), [0, 255, 127]);
+        assert!(parse_hex("#00").is_err());
+    }
+
+    #[test]
+    fn test_parse_type_hex() {
+        let expected = ChunkType::from_bytes_unchecked([0xab, b'B', b'C', b'D']);
+        assert_eq!(parse_type_hex("0xab424344"), Ok(expected));
+        assert_eq!(parse_type_hex("0XAB424344"), Ok(expected));
+        assert_eq!(parse_type_hex("ab:42:43:44"), Ok(expected));
+        for bad in [
+            "ab424344",
+            "0xab4243",
+            "0xab42434445",
+            "ab:42:43",
+            "a:b42:43:44",
+            "0xzz424344",
+        ] {
+            assert!(parse_type_hex(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_parse_type_list() {
+        let types = parse_type_list("# purge these\ntEXt\n\n  zTXt  # compressed\ntEXt\n").unwrap();
+        let names: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+        assert_eq!(names, ["tEXt", "zTXt"]);
+        assert!(parse_type_list("").unwrap().is_empty());
+
+        let errors = parse_type_list("tEXt\nab\niTXt\nr1St\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("line 2: 'ab'"), "{}", errors[0]);
+        assert!(errors[1].starts_with("line 4: 'r1St'"), "{}", errors[1]);
+    }
+
+    #[test]
+    fn test_parse_crc_corruption() {
+        assert_eq!(parse_crc_corruption("+1"), Ok(CrcCorruption::Delta(1)));
+        assert_eq!(parse_crc_corruption("-3"), Ok(CrcCorruption::Delta(-3)));
+        assert_eq!(
+            parse_crc_corruption("0xdeadbeef"),
+            Ok(CrcCorruption::Value(0xdeadbeef))
+        );
+        assert!(parse_crc_corruption("+0").is_err());
+        assert!(parse_crc_corruption("5").is_err());
+        assert!(parse_crc_corruption("0xnope").is_err());
+        assert_eq!(CrcCorruption::Delta(-1).apply(0), u32::MAX);
+    }
+}
@@ -0,0 +1,439 @@
+//! The audit log behind `--audit-log` and `PNGME_LOG_FILE`: a JSON line
+//! for every edit saying when it was made, by which command, to which
+//! files, which chunk types it changed, and the size and SHA-256 of the
+//! file before and after.
+//!
+//! The log is opened before the edit, so one that can't be written stops
+//! the edit instead of letting it go unrecorded. Each line is appended
+//! with a single write to a file opened for appending, so lines from
+//! several pngme processes logging at once don't interleave.
+//!
+//! The input is tallied through the handle the edit locked it with and
+//! the output as it is written, each streamed rather than held, and the
+//! line is appended before the lock is let go, so it describes the edit
+//! made and not one another process made meanwhile.
+
+use crate::Result;
+use crate::context::{self, Context};
+use pngme::baseline::{self, ChunkRecord};
+use pngme::chunk_type::ChunkType;
+use pngme::format::Utc;
+use pngme::hex;
+use pngme::png::Png;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// UTC, in RFC 3339 with milliseconds.
+    pub timestamp: String,
+    pub command: String,
+    pub input: PathBuf,
+    /// `-` for stdout.
+    pub output: PathBuf,
+    /// The types of the chunks added, removed or changed, each once, in
+    /// the order they come in the file.
+    pub chunk_types: Vec<String>,
+    pub size_before: usize,
+    /// `None`, like the SHA-256 after, when the file went to stdout.
+    pub size_after: Option<usize>,
+    pub sha256_before: String,
+    pub sha256_after: Option<String>,
+}
+
+impl Entry {
+    /// The edit by `command` that turned `before`, read from `input`, into
+    /// `after`, written to `output`.
+    pub fn new(
+        command: &str,
+        input: &Path,
+        output: &Path,
+        before: Tallied,
+        after: Option<Tallied>,
+    ) -> Self {
+        let chunk_types = match (&before.chunks, after.as_ref().map(|a| &a.chunks)) {
+            (Some(before), Some(Some(after))) => changed_types(before, after),
+            _ => Vec::new(),
+        };
+        Entry {
+            timestamp: rfc3339(SystemTime::now()),
+            command: command.to_string(),
+            input: absolute(input),
+            output: match output == Path::new("-") {
+                true => output.to_path_buf(),
+                false => absolute(output),
+            },
+            chunk_types,
+            size_before: before.size,
+            size_after: after.as_ref().map(|a| a.size),
+            sha256_before: before.sha256,
+            sha256_after: after.map(|a| a.sha256),
+        }
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The chunk types that differ between the two files.
+fn changed_types(before: &[ChunkRecord], after: &[ChunkRecord]) -> Vec<String> {
+    let mut types: Vec<String> = Vec::new();
+    for change in baseline::compare_chunks(before, after) {
+        if !types.contains(&change.chunk_type) {
+            types.push(change.chunk_type);
+        }
+    }
+    types
+}
+
+/// e.g. `2023-11-14T22:13:20.000Z`.
+fn rfc3339(time: SystemTime) -> String {
+    let utc = Utc::from(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        utc.year, utc.month, utc.day, utc.hour, utc.minute, utc.second, utc.millis
+    )
+}
+
+/// The size, SHA-256 and chunks of a file, taken as its bytes stream
+/// past. Written to, it only counts what it's given.
+#[derive(Default)]
+pub struct Tally {
+    size: usize,
+    sha256: Sha256,
+    chunks: Scanner,
+}
+
+impl Tally {
+    pub fn finish(self) -> Tallied {
+        Tallied {
+            size: self.size,
+            sha256: hex::encode(&self.sha256.finalize()),
+            chunks: self.chunks.records(),
+        }
+    }
+}
+
+impl Write for Tally {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.size += buf.len();
+        self.sha256.update(buf);
+        self.chunks.update(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What a `Tally` found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tallied {
+    size: usize,
+    sha256: String,
+    /// `None` if the file isn't a png the scan could follow.
+    chunks: Option<Vec<ChunkRecord>>,
+}
+
+/// Follows the chunks of a png as its bytes stream past, recording each
+/// by its type, length and stored CRC, which stands in for its data.
+#[derive(Default)]
+struct Scanner {
+    /// The signature, a chunk's header or its CRC, as far as it has come.
+    partial: Vec<u8>,
+    signature_seen: bool,
+    /// The type and length of the chunk whose data or CRC is coming.
+    current: Option<([u8; 4], u32)>,
+    /// How much of the current chunk's data is still to come.
+    data_left: usize,
+    records: Vec<ChunkRecord>,
+    lost: bool,
+}
+
+impl Scanner {
+    fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() && !self.lost {
+            if self.data_left > 0 {
+                let skipped = self.data_left.min(bytes.len());
+                self.data_left -= skipped;
+                bytes = &bytes[skipped..];
+                continue;
+            }
+            let want = match (self.signature_seen, self.current) {
+                (true, Some(_)) => 4,
+                _ => 8,
+            };
+            let taken = (want - self.partial.len()).min(bytes.len());
+            self.partial.extend_from_slice(&bytes[..taken]);
+            bytes = &bytes[taken..];
+            if self.partial.len() < want {
+                continue;
+            }
+            let part = std::mem::take(&mut self.partial);
+            if !self.signature_seen {
+                self.signature_seen = true;
+                self.lost = part != Png::STANDARD_HEADER;
+            } else if let Some((chunk_type, length)) = self.current.take() {
+                self.records.push(ChunkRecord {
+                    chunk_type: ChunkType::from_bytes_unchecked(chunk_type).to_string(),
+                    length,
+                    digest: hex::encode(&part),
+                });
+            } else {
+                let length = u32::from_be_bytes([part[0], part[1], part[2], part[3]]);
+                // The spec caps lengths at 2^31 - 1, so this is no chunk
+                self.lost = length > i32::MAX as u32;
+                self.current = Some(([part[4], part[5], part[6], part[7]], length));
+                self.data_left = length as usize;
+            }
+        }
+    }
+    fn records(self) -> Option<Vec<ChunkRecord>> {
+        (self.signature_seen && !self.lost).then_some(self.records)
+    }
+}
+
+/// A writer that also gives everything written to a `Tally`.
+pub struct Tee<'a, W>(pub W, pub &'a mut Tally);
+
+impl<W: Write> Write for Tee<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.0.write(buf)?;
+        self.1.write_all(&buf[..written])?;
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open audit log \"{}\"", path.display()))?;
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+    pub fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .with_context(|| format!("failed to write audit log \"{}\"", self.path.display()))
+    }
+}
+
+/// An edit to log once it has been written.
+struct Pending {
+    log: AuditLog,
+    best_effort: bool,
+    command: &'static str,
+    input: PathBuf,
+    /// `None` if the file goes to stdout.
+    output: Option<PathBuf>,
+    before: Option<Tallied>,
+}
+
+thread_local! {
+    static PENDING: RefCell<Option<Pending>> = const { RefCell::new(None) };
+}
+
+/// Logs the edit `command` makes of `input` to `log` when it is written to
+/// `output`, or stdout for `None`, until the guard is dropped. With
+/// `best_effort` a line that can't be written is only warned about.
+pub fn begin(
+    log: AuditLog,
+    best_effort: bool,
+    command: &'static str,
+    input: &Path,
+    output: Option<&Path>,
+) -> Logging {
+    PENDING.set(Some(Pending {
+        log,
+        best_effort,
+        command,
+        input: input.to_path_buf(),
+        output: output.map(Path::to_path_buf),
+        before: None,
+    }));
+    Logging
+}
+
+/// Keeps an edit being logged until dropped.
+pub struct Logging;
+
+impl Drop for Logging {
+    fn drop(&mut self) {
+        PENDING.take();
+    }
+}
+
+/// If `path` is the input of the edit being logged, tallies it through
+/// `file`, the handle the edit locked it with, and rewinds that for the
+/// edit to read.
+pub fn read_input(path: &Path, file: &mut File) -> io::Result<()> {
+    let wanted = PENDING.with_borrow(|pending| {
+        pending
+            .as_ref()
+            .is_some_and(|p| p.input == path && p.before.is_none())
+    });
+    if !wanted {
+        return Ok(());
+    }
+    let mut tally = Tally::default();
+    io::copy(file, &mut tally)?;
+    file.seek(SeekFrom::Start(0))?;
+    PENDING.with_borrow_mut(|pending| {
+        if let Some(pending) = pending {
+            pending.before = Some(tally.finish());
+        }
+    });
+    Ok(())
+}
+
+/// Whether `path` is the output of the edit being logged, which is to be
+/// tallied as it's written and passed to `written`.
+pub fn is_output(path: &Path) -> bool {
+    PENDING.with_borrow(|pending| {
+        pending
+            .as_ref()
+            .is_some_and(|p| p.output.as_deref() == Some(path))
+    })
+}
+
+/// Logs the edit being logged, now that it has been written: to its
+/// output as `after` tallied, or for `None` to stdout.
+pub fn written(after: Option<Tally>) -> Result<()> {
+    let Some(mut pending) = PENDING.take() else {
+        return Ok(());
+    };
+    let before = pending
+        .before
+        .take()
+        .expect("edited files are read through lock_png_file");
+    let after = after.map(Tally::finish);
+    // Written back over itself unchanged
+    if pending.output.as_ref() == Some(&pending.input)
+        && after.as_ref().is_some_and(|a| a.sha256 == before.sha256)
+    {
+        return Ok(());
+    }
+    let output = pending.output.as_deref().unwrap_or(Path::new("-"));
+    let entry = Entry::new(pending.command, &pending.input, output, before, after);
+    match pending.log.append(&entry) {
+        Err(e) if pending.best_effort => {
+            eprintln!("Warning: {}", context::one_line(e.as_ref()));
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::builder::PngBuilder;
+    use pngme::chunk::Chunk;
+    use pngme::digest::sha256_hex;
+    use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_rfc3339() {
+        let at = |secs: u64, millis: u64| {
+            rfc3339(UNIX_EPOCH + Duration::from_millis(secs * 1000 + millis))
+        };
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(951_782_400, 5), "2000-02-29T00:00:00.005Z");
+        assert_eq!(at(1_700_000_000, 999), "2023-11-14T22:13:20.999Z");
+        assert_eq!(at(4_102_444_799, 0), "2099-12-31T23:59:59.000Z");
+    }
+
+    fn tally(bytes: &[u8]) -> Tallied {
+        let mut tally = Tally::default();
+        // In pieces, as a stream would come
+        for piece in bytes.chunks(5) {
+            tally.write_all(piece).unwrap();
+        }
+        tally.finish()
+    }
+
+    #[test]
+    fn test_entry() {
+        let before = PngBuilder::new(1, 1).build().unwrap();
+        let mut after = before.clone();
+        after.append_chunks([Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![1])]);
+        let (before, after) = (before.as_bytes(), after.as_bytes());
+        let entry = Entry::new(
+            "encode",
+            Path::new("a.png"),
+            Path::new("-"),
+            tally(&before),
+            None,
+        );
+        assert_eq!(entry.output, Path::new("-"));
+        assert!(entry.input.is_absolute() && entry.input.ends_with("a.png"));
+        assert_eq!((entry.size_after, entry.sha256_after), (None, None));
+
+        let entry = Entry::new(
+            "encode",
+            Path::new("a.png"),
+            Path::new("b.png"),
+            tally(&before),
+            Some(tally(&after)),
+        );
+        assert_eq!(entry.chunk_types, ["ruSt"]);
+        assert_eq!(entry.size_after, Some(before.len() + 13));
+        assert_eq!(entry.sha256_before, sha256_hex(&before));
+        assert_eq!(entry.sha256_after, Some(sha256_hex(&after)));
+    }
+
+    /// The records `Tally` should make of `png`'s chunks.
+    fn records(png: &Png) -> Vec<ChunkRecord> {
+        png.chunks()
+            .iter()
+            .map(|c| ChunkRecord {
+                chunk_type: c.chunk_type().to_string(),
+                length: c.length(),
+                digest: format!("{:08x}", c.crc()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tally_follows_chunks() {
+        let mut png = PngBuilder::new(1, 1).build().unwrap();
+        let tallied = tally(&png.as_bytes());
+        assert_eq!(tallied.chunks, Some(records(&png)));
+
+        // A changed chunk differs in its stored CRC
+        png.replace_chunk_at(1, Chunk::new(ChunkType::IDAT, vec![2]));
+        let changed = tally(&png.as_bytes());
+        assert_eq!(
+            changed_types(&tallied.chunks.unwrap(), &changed.chunks.unwrap()),
+            ["IDAT"]
+        );
+
+        assert_eq!(tally(b"not a png").chunks, None);
+        let mut huge = Png::STANDARD_HEADER.to_vec();
+        huge.extend_from_slice(&[0xff; 8]);
+        assert_eq!(tally(&huge).chunks, None);
+    }
+}
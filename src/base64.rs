@@ -0,0 +1,132 @@
+//! Base64 with the standard alphabet and padding (RFC 4648), for carrying
+//! binary payloads through text. Small enough to keep here rather than
+//! take a dependency.
+
+use std::fmt::{self, Display};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base64Error {
+    /// A byte outside the alphabet, or padding before the end, and where it is.
+    InvalidByte { offset: usize, byte: u8 },
+    /// The text stops partway through a group of four, at this length.
+    Truncated { len: usize },
+}
+
+impl Display for Base64Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Base64Error::InvalidByte { offset, byte } => {
+                write!(f, "Invalid base64 byte 0x{byte:02x}")?;
+                if byte.is_ascii_graphic() {
+                    write!(f, " ('{}')", *byte as char)?;
+                }
+                write!(f, " at offset {offset}")
+            }
+            Base64Error::Truncated { len } => write!(
+                f,
+                "Base64 text is {len} bytes long, which isn't a whole number of 4-byte groups"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Base64Error {}
+
+pub fn encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bits = group
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn value(byte: u8) -> Option<u32> {
+    ALPHABET.iter().position(|&b| b == byte).map(|v| v as u32)
+}
+
+pub fn decode(text: &str) -> Result<Vec<u8>, Base64Error> {
+    let bytes = text.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return Err(Base64Error::Truncated { len: bytes.len() });
+    }
+    let mut data = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (n, group) in bytes.chunks(4).enumerate() {
+        let start = n * 4;
+        let last = start + 4 == bytes.len();
+        // Padding only at the very end, and at most two of it
+        let padding = match group {
+            [_, _, b'=', b'='] if last => 2,
+            [_, _, _, b'='] if last => 1,
+            _ => 0,
+        };
+        let mut bits = 0;
+        for (i, &byte) in group[..4 - padding].iter().enumerate() {
+            let value = value(byte).ok_or(Base64Error::InvalidByte {
+                offset: start + i,
+                byte,
+            })?;
+            bits |= value << (18 - 6 * i);
+        }
+        data.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (data, text) in vectors {
+            assert_eq!(encode(data.as_bytes()), text);
+            assert_eq!(decode(text).unwrap(), data.as_bytes());
+        }
+        let binary: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&binary)).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_invalid_text() {
+        assert_eq!(
+            decode("Zm9v!mFy"),
+            Err(Base64Error::InvalidByte {
+                offset: 4,
+                byte: b'!'
+            })
+        );
+        assert_eq!(
+            decode("Zg==Zm9v"),
+            Err(Base64Error::InvalidByte {
+                offset: 2,
+                byte: b'='
+            })
+        );
+        assert_eq!(decode("Zm9vY"), Err(Base64Error::Truncated { len: 5 }));
+        assert_eq!(
+            decode("Zm9v\0mFy").unwrap_err().to_string(),
+            "Invalid base64 byte 0x00 at offset 4"
+        );
+    }
+}
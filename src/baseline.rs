@@ -0,0 +1,263 @@
+//! Snapshots of the chunk structure of a set of files, for telling later
+//! which of them changed. A manifest maps each file to its chunks in order,
+//! each with its type, length and a digest of its data, and serializes to
+//! JSON as is.
+//!
+//! The digest is a CRC-64, which catches any ordinary edit but not one
+//! crafted to collide with it.
+
+use crate::chunk_type::ChunkType;
+use crate::digest::Algorithm;
+use crate::png::Png;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Digest of a chunk's data, as 16 hex digits.
+pub fn data_digest(data: &[u8]) -> String {
+    Algorithm::Crc64.hex(data)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRecord {
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+    pub length: u32,
+    pub digest: String,
+}
+
+/// Every file's chunks, keyed by path relative to the scanned directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, Vec<ChunkRecord>>,
+}
+
+impl Manifest {
+    pub fn insert(&mut self, path: impl Into<String>, png: &Png) {
+        self.files.insert(path.into(), records(png));
+    }
+    /// What changed from `self`, the baseline, to `current`, by path.
+    pub fn compare(&self, current: &Manifest) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (path, before) in &self.files {
+            let kind = match current.files.get(path) {
+                None => FindingKind::Missing,
+                Some(after) => {
+                    let changes = compare_chunks(before, after);
+                    if changes.is_empty() {
+                        continue;
+                    }
+                    FindingKind::Changed(changes)
+                }
+            };
+            findings.push(Finding {
+                path: path.clone(),
+                kind,
+            });
+        }
+        for path in current.files.keys() {
+            if !self.files.contains_key(path) {
+                findings.push(Finding {
+                    path: path.clone(),
+                    kind: FindingKind::New,
+                });
+            }
+        }
+        findings.sort_by(|a, b| a.path.cmp(&b.path));
+        findings
+    }
+}
+
+pub fn records(png: &Png) -> Vec<ChunkRecord> {
+    png.chunks()
+        .iter()
+        .map(|c| ChunkRecord {
+            chunk_type: c.chunk_type().to_string(),
+            length: c.length(),
+            digest: data_digest(c.data()),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkChange {
+    pub kind: ChangeKind,
+    /// Position in the baseline for a removed chunk, otherwise in the
+    /// current file.
+    pub index: usize,
+    pub chunk_type: String,
+}
+
+impl ChunkChange {
+    /// Whether the change is to the image data rather than to metadata.
+    pub fn is_pixel_data(&self) -> bool {
+        self.chunk_type == ChunkType::IDAT.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FindingKind {
+    New,
+    Missing,
+    Changed(Vec<ChunkChange>),
+}
+
+impl FindingKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FindingKind::New => "new",
+            FindingKind::Missing => "missing",
+            FindingKind::Changed(_) => "changed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub path: String,
+    pub kind: FindingKind,
+}
+
+/// Lines the two lists up by chunk type, keeping as many in order as
+/// possible. Chunks lined up with different data are modified, and the
+/// rest were added or removed.
+pub fn compare_chunks(before: &[ChunkRecord], after: &[ChunkRecord]) -> Vec<ChunkChange> {
+    // common[i][j] is the longest common run of types in before[i..] and after[j..]
+    let mut common = vec![vec![0; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i].chunk_type == after[j].chunk_type {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let change = |kind, index, record: &ChunkRecord| ChunkChange {
+        kind,
+        index,
+        chunk_type: record.chunk_type.clone(),
+    };
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i].chunk_type == after[j].chunk_type {
+            if before[i] != after[j] {
+                changes.push(change(ChangeKind::Modified, j, &after[j]));
+            }
+            i += 1;
+            j += 1;
+        } else if j == after.len() || (i < before.len() && common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(change(ChangeKind::Removed, i, &before[i]));
+            i += 1;
+        } else {
+            changes.push(change(ChangeKind::Added, j, &after[j]));
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(chunk_type: &str, data: &str) -> ChunkRecord {
+        ChunkRecord {
+            chunk_type: chunk_type.to_string(),
+            length: data.len() as u32,
+            digest: data_digest(data.as_bytes()),
+        }
+    }
+
+    fn kinds(changes: &[ChunkChange]) -> Vec<(ChangeKind, usize, &str)> {
+        changes
+            .iter()
+            .map(|c| (c.kind, c.index, c.chunk_type.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare_chunks() {
+        let before = [
+            record("IHDR", "h"),
+            record("tEXt", "a"),
+            record("IDAT", "pixels"),
+            record("IEND", ""),
+        ];
+        assert!(compare_chunks(&before, &before).is_empty());
+
+        let after = [
+            record("IHDR", "h"),
+            record("tEXt", "b"),
+            record("IDAT", "pixels"),
+            record("ruSt", "new"),
+            record("IEND", ""),
+        ];
+        let changes = compare_chunks(&before, &after);
+        assert_eq!(
+            kinds(&changes),
+            [
+                (ChangeKind::Modified, 1, "tEXt"),
+                (ChangeKind::Added, 3, "ruSt")
+            ]
+        );
+        assert!(!changes[0].is_pixel_data());
+
+        let after = [
+            record("IHDR", "h"),
+            record("IDAT", "other"),
+            record("IEND", ""),
+        ];
+        let changes = compare_chunks(&before, &after);
+        assert_eq!(
+            kinds(&changes),
+            [
+                (ChangeKind::Removed, 1, "tEXt"),
+                (ChangeKind::Modified, 1, "IDAT")
+            ]
+        );
+        assert!(changes[1].is_pixel_data());
+    }
+
+    #[test]
+    fn test_manifest_compare() {
+        let mut baseline = Manifest::default();
+        baseline
+            .files
+            .insert("a.png".into(), vec![record("IHDR", "h")]);
+        baseline
+            .files
+            .insert("gone.png".into(), vec![record("IHDR", "h")]);
+        let mut current = baseline.clone();
+        current.files.remove("gone.png");
+        current
+            .files
+            .insert("new.png".into(), vec![record("IHDR", "h")]);
+        let findings = baseline.compare(&current);
+        let summary: Vec<_> = findings
+            .iter()
+            .map(|f| (f.path.as_str(), f.kind.as_str()))
+            .collect();
+        assert_eq!(summary, [("gone.png", "missing"), ("new.png", "new")]);
+
+        let json = serde_json::to_string(&baseline).unwrap();
+        assert_eq!(serde_json::from_str::<Manifest>(&json).unwrap(), baseline);
+    }
+}
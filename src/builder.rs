@@ -0,0 +1,511 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{ParseOptions, Png};
+use crate::text;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+/// Pixel layouts the builder knows how to generate, all at 8 bits per sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Rgb,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+    fn code(&self) -> u8 {
+        match self {
+            ColorType::Rgb => 2,
+            ColorType::Rgba => 6,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BuilderError {
+    ZeroDimension,
+    TooLarge { width: u32, height: u32 },
+    FillMismatch { expected: usize, got: usize },
+}
+
+impl std::fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuilderError::ZeroDimension => write!(f, "Width and height must be non-zero"),
+            BuilderError::TooLarge { width, height } => write!(
+                f,
+                "{width}x{height} exceeds the maximum of {0}x{0}",
+                PngBuilder::MAX_DIMENSION
+            ),
+            BuilderError::FillMismatch { expected, got } => write!(
+                f,
+                "Fill color has {got} channels but the color type needs {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuilderError {}
+
+/// Generates a minimal solid-color PNG (IHDR, one IDAT, IEND), with any extra
+/// chunks placed just before IEND.
+#[derive(Debug)]
+pub struct PngBuilder {
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    fill: Option<Vec<u8>>,
+    chunks: Vec<Chunk>,
+}
+
+impl PngBuilder {
+    pub const MAX_DIMENSION: u32 = 4096;
+
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            color_type: ColorType::Rgb,
+            fill: None,
+            chunks: Vec::new(),
+        }
+    }
+    pub fn color_type(mut self, color_type: ColorType) -> Self {
+        self.color_type = color_type;
+        self
+    }
+    /// Sample values for every pixel, one byte per channel. Defaults to all zeros.
+    pub fn fill(mut self, color: &[u8]) -> Self {
+        self.fill = Some(color.to_vec());
+        self
+    }
+    pub fn with_chunk(mut self, chunk: Chunk) -> Self {
+        self.chunks.push(chunk);
+        self
+    }
+    pub fn build(self) -> Result<Png, BuilderError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(BuilderError::ZeroDimension);
+        }
+        if self.width > Self::MAX_DIMENSION || self.height > Self::MAX_DIMENSION {
+            return Err(BuilderError::TooLarge {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        let channels = self.color_type.channels();
+        let fill = self.fill.unwrap_or_else(|| vec![0; channels]);
+        if fill.len() != channels {
+            return Err(BuilderError::FillMismatch {
+                expected: channels,
+                got: fill.len(),
+            });
+        }
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        // bit depth, color type, compression, filter, interlace
+        ihdr.extend_from_slice(&[8, self.color_type.code(), 0, 0, 0]);
+
+        // Every scanline starts with filter type 0 (None)
+        let mut scanline = vec![0];
+        for _ in 0..self.width {
+            scanline.extend_from_slice(&fill);
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for _ in 0..self.height {
+            encoder
+                .write_all(&scanline)
+                .expect("writing to a Vec can't fail");
+        }
+        let idat = encoder.finish().expect("writing to a Vec can't fail");
+
+        let mut chunks = vec![
+            Chunk::new(ChunkType::IHDR, ihdr),
+            Chunk::new(ChunkType::IDAT, idat),
+        ];
+        chunks.extend(self.chunks);
+        chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+/// Marks chunk data wrapped by `ChunkBuilder::envelope`.
+pub const ENVELOPE_MAGIC: &[u8] = b"PMENV1\0";
+
+/// A MIME type and file name stored ahead of a payload, as
+/// `ENVELOPE_MAGIC`, then each field followed by a NUL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub mime: String,
+    pub filename: String,
+}
+
+impl Envelope {
+    fn wrap(&self, payload: &[u8]) -> Vec<u8> {
+        let mut data = ENVELOPE_MAGIC.to_vec();
+        for field in [&self.mime, &self.filename] {
+            data.extend_from_slice(field.as_bytes());
+            data.push(0);
+        }
+        data.extend_from_slice(payload);
+        data
+    }
+    /// Splits enveloped chunk data into the envelope and the payload, or
+    /// `None` if `data` isn't enveloped.
+    pub fn parse(data: &[u8]) -> Option<(Envelope, &[u8])> {
+        let rest = data.strip_prefix(ENVELOPE_MAGIC)?;
+        let mut fields = rest.splitn(3, |&b| b == 0);
+        let mime = std::str::from_utf8(fields.next()?).ok()?;
+        let filename = std::str::from_utf8(fields.next()?).ok()?;
+        let payload = fields.next()?;
+        let envelope = Envelope {
+            mime: mime.to_string(),
+            filename: filename.to_string(),
+        };
+        Some((envelope, payload))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkBuildError {
+    /// The final data is longer than `max_size`.
+    TooLarge { len: usize, max: u32 },
+    /// The chunk type's reserved bit is set, which the spec forbids.
+    ReservedBit(ChunkType),
+    /// An envelope field contains a NUL, which would end it early.
+    NulInEnvelope,
+    /// Two options that can't be used together.
+    Conflict(&'static str, &'static str),
+}
+
+impl std::fmt::Display for ChunkBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkBuildError::TooLarge { len, max } => {
+                write!(
+                    f,
+                    "The chunk would hold {len} bytes, more than the maximum of {max}"
+                )
+            }
+            ChunkBuildError::ReservedBit(chunk_type) => write!(
+                f,
+                "{chunk_type} has its reserved bit set (a lowercase third letter)"
+            ),
+            ChunkBuildError::NulInEnvelope => {
+                write!(f, "Envelope MIME type and file name can't contain NUL")
+            }
+            ChunkBuildError::Conflict(a, b) => write!(f, "{a} can't be used with {b}"),
+        }
+    }
+}
+
+impl std::error::Error for ChunkBuildError {}
+
+/// Builds a chunk from a payload and options, returned by `Chunk::builder`.
+/// `build` wraps the payload in the envelope, then compresses it, then
+/// checks the size, then computes the CRC unless one was given.
+#[derive(Debug, Clone)]
+pub struct ChunkBuilder {
+    chunk_type: ChunkType,
+    data: Vec<u8>,
+    compressed: bool,
+    envelope: Option<Envelope>,
+    max_size: u32,
+    crc: Option<u32>,
+    allow_reserved_bit: bool,
+}
+
+impl ChunkBuilder {
+    pub fn new(chunk_type: ChunkType) -> Self {
+        Self {
+            chunk_type,
+            data: Vec::new(),
+            compressed: false,
+            envelope: None,
+            max_size: ParseOptions::SPEC_MAX_CHUNK_SIZE,
+            crc: None,
+            allow_reserved_bit: false,
+        }
+    }
+    pub fn data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.data = data.into();
+        self
+    }
+    /// Store the data zlib-compressed.
+    pub fn compressed(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+    /// Precede the payload with its MIME type and file name.
+    pub fn envelope(mut self, mime: impl Into<String>, filename: impl Into<String>) -> Self {
+        self.envelope = Some(Envelope {
+            mime: mime.into(),
+            filename: filename.into(),
+        });
+        self
+    }
+    /// Fail if the final data is longer than this. Defaults to the spec's maximum.
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+    /// Store this CRC instead of computing one. The data is used as given,
+    /// so this can't be combined with compression or an envelope.
+    pub fn crc(mut self, crc: u32) -> Self {
+        self.crc = Some(crc);
+        self
+    }
+    /// Accept a chunk type with its reserved bit set, for making test fixtures.
+    pub fn allow_reserved_bit(mut self, allow: bool) -> Self {
+        self.allow_reserved_bit = allow;
+        self
+    }
+    pub fn build(self) -> Result<Chunk, ChunkBuildError> {
+        if self.crc.is_some() && self.compressed {
+            return Err(ChunkBuildError::Conflict("crc", "compressed"));
+        }
+        if self.crc.is_some() && self.envelope.is_some() {
+            return Err(ChunkBuildError::Conflict("crc", "envelope"));
+        }
+        if !self.allow_reserved_bit && !self.chunk_type.is_reserved_bit_valid() {
+            return Err(ChunkBuildError::ReservedBit(self.chunk_type));
+        }
+        let mut data = self.data;
+        if let Some(envelope) = &self.envelope {
+            if envelope.mime.contains('\0') || envelope.filename.contains('\0') {
+                return Err(ChunkBuildError::NulInEnvelope);
+            }
+            data = envelope.wrap(&data);
+        }
+        if self.compressed {
+            data = text::deflate(&data);
+        }
+        if data.len() > self.max_size as usize {
+            return Err(ChunkBuildError::TooLarge {
+                len: data.len(),
+                max: self.max_size,
+            });
+        }
+        Ok(match self.crc {
+            Some(crc) => Chunk::with_crc(self.chunk_type, data, crc),
+            None => Chunk::new(self.chunk_type, data),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn decode(png: &Png) -> (png::OutputInfo, Vec<u8>) {
+        let bytes = png.as_bytes();
+        let decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        buf.truncate(info.buffer_size());
+        (info, buf)
+    }
+
+    #[test]
+    fn test_minimal_png_decodes() {
+        let png = PngBuilder::new(1, 1).build().unwrap();
+        let (info, pixels) = decode(&png);
+        assert_eq!((info.width, info.height), (1, 1));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(pixels, vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_solid_rgba_fill() {
+        let png = PngBuilder::new(3, 2)
+            .color_type(ColorType::Rgba)
+            .fill(&[255, 0, 128, 64])
+            .build()
+            .unwrap();
+        let (info, pixels) = decode(&png);
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(pixels, [255, 0, 128, 64].repeat(6));
+    }
+
+    #[test]
+    fn test_extra_chunks_before_iend() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+        let png = PngBuilder::new(2, 2).with_chunk(chunk).build().unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+        decode(&png);
+    }
+
+    #[test]
+    fn test_invalid_dimensions() {
+        assert_eq!(
+            PngBuilder::new(0, 1).build().unwrap_err(),
+            BuilderError::ZeroDimension
+        );
+        assert_eq!(
+            PngBuilder::new(1, PngBuilder::MAX_DIMENSION + 1)
+                .build()
+                .unwrap_err(),
+            BuilderError::TooLarge {
+                width: 1,
+                height: PngBuilder::MAX_DIMENSION + 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_fill_mismatch() {
+        let err = PngBuilder::new(1, 1)
+            .color_type(ColorType::Rgba)
+            .fill(&[1, 2, 3])
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            BuilderError::FillMismatch {
+                expected: 4,
+                got: 3
+            }
+        );
+    }
+
+    fn rust() -> ChunkType {
+        ChunkType::from_str("ruSt").unwrap()
+    }
+
+    #[test]
+    fn test_chunk_builder_plain() {
+        let chunk = Chunk::builder(rust()).data("hello").build().unwrap();
+        assert_eq!(chunk, Chunk::new(rust(), b"hello".to_vec()));
+        let empty = Chunk::builder(rust()).build().unwrap();
+        assert!(empty.data().is_empty());
+    }
+
+    #[test]
+    fn test_chunk_builder_compressed() {
+        let chunk = Chunk::builder(rust())
+            .data("hello ".repeat(100))
+            .compressed(true)
+            .build()
+            .unwrap();
+        assert!(chunk.data().len() < 600);
+        assert_eq!(chunk.crc(), chunk.computed_crc());
+        let mut inflated = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::ZlibDecoder::new(chunk.data()),
+            &mut inflated,
+        )
+        .unwrap();
+        assert_eq!(inflated, "hello ".repeat(100));
+    }
+
+    #[test]
+    fn test_chunk_builder_envelope() {
+        let chunk = Chunk::builder(rust())
+            .data("{}")
+            .envelope("application/json", "a.json")
+            .build()
+            .unwrap();
+        let (envelope, payload) = Envelope::parse(chunk.data()).unwrap();
+        assert_eq!(envelope.mime, "application/json");
+        assert_eq!(envelope.filename, "a.json");
+        assert_eq!(payload, b"{}");
+        assert!(Envelope::parse(b"{}").is_none());
+
+        let err = Chunk::builder(rust())
+            .envelope("text/plain", "a\0b")
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ChunkBuildError::NulInEnvelope);
+    }
+
+    #[test]
+    fn test_chunk_builder_envelope_then_compression() {
+        let chunk = Chunk::builder(rust())
+            .data("payload")
+            .envelope("text/plain", "a.txt")
+            .compressed(true)
+            .build()
+            .unwrap();
+        let mut inflated = Vec::new();
+        std::io::Read::read_to_end(
+            &mut flate2::read::ZlibDecoder::new(chunk.data()),
+            &mut inflated,
+        )
+        .unwrap();
+        assert_eq!(Envelope::parse(&inflated).unwrap().1, b"payload");
+    }
+
+    #[test]
+    fn test_chunk_builder_max_size() {
+        assert!(
+            Chunk::builder(rust())
+                .data("four")
+                .max_size(4)
+                .build()
+                .is_ok()
+        );
+        assert_eq!(
+            Chunk::builder(rust())
+                .data("five!")
+                .max_size(4)
+                .build()
+                .unwrap_err(),
+            ChunkBuildError::TooLarge { len: 5, max: 4 }
+        );
+    }
+
+    #[test]
+    fn test_chunk_builder_crc() {
+        let chunk = Chunk::builder(rust()).data("x").crc(7).build().unwrap();
+        assert_eq!(chunk.crc(), 7);
+        assert_ne!(chunk.computed_crc(), 7);
+    }
+
+    #[test]
+    fn test_chunk_builder_reserved_bit() {
+        let rust = ChunkType::from_str("Rust").unwrap();
+        assert_eq!(
+            Chunk::builder(rust).build().unwrap_err(),
+            ChunkBuildError::ReservedBit(rust)
+        );
+        assert!(
+            Chunk::builder(rust)
+                .allow_reserved_bit(true)
+                .build()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_chunk_builder_conflicts() {
+        let err = Chunk::builder(rust())
+            .crc(7)
+            .compressed(true)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ChunkBuildError::Conflict("crc", "compressed"));
+        let err = Chunk::builder(rust())
+            .crc(7)
+            .envelope("text/plain", "a.txt")
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ChunkBuildError::Conflict("crc", "envelope"));
+    }
+}
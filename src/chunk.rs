@@ -1,55 +1,213 @@
 #![allow(unused_variables, unused)]
-use crate::chunk_type::ChunkType;
+use crate::analysis::KnownFormat;
+use crate::builder::ChunkBuilder;
+use crate::chunk_type::{ChunkType, ChunkTypeError};
+use crate::format::{format_crc, format_size};
+use crate::png::ParseOptions;
+use crate::timings::{self, Phase};
 use std::convert::TryFrom;
+use std::sync::OnceLock;
 
-#[derive(Debug)]
+/// A chunk that owns its data. The CRC is worked out the first time it's
+/// needed and kept until the data changes, unless it was given, as a
+/// parsed chunk's is.
+#[derive(Clone)]
 pub struct Chunk {
-    length: u32,
     chunk_type: ChunkType,
     chunk_data: Vec<u8>,
-    crc: u32,
+    crc: OnceLock<u32>,
+}
+
+impl std::fmt::Debug for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Chunk")
+            .field("chunk_type", &self.chunk_type)
+            .field("chunk_data", &self.chunk_data)
+            .field("crc", &self.crc())
+            .finish()
+    }
+}
+
+/// Chunks are equal if they serialize the same, whether or not the CRC
+/// has been worked out yet.
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.chunk_type == other.chunk_type
+            && self.chunk_data == other.chunk_data
+            && self.crc() == other.crc()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InvalidChunk {
+    /// The input ends before a full 8-byte signature.
     Header,
-    Length,
-    Type,
-    Data,
-    Crc,
+    /// The first 8 bytes aren't the PNG signature. `detected` is the format
+    /// they belong to, if it's a common one.
+    InvalidSignature {
+        found: [u8; 8],
+        detected: Option<KnownFormat>,
+    },
+    /// The length field disagrees with the data bytes the chunk was given.
+    Length {
+        declared: u32,
+        available: usize,
+    },
+    /// The type bytes aren't all ASCII letters. `found` is the four bytes
+    /// as they are in the file.
+    Type {
+        found: [u8; 4],
+        error: ChunkTypeError,
+    },
+    /// The input ends before the chunk does.
+    Truncated {
+        needed: usize,
+        got: usize,
+    },
+    Crc {
+        stored: u32,
+        computed: u32,
+    },
+    TooLarge {
+        length: u32,
+        max: u32,
+    },
+    /// The data was asked for as text but isn't UTF-8.
+    NotUtf8 {
+        valid_up_to: usize,
+    },
+    /// The input is longer than `ParseOptions::max_file_size`.
+    FileTooLarge {
+        max: u64,
+    },
+    /// The input has more chunks than `ParseOptions::max_chunks`.
+    TooManyChunks {
+        max: usize,
+    },
+    /// The compressed chunks inflate to more than
+    /// `ParseOptions::max_total_decompressed`.
+    TooMuchDecompressed {
+        max: u64,
+    },
+}
+
+/// A stored CRC that isn't the one the chunk's type and data give.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrcMismatch {
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl std::fmt::Display for CrcMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Chunk has CRC {} but its contents give {}",
+            format_crc(self.stored),
+            format_crc(self.computed)
+        )
+    }
+}
+
+impl std::error::Error for CrcMismatch {}
+
+impl From<CrcMismatch> for InvalidChunk {
+    fn from(mismatch: CrcMismatch) -> Self {
+        InvalidChunk::Crc {
+            stored: mismatch.stored,
+            computed: mismatch.computed,
+        }
+    }
+}
+
+/// Compares `stored` with the CRC of `chunk_type` and `data`.
+fn check_crc(chunk_type: &ChunkType, data: &[u8], stored: u32) -> Result<(), CrcMismatch> {
+    let computed = timings::time(Phase::Crc, || crc_of(chunk_type, data));
+    if computed == stored {
+        Ok(())
+    } else {
+        Err(CrcMismatch { stored, computed })
+    }
+}
+
+impl InvalidChunk {
+    /// Whether this is a limit set in `ParseOptions` being reached, which a
+    /// valid file can also do.
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            InvalidChunk::TooLarge { .. }
+                | InvalidChunk::FileTooLarge { .. }
+                | InvalidChunk::TooManyChunks { .. }
+                | InvalidChunk::TooMuchDecompressed { .. }
+        )
+    }
 }
 
 // impl
 
 impl std::fmt::Display for Chunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} ", self.length)?;
+        write!(f, "{} ", self.length())?;
         write!(f, "{} ", self.chunk_type)?;
-        write!(
-            f,
-            "{} ",
-            std::str::from_utf8(&self.chunk_data).unwrap(),
-        )?;
-        write!(f, "{} ", self.crc)
+        write!(f, "{} ", String::from_utf8_lossy(&self.chunk_data))?;
+        write!(f, "{} ", format_crc(self.crc()))
     }
 }
 
+pub(crate) const X25: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+fn crc_of(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let mut digest = X25.digest();
+    digest.update(&chunk_type.bytes());
+    digest.update(data);
+    digest.finalize()
+}
+
 impl Chunk {
+    /// A chunk with the correct CRC, which isn't computed until it's needed.
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let length = data.len() as u32;
-        let mut whole = chunk_type.bytes().to_vec();
-        whole.extend_from_slice(&data);
-        const X25: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let val = X25.checksum(&whole);
         Self {
-            length,
             chunk_type,
             chunk_data: data,
-            crc: val,
+            crc: OnceLock::new(),
+        }
+    }
+    /// Starts building a chunk with options such as compression, checked
+    /// when `build` is called. `new` is the shortcut for plain data.
+    pub fn builder(chunk_type: ChunkType) -> ChunkBuilder {
+        ChunkBuilder::new(chunk_type)
+    }
+    /// Builds a chunk with `crc` stored as given rather than computed, which
+    /// is how a chunk with a wrong CRC is kept or deliberately produced.
+    pub fn with_crc(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Self {
+        Self {
+            chunk_type,
+            chunk_data: data,
+            crc: OnceLock::from(crc),
         }
     }
+    /// The CRC the chunk should have, which differs from `crc()` for a
+    /// chunk built with a wrong one.
+    pub fn computed_crc(&self) -> u32 {
+        crc_of(&self.chunk_type, &self.chunk_data)
+    }
+    /// Checks the stored CRC against the type and data, for a chunk that
+    /// was parsed with `ParseOptions::ignore_crc` or built with `with_crc`.
+    pub fn verify_crc(&self) -> Result<(), CrcMismatch> {
+        check_crc(&self.chunk_type, &self.chunk_data, self.crc())
+    }
+    /// The length of the data, as the length field stores it.
+    ///
+    /// Panics if the data is longer than the 2^31 - 1 bytes the spec allows,
+    /// which parsing and `ChunkBuilder` never produce.
     pub fn length(&self) -> u32 {
-        self.length
+        let len = self.chunk_data.len();
+        assert!(
+            len <= ParseOptions::SPEC_MAX_CHUNK_SIZE as usize,
+            "chunk data of {len} bytes is too long for a length field"
+        );
+        len as u32
     }
     pub fn chunk_type(&self) -> &ChunkType {
         &self.chunk_type
@@ -57,24 +215,35 @@ impl Chunk {
     pub fn data(&self) -> &[u8] {
         &self.chunk_data
     }
+    /// The data, to change in place. The CRC is worked out afresh the next
+    /// time it's needed, so a wrong one given to `with_crc` is dropped.
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        self.crc.take();
+        &mut self.chunk_data
+    }
+    /// The data as text, or where it stops being UTF-8.
     pub fn data_as_string(&self) -> Result<String, InvalidChunk> {
-        match String::from_utf8(self.chunk_data.clone()) {
-            Ok(val) => Ok(val),
-            Err(_) => { 
-                eprintln!("{:?}", self.data());
-                Err(InvalidChunk::Data)?
-            },
-        }
+        std::str::from_utf8(&self.chunk_data)
+            .map(str::to_string)
+            .map_err(|e| InvalidChunk::NotUtf8 {
+                valid_up_to: e.valid_up_to(),
+            })
     }
     pub fn crc(&self) -> u32 {
-        self.crc
+        *self.crc.get_or_init(|| {
+            timings::time(Phase::Crc, || crc_of(&self.chunk_type, &self.chunk_data))
+        })
+    }
+    /// Length of `as_bytes()`: the data and 12 bytes of length, type and CRC.
+    pub fn serialized_len(&self) -> usize {
+        self.chunk_data.len() + 12
     }
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut ans = vec![];
-        ans.extend_from_slice(&self.length.to_be_bytes());
+        let mut ans = Vec::with_capacity(self.serialized_len());
+        ans.extend_from_slice(&self.length().to_be_bytes());
         ans.extend_from_slice(&self.chunk_type.bytes());
         ans.extend_from_slice(&self.chunk_data);
-        ans.extend_from_slice(&self.crc.to_be_bytes());
+        ans.extend_from_slice(&self.crc().to_be_bytes());
         ans
     }
 }
@@ -82,11 +251,81 @@ impl Chunk {
 impl std::fmt::Display for InvalidChunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            InvalidChunk::Length => write!(f, "Invalid Length"),
-            InvalidChunk::Header => write!(f, "Invalid Header"),
-            InvalidChunk::Type => write!(f, "Invalid Type"),
-            InvalidChunk::Data => write!(f, "Invalid Data"),
-            InvalidChunk::Crc => write!(f, "Invalid Crc"),
+            InvalidChunk::Header => write!(f, "File is too short to hold a PNG signature"),
+            InvalidChunk::InvalidSignature {
+                found,
+                detected: Some(format),
+            } => {
+                write!(f, "input is a {} (", format.name())?;
+                for (i, byte) in found[..format.magic_len()].iter().enumerate() {
+                    let space = if i == 0 { "" } else { " " };
+                    write!(f, "{space}{byte:02X}")?;
+                }
+                write!(f, "), not a PNG")
+            }
+            InvalidChunk::InvalidSignature {
+                found,
+                detected: None,
+            } => {
+                write!(f, "Not a PNG file: expected signature")?;
+                for byte in crate::png::Png::STANDARD_HEADER {
+                    write!(f, " {byte:02x}")?;
+                }
+                write!(f, ", found")?;
+                for byte in found {
+                    write!(f, " {byte:02x}")?;
+                }
+                Ok(())
+            }
+            InvalidChunk::Length {
+                declared,
+                available,
+            } => write!(
+                f,
+                "Chunk declares a length of {declared} but has {available} data bytes"
+            ),
+            InvalidChunk::Type { found, error } => {
+                write!(f, "Invalid chunk type")?;
+                for byte in found {
+                    write!(f, " {byte:02x}")?;
+                }
+                write!(f, ": {error}")
+            }
+            InvalidChunk::Truncated { needed, got } => write!(
+                f,
+                "Chunk is cut short: it needs {needed} bytes but only {got} are left"
+            ),
+            InvalidChunk::Crc { stored, computed } => write!(
+                f,
+                "{}",
+                CrcMismatch {
+                    stored: *stored,
+                    computed: *computed,
+                }
+            ),
+            InvalidChunk::TooLarge { length, max } => write!(
+                f,
+                "Chunk declares a length of {}, more than the maximum of {}",
+                format_size(u64::from(*length), false),
+                format_size(u64::from(*max), false)
+            ),
+            InvalidChunk::NotUtf8 { valid_up_to } => write!(
+                f,
+                "Chunk data isn't UTF-8 text, from byte {valid_up_to} (0x{valid_up_to:x})"
+            ),
+            InvalidChunk::FileTooLarge { max } => write!(
+                f,
+                "File is larger than the maximum of {}",
+                format_size(*max, false)
+            ),
+            InvalidChunk::TooManyChunks { max } => {
+                write!(f, "File has more than the maximum of {max} chunks")
+            }
+            InvalidChunk::TooMuchDecompressed { max } => write!(
+                f,
+                "Compressed chunks inflate to more than the maximum of {}",
+                format_size(*max, false)
+            ),
         }
     }
 }
@@ -96,14 +335,86 @@ impl std::error::Error for InvalidChunk {}
 impl TryFrom<&[u8]> for Chunk {
     type Error = InvalidChunk;
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let length = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
-        let chunk_type = ChunkType::try_from([value[4], value[5], value[6], value[7]]).unwrap();
+        Ok(ChunkRef::parse(value, 0)?.to_owned())
+    }
+}
+
+/// A chunk borrowed straight out of the file buffer, for read-only work that
+/// shouldn't pay for copying every payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+    offset: usize,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Parses exactly one serialized chunk and verifies its CRC. `offset` is
+    /// where `value` starts in the file and is only recorded, not used.
+    pub fn parse(value: &'a [u8], offset: usize) -> Result<Self, InvalidChunk> {
+        Self::parse_inner(value, offset, true, false)
+    }
+    /// Like `parse`, but keeps the stored CRC even when it doesn't match.
+    pub fn parse_unverified(value: &'a [u8], offset: usize) -> Result<Self, InvalidChunk> {
+        Self::parse_inner(value, offset, false, false)
+    }
+    /// `parse` as `options.ignore_crc` and `options.any_chunk_type` say.
+    pub(crate) fn parse_with(
+        value: &'a [u8],
+        offset: usize,
+        options: &ParseOptions,
+    ) -> Result<Self, InvalidChunk> {
+        Self::parse_inner(value, offset, !options.ignore_crc, options.any_chunk_type)
+    }
+    /// `parse_with` where a chunk is expected to start. With
+    /// `options.lenient` a chunk whose type isn't letters is kept with that
+    /// type if its CRC is right, since the CRC shows it's a chunk as written
+    /// rather than bytes that happen to look like one. Not for every offset
+    /// a lenient parse skips, which would checksum a chunk's worth of bytes
+    /// at each of them.
+    pub(crate) fn parse_expected(
+        value: &'a [u8],
+        offset: usize,
+        options: &ParseOptions,
+    ) -> Result<Self, InvalidChunk> {
+        match Self::parse_with(value, offset, options) {
+            Err(InvalidChunk::Type { .. }) if options.lenient => {
+                Self::parse_inner(value, offset, true, true)
+            }
+            parsed => parsed,
+        }
+    }
+    fn parse_inner(
+        value: &'a [u8],
+        offset: usize,
+        verify_crc: bool,
+        any_type: bool,
+    ) -> Result<Self, InvalidChunk> {
         let len = value.len();
+        if len < 12 {
+            Err(InvalidChunk::Truncated {
+                needed: 12,
+                got: len,
+            })?
+        }
+        let length = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
         if (length as usize) != len - 12 {
-            Err(InvalidChunk::Length)?
+            Err(InvalidChunk::Length {
+                declared: length,
+                available: len - 12,
+            })?
         }
-        let chunk_data = value[8..(8 + length as usize)].to_vec();
-
+        let bytes = [value[4], value[5], value[6], value[7]];
+        let chunk_type = if any_type {
+            ChunkType::from_bytes_unchecked(bytes)
+        } else {
+            ChunkType::try_from(bytes).map_err(|error| InvalidChunk::Type {
+                found: bytes,
+                error,
+            })?
+        };
+        let data = &value[8..len - 4];
         let crc = u32::from_be_bytes([
             value[len - 4],
             value[len - 3],
@@ -111,41 +422,89 @@ impl TryFrom<&[u8]> for Chunk {
             value[len - 1],
         ]);
         let chunk = Self {
-            length,
             chunk_type,
-            chunk_data,
+            data,
             crc,
+            offset,
         };
-
-        const X25: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let val = X25.checksum(&value[4..len - 4]);
-        if val != crc {
-            Err(InvalidChunk::Crc)?
+        if verify_crc {
+            chunk.verify_crc()?;
         }
         Ok(chunk)
     }
+    /// The chunk whose header is at `offset` in `value` and whose data
+    /// ends at `data_end`, whatever its length field says. The CRC is the
+    /// four bytes after the data, taken as they are.
+    ///
+    /// Panics if `value` is too short for that. The type is taken as it
+    /// is; `recover_length` has already checked it.
+    pub(crate) fn with_length(value: &'a [u8], offset: usize, data_end: usize) -> Self {
+        Self {
+            chunk_type: ChunkType::from_bytes_unchecked(
+                <[u8; 4]>::try_from(&value[offset + 4..offset + 8]).unwrap(),
+            ),
+            data: &value[offset + 8..data_end],
+            crc: u32::from_be_bytes(value[data_end..data_end + 4].try_into().unwrap()),
+            offset,
+        }
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    /// As `Chunk::serialized_len`.
+    pub fn serialized_len(&self) -> usize {
+        self.data.len() + 12
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    pub fn data_as_str(&self) -> Result<&'a str, InvalidChunk> {
+        std::str::from_utf8(self.data).map_err(|e| InvalidChunk::NotUtf8 {
+            valid_up_to: e.valid_up_to(),
+        })
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    /// As `Chunk::verify_crc`.
+    pub fn verify_crc(&self) -> Result<(), CrcMismatch> {
+        check_crc(&self.chunk_type, self.data, self.crc)
+    }
+    /// Byte offset of the chunk's length field within the file.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn to_owned(self) -> Chunk {
+        Chunk::with_crc(self.chunk_type, self.data.to_vec(), self.crc)
+    }
+}
+
+/// An owned chunk seen as a `ChunkRef`, so code that only reads chunks can
+/// take either kind. It isn't in a file, so its offset is 0.
+impl<'a> From<&'a Chunk> for ChunkRef<'a> {
+    fn from(chunk: &'a Chunk) -> Self {
+        Self {
+            chunk_type: chunk.chunk_type,
+            data: &chunk.chunk_data,
+            crc: chunk.crc(),
+            offset: 0,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
+    use crate::testutil::raw_chunk;
     use std::str::FromStr;
 
     fn testing_chunk() -> Chunk {
-        let data_length: u32 = 42;
-        let chunk_type = "RuSt".as_bytes();
         let message_bytes = "This is where your secret message will be!".as_bytes();
-        let crc: u32 = 2882656334;
-
-        let chunk_data: Vec<u8> = data_length
-            .to_be_bytes()
-            .iter()
-            .chain(chunk_type.iter())
-            .chain(message_bytes.iter())
-            .chain(crc.to_be_bytes().iter())
-            .copied()
-            .collect();
+        let chunk_data = raw_chunk(b"RuSt", message_bytes, 2882656334);
 
         Chunk::try_from(chunk_data.as_ref()).unwrap()
     }
@@ -189,19 +548,8 @@ mod tests {
 
     #[test]
     fn test_valid_chunk_from_bytes() {
-        let data_length: u32 = 42;
-        let chunk_type = "RuSt".as_bytes();
         let message_bytes = "This is where your secret message will be!".as_bytes();
-        let crc: u32 = 2882656334;
-
-        let chunk_data: Vec<u8> = data_length
-            .to_be_bytes()
-            .iter()
-            .chain(chunk_type.iter())
-            .chain(message_bytes.iter())
-            .chain(crc.to_be_bytes().iter())
-            .copied()
-            .collect();
+        let chunk_data = raw_chunk(b"RuSt", message_bytes, 2882656334);
 
         let chunk = Chunk::try_from(chunk_data.as_ref()).unwrap();
 
@@ -216,40 +564,175 @@ mod tests {
 
     #[test]
     fn test_invalid_chunk_from_bytes() {
-        let data_length: u32 = 42;
-        let chunk_type = "RuSt".as_bytes();
         let message_bytes = "This is where your secret message will be!".as_bytes();
-        let crc: u32 = 2882656333;
-
-        let chunk_data: Vec<u8> = data_length
-            .to_be_bytes()
-            .iter()
-            .chain(chunk_type.iter())
-            .chain(message_bytes.iter())
-            .chain(crc.to_be_bytes().iter())
-            .copied()
-            .collect();
+        let chunk_data = raw_chunk(b"RuSt", message_bytes, 2882656333);
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
 
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_empty_chunk_round_trip() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new());
+        let bytes = chunk.as_bytes();
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(&bytes[..8], &[0, 0, 0, 0, b'r', b'u', b'S', b't']);
+
+        let parsed = Chunk::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(parsed.length(), 0);
+        assert!(parsed.data().is_empty());
+        assert_eq!(parsed.data_as_string().unwrap(), "");
+        assert_eq!(parsed, chunk);
+        let _chunk_string = format!("{}", parsed);
+    }
+
+    #[test]
+    fn test_type_only_crc() {
+        // IEND has no data, so its CRC covers just the four type bytes
+        let chunk = Chunk::new(ChunkType::IEND, Vec::new());
+        assert_eq!(chunk.crc(), 0xAE42_6082);
+        assert_eq!(
+            chunk.as_bytes(),
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn test_chunk_from_short_slices() {
+        let bytes = testing_chunk().as_bytes();
+        for len in 0..bytes.len() {
+            assert!(Chunk::try_from(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_chunk_with_invalid_type_bytes() {
+        let found = [0x00, 0x41, 0x42, 0x43];
+        let bytes = raw_chunk(&found, b"data", crate::testutil::crc(&found, b"data"));
+        let error = Chunk::try_from(bytes.as_ref()).unwrap_err();
+        assert_eq!(
+            error,
+            InvalidChunk::Type {
+                found,
+                error: ChunkTypeError::InvalidByte {
+                    position: 0,
+                    byte: 0
+                }
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Invalid chunk type 00 41 42 43: Byte 0 of the chunk type is 0x00, but only ASCII letters are allowed"
+        );
+    }
+
+    #[test]
+    fn test_chunk_display_non_utf8() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let chunk = Chunk::new(chunk_type, vec![0xff, 0xfe, b'a']);
+        let _chunk_string = format!("{}", chunk);
+    }
+
+    #[test]
+    fn test_with_crc_keeps_the_given_crc() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let good = Chunk::new(chunk_type, b"data".to_vec());
+        let bad = Chunk::with_crc(chunk_type, b"data".to_vec(), good.crc() ^ 1);
+        assert_eq!(bad.crc(), good.crc() ^ 1);
+        assert_eq!(bad.computed_crc(), good.crc());
+        assert_eq!(bad.length(), 4);
+
+        let bytes = bad.as_bytes();
+        assert_eq!(
+            ChunkRef::parse(&bytes, 0).unwrap_err(),
+            InvalidChunk::Crc {
+                stored: good.crc() ^ 1,
+                computed: good.crc()
+            }
+        );
+        let kept = ChunkRef::to_owned(ChunkRef::parse_unverified(&bytes, 0).unwrap());
+        assert_eq!(kept, bad);
+        // Parsed without checking, the mismatch still shows when asked
+        let mismatch = CrcMismatch {
+            stored: good.crc() ^ 1,
+            computed: good.crc(),
+        };
+        assert_eq!(kept.verify_crc(), Err(mismatch));
+        assert_eq!(
+            ChunkRef::parse_unverified(&bytes, 0).unwrap().verify_crc(),
+            Err(mismatch)
+        );
+        assert_eq!(good.verify_crc(), Ok(()));
+    }
+
+    #[test]
+    fn test_crc_follows_data_changes() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let mut chunk = Chunk::new(chunk_type, b"data".to_vec());
+        let first = chunk.crc();
+        assert_eq!(chunk.crc(), first);
+        chunk.data_mut().extend_from_slice(b" and more");
+        assert_eq!(chunk.length(), 13);
+        assert_eq!(
+            chunk.crc(),
+            Chunk::new(chunk_type, b"data and more".to_vec()).crc()
+        );
+        assert_ne!(chunk.crc(), first);
+        assert_eq!(Chunk::try_from(chunk.as_bytes().as_slice()).unwrap(), chunk);
+
+        // A wrong CRC is kept until the data changes
+        let mut bad = Chunk::with_crc(chunk_type, b"data".to_vec(), first ^ 1);
+        assert_eq!(bad.crc(), first ^ 1);
+        assert_ne!(bad, Chunk::new(chunk_type, b"data".to_vec()));
+        bad.data_mut().clear();
+        assert_eq!(bad.crc(), bad.computed_crc());
+        assert_eq!(bad.verify_crc(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_chunk_messages() {
+        let bytes = testing_chunk().as_bytes();
+        let message = |bytes: &[u8]| ChunkRef::parse(bytes, 0).unwrap_err().to_string();
+        assert_eq!(
+            message(&bytes[..10]),
+            "Chunk is cut short: it needs 12 bytes but only 10 are left"
+        );
+        assert_eq!(
+            message(&bytes[..20]),
+            "Chunk declares a length of 42 but has 8 data bytes"
+        );
+        let mut bad_type = bytes.clone();
+        bad_type[6] = b'1';
+        assert_eq!(
+            message(&bad_type),
+            "Invalid chunk type 52 75 31 74: Byte 2 of the chunk type is 0x31 ('1'), but only ASCII letters are allowed"
+        );
+        let mut bad_crc = bytes.clone();
+        *bad_crc.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            message(&bad_crc),
+            "Chunk has CRC 0xabd1d84f but its contents give 0xabd1d84e"
+        );
+        let too_large = InvalidChunk::TooLarge {
+            length: 5000,
+            max: 4096,
+        };
+        assert_eq!(
+            too_large.to_string(),
+            "Chunk declares a length of 5000 bytes, more than the maximum of 4096 bytes"
+        );
+        let binary = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![b'a', 0xff]);
+        assert_eq!(
+            binary.data_as_string().unwrap_err().to_string(),
+            "Chunk data isn't UTF-8 text, from byte 1 (0x1)"
+        );
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
-        let data_length: u32 = 42;
-        let chunk_type = "RuSt".as_bytes();
         let message_bytes = "This is where your secret message will be!".as_bytes();
-        let crc: u32 = 2882656334;
-
-        let chunk_data: Vec<u8> = data_length
-            .to_be_bytes()
-            .iter()
-            .chain(chunk_type.iter())
-            .chain(message_bytes.iter())
-            .chain(crc.to_be_bytes().iter())
-            .copied()
-            .collect();
+        let chunk_data = raw_chunk(b"RuSt", message_bytes, 2882656334);
 
         let chunk: Chunk = TryFrom::try_from(chunk_data.as_ref()).unwrap();
 
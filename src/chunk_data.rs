@@ -0,0 +1,457 @@
+//! The payloads of well-known chunk types, decoded. `KnownChunk::parse`
+//! is the one place that decides which types are understood, so `info`,
+//! `print --data` and `tags` all pick up a type once it's added here.
+//!
+//! ```
+//! use pngme::chunk::Chunk;
+//! use pngme::chunk_data::{GAMA, KnownChunk};
+//! let chunk = Chunk::new(GAMA, 45455u32.to_be_bytes().to_vec());
+//! let known = KnownChunk::parse(&chunk);
+//! assert_eq!(known, KnownChunk::Gamma(45455));
+//! assert_eq!(known.to_string(), "0.45455");
+//! assert_eq!(known.to_chunk().unwrap(), chunk);
+//! ```
+//!
+//! Compressed payloads are kept as they're stored rather than inflated and
+//! compressed again, except in text, whose value is inflated so it can be
+//! read; a text chunk written by another encoder may come back from
+//! `to_chunk` compressed differently.
+
+use crate::chunk::{Chunk, ChunkRef};
+use crate::chunk_type::ChunkType;
+use crate::format::format_size;
+use crate::text::{self, ICCP, TextEntry, TextErrorKind};
+use serde_json::{Value, json};
+use std::fmt::{self, Display};
+
+pub const TIME: ChunkType = ChunkType::literal(*b"tIME");
+pub const PHYS: ChunkType = ChunkType::literal(*b"pHYs");
+pub const GAMA: ChunkType = ChunkType::literal(*b"gAMA");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkDataError {
+    /// The type has a fixed size and the data isn't it.
+    Length {
+        expected: usize,
+        actual: usize,
+    },
+    /// A field holds a value the spec doesn't allow.
+    OutOfRange {
+        field: &'static str,
+        value: u32,
+    },
+    Text(TextErrorKind),
+}
+
+impl Display for ChunkDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkDataError::Length { expected, actual } => {
+                write!(f, "Data is {actual} bytes, not {expected}")
+            }
+            ChunkDataError::OutOfRange { field, value } => {
+                write!(f, "{value} is out of range for the {field}")
+            }
+            ChunkDataError::Text(kind) => write!(f, "{kind}"),
+        }
+    }
+}
+
+impl std::error::Error for ChunkDataError {}
+
+impl From<TextErrorKind> for ChunkDataError {
+    fn from(kind: TextErrorKind) -> Self {
+        ChunkDataError::Text(kind)
+    }
+}
+
+fn fixed<const N: usize>(data: &[u8]) -> Result<&[u8; N], ChunkDataError> {
+    data.try_into().map_err(|_| ChunkDataError::Length {
+        expected: N,
+        actual: data.len(),
+    })
+}
+
+/// tIME: when the image was last changed, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeChunk {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    /// Up to 60, for a leap second.
+    pub second: u8,
+}
+
+impl TimeChunk {
+    pub fn parse(data: &[u8]) -> Result<TimeChunk, ChunkDataError> {
+        let &[y0, y1, month, day, hour, minute, second] = fixed::<7>(data)?;
+        let fields = [
+            ("month", month, 1..=12),
+            ("day", day, 1..=31),
+            ("hour", hour, 0..=23),
+            ("minute", minute, 0..=59),
+            ("second", second, 0..=60),
+        ];
+        for (field, value, range) in fields {
+            if !range.contains(&value) {
+                let value = value.into();
+                return Err(ChunkDataError::OutOfRange { field, value });
+            }
+        }
+        Ok(TimeChunk {
+            year: u16::from_be_bytes([y0, y1]),
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.year.to_be_bytes().to_vec();
+        data.extend([self.month, self.day, self.hour, self.minute, self.second]);
+        Chunk::new(TIME, data)
+    }
+}
+
+/// ISO 8601, e.g. `2024-05-01T12:30:00Z`.
+impl Display for TimeChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// pHYs: the intended pixel size or aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysChunk {
+    pub x: u32,
+    pub y: u32,
+    /// Pixels per metre if set, otherwise `x` and `y` only give the aspect
+    /// ratio.
+    pub per_metre: bool,
+}
+
+impl PhysChunk {
+    pub fn parse(data: &[u8]) -> Result<PhysChunk, ChunkDataError> {
+        let data = fixed::<9>(data)?;
+        let unit = data[8];
+        if unit > 1 {
+            return Err(ChunkDataError::OutOfRange {
+                field: "unit",
+                value: unit.into(),
+            });
+        }
+        Ok(PhysChunk {
+            x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            per_metre: unit == 1,
+        })
+    }
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.x.to_be_bytes().to_vec();
+        data.extend(self.y.to_be_bytes());
+        data.push(self.per_metre as u8);
+        Chunk::new(PHYS, data)
+    }
+}
+
+/// e.g. `2835x2835 pixels per metre (72 dpi)` or `aspect ratio 2:1`.
+impl Display for PhysChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.per_metre {
+            return write!(f, "aspect ratio {}:{}", self.x, self.y);
+        }
+        write!(f, "{}x{} pixels per metre", self.x, self.y)?;
+        let dpi = |per_metre: u32| (per_metre as f64 * 0.0254).round();
+        if self.x == self.y {
+            write!(f, " ({} dpi)", dpi(self.x))
+        } else {
+            write!(f, " ({}x{} dpi)", dpi(self.x), dpi(self.y))
+        }
+    }
+}
+
+/// iCCP: an embedded color profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IccpChunk {
+    pub name: String,
+    /// The zlib stream as stored. `profile` inflates it.
+    pub compressed: Vec<u8>,
+}
+
+impl IccpChunk {
+    pub fn parse(data: &[u8]) -> Result<IccpChunk, ChunkDataError> {
+        let (name, rest) = text::split_null(data)?;
+        if !text::is_valid_keyword(name) {
+            return Err(TextErrorKind::BadKeyword.into());
+        }
+        let (&method, compressed) = rest.split_first().ok_or(TextErrorKind::MissingSeparator)?;
+        if method != 0 {
+            return Err(TextErrorKind::UnknownCompression(method).into());
+        }
+        Ok(IccpChunk {
+            name: text::latin1(name),
+            compressed: compressed.to_vec(),
+        })
+    }
+    /// The profile itself.
+    pub fn profile(&self) -> Result<Vec<u8>, ChunkDataError> {
+        Ok(text::inflate(0, &self.compressed)?)
+    }
+    pub fn to_chunk(&self) -> Result<Chunk, ChunkDataError> {
+        let mut data = text::to_latin1(&self.name)
+            .filter(|name| text::is_valid_keyword(name))
+            .ok_or(TextErrorKind::BadKeyword)?;
+        data.extend([0, 0]);
+        data.extend_from_slice(&self.compressed);
+        Ok(Chunk::new(ICCP, data))
+    }
+}
+
+impl Display for IccpChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {} compressed",
+            self.name,
+            format_size(self.compressed.len() as u64, true)
+        )
+    }
+}
+
+/// A chunk with its data decoded, if its type is one this module knows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KnownChunk<'a> {
+    /// tEXt, zTXt or iTXt. The entry's `index` is 0, as a lone chunk has
+    /// no position.
+    Text(TextEntry),
+    Time(TimeChunk),
+    Phys(PhysChunk),
+    /// gAMA: the image gamma times 100,000.
+    Gamma(u32),
+    Iccp(IccpChunk),
+    /// A known type whose data doesn't decode.
+    Malformed(ChunkRef<'a>, ChunkDataError),
+    /// Any other type.
+    Unknown(ChunkRef<'a>),
+}
+
+impl<'a> KnownChunk<'a> {
+    /// Decodes `chunk`, owned or borrowed.
+    pub fn parse(chunk: impl Into<ChunkRef<'a>>) -> KnownChunk<'a> {
+        let chunk = chunk.into();
+        let chunk_type = *chunk.chunk_type();
+        let data = chunk.data();
+        let parsed = if text::is_text_type(&chunk_type) {
+            match TextEntry::parse(0, chunk) {
+                Some(Ok(entry)) => Ok(KnownChunk::Text(entry)),
+                Some(Err(e)) => Err(e.kind.into()),
+                None => unreachable!("is_text_type and TextEntry::parse agree"),
+            }
+        } else if chunk_type == TIME {
+            TimeChunk::parse(data).map(KnownChunk::Time)
+        } else if chunk_type == PHYS {
+            PhysChunk::parse(data).map(KnownChunk::Phys)
+        } else if chunk_type == GAMA {
+            fixed::<4>(data).map(|&gamma| KnownChunk::Gamma(u32::from_be_bytes(gamma)))
+        } else if chunk_type == ICCP {
+            IccpChunk::parse(data).map(KnownChunk::Iccp)
+        } else {
+            return KnownChunk::Unknown(chunk);
+        };
+        parsed.unwrap_or_else(|e| KnownChunk::Malformed(chunk, e))
+    }
+    /// What the chunk holds, for a heading such as `info` prints, or `None`
+    /// for a type this module doesn't know.
+    pub fn label(&self) -> Option<&'static str> {
+        Some(match self {
+            KnownChunk::Text(_) => "Text",
+            KnownChunk::Time(_) => "Last modified",
+            KnownChunk::Phys(_) => "Pixel size",
+            KnownChunk::Gamma(_) => "Gamma",
+            KnownChunk::Iccp(_) => "ICC profile",
+            KnownChunk::Malformed(..) => "Unreadable",
+            KnownChunk::Unknown(_) => return None,
+        })
+    }
+    /// The chunk again. Decoded chunks are encoded afresh, so a text
+    /// chunk's value may come back compressed differently; the others come
+    /// back byte for byte.
+    pub fn to_chunk(&self) -> Result<Chunk, ChunkDataError> {
+        match self {
+            KnownChunk::Text(entry) => Ok(entry.to_chunk()?),
+            KnownChunk::Time(time) => Ok(time.to_chunk()),
+            KnownChunk::Phys(phys) => Ok(phys.to_chunk()),
+            KnownChunk::Gamma(gamma) => Ok(Chunk::new(GAMA, gamma.to_be_bytes().to_vec())),
+            KnownChunk::Iccp(iccp) => iccp.to_chunk(),
+            KnownChunk::Malformed(chunk, _) | KnownChunk::Unknown(chunk) => {
+                Ok(ChunkRef::to_owned(*chunk))
+            }
+        }
+    }
+    /// The decoded fields, for `--json` output. `null` for an unknown type.
+    pub fn to_json(&self) -> Value {
+        match self {
+            KnownChunk::Text(entry) => json!({
+                "keyword": entry.keyword,
+                "value": entry.value,
+                "compressed": entry.compressed,
+                "language": entry.language,
+                "translated_keyword": entry.translated_keyword,
+            }),
+            KnownChunk::Time(time) => json!({
+                "year": time.year,
+                "month": time.month,
+                "day": time.day,
+                "hour": time.hour,
+                "minute": time.minute,
+                "second": time.second,
+                "iso8601": time.to_string(),
+            }),
+            KnownChunk::Phys(phys) => json!({
+                "x": phys.x,
+                "y": phys.y,
+                "unit": if phys.per_metre { "metre" } else { "unknown" },
+            }),
+            KnownChunk::Gamma(gamma) => json!({
+                "gamma": gamma,
+                "value": *gamma as f64 / 100_000.0,
+            }),
+            KnownChunk::Iccp(iccp) => json!({
+                "name": iccp.name,
+                "compressed_bytes": iccp.compressed.len(),
+            }),
+            KnownChunk::Malformed(_, e) => json!({ "error": e.to_string() }),
+            KnownChunk::Unknown(_) => Value::Null,
+        }
+    }
+}
+
+/// One line for `info` and `print --data`. Unknown chunks show their type.
+impl Display for KnownChunk<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KnownChunk::Text(entry) => write!(f, "{} = {:?}", entry.keyword, entry.value),
+            KnownChunk::Time(time) => write!(f, "{time}"),
+            KnownChunk::Phys(phys) => write!(f, "{phys}"),
+            KnownChunk::Gamma(gamma) => write!(f, "{}", *gamma as f64 / 100_000.0),
+            KnownChunk::Iccp(iccp) => write!(f, "{iccp}"),
+            KnownChunk::Malformed(chunk, e) => write!(f, "{}: {e}", chunk.chunk_type()),
+            KnownChunk::Unknown(chunk) => write!(f, "{}", chunk.chunk_type()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// One chunk of every type `KnownChunk` decodes, and one it doesn't.
+    fn fixture() -> Vec<Chunk> {
+        let mut compressed = TextEntry::new("Comment", "squashed");
+        compressed.chunk_type = text::ZTXT;
+        compressed.compressed = true;
+        let mut international = TextEntry::new("Title", "café ☕");
+        international.compressed = true;
+        vec![
+            TextEntry::new("Author", "ferris").to_chunk().unwrap(),
+            compressed.to_chunk().unwrap(),
+            international.to_chunk().unwrap(),
+            TimeChunk {
+                year: 2024,
+                month: 5,
+                day: 1,
+                hour: 12,
+                minute: 30,
+                second: 60,
+            }
+            .to_chunk(),
+            PhysChunk {
+                x: 2835,
+                y: 2835,
+                per_metre: true,
+            }
+            .to_chunk(),
+            Chunk::new(GAMA, 45455u32.to_be_bytes().to_vec()),
+            IccpChunk {
+                name: "Display P3".to_string(),
+                compressed: text::deflate(b"profile bytes"),
+            }
+            .to_chunk()
+            .unwrap(),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"?".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn test_every_type_is_classified_and_round_trips() {
+        let chunks = fixture();
+        let known: Vec<_> = chunks.iter().map(KnownChunk::parse).collect();
+        let labels: Vec<_> = known.iter().map(KnownChunk::label).collect();
+        assert_eq!(
+            labels,
+            [
+                Some("Text"),
+                Some("Text"),
+                Some("Text"),
+                Some("Last modified"),
+                Some("Pixel size"),
+                Some("Gamma"),
+                Some("ICC profile"),
+                None
+            ]
+        );
+        for (chunk, known) in chunks.iter().zip(&known) {
+            assert_eq!(known.to_chunk().unwrap(), *chunk, "{known}");
+        }
+        let KnownChunk::Iccp(iccp) = &known[6] else {
+            unreachable!()
+        };
+        assert_eq!(iccp.profile().unwrap(), b"profile bytes");
+        let lines: Vec<_> = known.iter().map(ToString::to_string).collect();
+        assert_eq!(lines[2], "Title = \"café ☕\"");
+        assert_eq!(lines[3], "2024-05-01T12:30:60Z");
+        assert_eq!(lines[4], "2835x2835 pixels per metre (72 dpi)");
+        assert_eq!(known[5].to_json()["value"], 0.45455);
+    }
+
+    #[test]
+    fn test_malformed_data() {
+        let time = Chunk::new(TIME, vec![0x07, 0xe8, 13, 1, 0, 0, 0]);
+        assert_eq!(
+            KnownChunk::parse(&time),
+            KnownChunk::Malformed(
+                ChunkRef::from(&time),
+                ChunkDataError::OutOfRange {
+                    field: "month",
+                    value: 13
+                }
+            )
+        );
+        let gamma = Chunk::new(GAMA, vec![0; 3]);
+        assert_eq!(
+            KnownChunk::parse(&gamma).to_string(),
+            "gAMA: Data is 3 bytes, not 4"
+        );
+        let phys = Chunk::new(PHYS, [[0; 8].as_slice(), &[2]].concat());
+        assert!(matches!(
+            KnownChunk::parse(&phys),
+            KnownChunk::Malformed(_, ChunkDataError::OutOfRange { field: "unit", .. })
+        ));
+        let text = Chunk::new(text::TEXT, b"no separator".to_vec());
+        let known = KnownChunk::parse(&text);
+        assert!(matches!(
+            known,
+            KnownChunk::Malformed(_, ChunkDataError::Text(TextErrorKind::MissingSeparator))
+        ));
+        // Kept as it was
+        assert_eq!(known.to_chunk().unwrap(), text);
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::fmt::Display;
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct ChunkType {
     a: u8,
     b: u8,
@@ -10,26 +10,40 @@ pub struct ChunkType {
     d: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ChunkTypeError {
-    InvalidChunk,
+    /// A chunk type is exactly four bytes; this holds how many there were.
+    InvalidLength(usize),
+    /// A byte that isn't an ASCII letter, and its position (0-3).
+    InvalidByte { position: usize, byte: u8 },
 }
 
 impl std::fmt::Display for ChunkTypeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ChunkTypeError::InvalidChunk => write!(f, "Invalid Chunk")?,
+            ChunkTypeError::InvalidLength(len) => {
+                write!(f, "Chunk type must be 4 bytes, not {len}")
+            }
+            ChunkTypeError::InvalidByte { position, byte } => {
+                write!(f, "Byte {position} of the chunk type is 0x{byte:02x}")?;
+                if byte.is_ascii_graphic() {
+                    write!(f, " ('{}')", *byte as char)?;
+                }
+                write!(f, ", but only ASCII letters are allowed")
+            }
         }
-        Ok(())
     }
 }
 impl std::error::Error for ChunkTypeError {}
 
-fn check(val: u8) -> Result<u8, ChunkTypeError> {
-    if (65..91).contains(&val) || (97..123).contains(&val) {
+fn check(position: usize, val: u8) -> Result<u8, ChunkTypeError> {
+    if val.is_ascii_alphabetic() {
         Ok(val)
     } else {
-        Err(ChunkTypeError::InvalidChunk)?
+        Err(ChunkTypeError::InvalidByte {
+            position,
+            byte: val,
+        })
     }
 }
 
@@ -38,10 +52,10 @@ impl TryFrom<[u8; 4]> for ChunkType {
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
         Ok({
             Self {
-                a: check(value[0])?,
-                b: check(value[1])?,
-                c: check(value[2])?,
-                d: check(value[3])?,
+                a: check(0, value[0])?,
+                b: check(1, value[1])?,
+                c: check(2, value[2])?,
+                d: check(3, value[3])?,
             }
         })
     }
@@ -51,42 +65,67 @@ impl std::str::FromStr for ChunkType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let byte = s.as_bytes();
         if byte.len() != 4 {
-            return Err(ChunkTypeError::InvalidChunk);
+            return Err(ChunkTypeError::InvalidLength(byte.len()));
         }
         Ok(Self {
-            a: check(byte[0])?,
-            b: check(byte[1])?,
-            c: check(byte[2])?,
-            d: check(byte[3])?,
+            a: check(0, byte[0])?,
+            b: check(1, byte[1])?,
+            c: check(2, byte[2])?,
+            d: check(3, byte[3])?,
         })
     }
 }
 
 impl ChunkType {
+    pub const IHDR: ChunkType = ChunkType::literal(*b"IHDR");
+    pub const IDAT: ChunkType = ChunkType::literal(*b"IDAT");
+    pub const IEND: ChunkType = ChunkType::literal(*b"IEND");
+
+    pub(crate) const fn literal(bytes: [u8; 4]) -> Self {
+        Self {
+            a: bytes[0],
+            b: bytes[1],
+            c: bytes[2],
+            d: bytes[3],
+        }
+    }
+    /// A chunk type of any four bytes, letters or not. The spec only allows
+    /// ASCII letters, so a file with such a chunk is malformed and most
+    /// decoders will reject it; this is for producing such files on
+    /// purpose, to see how other parsers cope.
+    pub const fn from_bytes_unchecked(bytes: [u8; 4]) -> Self {
+        Self::literal(bytes)
+    }
     pub fn bytes(&self) -> [u8; 4] {
         [self.a, self.b, self.c, self.d]
     }
-    fn is_valid(&self) -> bool {
+    /// Whether all four bytes are ASCII letters, as the spec requires. Only
+    /// false for a type made with `from_bytes_unchecked` or parsed with
+    /// `ParseOptions::any_chunk_type`.
+    pub fn is_ascii_letters(&self) -> bool {
+        self.bytes().iter().all(u8::is_ascii_alphabetic)
+    }
+    pub fn is_valid(&self) -> bool {
         b'A' <= self.c && b'Z' >= self.c
     }
-    fn is_critical(&self) -> bool {
+    pub fn is_critical(&self) -> bool {
         self.a & (1 << 5) == 0
     }
-    fn is_public(&self) -> bool {
+    pub fn is_public(&self) -> bool {
         self.b & (1 << 5) == 0
     }
-    fn is_reserved_bit_valid(&self) -> bool {
+    pub fn is_reserved_bit_valid(&self) -> bool {
         self.c & (1 << 5) == 0
     }
-    fn is_safe_to_copy(&self) -> bool {
+    pub fn is_safe_to_copy(&self) -> bool {
         self.d & (1 << 5) != 0
     }
 }
 
 impl Display for ChunkType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let str = String::from_utf8(vec![self.a, self.b, self.c, self.d]).unwrap();
-        write!(f, "{}", str)
+        // Bytes that aren't printable are escaped, e.g. \xabBCD
+        write!(f, "{}", self.bytes().escape_ascii())
     }
 }
 
@@ -174,6 +213,44 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_unchecked_chunk_type() {
+        let chunk = ChunkType::from_bytes_unchecked([0xab, b'B', b'C', b'D']);
+        assert!(!chunk.is_ascii_letters());
+        assert!(ChunkType::from_str("RuSt").unwrap().is_ascii_letters());
+        assert_eq!(chunk.to_string(), "\\xabBCD");
+        let chunk = ChunkType::from_bytes_unchecked([b'1', 0, b'\\', b'd']);
+        assert_eq!(chunk.to_string(), "1\\x00\\\\d");
+    }
+
+    #[test]
+    pub fn test_chunk_type_from_non_ascii_str() {
+        // Four bytes, but not four letters
+        assert!(ChunkType::from_str("aé!").is_err());
+        assert!(ChunkType::from_str("ééé").is_err());
+        assert!(ChunkType::from_str("").is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_error_position() {
+        assert_eq!(
+            ChunkType::from_str("Ru1t").unwrap_err(),
+            ChunkTypeError::InvalidByte {
+                position: 2,
+                byte: b'1'
+            }
+        );
+        assert_eq!(
+            ChunkType::from_str("RuStY").unwrap_err(),
+            ChunkTypeError::InvalidLength(5)
+        );
+        let err = ChunkType::try_from([b'a', b'b', b'c', 0]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Byte 3 of the chunk type is 0x00, but only ASCII letters are allowed"
+        );
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
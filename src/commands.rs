@@ -1,33 +1,840 @@
-use clap::{Parser, Subcommand};
+use crate::args::{
+    CrcCorruption, FileFormat, ListFormat, PayloadEncoding, parse_byte, parse_chunk_spec,
+    parse_color_type, parse_crc_corruption, parse_delimited, parse_file_format, parse_fill,
+    parse_hex, parse_kv, parse_list_format, parse_pattern, parse_payload_encoding, parse_position,
+    parse_type_hex,
+};
+use clap::{ArgGroup, Parser, Subcommand};
+use pngme::builder::{ColorType, PngBuilder};
+use pngme::chunk_type::ChunkType;
+use pngme::digest::Algorithm;
+use pngme::format::Delimited;
+use pngme::png::ParseOptions;
+use pngme::redundant;
+use pngme::selector::ChunkSelector;
+use pngme::survival::Position;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 /// Simple program to hide a secret message in a png file
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     /// Subcommands
     #[command(subcommand)]
     pub command: Option<Commands>,
 
+    /// Print the version, the cargo features this build has, the payload
+    /// formats it reads and writes and the codes verify reports, as JSON
+    #[arg(long, exclusive = true)]
+    pub version_json: bool,
+
+    /// Skip over bytes that don't form valid chunks instead of failing, and
+    /// keep chunks whose type isn't letters if their CRC is right
+    #[arg(long, global = true)]
+    pub lenient: bool,
+
+    /// Reject chunks declaring a length above this many bytes
+    #[arg(long, global = true, default_value_t = ParseOptions::SPEC_MAX_CHUNK_SIZE)]
+    pub max_chunk_size: u32,
+
+    /// Reject files larger than this many bytes
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub max_file_size: Option<u64>,
+
+    /// Reject files with more than this many chunks. Real files have a few
+    /// dozen, and a crafted one can hold millions of empty chunks
+    #[arg(long, global = true, value_name = "N", default_value_t = Args::DEFAULT_MAX_CHUNKS)]
+    pub max_chunks: usize,
+
+    /// Reject files whose zTXt, iTXt and iCCP chunks inflate to more than
+    /// this many bytes between them
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub max_decompressed: Option<u64>,
+
+    /// Read the png from text: with base64, the file, or the argument
+    /// itself if no such file exists, is a data URL or bare base64. A
+    /// `data:image/png;base64,` URL in place of a file is read as one anyway.
+    /// Only commands that don't edit read text
+    #[arg(long, global = true, value_name = "png|base64", value_parser = parse_file_format, default_value = "png")]
+    pub input_format: FileFormat,
+
+    /// Write an edited png as a data URL, to --output if given and
+    /// otherwise to stdout, leaving the input alone
+    #[arg(long, global = true, value_name = "png|base64", value_parser = parse_file_format, default_value = "png")]
+    pub output_format: FileFormat,
+
+    /// Print results as JSON
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Don't print what encode and remove changed
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+
+    /// Show sizes in KiB/MiB instead of exact byte counts
+    #[arg(long, global = true)]
+    pub human_readable: bool,
+
+    /// Replace a symlinked output with a regular file instead of writing through it
+    #[arg(long, global = true)]
+    pub no_follow_symlinks: bool,
+
+    /// Give a file rewritten in place the modification time it had before
+    #[arg(long, global = true)]
+    pub preserve_timestamps: bool,
+
+    /// Before overwriting a file, copy it to its name with SUFFIX added,
+    /// e.g. `--backup '~'` or `--backup .bak`
+    #[arg(long, global = true, value_name = "SUFFIX")]
+    pub backup: Option<String>,
+
+    /// Seconds a command that edits a file waits for another pngme editing
+    /// it to finish. Commands that only read never wait
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 10)]
+    pub lock_timeout: u64,
+
+    /// Record what each edit removes and adds in this directory, so
+    /// `undo` can revert it
+    #[arg(long, global = true, value_name = "DIR")]
+    pub undo_log: Option<PathBuf>,
+
+    /// When writing an edited file, move valid chunks found after IEND to
+    /// just before it instead of leaving them where they are
+    #[arg(long, global = true)]
+    pub relocate_post_iend: bool,
+
+    /// Write a png even if IHDR isn't its first chunk or it has no IEND,
+    /// which most tools refuse to open, e.g. to see how parsers cope
+    #[arg(long, global = true)]
+    pub allow_invalid: bool,
+
+    /// Append a JSON line to this file for every edit: when, which command,
+    /// the files, the chunk types changed, and sizes and SHA-256 before and
+    /// after. Failing to write it fails the command
+    #[arg(long, global = true, value_name = "FILE", env = "PNGME_LOG_FILE")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Only warn when the audit log can't be written
+    #[arg(long, global = true)]
+    pub audit_best_effort: bool,
+
+    /// After the command, print to stderr how long reading, parsing,
+    /// checking CRCs, editing, serializing and writing took, in
+    /// milliseconds. With --json they are a "timings" field of the
+    /// command's output
+    #[arg(long, global = true)]
+    pub timings: bool,
+
+    /// After a command writes a png, decode it and the file it was made
+    /// from and fail if their pixels differ
+    #[cfg(feature = "render-check")]
+    #[arg(long, global = true)]
+    pub verify_render: bool,
+}
+
+impl Args {
+    pub const DEFAULT_MAX_CHUNKS: usize = 100_000;
+    pub const DEFAULT_MAX_INFLATE: u64 = 64 * 1024 * 1024;
+}
+
+/// `-o/--output`, shared by every command that edits a file.
+#[derive(clap::Args, Debug, Clone)]
+pub struct OutputArg {
+    /// Write the edited file here instead of over the input, or to stdout with -
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+/// `--files-from`, shared by every command that can run over many files.
+#[derive(clap::Args, Debug, Clone)]
+pub struct BatchArg {
+    /// Also run on each path listed in this file, or on stdin with -, one
+    /// per line. Blank lines and lines starting with # are skipped
+    #[arg(long, value_name = "PATH|-")]
+    pub files_from: Option<PathBuf>,
+    /// The paths in --files-from end in NUL instead, as find -print0 writes them
+    #[arg(long, requires = "files_from")]
+    pub null: bool,
+    /// Set for each run over many files, so what it prints says which file
+    #[arg(skip)]
+    pub labelled: bool,
+}
+
+/// How a command that prints a table prints it. `--format` conflicts with
+/// `--json`, so at most one of them is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Delimited(Delimited),
+}
+
+impl OutputFormat {
+    pub fn new(json: bool, delimited: Option<Delimited>) -> Self {
+        match (json, delimited) {
+            (_, Some(delimited)) => OutputFormat::Delimited(delimited),
+            (true, None) => OutputFormat::Json,
+            (false, None) => OutputFormat::Text,
+        }
+    }
+}
+
+/// `--format csv|tsv` and `--no-header`, for commands that print a table.
+#[derive(clap::Args, Debug, Clone)]
+pub struct TableArg {
+    /// Print the table as comma- or tab-separated values
+    #[arg(long, value_name = "csv|tsv", value_parser = parse_delimited, conflicts_with = "json")]
+    pub format: Option<Delimited>,
+    /// Leave out the header row of --format
+    #[arg(long, requires = "format")]
+    pub no_header: bool,
+}
+
+fn dimension() -> clap::builder::RangedI64ValueParser<u32> {
+    clap::value_parser!(u32).range(1..=PngBuilder::MAX_DIMENSION as i64)
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(Subcommand, Debug, Clone)]
 pub enum Commands {
+    /// Create a minimal solid-color png to encode messages into
+    Init {
+        file: PathBuf,
+        #[arg(long, default_value_t = 1, value_parser = dimension())]
+        width: u32,
+        #[arg(long, default_value_t = 1, value_parser = dimension())]
+        height: u32,
+        /// Pixel format, 8bit-rgb or 8bit-rgba
+        #[arg(long, default_value = "8bit-rgba", value_parser = parse_color_type)]
+        color: ColorType,
+        /// Hex color for every pixel, one byte per channel, e.g. ff8800ff
+        #[arg(long, value_parser = parse_fill)]
+        // Spelled out so clap treats it as a single value rather than a list
+        fill: Option<::std::vec::Vec<u8>>,
+        /// Overwrite the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
     ///  Encode the png file
+    ///
+    /// Each message is made into a chunk in this order: --template fills in
+    /// its placeholders, --input-encoding decodes the result, --auto and
+    /// --transform wrap that in an envelope, and --corrupt-crc breaks the
+    /// CRC of the chunk as stored.
     Encode {
-        file: String,
-        chunktype: String,
-        message: String,
-        output_path: Option<String>
+        /// Left out with --files-from, which lists every file, so the chunk
+        /// type comes first
+        #[arg(required_unless_present = "files_from")]
+        file: Option<PathBuf>,
+        /// The chunk type, or with --redundant or --type-hex the message
+        #[arg(required_unless_present_any = ["chunks", "kv", "redundant", "type_hex", "files_from"])]
+        chunktype: Option<String>,
+        #[arg(required_unless_present_any = ["chunks", "kv", "redundant", "type_hex", "files_from"])]
+        message: Option<String>,
+        /// Deprecated: give the output with --output, since an unquoted
+        /// message's second word lands here
+        output_path: Option<PathBuf>,
+        /// Any further arguments, only to refuse them with a better error
+        /// than clap's: they're most likely a message that wasn't quoted
+        #[arg(hide = true)]
+        extra: Vec<String>,
+        /// Write the edited file here instead of over the input, or to stdout with -
+        #[arg(short, long, value_name = "PATH", conflicts_with = "output_path")]
+        output: Option<PathBuf>,
+        /// Additional chunk to encode, may be repeated
+        #[arg(long = "chunk", value_name = "TYPE=MESSAGE", value_parser = parse_chunk_spec)]
+        chunks: Vec<(ChunkType, String)>,
+        /// Chunk to encode as one argument, split on the first colon; the
+        /// rest, colons and all, is the message. May be repeated
+        #[arg(long, value_name = "TYPE:MESSAGE", value_parser = parse_kv)]
+        kv: Vec<(ChunkType, String)>,
+        /// Refuse to grow the file by more than this percentage unless --force is given
+        #[arg(long, value_name = "PERCENT")]
+        max_growth: Option<u32>,
+        /// Encode even past --max-growth, or when the file already has a
+        /// standard chunk the spec allows only one of, such as tIME
+        #[arg(long)]
+        force: bool,
+        /// Deliberately store a wrong CRC in the new chunks, for making broken
+        /// test fixtures: +N/-N from the correct value, or an exact 0xXXXXXXXX
+        #[arg(long, value_name = "DELTA|VALUE", value_parser = parse_crc_corruption, allow_hyphen_values = true)]
+        corrupt_crc: Option<CrcCorruption>,
+        /// Store the message in this many differently typed chunks, so it
+        /// survives tools that strip some of them
+        #[arg(long, value_name = "COPIES", value_parser = clap::value_parser!(u32).range(1..=redundant::MAX_COPIES as i64))]
+        redundant: Option<u32>,
+        /// Chunk type for a --redundant copy instead of pmRa, pmRb..., given
+        /// once for each copy, each a different type
+        #[arg(long = "type", value_name = "TYPE", requires = "redundant", value_parser = ChunkType::from_str)]
+        types: Vec<ChunkType>,
+        /// The chunk type as four hex bytes, 0xab424344 or ab:42:43:44, which
+        /// needn't be letters. Such a chunk breaks the spec, for testing how
+        /// other parsers cope; chunks with such types are read too
+        #[arg(long, value_name = "BYTES", value_parser = parse_type_hex, conflicts_with = "redundant")]
+        type_hex: Option<ChunkType>,
+        /// Put each new chunk where the spec has chunks of its type go, e.g.
+        /// gAMA before PLTE and tEXt before IDAT, instead of before IEND.
+        /// Other ancillary chunks go before IDAT
+        #[arg(long)]
+        conventional: bool,
+        /// Transform each new chunk's message before storing it, and record
+        /// that in an envelope decode undoes: deflate or base64. May be
+        /// repeated; they're applied in the order given
+        #[arg(long = "transform", value_name = "NAME", conflicts_with = "redundant")]
+        transforms: Vec<String>,
+        /// Deflate each new chunk's message first if that saves enough to
+        /// be worth it, and record the choice in an envelope decode undoes.
+        /// Any --transform steps follow it, and can't include deflate
+        #[arg(long, conflicts_with = "redundant")]
+        auto: bool,
+        /// Read each message as base64 or hex and store the bytes it
+        /// stands for, for binary payloads that can't be typed as they are.
+        /// With --template, the placeholders are filled in first
+        #[arg(long, value_name = "raw|base64|hex", value_parser = parse_payload_encoding, default_value = "raw")]
+        input_encoding: PayloadEncoding,
+        /// Fill in placeholders in each message first: {env:VAR},
+        /// {filename}, {filesize} and {sha256} of the file before the edit,
+        /// and {now} or {now:%Y-%m-%dT%H:%M:%SZ}, which is SOURCE_DATE_EPOCH
+        /// if that's set. {{ and }} are literal braces
+        #[arg(long)]
+        template: bool,
+        /// With --template, leave {env:VAR} empty for a variable that isn't
+        /// set instead of failing
+        #[arg(long, requires = "template")]
+        allow_missing: bool,
+        /// Also say how likely each new chunk is to survive common tools
+        #[arg(short, long, conflicts_with_all = ["quiet", "json"])]
+        verbose: bool,
+        #[command(flatten)]
+        batch: BatchArg,
     },
+    /// Print a chunk's message
+    ///
+    /// Exits with 3 if there is no such chunk, with 4 if its data isn't
+    /// UTF-8 text and neither --raw nor --output was given, and with 5 if
+    /// the output was cut off at --max-output-bytes.
+    #[command(group(
+        ArgGroup::new("target")
+            .required(true)
+            .args(["chunktype", "type_hex", "select", "keyword", "redundant"])
+    ))]
     Decode {
-        file: String,
-        chunktype: String,
+        file: PathBuf,
+        #[arg(value_parser = ChunkType::from_str)]
+        chunktype: Option<ChunkType>,
+        /// The chunk type as four hex bytes, 0xab424344 or ab:42:43:44, which
+        /// needn't be letters. Such a chunk breaks the spec, for testing how
+        /// other parsers cope; chunks with such types are read too
+        #[arg(
+            long,
+            value_name = "BYTES",
+            value_parser = parse_type_hex
+        )]
+        type_hex: Option<ChunkType>,
+        /// Decode the first chunk a selector picks out instead, e.g.
+        /// 'private,larger-than=1024'. Predicates, all of which must hold:
+        /// type=T, index=N, larger-than=N, critical, ancillary, public, private
+        #[arg(long, value_name = "EXPR", value_parser = ChunkSelector::from_str)]
+        select: Option<ChunkSelector>,
+        /// Write the payload bytes as they are, without a trailing newline.
+        /// With --output-encoding, the encoded text without one
+        #[arg(long)]
+        raw: bool,
+        /// Print the value of a tEXt, zTXt or iTXt keyword instead of a chunk
+        #[arg(long)]
+        keyword: Option<String>,
+        /// Only print the Nth entry for the keyword, counting from 1
+        #[arg(
+            long,
+            conflicts_with_all = ["chunktype", "type_hex", "select", "redundant"],
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        nth: Option<u32>,
+        /// Match the keyword regardless of case
+        #[arg(long, conflicts_with_all = ["chunktype", "type_hex", "select", "redundant"])]
+        ignore_case: bool,
+        /// Print the message from any intact copy written by encode --redundant
+        #[arg(long)]
+        redundant: bool,
+        /// Write the chunk's data to this file instead of stdout
+        #[arg(short, long, value_name = "PATH", conflicts_with_all = ["keyword", "redundant"])]
+        output: Option<PathBuf>,
+        /// Indent JSON and line up key=value pairs; anything else is printed
+        /// as usual
+        #[arg(long, conflicts_with_all = ["raw", "output", "output_encoding"])]
+        pretty: bool,
+        /// Print control characters and escape sequences as they are even
+        /// to a terminal, where they're otherwise shown escaped
+        #[arg(long)]
+        no_sanitize: bool,
+        /// Print a payload written with encode --transform as it's stored,
+        /// envelope and all, instead of undoing the transforms
+        #[arg(long, conflicts_with_all = ["keyword", "redundant"])]
+        keep_envelope: bool,
+        /// Inflate a payload that starts like a zlib or gzip stream, saying
+        /// so on stderr. One that fails to inflate is printed as stored.
+        /// It looks at the payload after any envelope is undone, so with
+        /// --keep-envelope an envelope is printed as stored
+        #[arg(long, conflicts_with_all = ["keyword", "redundant"])]
+        auto_inflate: bool,
+        /// The most --auto-inflate inflates a payload to; past it the
+        /// payload is printed as stored
+        #[arg(long, value_name = "BYTES", requires = "auto_inflate", default_value_t = Args::DEFAULT_MAX_INFLATE)]
+        max_inflate: u64,
+        /// Print the payload as base64 or hex, so binary data can pass
+        /// through a terminal and back into encode --input-encoding. The
+        /// payload is encoded last, after envelopes and --auto-inflate
+        #[arg(long, value_name = "raw|base64|hex", value_parser = parse_payload_encoding, default_value = "raw")]
+        output_encoding: PayloadEncoding,
+        /// Print at most this many bytes of the payload, saying so on stderr
+        /// and exiting with 5 if there was more. Text is cut between
+        /// characters. Doesn't apply to --output
+        #[arg(
+            long,
+            alias = "limit-output",
+            value_name = "BYTES",
+            env = "PNGME_MAX_OUTPUT"
+        )]
+        max_output_bytes: Option<u64>,
     },
+    /// Remove a chunk by type, or by its position as shown by `list`
+    #[command(group(
+        ArgGroup::new("target")
+            .required(true)
+            .args(["chunktype", "type_hex", "index", "select", "redundant", "types_file"])
+    ))]
     Remove {
-        file: String,
-        chunktype: String,
+        file: PathBuf,
+        #[arg(value_parser = ChunkType::from_str)]
+        chunktype: Option<ChunkType>,
+        /// The chunk type as four hex bytes, 0xab424344 or ab:42:43:44, which
+        /// needn't be letters. Such a chunk breaks the spec, for testing how
+        /// other parsers cope; chunks with such types are read too
+        #[arg(
+            long,
+            value_name = "BYTES",
+            value_parser = parse_type_hex
+        )]
+        type_hex: Option<ChunkType>,
+        #[arg(long)]
+        index: Option<usize>,
+        /// Remove every chunk a selector picks out, e.g. 'private,larger-than=4096'.
+        /// Predicates, all of which must hold: type=T, index=N, larger-than=N,
+        /// critical, ancillary, public, private
+        #[arg(long, value_name = "EXPR", value_parser = ChunkSelector::from_str)]
+        select: Option<ChunkSelector>,
+        /// Remove every copy written by encode --redundant
+        #[arg(long)]
+        redundant: bool,
+        /// Remove every chunk of each type listed in this file, one per line,
+        /// with `#` starting a comment
+        #[arg(long, alias = "chunk-type-file", value_name = "FILE")]
+        types_file: Option<PathBuf>,
+        /// Allow removing critical chunks such as IHDR and IEND, and write
+        /// the file without them as --allow-invalid would
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        output: OutputArg,
     },
     Print {
-        file: String,
+        file: PathBuf,
+        /// Also say in one line what each chunk's data holds, e.g. the keys
+        /// of a JSON object
+        #[arg(long)]
+        data: bool,
+    },
+    /// List every chunk with its index, type and length
+    List {
+        file: PathBuf,
+        /// csv or tsv for the columns index, type, length, crc_hex, offset,
+        /// critical and safe_to_copy. Anything else is a template each chunk
+        /// is printed through, e.g. '{index}\t{type}\t{crc:x}'. Fields: index,
+        /// type, length, crc, offset, critical, safe_to_copy, data_sha256
+        #[arg(
+            long,
+            value_name = "csv|tsv|TEMPLATE",
+            value_parser = parse_list_format,
+            conflicts_with = "json"
+        )]
+        format: Option<ListFormat>,
+        /// Leave out the header row of --format csv or tsv
+        #[arg(long, requires = "format")]
+        no_header: bool,
+        /// Add a column with a digest of each chunk's data, its first 8 hex
+        /// digits, to tell at a glance which chunks hold the same data
+        #[arg(long, value_name = "sha256|blake3|crc32|crc64", value_parser = Algorithm::from_str)]
+        digest: Option<Algorithm>,
+        /// Show the whole digest instead
+        #[arg(long, requires = "digest")]
+        long_digest: bool,
+        /// Only list the chunks a selector picks out, e.g. 'ancillary,public'.
+        /// Predicates, all of which must hold: type=T, index=N, larger-than=N,
+        /// critical, ancillary, public, private
+        #[arg(long, value_name = "EXPR", value_parser = ChunkSelector::from_str)]
+        select: Option<ChunkSelector>,
+    },
+    /// Count the chunks of each type and the bytes they take up
+    Stats {
+        file: PathBuf,
+        #[command(flatten)]
+        table: TableArg,
+    },
+    /// Overwrite bytes in the data of the first chunk of a type, fixing up its
+    /// length and CRC. Patching a critical chunk drops unknown chunks that
+    /// aren't marked safe to copy
+    Patch {
+        file: PathBuf,
+        #[arg(value_parser = ChunkType::from_str)]
+        chunktype: ChunkType,
+        /// Where in the chunk's data the patch starts
+        #[arg(long)]
+        offset: usize,
+        /// The new bytes, in hex
+        #[arg(long, value_parser = parse_hex)]
+        bytes: ::std::vec::Vec<u8>,
+        /// How many existing bytes the new ones replace, if not as many as
+        /// there are new ones; 0 inserts them
+        #[arg(long, value_name = "N")]
+        replace: Option<usize>,
+        #[command(flatten)]
+        output: OutputArg,
+    },
+    /// Overwrite the data of every chunk of a type with zeros, keeping its
+    /// length, so the file's size and every offset in it stay the same
+    Redact {
+        file: PathBuf,
+        #[arg(value_parser = ChunkType::from_str)]
+        chunktype: ChunkType,
+        /// The byte to overwrite with instead of 0, as 255 or 0xff
+        #[arg(long, value_name = "BYTE", value_parser = parse_byte)]
+        fill: Option<u8>,
+        /// Bytes in hex to repeat over the data instead, e.g.
+        /// 5245444143544544 for "REDACTED"
+        #[arg(long, value_name = "HEX", value_parser = parse_pattern, conflicts_with = "fill")]
+        pattern: Option<::std::vec::Vec<u8>>,
+        /// Allow redacting critical chunks such as IDAT, which leaves an
+        /// image that won't decode and drops unknown chunks that aren't
+        /// marked safe to copy
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        output: OutputArg,
+    },
+    /// Remove ancillary chunks, leaving only the ones needed to display the image
+    Strip {
+        #[arg(required_unless_present = "files_from")]
+        file: Option<PathBuf>,
+        /// Only remove unknown chunks that aren't marked safe to copy
+        #[arg(long)]
+        unsafe_only: bool,
+        /// Of the chunks that would be removed, only remove the ones a
+        /// selector picks out, e.g. 'private'. Predicates, all of which must
+        /// hold: type=T, index=N, larger-than=N, critical, ancillary, public,
+        /// private
+        #[arg(long, value_name = "EXPR", value_parser = ChunkSelector::from_str)]
+        select: Option<ChunkSelector>,
+        #[command(flatten)]
+        output: OutputArg,
+        #[command(flatten)]
+        batch: BatchArg,
+    },
+    /// Summarize a chunk's payload: entropy, printable bytes, histogram and format guess
+    Inspect {
+        file: PathBuf,
+        #[arg(value_parser = ChunkType::from_str)]
+        chunktype: ChunkType,
+    },
+    /// Read as much of a damaged or truncated file as parses, and say how
+    /// many chunks that was and where and why reading stopped
+    Salvage {
+        file: PathBuf,
+        /// Print the message in the first recovered chunk of this type; the
+        /// report goes to stderr
+        #[arg(long, value_name = "TYPE", value_parser = ChunkType::from_str)]
+        decode: Option<ChunkType>,
+        /// Write the recovered chunks here as a png of their own, with an
+        /// IEND added if they lack one
+        #[arg(long, value_name = "PATH")]
+        write_recovered: Option<PathBuf>,
+    },
+    /// Show the image parameters from IHDR
+    Info {
+        file: PathBuf,
+        /// Also list the PLTE colors, with tRNS alpha where present
+        #[arg(long)]
+        palette: bool,
+        /// Also inflate the image data, without keeping it, and say whether
+        /// its size is what IHDR calls for
+        #[arg(long)]
+        inflate_check: bool,
+    },
+    /// Show the textual metadata from every tEXt, zTXt and iTXt chunk
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Tags {
+        #[command(subcommand)]
+        action: Option<TagsAction>,
+        #[arg(required = true)]
+        file: Option<PathBuf>,
+        /// Only print the values stored under this keyword
+        #[arg(long)]
+        keyword: Option<String>,
+    },
+    /// Work with the frames of an animated PNG
+    Frames {
+        #[command(subcommand)]
+        action: FramesAction,
+    },
+    /// Recompute the CRC of every chunk whose stored CRC is wrong, and drop
+    /// any stray bytes a --lenient parse skipped
+    Repair {
+        file: PathBuf,
+        /// Also rewrite length fields that don't match the chunk's data, found
+        /// by where its stored CRC matches
+        #[arg(long)]
+        fix_lengths: bool,
+        #[command(flatten)]
+        output: OutputArg,
+    },
+    /// Check that the file parses cleanly and its chunks are laid out correctly
+    Verify {
+        #[arg(required_unless_present = "files_from")]
+        file: Option<PathBuf>,
+        /// Fail on warnings as well as errors
+        #[arg(long)]
+        strict: bool,
+        #[command(flatten)]
+        batch: BatchArg,
+    },
+    /// Add a chunk to every PNG under a directory that lacks one of its type,
+    /// and keep doing so as files are created or modified
+    Watch {
+        dir: PathBuf,
+        #[arg(long = "type", value_name = "TYPE", value_parser = ChunkType::from_str)]
+        chunk_type: ChunkType,
+        /// File whose bytes become the chunk data
+        #[arg(long)]
+        message_file: PathBuf,
+        /// Do a single pass over the directory and exit
+        #[arg(long)]
+        once: bool,
+        /// Wait until a file has gone this long without changing before touching it
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        debounce: u64,
+    },
+    /// Run the steps of a TOML rules file against the png and write it once
+    Apply {
+        #[arg(long)]
+        rules: PathBuf,
+        file: PathBuf,
+        /// Show what each step would do without writing the file
+        #[arg(long, conflicts_with = "output")]
+        dry_run: bool,
+        #[command(flatten)]
+        output: OutputArg,
+    },
+    /// Shrink the file without touching the image data, showing what each pass saves
+    Optimize {
+        file: PathBuf,
+        /// Report the savings without changing the file
+        #[arg(long, conflicts_with = "output")]
+        dry_run: bool,
+        /// Also remove every chunk of this ancillary type, may be repeated
+        #[arg(long, value_name = "TYPE", value_parser = ChunkType::from_str)]
+        remove: Vec<ChunkType>,
+        /// Keep chunks and bytes after IEND
+        #[arg(long)]
+        no_strip_trailing: bool,
+        /// Keep ancillary chunks with no data
+        #[arg(long)]
+        no_drop_empty: bool,
+        /// Keep repeated identical ancillary chunks
+        #[arg(long)]
+        no_dedup: bool,
+        /// Keep the image data split across IDAT chunks as it is
+        #[arg(long)]
+        no_merge_idat: bool,
+        #[command(flatten)]
+        output: OutputArg,
+    },
+    /// Record the chunks of every PNG under a directory, or report which
+    /// files changed since they were recorded
+    #[command(group(
+        ArgGroup::new("mode")
+            .required(true)
+            .args(["write_baseline", "baseline"])
+    ))]
+    Scan {
+        dir: PathBuf,
+        /// Write the chunks of every file to this manifest
+        #[arg(long, value_name = "FILE")]
+        write_baseline: Option<PathBuf>,
+        /// Compare every file with a manifest from --write-baseline
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<PathBuf>,
+    },
+    /// Revert the most recent edit to a file recorded with --undo-log
+    Undo { file: PathBuf },
+    /// Check this build end to end in memory: build, encode, parse, decode and CRCs
+    SelfTest,
+    /// Say how likely a chunk is to survive optimizers, editors and re-encoding
+    Advise {
+        #[arg(long = "type", value_name = "TYPE", value_parser = ChunkType::from_str)]
+        chunk_type: ChunkType,
+        /// Where the chunk sits: before-idat, after-idat or after-iend
+        #[arg(long, default_value = "after-idat", value_parser = parse_position)]
+        position: Position,
+    },
+    /// Open a prompt for exploring a file and staging chunk removals
+    Shell { file: PathBuf },
+    /// Explain what each letter of a chunk type encodes
+    ChunkType {
+        #[arg(required = true)]
+        types: Vec<String>,
+    },
+    /// Explain a code from verify: what it means, what causes it and which
+    /// command fixes it
+    Explain {
+        /// e.g. crc-mismatch or bad-order
+        code: String,
+    },
+    /// Say whether two files show the same pixels, however differently
+    /// their chunks store them
+    ///
+    /// Exits with 1 if they don't, printing the first pixel that differs.
+    #[cfg(feature = "render-check")]
+    ComparePixels {
+        first: PathBuf,
+        second: PathBuf,
+        /// Compare color only, not transparency
+        #[arg(long)]
+        ignore_alpha: bool,
+        /// Count channel values this far apart as equal, on the 0-255 scale,
+        /// or 0-65535 if either file is 16-bit
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        tolerance: u16,
+    },
+}
+
+impl Commands {
+    /// The file and `--files-from` of a command that can run over many
+    /// files, which runs once per file.
+    pub fn batch(&mut self) -> Option<(&mut Option<PathBuf>, &mut BatchArg)> {
+        match self {
+            Commands::Encode { file, batch, .. }
+            | Commands::Strip { file, batch, .. }
+            | Commands::Verify { file, batch, .. } => Some((file, batch)),
+            _ => None,
+        }
+    }
+    /// With `--files-from`, encode takes none of its files as arguments,
+    /// so what clap read as the file is the chunk type, or with
+    /// --redundant or --type-hex the message, and the rest move up one.
+    pub fn unbind_file(&mut self) -> Result<(), String> {
+        let Commands::Encode {
+            file,
+            chunktype,
+            message,
+            output_path,
+            extra,
+            batch,
+            ..
+        } = self
+        else {
+            return Ok(());
+        };
+        if batch.files_from.is_none() {
+            return Ok(());
+        }
+        let Some(first) = file.take() else {
+            return Ok(());
+        };
+        let first = first
+            .into_os_string()
+            .into_string()
+            .map_err(|s| format!("\"{}\" isn't valid UTF-8", s.to_string_lossy()))?;
+        if let Some(path) = output_path.take() {
+            extra.insert(0, path.display().to_string());
+        }
+        *output_path = message.take().map(PathBuf::from);
+        *message = chunktype.replace(first);
+        Ok(())
+    }
+    /// For a command that writes an edited png, what it is called, the
+    /// file it edits and `--output`, if given.
+    pub fn edited(&self) -> Option<Edit<'_>> {
+        let (command, input, output) = match self {
+            Commands::Encode {
+                file: Some(file),
+                output,
+                output_path,
+                ..
+            } => ("encode", file, output.as_ref().or(output_path.as_ref())),
+            Commands::Strip {
+                file: Some(file),
+                output,
+                ..
+            } => ("strip", file, output.output.as_ref()),
+            Commands::Remove { file, output, .. } => ("remove", file, output.output.as_ref()),
+            Commands::Patch { file, output, .. } => ("patch", file, output.output.as_ref()),
+            Commands::Redact { file, output, .. } => ("redact", file, output.output.as_ref()),
+            Commands::Repair { file, output, .. } => ("repair", file, output.output.as_ref()),
+            Commands::Apply { file, output, .. } => ("apply", file, output.output.as_ref()),
+            Commands::Optimize { file, output, .. } => ("optimize", file, output.output.as_ref()),
+            Commands::Tags {
+                action: Some(TagsAction::Set { file, output, .. }),
+                ..
+            } => ("tags set", file, output.output.as_ref()),
+            Commands::Tags {
+                action: Some(TagsAction::Remove { file, output, .. }),
+                ..
+            } => ("tags remove", file, output.output.as_ref()),
+            _ => return None,
+        };
+        Some(Edit {
+            command,
+            input,
+            output,
+        })
+    }
+}
+
+/// What `Commands::edited` returns.
+#[derive(Debug, Clone, Copy)]
+pub struct Edit<'a> {
+    pub command: &'static str,
+    pub input: &'a PathBuf,
+    pub output: Option<&'a PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TagsAction {
+    /// Set a keyword, updating its first entry or adding a tEXt before IDAT.
+    /// Later duplicates are left as they are
+    Set {
+        file: PathBuf,
+        keyword: String,
+        value: String,
+        #[command(flatten)]
+        output: OutputArg,
+    },
+    /// Remove every entry stored under a keyword
+    Remove {
+        file: PathBuf,
+        keyword: String,
+        #[command(flatten)]
+        output: OutputArg,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FramesAction {
+    /// Write every frame as a PNG of its own, its rectangle as stored rather
+    /// than composited, named after the file and the frame's number from 1
+    Extract {
+        file: PathBuf,
+        /// Where to write the frames, created if need be
+        #[arg(long, value_name = "DIR")]
+        dir: PathBuf,
     },
 }
@@ -0,0 +1,273 @@
+//! Saying what the CLI was doing when something failed. The library's
+//! errors say what went wrong; wrapping them here adds the operation and
+//! the file, and `report` prints the whole chain, outermost first.
+
+use crate::Error;
+use clap::ArgMatches;
+use pngme::data_url;
+use std::fmt::Display;
+use std::path::Path;
+
+/// An error with a line of context about the operation it interrupted.
+#[derive(Debug)]
+pub struct ContextError {
+    message: String,
+    source: Error,
+}
+
+impl Display for ContextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+pub trait Context<T> {
+    fn context(self, message: impl Display) -> Result<T, Error>;
+    fn with_context<M: Display>(self, message: impl FnOnce() -> M) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, message: impl Display) -> Result<T, Error> {
+        self.with_context(|| message)
+    }
+    fn with_context<M: Display>(self, message: impl FnOnce() -> M) -> Result<T, Error> {
+        self.map_err(|source| {
+            Box::new(ContextError {
+                message: message().to_string(),
+                source: source.into(),
+            }) as Error
+        })
+    }
+}
+
+/// A failure whose reason has already been printed, which only needs the
+/// exit status to show it.
+#[derive(Debug)]
+pub struct Reported;
+
+impl Display for Reported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("failed")
+    }
+}
+
+impl std::error::Error for Reported {}
+
+/// Why `decode` has nothing to print, each with its own exit status so a
+/// script can tell a file without the message from one whose message is
+/// binary.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// No chunk of the type, keyword or selector. Exits with 3.
+    Missing(String),
+    /// A payload that isn't text, without --raw or --output. Exits with 4.
+    NotUtf8 { chunk_type: String, len: u64 },
+    /// Output cut off at --max-output-bytes, which has already said so.
+    /// Exits with 5.
+    Truncated { total: u64 },
+}
+
+impl DecodeError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DecodeError::Missing(_) => 3,
+            DecodeError::NotUtf8 { .. } => 4,
+            DecodeError::Truncated { .. } => 5,
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Missing(what) => write!(f, "{what} wasn't found in the png"),
+            DecodeError::NotUtf8 { chunk_type, len } => write!(
+                f,
+                "the {chunk_type} payload is {len} bytes of non-UTF-8 data; use --raw or --output"
+            ),
+            DecodeError::Truncated { total } => {
+                write!(f, "output truncated, {total} bytes total")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The exit status for `error`: a `DecodeError`'s own anywhere in the
+/// chain, otherwise 1.
+pub fn exit_code(error: &(dyn std::error::Error + 'static)) -> i32 {
+    let mut error = Some(error);
+    while let Some(e) = error {
+        if let Some(decode) = e.downcast_ref::<DecodeError>() {
+            return decode.exit_code();
+        }
+        error = e.source();
+    }
+    1
+}
+
+/// Whether `error` or any of its sources is `Reported`, or a truncation,
+/// whose notice is printed where the output stops.
+pub fn is_reported(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut error = Some(error);
+    while let Some(e) = error {
+        if e.is::<Reported>() || matches!(e.downcast_ref(), Some(DecodeError::Truncated { .. })) {
+            return true;
+        }
+        error = e.source();
+    }
+    false
+}
+
+/// `error: ` and the error, then `caused by: ` and each of its sources.
+pub fn report(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut lines = vec![format!("error: {error}")];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        lines.push(format!("caused by: {cause}"));
+        source = cause.source();
+    }
+    lines.join("\n")
+}
+
+/// The error and its sources on one line, separated by `: `, for
+/// reporting one failure among many.
+pub fn one_line(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut line = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        line.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    line
+}
+
+/// What the subcommand in `matches` was doing, for the top of the chain,
+/// e.g. `failed to encode chunk 'ruSt' into "a.png"`. `file` stands in for
+/// the file argument, for a command run on each of a list of files.
+pub fn describe(matches: &ArgMatches, file: Option<&Path>) -> Option<String> {
+    let (name, args) = matches.subcommand()?;
+    let raw = |id| {
+        let mut values = args.try_get_raw(id).ok()??;
+        values.next().map(|v| v.to_string_lossy().into_owned())
+    };
+    let target = match file {
+        Some(file) => Some(file.display().to_string()),
+        None => raw("file").or_else(|| raw("dir")),
+    };
+    // A whole data URL would drown the message
+    let target = target.map(|target| match target.char_indices().nth(40) {
+        Some((end, _)) if data_url::is_data_url(&target) => format!("{}...", &target[..end]),
+        _ => target,
+    });
+    // With --redundant or --type-hex encode's first positional argument is
+    // the message, and with --files-from it's given no file
+    let chunk_type = if name != "encode" {
+        raw("chunktype")
+    } else if raw("redundant").is_some() || raw("type_hex").is_some() {
+        None
+    } else if raw("files_from").is_some() {
+        raw("file")
+    } else {
+        raw("chunktype")
+    };
+    Some(match (name, target, chunk_type) {
+        ("encode", Some(file), Some(t)) => format!("failed to encode chunk '{t}' into \"{file}\""),
+        ("decode" | "remove", Some(file), Some(t)) => {
+            format!("failed to {name} chunk '{t}' from \"{file}\"")
+        }
+        (_, Some(file), _) => format!("failed to {name} \"{file}\""),
+        (_, None, _) => format!("failed to {name}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Args;
+    use clap::CommandFactory;
+    use std::io;
+
+    fn describe_args(args: &[&str]) -> Option<String> {
+        describe(&Args::command().get_matches_from(args), None)
+    }
+
+    #[test]
+    fn test_report_prints_the_chain() {
+        let error = Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("failed to create temporary file \".a.png.pngme-tmp\"")
+            .with_context(|| "failed to write output \"a.png\"")
+            .unwrap_err();
+        assert_eq!(
+            report(error.as_ref()),
+            "error: failed to write output \"a.png\"\n\
+             caused by: failed to create temporary file \".a.png.pngme-tmp\"\n\
+             caused by: permission denied"
+        );
+        assert_eq!(
+            one_line(error.as_ref()),
+            "failed to write output \"a.png\": failed to create temporary file \
+             \".a.png.pngme-tmp\": permission denied"
+        );
+    }
+
+    #[test]
+    fn test_exit_code_is_found_through_context() {
+        let missing = Err::<(), _>(DecodeError::Missing("ruSt".to_string()))
+            .context("failed to decode chunk 'ruSt' from \"a.png\"")
+            .unwrap_err();
+        assert_eq!(exit_code(missing.as_ref()), 3);
+        let binary: Error = Box::new(DecodeError::NotUtf8 {
+            chunk_type: "ruSt".to_string(),
+            len: 58,
+        });
+        assert_eq!(exit_code(binary.as_ref()), 4);
+        let other: Error = Box::new(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(exit_code(other.as_ref()), 1);
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe_args(&["pngme", "encode", "a.png", "ruSt", "hi"]).unwrap(),
+            "failed to encode chunk 'ruSt' into \"a.png\""
+        );
+        assert_eq!(
+            describe_args(&["pngme", "remove", "a.png", "--index", "2"]).unwrap(),
+            "failed to remove \"a.png\""
+        );
+        assert_eq!(
+            describe_args(&["pngme", "scan", "shots", "--baseline", "b.json"]).unwrap(),
+            "failed to scan \"shots\""
+        );
+        assert_eq!(
+            describe_args(&["pngme", "self-test"]).unwrap(),
+            "failed to self-test"
+        );
+        assert_eq!(describe_args(&["pngme"]), None);
+        let matches = Args::command().get_matches_from(["pngme", "verify", "--files-from", "-"]);
+        assert_eq!(
+            describe(&matches, Some(Path::new("b.png"))).unwrap(),
+            "failed to verify \"b.png\""
+        );
+        let matches = Args::command().get_matches_from([
+            "pngme",
+            "encode",
+            "--files-from",
+            "-",
+            "ruSt",
+            "hi",
+        ]);
+        assert_eq!(
+            describe(&matches, Some(Path::new("b.png"))).unwrap(),
+            "failed to encode chunk 'ruSt' into \"b.png\""
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! PNGs as text: `data:image/png;base64,...` URLs, as browsers and design
+//! tools copy images, and bare base64. Whitespace is ignored, so text
+//! wrapped across lines when it was pasted still decodes.
+
+use crate::base64::{self, Base64Error};
+use crate::png::Png;
+use std::fmt::{self, Display};
+
+/// What `encode` puts before the base64.
+pub const PREFIX: &str = "data:image/png;base64,";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataUrlError {
+    /// A `data:` URL with no comma to end its header.
+    NoData,
+    /// A data URL of some other type, such as `image/jpeg`.
+    WrongType(String),
+    /// A data URL whose data isn't base64, but percent-encoded text.
+    NotBase64,
+    Base64(Base64Error),
+    /// The base64 decodes to something other than a png.
+    NotPng,
+}
+
+impl Display for DataUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataUrlError::NoData => write!(f, "The data URL has no ',' before its data"),
+            DataUrlError::WrongType(mime) => {
+                write!(f, "The data URL is {mime}, not image/png")
+            }
+            DataUrlError::NotBase64 => write!(f, "The data URL isn't base64-encoded"),
+            DataUrlError::Base64(e) => write!(f, "{e}"),
+            DataUrlError::NotPng => {
+                write!(f, "The base64 decodes to data without a png signature")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DataUrlError {}
+
+impl From<Base64Error> for DataUrlError {
+    fn from(e: Base64Error) -> Self {
+        DataUrlError::Base64(e)
+    }
+}
+
+/// Whether `text` is a data URL, of any type.
+pub fn is_data_url(text: &str) -> bool {
+    text.trim_start()
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// The png in `text`, a data URL or bare base64.
+pub fn decode(text: &str) -> Result<Vec<u8>, DataUrlError> {
+    let text = text.trim();
+    let data = if is_data_url(text) {
+        let (header, data) = text[5..].split_once(',').ok_or(DataUrlError::NoData)?;
+        let mut params = header.split(';');
+        let mime = params.next().unwrap_or_default().trim();
+        if !mime.eq_ignore_ascii_case("image/png") {
+            let mime = if mime.is_empty() { "text/plain" } else { mime };
+            return Err(DataUrlError::WrongType(mime.to_string()));
+        }
+        if !params.any(|param| param.trim().eq_ignore_ascii_case("base64")) {
+            return Err(DataUrlError::NotBase64);
+        }
+        data
+    } else {
+        text
+    };
+    let data: String = data.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let bytes = base64::decode(&data)?;
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Err(DataUrlError::NotPng);
+    }
+    Ok(bytes)
+}
+
+/// `png` as a data URL.
+pub fn encode(png: &[u8]) -> String {
+    format!("{PREFIX}{}", base64::encode(png))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+
+    #[test]
+    fn test_round_trip() {
+        let png = PngBuilder::new(2, 2).build().unwrap().as_bytes();
+        let url = encode(&png);
+        assert!(url.starts_with(PREFIX));
+        assert_eq!(decode(&url).unwrap(), png);
+        // Bare, wrapped, and with the header spelled differently
+        let base64 = &url[PREFIX.len()..];
+        assert_eq!(decode(base64).unwrap(), png);
+        let wrapped: Vec<&str> = base64
+            .as_bytes()
+            .chunks(20)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect();
+        assert_eq!(decode(&wrapped.join("\r\n")).unwrap(), png);
+        let url = format!("  DATA:Image/PNG;name=a.png;base64,{base64}\n");
+        assert_eq!(decode(&url).unwrap(), png);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            decode("data:image/jpeg;base64,/9j/"),
+            Err(DataUrlError::WrongType("image/jpeg".into()))
+        );
+        assert_eq!(
+            decode("data:,hello"),
+            Err(DataUrlError::WrongType("text/plain".into()))
+        );
+        assert_eq!(
+            decode("data:image/png,%89PNG"),
+            Err(DataUrlError::NotBase64)
+        );
+        assert_eq!(decode("data:image/png"), Err(DataUrlError::NoData));
+        assert!(matches!(
+            decode("data:image/png;base64,iVBOR*"),
+            Err(DataUrlError::Base64(_))
+        ));
+        assert_eq!(decode("aGVsbG8="), Err(DataUrlError::NotPng));
+    }
+}
@@ -0,0 +1,111 @@
+//! Digests of chunk data, by whichever algorithm is asked for, as hex.
+//! `list --digest`, `list --format '{data_sha256}'` and the baseline
+//! manifest all take their digests from here, so the same data always
+//! shows the same digest wherever it's printed.
+
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+const CRC64: crc::Crc<u64> = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+
+/// SHA-256 of `data` as 64 lowercase hex digits.
+pub fn sha256_hex(data: &[u8]) -> String {
+    crate::hex::encode(&Sha256::digest(data))
+}
+
+/// How many hex digits a short digest keeps.
+pub const SHORT_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+    /// The CRC-32 of PNG chunks, but over the data alone, without the type.
+    Crc32,
+    /// CRC-64/XZ, what the baseline manifest records.
+    Crc64,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 4] = [
+        Algorithm::Sha256,
+        Algorithm::Blake3,
+        Algorithm::Crc32,
+        Algorithm::Crc64,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Crc32 => "crc32",
+            Algorithm::Crc64 => "crc64",
+        }
+    }
+    /// The digest of `data` in lowercase hex, all of it.
+    pub fn hex(&self, data: &[u8]) -> String {
+        match self {
+            Algorithm::Sha256 => sha256_hex(data),
+            Algorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+            Algorithm::Crc32 => format!("{:08x}", CRC32.checksum(data)),
+            Algorithm::Crc64 => format!("{:016x}", CRC64.checksum(data)),
+        }
+    }
+    /// The first `SHORT_LEN` hex digits of the digest, enough to tell
+    /// chunks apart at a glance.
+    pub fn short_hex(&self, data: &[u8]) -> String {
+        let mut hex = self.hex(data);
+        hex.truncate(SHORT_LEN);
+        hex
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Algorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name() == s)
+            .ok_or_else(|| format!("expected sha256, blake3, crc32 or crc64, got '{s}'"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let digests: Vec<String> = Algorithm::ALL.iter().map(|a| a.hex(b"abc")).collect();
+        assert_eq!(
+            digests,
+            [
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+                "352441c2",
+                "2cd8094a1a277627",
+            ]
+        );
+        assert_eq!(Algorithm::Sha256.short_hex(b"abc"), "ba7816bf");
+        assert_eq!(Algorithm::Crc32.short_hex(b"abc"), "352441c2");
+        assert_eq!(Algorithm::Crc64.short_hex(b""), "00000000");
+    }
+
+    #[test]
+    fn test_parse() {
+        for algorithm in Algorithm::ALL {
+            assert_eq!(algorithm.name().parse(), Ok(algorithm));
+        }
+        assert!("md5".parse::<Algorithm>().is_err());
+        assert!("SHA256".parse::<Algorithm>().is_err());
+    }
+}
@@ -0,0 +1,812 @@
+//! A PNG file opened for editing, saved the way the `pngme` CLI saves the
+//! files it edits: through a temporary file renamed into place, keeping a
+//! symlink and the permissions of the file being replaced, and optionally
+//! a backup of it.
+//!
+//! ```no_run
+//! use pngme::engine::{Engine, EngineOptions};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let options = EngineOptions {
+//!     backup: Some("~".into()),
+//!     ..Default::default()
+//! };
+//! let mut engine = Engine::open_with("a.png", options)?;
+//! engine.encode("ruSt".parse()?, "hidden message")?;
+//! engine.save()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::chunk::{Chunk, InvalidChunk};
+use crate::chunk_type::ChunkType;
+use crate::message::{self, EncodeOptions, MessageError};
+use crate::message_template::FileFacts;
+use crate::png::{InvalidStructure, ParseError, ParseOptions, Png, SizeReport};
+use crate::timings::{self, Phase};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How files are read and written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOptions {
+    /// Write a temporary file and rename it into place, so the destination
+    /// is either fully written or untouched. Otherwise the file is written
+    /// over where it is, which keeps its hard links.
+    pub atomic: bool,
+    /// Before replacing a file, copy it to its path with this suffix added,
+    /// e.g. `~` or `.bak`.
+    pub backup: Option<String>,
+    /// Parse with `ParseOptions::lenient`.
+    pub lenient: bool,
+    /// Keep a symlink at the destination and rewrite its target.
+    pub follow_symlinks: bool,
+    /// Give a file being replaced the modification time it had before.
+    pub preserve_timestamps: bool,
+    /// Save a png even if IHDR isn't first or there's no IEND, which most
+    /// tools refuse to open.
+    pub allow_invalid: bool,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            atomic: true,
+            backup: None,
+            lenient: false,
+            follow_symlinks: true,
+            preserve_timestamps: false,
+            allow_invalid: false,
+        }
+    }
+}
+
+/// The step of a write that failed, with the path it was operating on.
+#[derive(Debug)]
+pub struct WriteError {
+    step: &'static str,
+    path: PathBuf,
+    source: io::Error,
+}
+
+impl WriteError {
+    fn at(step: &'static str, path: &Path) -> impl FnOnce(io::Error) -> WriteError {
+        let path = path.to_path_buf();
+        move |source| WriteError { step, path, source }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to {} \"{}\"", self.step, self.path.display())
+    }
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Something about the file being replaced that a write couldn't carry
+/// over, going ahead without it.
+#[derive(Debug)]
+pub struct WriteWarning {
+    /// What wasn't kept, e.g. "permissions".
+    lost: &'static str,
+    path: PathBuf,
+    source: io::Error,
+}
+
+impl WriteWarning {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl std::fmt::Display for WriteWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "couldn't keep the {} of {}: {}",
+            self.lost,
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: ParseError,
+    },
+    Write(WriteError),
+    /// No chunk of the type asked for.
+    NotFound(ChunkType),
+    /// The chunk asked for as text isn't UTF-8.
+    NotText(ChunkType, InvalidChunk),
+    /// Saving would write a png without IHDR first or without IEND.
+    Invalid(InvalidStructure),
+    /// `encode` refused the chunk.
+    Message(MessageError),
+}
+
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::Read { path, .. } => write!(f, "failed to read \"{}\"", path.display()),
+            EngineError::Parse { path, .. } => write!(f, "failed to parse \"{}\"", path.display()),
+            EngineError::Write(e) => write!(f, "{e}"),
+            EngineError::NotFound(chunk_type) => write!(f, "{chunk_type} wasnt found in the png"),
+            EngineError::NotText(chunk_type, e) => write!(f, "{chunk_type}: {e}"),
+            EngineError::Invalid(_) => write!(f, "refusing to save an invalid png"),
+            EngineError::Message(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Read { source, .. } => Some(source),
+            EngineError::Parse { source, .. } => Some(source),
+            EngineError::Write(e) => e.source(),
+            EngineError::Invalid(e) => Some(e),
+            EngineError::Message(e) => e.source(),
+            EngineError::NotFound(_) | EngineError::NotText(..) => None,
+        }
+    }
+}
+
+impl From<WriteError> for EngineError {
+    fn from(e: WriteError) -> Self {
+        EngineError::Write(e)
+    }
+}
+
+impl From<MessageError> for EngineError {
+    fn from(e: MessageError) -> Self {
+        EngineError::Message(e)
+    }
+}
+
+/// A PNG read from `path`, edited in memory until it's saved.
+#[derive(Debug, Clone)]
+pub struct Engine {
+    path: PathBuf,
+    png: Png,
+    options: EngineOptions,
+    /// The file as it was opened, for `message_template` placeholders.
+    original: Option<FileFacts>,
+}
+
+impl Engine {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, EngineError> {
+        Self::open_with(path, EngineOptions::default())
+    }
+    pub fn open_with(path: impl AsRef<Path>, options: EngineOptions) -> Result<Self, EngineError> {
+        let parse_options = ParseOptions {
+            lenient: options.lenient,
+            ..Default::default()
+        };
+        Self::open_with_parse_options(path, &parse_options, options)
+    }
+    /// `open_with`, parsing with `parse_options` in place of the defaults;
+    /// `options.lenient` is ignored.
+    pub fn open_with_parse_options(
+        path: impl AsRef<Path>,
+        parse_options: &ParseOptions,
+        options: EngineOptions,
+    ) -> Result<Self, EngineError> {
+        let path = path.as_ref().to_path_buf();
+        let bytes = match timings::time(Phase::Read, || fs::read(&path)) {
+            Ok(bytes) => bytes,
+            Err(source) => return Err(EngineError::Read { path, source }),
+        };
+        match Png::parse_with(&bytes, parse_options) {
+            Ok(png) => Ok(Self {
+                original: Some(FileFacts::new(&path, &bytes)),
+                ..Self::from_png(path, png, options)
+            }),
+            Err(source) => Err(EngineError::Parse { path, source }),
+        }
+    }
+    /// An engine for `png`, which is saved to `path`. `path` needn't exist.
+    pub fn from_png(path: impl Into<PathBuf>, png: Png, options: EngineOptions) -> Self {
+        Self {
+            path: path.into(),
+            png,
+            options,
+            original: None,
+        }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+    pub fn png(&self) -> &Png {
+        &self.png
+    }
+    pub fn png_mut(&mut self) -> &mut Png {
+        &mut self.png
+    }
+    pub fn into_png(self) -> Png {
+        self.png
+    }
+    /// The name, size and SHA-256 of the file as it was when opened, before
+    /// any edits; `None` for an engine made with `from_png`.
+    pub fn original(&self) -> Option<&FileFacts> {
+        self.original.as_ref()
+    }
+    /// Adds a chunk of `chunk_type` holding `data` before IEND, as
+    /// `pngme encode` does, refusing a second chunk of a type the spec
+    /// allows only one of.
+    pub fn encode(
+        &mut self,
+        chunk_type: ChunkType,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<SizeReport, EngineError> {
+        self.encode_with(chunk_type, data, EncodeOptions::default())
+    }
+    /// `encode` with the options `pngme encode` is given, such as
+    /// `--conventional`, `--max-growth` and `--force`.
+    pub fn encode_with(
+        &mut self,
+        chunk_type: ChunkType,
+        data: impl Into<Vec<u8>>,
+        options: EncodeOptions,
+    ) -> Result<SizeReport, EngineError> {
+        let chunks = vec![Chunk::new(chunk_type, data.into())];
+        Ok(message::encode_chunks(&mut self.png, chunks, options)?.report)
+    }
+    /// Removes the first chunk of `chunk_type`.
+    pub fn remove(&mut self, chunk_type: &ChunkType) -> Result<Chunk, EngineError> {
+        let index = self
+            .png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type() == chunk_type)
+            .ok_or(EngineError::NotFound(*chunk_type))?;
+        Ok(self.png.remove_chunk_at(index).expect("index is in range"))
+    }
+    /// The data of the first chunk of `chunk_type`, as text.
+    pub fn decode(&self, chunk_type: &ChunkType) -> Result<String, EngineError> {
+        let chunk = self
+            .png
+            .chunks()
+            .iter()
+            .find(|c| c.chunk_type() == chunk_type)
+            .ok_or(EngineError::NotFound(*chunk_type))?;
+        std::str::from_utf8(chunk.data())
+            .map(str::to_string)
+            .map_err(|e| {
+                let kind = InvalidChunk::NotUtf8 {
+                    valid_up_to: e.valid_up_to(),
+                };
+                EngineError::NotText(*chunk_type, kind)
+            })
+    }
+    /// Whether the png changed since it was opened or last saved.
+    pub fn is_modified(&self) -> bool {
+        self.png.is_modified()
+    }
+    /// Writes the png over the file it was opened from, returning what of
+    /// the file it replaced couldn't be kept, as `write_file` does.
+    pub fn save(&mut self) -> Result<Vec<WriteWarning>, EngineError> {
+        let warnings = self.save_as(&self.path)?;
+        self.png.mark_clean();
+        Ok(warnings)
+    }
+    /// Writes the png to `path`, leaving the file it was opened from alone.
+    pub fn save_as(&self, path: impl AsRef<Path>) -> Result<Vec<WriteWarning>, EngineError> {
+        let bytes = if self.options.allow_invalid {
+            self.png.as_bytes()
+        } else {
+            self.png
+                .as_bytes_validated()
+                .map_err(EngineError::Invalid)?
+        };
+        Ok(write_file(path.as_ref(), &self.options, |f| {
+            f.write_all(&bytes)
+        })?)
+    }
+}
+
+/// How long to wait between attempts to replace a file that another process
+/// has open, about a second in total.
+pub const LOCK_RETRY_DELAYS: [Duration; 4] = [
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(600),
+];
+
+/// Whether `e` means another process has the file open. Windows refuses to
+/// replace such a file, and virus scanners and indexers briefly open freshly
+/// written ones, so this is usually worth waiting out. Unix never fails this way.
+pub fn is_locked(e: &io::Error) -> bool {
+    if cfg!(windows) {
+        // ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+        matches!(e.raw_os_error(), Some(5 | 32 | 33))
+    } else {
+        false
+    }
+}
+
+/// Runs `op`, and again after each of `delays` for as long as it fails with an
+/// error `retryable` accepts. Returns the last result.
+pub fn retry<T>(
+    delays: &[Duration],
+    retryable: impl Fn(&io::Error) -> bool,
+    mut sleep: impl FnMut(Duration),
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    for &delay in delays {
+        match op() {
+            Err(e) if retryable(&e) => sleep(delay),
+            result => return result,
+        }
+    }
+    op()
+}
+
+/// `fs::rename`, retried while the destination is locked by another process.
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    retry(&LOCK_RETRY_DELAYS, is_locked, thread::sleep, || {
+        fs::rename(from, to)
+    })
+}
+
+/// Follows `path` through any chain of symlinks to the file they point at,
+/// which may not exist yet.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // Same limit as Linux, so a loop errors instead of spinning
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target,
+                };
+            }
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(path),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::other("Too many levels of symbolic links"))
+}
+
+/// `path` with `suffix` added to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Has `write` produce `file` as `options` say: atomically or in place,
+/// after backing up the file being replaced. With `follow_symlinks`, a
+/// symlink at `file` is kept and its target rewritten. A file being
+/// replaced keeps its permissions, and with `preserve_timestamps` its
+/// modification time; where that can't be done this writes anyway and
+/// returns a warning for the caller to pass on.
+///
+/// An atomic write is done with anything `write` captured before the
+/// rename, so it may own a handle to `file` itself, which Windows wouldn't
+/// let us replace.
+pub fn write_file(
+    file: &Path,
+    options: &EngineOptions,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> Result<Vec<WriteWarning>, WriteError> {
+    timings::time(Phase::Write, || write_to_path(file, options, write))
+}
+
+fn write_to_path(
+    file: &Path,
+    options: &EngineOptions,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> Result<Vec<WriteWarning>, WriteError> {
+    let mut warnings = Vec::new();
+    let path = if options.follow_symlinks {
+        resolve_symlinks(file).map_err(WriteError::at("resolve", file))?
+    } else {
+        file.to_path_buf()
+    };
+    let path = path.as_path();
+    let original = fs::metadata(path).ok().filter(|m| m.is_file());
+    let back_up = || match (&options.backup, &original) {
+        (Some(suffix), Some(_)) => {
+            let backup = with_suffix(path, suffix);
+            fs::copy(path, &backup)
+                .map(drop)
+                .map_err(WriteError::at("write backup", &backup))
+        }
+        _ => Ok(()),
+    };
+    if !options.atomic {
+        back_up()?;
+        let mut f = File::create(path).map_err(WriteError::at("open", path))?;
+        write(&mut f).map_err(WriteError::at("write", path))?;
+        if let Some(original) = &original
+            && options.preserve_timestamps
+        {
+            warnings.extend(keep_mtime(&f, original, path));
+        }
+        f.sync_all().map_err(WriteError::at("sync", path))?;
+        return Ok(warnings);
+    }
+
+    let name = path.file_name().ok_or_else(|| {
+        let e = io::Error::new(io::ErrorKind::InvalidInput, "Output path has no file name");
+        WriteError::at("write to", path)(e)
+    })?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(".pngme-tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut f =
+        File::create(&tmp_path).map_err(WriteError::at("create temporary file", &tmp_path))?;
+    let result = write(&mut f)
+        .map_err(WriteError::at("write temporary file", &tmp_path))
+        .map(|_| {
+            if let Some(original) = &original {
+                warnings.extend(keep_metadata(&f, original, options, path));
+            }
+        })
+        .and_then(|_| {
+            f.sync_all()
+                .map_err(WriteError::at("sync temporary file", &tmp_path))
+        })
+        .and_then(|_| back_up())
+        .and_then(|_| {
+            rename(&tmp_path, path).map_err(|e| {
+                let step = if is_locked(&e) {
+                    "replace (it is open in another program)"
+                } else {
+                    "replace"
+                };
+                WriteError::at(step, path)(e)
+            })
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result.map(|_| warnings)
+}
+
+/// Gives the temporary file `f` the permissions of the `original` file it
+/// replaces at `path`, and its modification time if asked to.
+fn keep_metadata(
+    f: &File,
+    original: &fs::Metadata,
+    options: &EngineOptions,
+    path: &Path,
+) -> Vec<WriteWarning> {
+    let mut warnings = Vec::new();
+    if let Err(source) = f.set_permissions(original.permissions()) {
+        warnings.push(WriteWarning {
+            lost: "permissions",
+            path: path.to_path_buf(),
+            source,
+        });
+    }
+    if options.preserve_timestamps {
+        warnings.extend(keep_mtime(f, original, path));
+    }
+    warnings
+}
+
+fn keep_mtime(f: &File, original: &fs::Metadata, path: &Path) -> Option<WriteWarning> {
+    let kept = original.modified().and_then(|mtime| f.set_modified(mtime));
+    kept.err().map(|source| WriteWarning {
+        lost: "modification time",
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use std::cell::Cell;
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    fn rust() -> ChunkType {
+        ChunkType::from_str("ruSt").unwrap()
+    }
+
+    fn fixture(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join("a.png");
+        let png = PngBuilder::new(1, 1).build().unwrap();
+        fs::write(&path, png.as_bytes()).unwrap();
+        path
+    }
+
+    /// Every combination of `atomic`, `backup` and `lenient`.
+    fn all_options() -> Vec<EngineOptions> {
+        let mut all = Vec::new();
+        for atomic in [true, false] {
+            for backup in [None, Some(".bak".to_string())] {
+                for lenient in [true, false] {
+                    all.push(EngineOptions {
+                        atomic,
+                        backup: backup.clone(),
+                        lenient,
+                        preserve_timestamps: lenient,
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn test_edit_and_save() {
+        for options in all_options() {
+            let dir = TempDir::new().unwrap();
+            let path = fixture(&dir);
+            let original = fs::read(&path).unwrap();
+
+            let mut engine = Engine::open_with(&path, options.clone()).unwrap();
+            assert!(!engine.is_modified());
+            let report = engine.encode(rust(), "hello").unwrap();
+            assert_eq!(report.payload, 5);
+            assert_eq!(engine.decode(&rust()).unwrap(), "hello");
+            assert!(engine.is_modified());
+            // Everything about the file replaced was kept
+            let warnings = engine.save().unwrap();
+            assert!(warnings.is_empty(), "{warnings:?}");
+            assert!(!engine.is_modified());
+
+            let saved = Engine::open(&path).unwrap();
+            assert_eq!(saved.decode(&rust()).unwrap(), "hello", "{options:?}");
+            let last = saved.png().chunks().last().unwrap();
+            assert_eq!(*last.chunk_type(), ChunkType::IEND);
+            let backup = dir.path().join("a.png.bak");
+            match options.backup {
+                Some(_) => assert_eq!(fs::read(&backup).unwrap(), original, "{options:?}"),
+                None => assert!(!backup.exists(), "{options:?}"),
+            }
+            assert!(!dir.path().join(".a.png.pngme-tmp").exists());
+
+            let mut engine = saved;
+            assert_eq!(engine.remove(&rust()).unwrap().data(), b"hello");
+            assert!(matches!(
+                engine.remove(&rust()),
+                Err(EngineError::NotFound(_))
+            ));
+            engine.save().unwrap();
+            assert_eq!(fs::read(&path).unwrap(), original, "{options:?}");
+        }
+    }
+
+    #[test]
+    fn test_original_is_the_file_as_opened() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir);
+        let bytes = fs::read(&path).unwrap();
+        let mut engine = Engine::open(&path).unwrap();
+        engine.encode(rust(), "hello").unwrap();
+        engine.save().unwrap();
+        let original = engine.original().unwrap();
+        assert_eq!(original.filename, "a.png");
+        assert_eq!(original.filesize, bytes.len() as u64);
+        assert_eq!(original.sha256, crate::digest::sha256_hex(&bytes));
+
+        let engine = Engine::from_png(&path, engine.into_png(), EngineOptions::default());
+        assert_eq!(engine.original(), None);
+    }
+
+    #[test]
+    fn test_lenient() {
+        for options in all_options() {
+            let dir = TempDir::new().unwrap();
+            let path = fixture(&dir);
+            let mut bytes = fs::read(&path).unwrap();
+            bytes.splice(8..8, *b"junk");
+            fs::write(&path, &bytes).unwrap();
+
+            match Engine::open_with(&path, options.clone()) {
+                Ok(engine) => {
+                    assert!(options.lenient);
+                    assert_eq!(engine.png().stray_bytes()[0].bytes(), b"junk");
+                }
+                Err(EngineError::Parse { source, .. }) => {
+                    assert!(!options.lenient);
+                    assert_eq!(source.offset(), 8);
+                }
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_as_leaves_the_original() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir);
+        let original = fs::read(&path).unwrap();
+        let mut engine = Engine::open(&path).unwrap();
+        engine.encode(rust(), "hi").unwrap();
+        let copy = dir.path().join("b.png");
+        engine.save_as(&copy).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert_eq!(Engine::open(&copy).unwrap().decode(&rust()).unwrap(), "hi");
+    }
+
+    #[test]
+    fn test_non_atomic_keeps_hard_links() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir);
+        let link = dir.path().join("link.png");
+        fs::hard_link(&path, &link).unwrap();
+        let chunks = |path: &Path| Engine::open(path).unwrap().png().chunk_count();
+        for atomic in [false, true] {
+            let options = EngineOptions {
+                atomic,
+                ..Default::default()
+            };
+            let mut engine = Engine::open_with(&path, options).unwrap();
+            engine.encode(rust(), "hi").unwrap();
+            engine.save().unwrap();
+        }
+        // Renaming a new file into place broke the link
+        assert_eq!(chunks(&path), 5);
+        assert_eq!(chunks(&link), 4);
+    }
+
+    #[test]
+    fn test_encode_refuses_as_the_cli_does() {
+        let dir = TempDir::new().unwrap();
+        let mut engine = Engine::open(fixture(&dir)).unwrap();
+        let time: ChunkType = "tIME".parse().unwrap();
+        let stamp = [7, 234, 1, 1, 0, 0, 0];
+        engine.encode(time, stamp).unwrap();
+        let err = engine.encode(time, stamp).unwrap_err();
+        assert!(
+            matches!(err, EngineError::Message(MessageError::NotUnique(_))),
+            "{err}"
+        );
+        let force = EncodeOptions {
+            force: true,
+            ..Default::default()
+        };
+        engine.encode_with(time, stamp, force).unwrap();
+
+        let size = engine.png().total_size();
+        let limited = EncodeOptions {
+            max_growth: Some(10),
+            ..Default::default()
+        };
+        let err = engine
+            .encode_with(rust(), vec![0; size], limited)
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                EngineError::Message(MessageError::TooMuchGrowth { .. })
+            ),
+            "{err}"
+        );
+        assert_eq!(engine.png().total_size(), size);
+    }
+
+    #[test]
+    fn test_errors() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing.png");
+        let err = Engine::open(&missing).unwrap_err();
+        assert!(matches!(err, EngineError::Read { .. }));
+        assert_eq!(
+            err.to_string(),
+            format!("failed to read \"{}\"", missing.display())
+        );
+
+        let mut engine = Engine::open(fixture(&dir)).unwrap();
+        engine.encode(rust(), vec![0xff]).unwrap();
+        assert!(matches!(
+            engine.decode(&rust()),
+            Err(EngineError::NotText(..))
+        ));
+        let err = engine
+            .save_as(dir.path().join("no/such/dir.png"))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("failed to create temporary file")
+        );
+
+        engine.remove(&ChunkType::IEND).unwrap();
+        let copy = dir.path().join("b.png");
+        let err = engine.save_as(&copy).unwrap_err();
+        assert!(matches!(err, EngineError::Invalid(_)), "{err}");
+        assert!(!copy.exists());
+        let options = EngineOptions {
+            allow_invalid: true,
+            ..Default::default()
+        };
+        let png = engine.png().clone();
+        Engine::from_png(&copy, png, options).save().unwrap();
+        assert!(copy.exists());
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let attempts = Cell::new(0);
+        let mut slept = Vec::new();
+        let result = retry(
+            &LOCK_RETRY_DELAYS,
+            |_| true,
+            |d| slept.push(d),
+            || {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() < 3 {
+                    Err(io::Error::other("locked"))
+                } else {
+                    Ok(attempts.get())
+                }
+            },
+        );
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(slept, LOCK_RETRY_DELAYS[..2]);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let attempts = Cell::new(0);
+        let result: io::Result<()> = retry(
+            &LOCK_RETRY_DELAYS,
+            |_| true,
+            |_| {},
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(io::Error::other("locked"))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), LOCK_RETRY_DELAYS.len() + 1);
+    }
+
+    #[test]
+    fn test_retry_stops_on_other_errors() {
+        let attempts = Cell::new(0);
+        let result: io::Result<()> = retry(
+            &LOCK_RETRY_DELAYS,
+            |e| e.kind() == io::ErrorKind::ResourceBusy,
+            |_| panic!("should not wait"),
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(io::ErrorKind::NotFound.into())
+            },
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn test_is_locked() {
+        let sharing_violation = io::Error::from_raw_os_error(32);
+        assert_eq!(is_locked(&sharing_violation), cfg!(windows));
+        assert!(!is_locked(&io::ErrorKind::NotFound.into()));
+    }
+}
@@ -0,0 +1,180 @@
+//! How numbers are written in output meant to be read by scripts as well as
+//! people. Every command formats CRCs and sizes through these, so the formats
+//! don't drift and never depend on the locale.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A CRC as `0x` and eight lowercase hex digits, e.g. `0xae426082`.
+pub fn format_crc(crc: u32) -> String {
+    format!("0x{crc:08x}")
+}
+
+/// A size in bytes. Exact by default, e.g. `1536 bytes`; with
+/// `human_readable` sizes of 1 KiB and up are shown in binary units to one
+/// decimal place, e.g. `1.5 KiB`.
+pub fn format_size(bytes: u64, human_readable: bool) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes == 1 {
+        return "1 byte".to_string();
+    }
+    if !human_readable || bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // Move up a unit once the rounded value would read 1024.0
+    while value >= 1023.95 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// A time as a UTC calendar date and time of day, for writing it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utc {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millis: u32,
+    /// Whole seconds since the Unix epoch.
+    pub unix: u64,
+}
+
+impl From<SystemTime> for Utc {
+    /// Times before the epoch are taken as the epoch.
+    fn from(time: SystemTime) -> Self {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since.as_secs();
+        let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+        // Civil date from days since the epoch, after Howard Hinnant's
+        // days_from_civil inverse, with years starting in March
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        Utc {
+            year: year_of_era + era * 400 + i64::from(month <= 2),
+            month: month as u32,
+            day: day as u32,
+            hour: (secs_of_day / 3600) as u32,
+            minute: (secs_of_day / 60 % 60) as u32,
+            second: (secs_of_day % 60) as u32,
+            millis: since.subsec_millis(),
+            unix: secs,
+        }
+    }
+}
+
+/// Comma- or tab-separated values, one record per line. A field holding
+/// the separator, a quote or a line break is quoted, with quotes doubled,
+/// so any field reads back as it was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimited {
+    Csv,
+    Tsv,
+}
+
+impl Delimited {
+    pub fn separator(&self) -> char {
+        match self {
+            Delimited::Csv => ',',
+            Delimited::Tsv => '\t',
+        }
+    }
+    /// `fields` as one record, without the line ending.
+    pub fn record<S: AsRef<str>>(&self, fields: &[S]) -> String {
+        let separator = self.separator();
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                let field = field.as_ref();
+                if field.contains([separator, '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.to_string()
+                }
+            })
+            .collect();
+        fields.join(&separator.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc() {
+        let at = |secs: u64| Utc::from(UNIX_EPOCH + std::time::Duration::from_secs(secs));
+        let leap_day = at(951_782_400);
+        assert_eq!((leap_day.year, leap_day.month, leap_day.day), (2000, 2, 29));
+        let last = at(4_102_444_799);
+        assert_eq!(
+            (
+                last.year,
+                last.month,
+                last.day,
+                last.hour,
+                last.minute,
+                last.second
+            ),
+            (2099, 12, 31, 23, 59, 59)
+        );
+        assert_eq!(last.unix, 4_102_444_799);
+        assert_eq!(
+            at(0),
+            Utc::from(UNIX_EPOCH - std::time::Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_format_crc() {
+        assert_eq!(format_crc(0xae42_6082), "0xae426082");
+        assert_eq!(format_crc(0), "0x00000000");
+        assert_eq!(format_crc(u32::MAX), "0xffffffff");
+    }
+
+    #[test]
+    fn test_format_size_exact() {
+        assert_eq!(format_size(0, false), "0 bytes");
+        assert_eq!(format_size(1, false), "1 byte");
+        assert_eq!(format_size(1536, false), "1536 bytes");
+        assert_eq!(format_size(u64::MAX, false), "18446744073709551615 bytes");
+    }
+
+    #[test]
+    fn test_delimited_record() {
+        assert_eq!(Delimited::Csv.record(&["a", "b c", "1"]), "a,b c,1");
+        assert_eq!(
+            Delimited::Csv.record(&["x,y", "say \"hi\"", "two\nlines"]),
+            "\"x,y\",\"say \"\"hi\"\"\",\"two\nlines\""
+        );
+        assert_eq!(Delimited::Tsv.record(&["x,y", "a\tb"]), "x,y\t\"a\tb\"");
+        assert_eq!(Delimited::Csv.record::<&str>(&[]), "");
+    }
+
+    #[test]
+    fn test_format_size_human_readable() {
+        assert_eq!(format_size(1, true), "1 byte");
+        assert_eq!(format_size(1023, true), "1023 bytes");
+        assert_eq!(format_size(1024, true), "1.0 KiB");
+        assert_eq!(format_size(1536, true), "1.5 KiB");
+        assert_eq!(format_size(1024 * 1024 - 1, true), "1.0 MiB");
+        assert_eq!(format_size(5 * 1024 * 1024 + 1, true), "5.0 MiB");
+        assert_eq!(format_size(3 << 30, true), "3.0 GiB");
+        assert_eq!(format_size(u64::MAX, true), "16777216.0 TiB");
+    }
+}
@@ -0,0 +1,86 @@
+//! Hex text, two lowercase digits a byte, for payloads that come from
+//! tools printing digests and keys that way. Either case is read.
+
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HexError {
+    /// A byte that isn't a hex digit, and where it is.
+    InvalidByte { offset: usize, byte: u8 },
+    /// An odd number of digits, so the last byte is cut in half.
+    OddLength { len: usize },
+}
+
+impl Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HexError::InvalidByte { offset, byte } => {
+                write!(f, "Invalid hex byte 0x{byte:02x}")?;
+                if byte.is_ascii_graphic() {
+                    write!(f, " ('{}')", *byte as char)?;
+                }
+                write!(f, " at offset {offset}")
+            }
+            HexError::OddLength { len } => write!(
+                f,
+                "Hex text is {len} digits long, which isn't a whole number of bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+pub fn encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn decode(text: &str) -> Result<Vec<u8>, HexError> {
+    let bytes = text.as_bytes();
+    let digit = |offset: usize| {
+        let byte = bytes[offset];
+        (byte as char)
+            .to_digit(16)
+            .map(|d| d as u8)
+            .ok_or(HexError::InvalidByte { offset, byte })
+    };
+    // A bad digit is reported ahead of an odd length, as it's more specific
+    let data = (0..bytes.len() / 2)
+        .map(|i| Ok(digit(2 * i)? << 4 | digit(2 * i + 1)?))
+        .collect::<Result<Vec<u8>, HexError>>()?;
+    if !bytes.len().is_multiple_of(2) {
+        digit(bytes.len() - 1)?;
+        return Err(HexError::OddLength { len: bytes.len() });
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let binary: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&binary)).unwrap(), binary);
+        assert_eq!(encode(b"\0\xff"), "00ff");
+        assert_eq!(decode("00FFaB").unwrap(), b"\0\xff\xab");
+        assert_eq!(decode("").unwrap(), b"");
+    }
+
+    #[test]
+    fn test_invalid_text() {
+        assert_eq!(
+            decode("00fg"),
+            Err(HexError::InvalidByte {
+                offset: 3,
+                byte: b'g'
+            })
+        );
+        assert_eq!(decode("00f"), Err(HexError::OddLength { len: 3 }));
+        assert_eq!(
+            decode("0 ff").unwrap_err().to_string(),
+            "Invalid hex byte 0x20 at offset 1"
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! The image data stream, summarized without decoding the pixels: the IDAT
+//! chunks together form one zlib stream, whose header says how it was
+//! compressed and whose inflated length IHDR predicts.
+
+use crate::chunk::ChunkRef;
+use crate::chunk_type::ChunkType;
+use crate::format::format_size;
+use crate::ihdr::Ihdr;
+use flate2::read::ZlibDecoder;
+use std::fmt::{self, Display};
+use std::io::{self, Read};
+
+/// The two bytes that start a zlib stream (RFC 1950).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZlibHeader {
+    /// 8 for deflate, the only method PNG allows.
+    pub method: u8,
+    /// The LZ77 window in bytes, 256 to 32 KiB.
+    pub window_size: u32,
+    pub preset_dictionary: bool,
+    /// How hard the encoder says it tried, 0 to 3. Only informational.
+    pub level: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZlibHeaderError {
+    /// Fewer than two bytes of data.
+    Truncated,
+    /// The check bits don't make the header a multiple of 31.
+    BadCheck,
+}
+
+impl Display for ZlibHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZlibHeaderError::Truncated => write!(f, "Too short for a zlib header"),
+            ZlibHeaderError::BadCheck => write!(f, "The zlib header checksum is wrong"),
+        }
+    }
+}
+
+impl std::error::Error for ZlibHeaderError {}
+
+impl ZlibHeader {
+    pub fn parse(data: &[u8]) -> Result<ZlibHeader, ZlibHeaderError> {
+        let &[cmf, flg, ..] = data else {
+            return Err(ZlibHeaderError::Truncated);
+        };
+        if !(u16::from(cmf) << 8 | u16::from(flg)).is_multiple_of(31) {
+            return Err(ZlibHeaderError::BadCheck);
+        }
+        Ok(ZlibHeader {
+            method: cmf & 0x0f,
+            window_size: 1 << ((cmf >> 4) + 8),
+            preset_dictionary: flg & 0x20 != 0,
+            level: flg >> 6,
+        })
+    }
+    pub fn level_name(&self) -> &'static str {
+        match self.level {
+            0 => "fastest",
+            1 => "fast",
+            2 => "default",
+            _ => "maximum",
+        }
+    }
+}
+
+/// e.g. `deflate, 32.0 KiB window, default compression`.
+impl Display for ZlibHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.method {
+            8 => write!(f, "deflate")?,
+            method => write!(f, "method {method}")?,
+        }
+        write!(
+            f,
+            ", {} window, {} compression",
+            format_size(self.window_size.into(), true),
+            self.level_name()
+        )?;
+        if self.preset_dictionary {
+            write!(f, ", preset dictionary")?;
+        }
+        Ok(())
+    }
+}
+
+/// What `summarize` finds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    /// How many IDAT chunks there are.
+    pub chunks: usize,
+    /// Their data together.
+    pub compressed: u64,
+    /// Parsed from the first IDAT.
+    pub header: Result<ZlibHeader, ZlibHeaderError>,
+}
+
+/// The IDAT chunks in `chunks`, owned or borrowed, or `None` if there are
+/// none.
+pub fn summarize<'a>(
+    chunks: impl IntoIterator<Item = impl Into<ChunkRef<'a>>>,
+) -> Option<ImageData> {
+    let idats: Vec<ChunkRef> = chunks
+        .into_iter()
+        .map(Into::into)
+        .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+        .collect();
+    let first = idats.first()?;
+    Some(ImageData {
+        chunks: idats.len(),
+        compressed: idats.iter().map(|c| u64::from(c.length())).sum(),
+        header: ZlibHeader::parse(first.data()),
+    })
+}
+
+/// How many bytes the IDAT data in `parts`, in order, inflates to,
+/// reading at most `limit` + 1 so a stream that inflates enormously stops
+/// early. Nothing is kept, so memory use doesn't grow with the image.
+pub fn inflated_len<'a>(parts: impl IntoIterator<Item = &'a [u8]>, limit: u64) -> io::Result<u64> {
+    let stream = parts.into_iter().fold(
+        Box::new(io::empty()) as Box<dyn Read + 'a>,
+        |stream, part| Box::new(stream.chain(part)),
+    );
+    io::copy(
+        &mut ZlibDecoder::new(stream).take(limit.saturating_add(1)),
+        &mut io::sink(),
+    )
+}
+
+/// Why the image data doesn't fit IHDR, if it doesn't: it's corrupt, or it
+/// inflates to a different length than the image needs. `None` when it
+/// fits or IHDR's color type is unknown.
+pub fn check<'a>(ihdr: &Ihdr, parts: impl IntoIterator<Item = &'a [u8]>) -> Option<String> {
+    let expected = ihdr.image_data_len()?;
+    match inflated_len(parts, expected) {
+        Err(e) => Some(format!("The image data doesn't inflate: {e}")),
+        Ok(len) if len == expected => None,
+        Ok(len) => Some(format!(
+            "The image data inflates to {}, but a {}x{} {}-bit {} image needs {expected}",
+            if len > expected {
+                format!("more than {expected} bytes")
+            } else {
+                format_size(len, false)
+            },
+            ihdr.width,
+            ihdr.height,
+            ihdr.bit_depth,
+            ihdr.color_type_name()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::png::Png;
+
+    /// A file from the png crate, split into IDATs of at most `max_idat`
+    /// bytes.
+    fn encode(
+        width: u32,
+        height: u32,
+        color: png::ColorType,
+        depth: png::BitDepth,
+        max_idat: usize,
+    ) -> Png {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if color == png::ColorType::Indexed {
+            encoder.set_palette(vec![0; 3 * 16]);
+        }
+        let mut writer = encoder.write_header().unwrap();
+        let row = (width as usize * color.samples() * depth as usize).div_ceil(8);
+        let pixels = vec![0x5a; row * height as usize];
+        writer.write_image_data(&pixels).unwrap();
+        writer.finish().unwrap();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        let chunks = png.chunks().iter().flat_map(|chunk| {
+            if *chunk.chunk_type() != ChunkType::IDAT {
+                return vec![chunk.clone()];
+            }
+            chunk
+                .data()
+                .chunks(max_idat)
+                .map(|part| Chunk::new(ChunkType::IDAT, part.to_vec()))
+                .collect()
+        });
+        Png::from_chunks(chunks.collect())
+    }
+
+    #[test]
+    fn test_expected_len_matches_real_images() {
+        use png::{BitDepth, ColorType};
+        let cases = [
+            (7, 3, ColorType::Grayscale, BitDepth::One),
+            (9, 5, ColorType::Indexed, BitDepth::Four),
+            (4, 4, ColorType::GrayscaleAlpha, BitDepth::Sixteen),
+            (5, 2, ColorType::Rgb, BitDepth::Eight),
+            (3, 3, ColorType::Rgba, BitDepth::Sixteen),
+        ];
+        for (width, height, color, depth) in cases {
+            let png = encode(width, height, color, depth, 10);
+            let ihdr = Ihdr::try_from(&png.chunks()[0]).unwrap();
+            let idats = png
+                .chunks()
+                .iter()
+                .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+                .map(|c| c.data());
+            let len = inflated_len(idats.clone(), u64::MAX).unwrap();
+            assert_eq!(Some(len), ihdr.image_data_len(), "{color:?} {depth:?}");
+            assert_eq!(check(&ihdr, idats), None);
+
+            let summary = summarize(png.chunks()).unwrap();
+            assert!(summary.chunks > 1);
+            let header = summary.header.unwrap();
+            assert_eq!((header.method, header.preset_dictionary), (8, false));
+        }
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let png = encode(5, 2, png::ColorType::Rgb, png::BitDepth::Eight, 1 << 20);
+        let data = png.chunk_by_type("IDAT").unwrap().data();
+        let mut ihdr = Ihdr::try_from(&png.chunks()[0]).unwrap();
+        ihdr.height = 3;
+        assert_eq!(
+            check(&ihdr, [data]).unwrap(),
+            "The image data inflates to 32 bytes, but a 5x3 8-bit truecolor image needs 48"
+        );
+        ihdr.height = 1;
+        assert!(check(&ihdr, [data]).unwrap().contains("more than 16 bytes"));
+        assert!(
+            check(&ihdr, [&data[..data.len() / 2]])
+                .unwrap()
+                .starts_with("The image data doesn't inflate")
+        );
+    }
+
+    #[test]
+    fn test_zlib_header() {
+        let header = ZlibHeader::parse(&[0x78, 0xda]).unwrap();
+        assert_eq!(header.window_size, 32 * 1024);
+        assert_eq!(
+            header.to_string(),
+            "deflate, 32.0 KiB window, maximum compression"
+        );
+        assert_eq!(
+            ZlibHeader::parse(&[0x78, 0x9d]),
+            Err(ZlibHeaderError::BadCheck)
+        );
+        assert_eq!(ZlibHeader::parse(&[0x78]), Err(ZlibHeaderError::Truncated));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+/// The image parameters stored in the IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    /// The raw color type code: 0 grayscale, 2 truecolor, 3 indexed,
+    /// 4 grayscale with alpha, 6 truecolor with alpha.
+    pub color_type: u8,
+    pub compression: u8,
+    pub filter: u8,
+    pub interlace: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IhdrError {
+    NotIhdr,
+    BadLength(usize),
+}
+
+impl std::fmt::Display for IhdrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IhdrError::NotIhdr => write!(f, "Not an IHDR chunk"),
+            IhdrError::BadLength(len) => write!(f, "IHDR must be 13 bytes, not {len}"),
+        }
+    }
+}
+
+impl std::error::Error for IhdrError {}
+
+impl Ihdr {
+    pub const INDEXED: u8 = 3;
+
+    pub fn parse(data: &[u8]) -> Result<Ihdr, IhdrError> {
+        let Ok(data) = <&[u8; 13]>::try_from(data) else {
+            return Err(IhdrError::BadLength(data.len()));
+        };
+        Ok(Ihdr {
+            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression: data[10],
+            filter: data[11],
+            interlace: data[12],
+        })
+    }
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.width.to_be_bytes().to_vec();
+        data.extend(self.height.to_be_bytes());
+        data.extend([
+            self.bit_depth,
+            self.color_type,
+            self.compression,
+            self.filter,
+            self.interlace,
+        ]);
+        Chunk::new(ChunkType::IHDR, data)
+    }
+    pub fn color_type_name(&self) -> &'static str {
+        match self.color_type {
+            0 => "grayscale",
+            2 => "truecolor",
+            3 => "indexed",
+            4 => "grayscale with alpha",
+            6 => "truecolor with alpha",
+            _ => "unknown",
+        }
+    }
+    /// Samples per pixel, or `None` for an unknown color type.
+    pub fn channels(&self) -> Option<u8> {
+        match self.color_type {
+            0 | 3 => Some(1),
+            2 => Some(3),
+            4 => Some(2),
+            6 => Some(4),
+            _ => None,
+        }
+    }
+    /// How many bytes the image data inflates to: every scanline is a
+    /// filter byte and its pixels rounded up to whole bytes, and an Adam7
+    /// image has the scanlines of its seven reduced images, some of which
+    /// may be empty. `None` for an unknown color type.
+    pub fn image_data_len(&self) -> Option<u64> {
+        let bits_per_pixel = u64::from(self.channels()?) * u64::from(self.bit_depth);
+        let scanlines = |width: u64, height: u64| {
+            if width == 0 {
+                return 0;
+            }
+            height * (1 + (width * bits_per_pixel).div_ceil(8))
+        };
+        let (width, height) = (u64::from(self.width), u64::from(self.height));
+        if self.interlace != 1 {
+            return Some(scanlines(width, height));
+        }
+        // Where each pass starts and how far apart its pixels are, as (x, y)
+        const PASSES: [(u64, u64, u64, u64); 7] = [
+            (0, 0, 8, 8),
+            (4, 0, 8, 8),
+            (0, 4, 4, 8),
+            (2, 0, 4, 4),
+            (0, 2, 2, 4),
+            (1, 0, 2, 2),
+            (0, 1, 1, 2),
+        ];
+        let pass_len = |size: u64, start: u64, step: u64| size.saturating_sub(start).div_ceil(step);
+        Some(
+            PASSES
+                .iter()
+                .map(|&(x, y, dx, dy)| scanlines(pass_len(width, x, dx), pass_len(height, y, dy)))
+                .sum(),
+        )
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = IhdrError;
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if *chunk.chunk_type() != ChunkType::IHDR {
+            return Err(IhdrError::NotIhdr);
+        }
+        Ihdr::parse(chunk.data())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+
+    #[test]
+    fn test_builder_ihdr() {
+        let png = PngBuilder::new(3, 2).build().unwrap();
+        let ihdr = Ihdr::try_from(&png.chunks()[0]).unwrap();
+        assert_eq!((ihdr.width, ihdr.height, ihdr.bit_depth), (3, 2, 8));
+        assert_eq!(ihdr.color_type_name(), "truecolor");
+        assert_eq!(
+            Ihdr::try_from(&png.chunks()[1]).unwrap_err(),
+            IhdrError::NotIhdr
+        );
+        assert_eq!(Ihdr::parse(&[0; 12]).unwrap_err(), IhdrError::BadLength(12));
+        assert_eq!(ihdr.to_chunk(), png.chunks()[0]);
+    }
+
+    #[test]
+    fn test_image_data_len() {
+        let ihdr = |width, height, bit_depth, color_type, interlace| Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression: 0,
+            filter: 0,
+            interlace,
+        };
+        // 3 bytes a pixel and a filter byte a row
+        assert_eq!(ihdr(3, 2, 8, 2, 0).image_data_len(), Some(2 * (1 + 9)));
+        assert_eq!(ihdr(3, 2, 16, 6, 0).image_data_len(), Some(2 * (1 + 24)));
+        assert_eq!(ihdr(5, 1, 8, 4, 0).image_data_len(), Some(1 + 10));
+        // 10 pixels of 1 bit round up to 2 bytes, of 2 bits to 3
+        assert_eq!(ihdr(10, 4, 1, 0, 0).image_data_len(), Some(4 * (1 + 2)));
+        assert_eq!(ihdr(10, 4, 2, 3, 0).image_data_len(), Some(4 * (1 + 3)));
+        assert_eq!(ihdr(1, 1, 8, 5, 0).image_data_len(), None);
+        // A 1x1 Adam7 image is all in the first pass; the others are empty
+        assert_eq!(ihdr(1, 1, 8, 2, 1).image_data_len(), Some(1 + 3));
+        // 8x8 gray: pass widths 1,1,2,2,4,4,8 and heights 1,1,1,2,2,4,4
+        assert_eq!(
+            ihdr(8, 8, 8, 0, 1).image_data_len(),
+            Some(2 + 2 + 3 + 2 * 3 + 2 * 5 + 4 * 5 + 4 * 9)
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! A png given as text in place of a file: a `data:image/png;base64,` URL
+//! as the file argument, or with `--input-format base64` a file or argument
+//! holding a data URL or bare base64. It's decoded in memory, so only
+//! commands that read can take one; there's no file to edit.
+//!
+//! Files are opened by helpers far from the arguments, so the format is
+//! set once at startup, as `timings::enable` is.
+
+use crate::Result;
+use crate::context::Context;
+use pngme::data_url;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static BASE64: AtomicBool = AtomicBool::new(false);
+
+/// `--input-format base64`.
+pub fn read_base64() {
+    BASE64.store(true, Ordering::Relaxed);
+}
+
+/// Whether `file` is a data URL given in place of a file, rather than a
+/// path: it isn't one, and it starts with `data:`.
+fn is_data_url(file: &Path) -> bool {
+    file.to_str().is_some_and(data_url::is_data_url) && !file.exists()
+}
+
+/// Whether `file` is to be read as text rather than opened as a png.
+pub fn is_text(file: &Path) -> bool {
+    BASE64.load(Ordering::Relaxed) || is_data_url(file)
+}
+
+/// The png `file` stands for if it's given as text, decoded; `None` if it's
+/// an ordinary png file.
+pub fn read_text(file: &Path) -> Result<Option<Vec<u8>>> {
+    if is_data_url(file) {
+        let text = file.to_string_lossy();
+        return data_url::decode(&text)
+            .map(Some)
+            .context("failed to read the data URL given in place of a file");
+    }
+    if !BASE64.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+    let text = match fs::read_to_string(file) {
+        Ok(text) => text,
+        // The argument itself is the base64, as pasted
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return data_url::decode(&file.to_string_lossy())
+                .map(Some)
+                .context("failed to read the base64 given in place of a file");
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to read \"{}\"", file.display())),
+    };
+    data_url::decode(&text)
+        .map(Some)
+        .with_context(|| format!("failed to read \"{}\" as base64", file.display()))
+}
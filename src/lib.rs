@@ -0,0 +1,46 @@
+//! Reading and writing PNG chunks.
+//!
+//! The parsers (`ChunkType::try_from`/`from_str`, `Chunk::try_from`,
+//! `Png::try_from` and friends) return `Err` on malformed input and never
+//! panic, whatever bytes they are handed. The `fuzz/` targets check this.
+
+pub mod analysis;
+pub mod apng;
+pub mod base64;
+pub mod baseline;
+pub mod builder;
+pub mod chunk;
+pub mod chunk_data;
+pub mod chunk_type;
+pub mod data_url;
+pub mod digest;
+pub mod engine;
+pub mod format;
+pub mod hex;
+pub mod idat;
+pub mod ihdr;
+pub mod lock;
+pub mod message;
+pub mod message_template;
+pub mod optimize;
+pub mod palette;
+pub mod png;
+pub mod preview;
+pub mod redundant;
+#[cfg(feature = "render-check")]
+pub mod render;
+pub mod rewrite;
+pub mod rules;
+pub mod sanitize;
+pub mod selector;
+pub mod stamp;
+pub mod standard;
+pub mod survival;
+pub mod template;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+pub mod text;
+pub mod timings;
+pub mod transaction;
+pub mod transform;
+pub mod verify;
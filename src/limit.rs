@@ -0,0 +1,122 @@
+//! `decode --max-output-bytes`: a cap on how much of a payload goes to
+//! stdout, so a huge message can't flood a build log. Everything past the
+//! cap is counted but not printed, and `finish` says how much there was.
+
+use crate::Result;
+use crate::context::DecodeError;
+
+pub struct OutputLimit {
+    max: Option<u64>,
+    /// Bytes offered so far, printed or not.
+    total: u64,
+    printed: u64,
+    truncated: bool,
+}
+
+impl OutputLimit {
+    /// No cap with `None`.
+    pub fn new(max: Option<u64>) -> Self {
+        OutputLimit {
+            max,
+            total: 0,
+            printed: 0,
+            truncated: false,
+        }
+    }
+    /// The start of `bytes` that still fits. With `text` the cut is moved
+    /// back to the start of a UTF-8 sequence, so no character is split.
+    pub fn take<'a>(&mut self, bytes: &'a [u8], text: bool) -> &'a [u8] {
+        self.total += bytes.len() as u64;
+        let Some(max) = self.max else {
+            return bytes;
+        };
+        if self.truncated {
+            return &[];
+        }
+        let room = max - self.printed;
+        if bytes.len() as u64 <= room {
+            self.printed += bytes.len() as u64;
+            return bytes;
+        }
+        let mut cut = room as usize;
+        if text {
+            while cut > 0 && bytes[cut] & 0xc0 == 0x80 {
+                cut -= 1;
+            }
+        }
+        self.truncated = true;
+        self.printed += cut as u64;
+        &bytes[..cut]
+    }
+    /// Counts `len` more bytes that were never read, as being cut off.
+    pub fn skip(&mut self, len: u64) {
+        self.total += len;
+        self.truncated |= len > 0 && self.max.is_some();
+    }
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+    /// The room left, for reading no more of a payload than can be printed.
+    pub fn remaining(&self) -> Option<u64> {
+        self.max.map(|max| max - self.printed)
+    }
+    /// Ends the output with a notice on stderr if anything was cut off, and
+    /// fails so the exit status shows it.
+    pub fn finish(self) -> Result<()> {
+        if !self.truncated {
+            return Ok(());
+        }
+        eprintln!("[truncated, {} bytes total]", self.total);
+        Err(DecodeError::Truncated { total: self.total }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let mut limit = OutputLimit::new(None);
+        assert_eq!(limit.take(b"hello", true), b"hello");
+        assert_eq!(limit.remaining(), None);
+        assert!(limit.finish().is_ok());
+    }
+
+    #[test]
+    fn test_bytes_are_cut_at_the_limit() {
+        let mut limit = OutputLimit::new(Some(6));
+        assert_eq!(limit.take(b"abcd", false), b"abcd");
+        assert_eq!(limit.remaining(), Some(2));
+        assert_eq!(limit.take(b"ef\xe2\x98\x83", false), b"ef");
+        assert_eq!(limit.take(b"more", false), b"");
+        assert_eq!(limit.total, 13);
+        assert!(limit.finish().is_err());
+    }
+
+    #[test]
+    fn test_text_is_cut_at_a_character_boundary() {
+        // Two snowmen, three bytes each; a cut after 4 or 5 bytes would
+        // split the second
+        for max in [3, 4, 5] {
+            let mut limit = OutputLimit::new(Some(max));
+            assert_eq!(limit.take("☃☃".as_bytes(), true), "☃".as_bytes(), "{max}");
+        }
+        let mut limit = OutputLimit::new(Some(6));
+        assert_eq!(limit.take("☃☃".as_bytes(), true), "☃☃".as_bytes());
+        assert!(limit.finish().is_ok());
+        let mut limit = OutputLimit::new(Some(2));
+        assert_eq!(limit.take("☃".as_bytes(), true), b"");
+    }
+
+    #[test]
+    fn test_skipped_bytes_count() {
+        let mut limit = OutputLimit::new(Some(2));
+        limit.take(b"ab", false);
+        limit.skip(0);
+        assert!(!limit.truncated);
+        limit.skip(10);
+        assert_eq!(limit.total, 12);
+        assert!(limit.finish().is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Advisory locks for read-modify-write edits, so two edits of one file made
+//! at the same time can't interleave and lose one of them.
+//!
+//! Only editors take the lock; reading a file never waits for it. On most
+//! platforms it is advisory, so a tool that doesn't lock can still write the
+//! file underneath an edit. Windows enforces it, so while an edit is in
+//! progress other programs can't read the file either.
+
+use std::fs::{File, Metadata, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a locked file is tried again while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Another process held the lock for the whole timeout.
+    Locked {
+        path: PathBuf,
+        timeout: Duration,
+    },
+    Io(io::Error),
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockError::Locked { path, timeout } => write!(
+                f,
+                "{}: file is locked by another process (waited {}s)",
+                path.display(),
+                timeout.as_secs_f64()
+            ),
+            LockError::Io(e) => write!(f, "Failed to lock the file: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LockError::Locked { .. } => None,
+            LockError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        LockError::Io(e)
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+/// Without a stable file id, a replacement is spotted by its size and
+/// modification time, which the new file written by an edit will differ in.
+#[cfg(not(unix))]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.len() == b.len() && a.modified().ok() == b.modified().ok()
+}
+
+/// Takes an exclusive lock on `file`, opened from `path`, waiting up to
+/// `timeout` for another holder to let go. It is released when the returned
+/// handle and every clone of it are dropped, however the edit ends.
+///
+/// Edits replace the file rather than writing into it, so a wait can end
+/// with a lock on a file that is no longer at `path`. The new one is then
+/// opened and locked instead, and that is the handle returned.
+pub fn lock_exclusive(mut file: File, path: &Path, timeout: Duration) -> Result<File, LockError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match file.try_lock() {
+            Ok(()) => {
+                if same_file(&file.metadata()?, &path.metadata()?) {
+                    return Ok(file);
+                }
+                file = File::open(path)?;
+            }
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(POLL_INTERVAL.min(deadline - Instant::now()));
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(LockError::Locked {
+                    path: path.to_path_buf(),
+                    timeout,
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+/// Opens `path`, locks it and hands the locked file to `edit`. The lock is
+/// held until `edit` returns, so `edit` should read the file through the
+/// handle it is given and write its replacement before returning.
+pub fn edit_locked<T, E: From<LockError>>(
+    path: &Path,
+    timeout: Duration,
+    edit: impl FnOnce(&File) -> Result<T, E>,
+) -> Result<T, E> {
+    let file = File::open(path).map_err(LockError::from)?;
+    let file = lock_exclusive(file, path, timeout)?;
+    edit(&file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use std::fs;
+    use std::io::Read;
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    /// Appends a chunk the way the CLI does: read through the locked handle,
+    /// then rename a new file into place.
+    fn append(path: &Path, data: &str) -> Result<(), Box<dyn std::error::Error>> {
+        edit_locked(path, Duration::from_secs(10), |mut file| {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            let mut png = Png::try_from(bytes.as_slice())?;
+            // Wide enough a window that an unlocked edit would lose data
+            thread::sleep(Duration::from_millis(20));
+            let chunk_type = ChunkType::from_str("ruSt")?;
+            png.insert_before_iend(Chunk::new(chunk_type, data.as_bytes().to_vec()));
+            let tmp = path.with_extension(format!("{data}.tmp"));
+            fs::write(&tmp, png.as_bytes())?;
+            fs::rename(&tmp, path)?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_concurrent_edits_both_survive() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.png");
+        fs::write(&path, PngBuilder::new(1, 1).build().unwrap().as_bytes()).unwrap();
+        let threads: Vec<_> = (0..2)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for j in 0..5 {
+                        append(&path, &format!("t{i}e{j}")).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let png = Png::try_from(fs::read(&path).unwrap().as_slice()).unwrap();
+        let mut tags: Vec<&str> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "ruSt")
+            .map(|c| std::str::from_utf8(c.data()).unwrap())
+            .collect();
+        tags.sort();
+        let mut expected: Vec<String> = (0..2)
+            .flat_map(|i| (0..5).map(move |j| format!("t{i}e{j}")))
+            .collect();
+        expected.sort();
+        assert_eq!(tags, expected);
+    }
+
+    #[test]
+    fn test_times_out_while_locked() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.png");
+        fs::write(&path, b"data").unwrap();
+        let held = lock_exclusive(File::open(&path).unwrap(), &path, Duration::ZERO).unwrap();
+        let start = Instant::now();
+        let err = lock_exclusive(
+            File::open(&path).unwrap(),
+            &path,
+            Duration::from_millis(120),
+        )
+        .unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        assert!(matches!(err, LockError::Locked { .. }));
+        assert!(err.to_string().contains("locked by another process"));
+        drop(held);
+        assert!(lock_exclusive(File::open(&path).unwrap(), &path, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn test_error_releases_lock() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.png");
+        fs::write(&path, b"not a png").unwrap();
+        assert!(append(&path, "x").is_err());
+        assert!(lock_exclusive(File::open(&path).unwrap(), &path, Duration::ZERO).is_ok());
+    }
+}
@@ -1,89 +1,3060 @@
 mod args;
-mod chunk;
-mod chunk_type;
+mod audit;
 mod commands;
-mod png;
+mod context;
+mod input;
+mod limit;
+mod platform;
+mod self_test;
+mod shell;
+mod summary;
+mod undo;
+mod version;
+mod watch;
 use std::{
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
+    borrow::Cow,
+    cell::RefCell,
+    fs::{self, File},
+    io::{self, IsTerminal, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
     process::exit,
     str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
+use crate::args::{FileFormat, ListFormat, PayloadEncoding, parse_type_list};
+use crate::audit::{AuditLog, Tally};
 use crate::commands::Args;
-use chunk::Chunk;
-use chunk_type::ChunkType;
-use clap::Parser;
-use commands::Commands;
-use png::Png;
+use clap::error::ErrorKind;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use commands::{Commands, FramesAction, OutputFormat, TagsAction};
+use context::{Context, DecodeError};
+use limit::OutputLimit;
+use pngme::analysis::{self, Magic};
+use pngme::apng;
+use pngme::baseline::{self, Manifest};
+use pngme::builder::PngBuilder;
+use pngme::chunk::{Chunk, ChunkRef};
+use pngme::chunk_data::KnownChunk;
+use pngme::chunk_type::ChunkType;
+use pngme::data_url;
+use pngme::engine::{self, Engine, EngineOptions};
+use pngme::format::{format_crc, format_size};
+use pngme::idat::{self, ImageData};
+use pngme::ihdr::Ihdr;
+use pngme::lock;
+use pngme::message::{self, EncodeOptions, Encoded, MessageError};
+use pngme::message_template::{self, FileFacts};
+use pngme::optimize::{self, OptimizeOptions};
+use pngme::palette::{PLTE, Palette, TRNS};
+use pngme::png::{
+    InvalidStructure, Listing, ParseOptions, PartialParse, Png, ReadError, StructureViolation,
+    find_length_mismatches,
+};
+use pngme::preview::{self, Structured};
+use pngme::redundant;
+use pngme::rewrite::Rewrite;
+use pngme::rules::Rules;
+use pngme::sanitize;
+use pngme::standard;
+use pngme::survival::{self, Position, SurvivabilityReport};
+use pngme::template::ChunkFields;
+use pngme::text;
+use pngme::timings::{self, Timed};
+use pngme::transform::{self, Registry};
+use pngme::verify::{self, FindingKind, Severity};
+use summary::{ChunkChange, Summary};
+use undo::UndoLog;
+
+/// The columns of `list --format csv` and `tsv`.
+const LIST_COLUMNS: [&str; 7] = [
+    "index",
+    "type",
+    "length",
+    "crc_hex",
+    "offset",
+    "critical",
+    "safe_to_copy",
+];
+/// The columns of `stats --format csv` and `tsv`.
+const STATS_COLUMNS: [&str; 4] = ["type", "count", "total_bytes", "percent"];
+
+/// Growth above which `encode` warns that the payload dwarfs the image.
+const GROWTH_WARNING_PERCENT: f64 = 50.0;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub fn png_from_file(file: &str) -> Result<Png> {
-    let fpath = PathBuf::from(file);
-    if !fpath.exists() || fpath.is_dir() {
-        eprintln!("Please provide valid the path of png file");
-        exit(1)
+fn open_input(path: &Path) -> io::Result<File> {
+    let mut options = File::options();
+    options.read(true);
+    // Keep a FIFO from blocking the open until a writer shows up; it is
+    // rejected just below as not being a regular file anyway
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NONBLOCK);
+    options.open(path)
+}
+
+/// Opens `file` for reading, following symlinks. Problems are classified from
+/// the results of the open itself rather than checked up front, so there is
+/// no window between the check and the read.
+pub fn try_open_png_file(file: &Path) -> std::result::Result<File, String> {
+    let fail = |reason: &dyn std::fmt::Display| format!("{}: {reason}", file.display());
+    let f = open_input(file).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => fail(&"No such file"),
+        io::ErrorKind::PermissionDenied => fail(&"Permission denied"),
+        io::ErrorKind::IsADirectory => fail(&"Is a directory"),
+        _ => fail(&e),
+    })?;
+    let metadata = f.metadata().map_err(|e| fail(&e))?;
+    if metadata.is_dir() {
+        return Err(fail(&"Is a directory"));
+    }
+    if !metadata.is_file() {
+        return Err(fail(&"Not a regular file"));
+    }
+    Ok(f)
+}
+
+/// `try_open_png_file`, failing with the reason if the file can't be used.
+pub fn open_png_file(file: &Path) -> Result<File> {
+    Ok(try_open_png_file(file)?)
+}
+
+pub fn read_png_file(file: &Path) -> Result<Vec<u8>> {
+    if let Some(png) = input::read_text(file)? {
+        return Ok(png);
     }
-    let mut f = File::options().read(true).open(&fpath)?;
     let mut buffer = Vec::new();
-    f.read_to_end(&mut buffer)?;
-    if buffer[..Png::STANDARD_HEADER.len()] != Png::STANDARD_HEADER {
-        eprintln!("Not a valid PNG file");
-        exit(1)
+    Timed(open_png_file(file)?).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+pub fn png_from_file(file: &Path, options: &ParseOptions) -> Result<Png> {
+    let buffer = read_png_file(file)?;
+    Png::parse_with(&buffer, options).with_context(|| parse_failure(file))
+}
+
+/// The chunks of `buffer`, as read from `file`, borrowed rather than
+/// copied, for a command that only reads them.
+pub fn chunks_of<'a>(
+    file: &Path,
+    buffer: &'a [u8],
+    options: &ParseOptions,
+) -> Result<Vec<ChunkRef<'a>>> {
+    Png::parse_borrowed_with(buffer, options).with_context(|| parse_failure(file))
+}
+
+fn parse_failure(file: &Path) -> String {
+    if input::is_text(file) {
+        "failed to parse the png given as text".to_string()
+    } else {
+        format!("failed to parse \"{}\"", file.display())
+    }
+}
+
+/// Opens `file` for an edit and locks it, so a second editor waits up to
+/// `timeout` instead of overwriting this edit. The file must be read through
+/// the returned handle (or a clone), and the lock lasts until it is dropped.
+pub fn lock_png_file(file: &Path, timeout: Duration) -> Result<File> {
+    if input::is_text(file) {
+        return Err(
+            "A png given as a data URL or base64 can only be read; save it to a file to edit it"
+                .into(),
+        );
+    }
+    let mut lock = lock::lock_exclusive(try_open_png_file(file)?, file, timeout)?;
+    audit::read_input(file, &mut lock)?;
+    Ok(lock)
+}
+
+/// Reads and parses a png that is about to be edited.
+pub fn png_from_reader(reader: impl Read, options: &ParseOptions) -> Result<Png> {
+    let mut buffer = Vec::new();
+    Timed(reader).read_to_end(&mut buffer)?;
+    let png = Png::parse_with(&buffer, options)?;
+    timings::begin_edit();
+    Ok(png)
+}
+
+/// Fails if a png with these violations is about to be written, unless
+/// --allow-invalid was given.
+pub fn check_structure(
+    violations: Vec<StructureViolation>,
+    engine_options: &EngineOptions,
+) -> Result<()> {
+    if violations.is_empty() || engine_options.allow_invalid {
+        return Ok(());
+    }
+    Err(InvalidStructure(violations)).context(
+        "refusing to write a png most tools would reject; pass --allow-invalid to write it anyway",
+    )
+}
+
+pub fn write_png_file(file: &Path, png: &Png, engine_options: &EngineOptions) -> Result<()> {
+    check_structure(png.structure_violations(), engine_options)?;
+    write_output(file, engine_options, |f| f.write_all(&png.as_bytes()))
+}
+
+/// An edit to be recorded in the undo log: which command made it, and the
+/// file as it was before.
+pub struct Recording<'a> {
+    log: &'a UndoLog,
+    command: &'static str,
+    before: Png,
+}
+
+/// `write_png_file`, first logging the edit if it is being recorded. The
+/// record is dropped again if the write fails, so it can't undo an edit
+/// that never happened.
+pub fn write_edit(
+    file: &Path,
+    png: &Png,
+    engine_options: &EngineOptions,
+    recording: Option<Recording>,
+) -> Result<()> {
+    let Some(recording) = recording else {
+        return write_png_file(file, png, engine_options);
+    };
+    recording
+        .log
+        .push(file, recording.command, &recording.before, png)?;
+    if let Err(e) = write_png_file(file, png, engine_options) {
+        let _ = recording.log.pop(file);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// `println!` for a line about an edit, which goes to stderr instead when
+/// the edited file is being written to stdout.
+macro_rules! status {
+    ($output:expr, $($arg:tt)*) => {
+        $output.status(format_args!($($arg)*))
+    };
+}
+
+/// Where an edited file goes: back over the file it was read from, to
+/// another file, or with `--output -` to stdout. Every command that edits
+/// writes through this, so they all treat `--output` alike.
+pub enum Output {
+    InPlace(PathBuf),
+    File(PathBuf),
+    Stdout,
+    /// With `--output-format base64`, as a data URL to `--output` or, if
+    /// there's none, stdout.
+    DataUrl(Option<PathBuf>),
+}
+
+impl Output {
+    /// The target for an edit of `input` given `--output`. Naming the input
+    /// itself, by whatever path, is the same as leaving `--output` out.
+    pub fn resolve(input: &Path, output: Option<PathBuf>, format: FileFormat) -> Self {
+        if format == FileFormat::Base64 {
+            return Output::DataUrl(output.filter(|output| output != Path::new("-")));
+        }
+        let Some(output) = output else {
+            return Output::InPlace(input.to_path_buf());
+        };
+        if output == Path::new("-") {
+            return Output::Stdout;
+        }
+        let same = output == input
+            || fs::canonicalize(&output)
+                .is_ok_and(|o| fs::canonicalize(input).is_ok_and(|i| i == o));
+        if same {
+            Output::InPlace(input.to_path_buf())
+        } else {
+            Output::File(output)
+        }
+    }
+    /// Only edits made in place are recorded for undo; written anywhere
+    /// else, the input is left as it was and there is nothing to undo.
+    pub fn in_place(&self) -> bool {
+        matches!(self, Output::InPlace(_))
+    }
+    /// Prints a line about the edit, on stderr if the file itself is going
+    /// to stdout.
+    pub fn status(&self, line: impl std::fmt::Display) {
+        if matches!(self, Output::Stdout | Output::DataUrl(None)) {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+    }
+    /// Prints the edit's `--json` summary, on stderr if the file itself is
+    /// going to stdout.
+    pub fn json(&self, document: serde_json::Value) {
+        print_json(
+            document,
+            matches!(self, Output::Stdout | Output::DataUrl(None)),
+        );
+    }
+    /// Whether an edit that left the file `modified` or not can skip the
+    /// write, as it would go back over its own file unchanged. Says so if it can.
+    pub fn skips_unchanged(&self, modified: bool) -> bool {
+        match self {
+            Output::InPlace(file) if !modified => {
+                self.status(format_args!(
+                    "{}: no changes, file not rewritten",
+                    file.display()
+                ));
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Writes `png`, unless it would go back over its own file unchanged.
+    pub fn write_png(
+        &self,
+        png: &mut Png,
+        engine_options: &EngineOptions,
+        recording: Option<Recording>,
+    ) -> Result<()> {
+        timings::end_edit();
+        if self.skips_unchanged(png.is_modified()) {
+            return Ok(());
+        }
+        match self {
+            Output::InPlace(file) => write_edit(file, png, engine_options, recording)?,
+            _ => {
+                check_structure(png.structure_violations(), engine_options)?;
+                self.write_with(engine_options, |f| f.write_all(&png.as_bytes()))?
+            }
+        }
+        png.mark_clean();
+        Ok(())
+    }
+    /// Has `write` produce the file, written atomically unless it goes to
+    /// stdout, which is refused if it is a terminal.
+    pub fn write_with(
+        &self,
+        engine_options: &EngineOptions,
+        write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+    ) -> Result<()> {
+        timings::end_edit();
+        match self {
+            Output::InPlace(file) | Output::File(file) => {
+                write_output(file, engine_options, |f| write(f))
+            }
+            Output::Stdout => {
+                let mut stdout = io::stdout().lock();
+                if stdout.is_terminal() {
+                    return Err("Refusing to write a png to a terminal; redirect --output - to a file or pipe".into());
+                }
+                write(&mut stdout)?;
+                stdout.flush()?;
+                audit::written(None)
+            }
+            Output::DataUrl(path) => {
+                let mut png = Vec::new();
+                write(&mut png)?;
+                let url = data_url::encode(&png) + "\n";
+                match path {
+                    Some(path) => {
+                        write_output(path, engine_options, |f| f.write_all(url.as_bytes()))?
+                    }
+                    None => platform::write_stdout(url.as_bytes())?,
+                }
+                audit::written(None)
+            }
+        }
+    }
+}
+
+/// `engine::write_file`, saying which output it was writing when it failed
+/// and warning of anything about the file replaced that wasn't kept.
+pub fn write_output(
+    file: &Path,
+    engine_options: &EngineOptions,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> Result<()> {
+    let mut tally = audit::is_output(file).then(Tally::default);
+    let warnings = engine::write_file(file, engine_options, |f| match &mut tally {
+        Some(tally) => write(&mut audit::Tee(f, tally)),
+        None => write(f),
+    })
+    .with_context(|| format!("failed to write output \"{}\"", file.display()))?;
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+    if tally.is_some() {
+        audit::written(tally)?;
+    }
+    Ok(())
+}
+
+/// A file being edited. A lenient parse loads it whole to keep the stray
+/// bytes, as does an edit being recorded for undo; otherwise it is only
+/// indexed, and unchanged chunks are copied from the file when it is written.
+enum Editable {
+    Loaded(Png),
+    Indexed(Rewrite, File),
+}
+
+impl Editable {
+    fn from_file(mut f: File, options: &ParseOptions, load: bool) -> Result<Self> {
+        if options.lenient || load {
+            return Ok(Editable::Loaded(png_from_reader(f, options)?));
+        }
+        let rewrite = Rewrite::index(io::BufReader::new(&mut f), options)?;
+        timings::begin_edit();
+        Ok(Editable::Indexed(rewrite, f))
+    }
+    fn loaded(&self) -> Option<&Png> {
+        match self {
+            Editable::Loaded(png) => Some(png),
+            Editable::Indexed(..) => None,
+        }
+    }
+    fn chunk_count(&self) -> usize {
+        match self {
+            Editable::Loaded(png) => png.chunk_count(),
+            Editable::Indexed(rewrite, _) => rewrite.chunk_count(),
+        }
+    }
+    fn chunk_type(&self, index: usize) -> Option<ChunkType> {
+        match self {
+            Editable::Loaded(png) => png.chunks().get(index).map(|c| *c.chunk_type()),
+            Editable::Indexed(rewrite, _) => rewrite.chunk_type(index).copied(),
+        }
+    }
+    fn chunk_length(&self, index: usize) -> Option<u32> {
+        match self {
+            Editable::Loaded(png) => png.chunks().get(index).map(|c| c.length()),
+            Editable::Indexed(rewrite, _) => rewrite.chunk_length(index),
+        }
+    }
+    fn total_size(&self) -> usize {
+        match self {
+            Editable::Loaded(png) => png.total_size(),
+            Editable::Indexed(rewrite, _) => rewrite.total_size(),
+        }
+    }
+    /// The chunk at `index`, described for the summary of an edit that
+    /// removes it.
+    fn removal(&self, index: usize) -> Option<ChunkChange> {
+        Some(ChunkChange {
+            added: false,
+            index,
+            chunk_type: self.chunk_type(index)?,
+            length: self.chunk_length(index)?,
+            before_iend: false,
+        })
+    }
+    fn contains(&self, chunk_type: &ChunkType) -> bool {
+        (0..self.chunk_count()).any(|i| self.chunk_type(i).as_ref() == Some(chunk_type))
+    }
+    /// Adds `chunks` as `message::encode_chunks` does, describing where
+    /// each went for the summary.
+    fn encode(
+        &mut self,
+        chunks: Vec<Chunk>,
+        options: EncodeOptions,
+    ) -> std::result::Result<(Encoded, Vec<ChunkChange>), MessageError> {
+        let lengths: Vec<u32> = chunks.iter().map(Chunk::length).collect();
+        let encoded = match self {
+            Editable::Loaded(png) => message::encode_chunks(png, chunks, options),
+            Editable::Indexed(rewrite, _) => message::encode_chunks(rewrite, chunks, options),
+        }?;
+        let changes = encoded
+            .indices
+            .iter()
+            .zip(lengths)
+            .map(|(&index, length)| {
+                // With only new chunks between it and IEND
+                let next = (index + 1..self.chunk_count()).find(|i| !encoded.indices.contains(i));
+                ChunkChange {
+                    added: true,
+                    index,
+                    chunk_type: self.chunk_type(index).expect("an added chunk"),
+                    length,
+                    before_iend: next.and_then(|i| self.chunk_type(i)) == Some(ChunkType::IEND),
+                }
+            })
+            .collect();
+        Ok((encoded, changes))
+    }
+    fn remove_chunk_at(&mut self, index: usize) -> bool {
+        match self {
+            Editable::Loaded(png) => png.remove_chunk_at(index).is_some(),
+            Editable::Indexed(rewrite, _) => rewrite.remove_chunk_at(index).is_some(),
+        }
+    }
+    fn relocate_post_iend(&mut self) -> usize {
+        match self {
+            Editable::Loaded(png) => png.relocate_post_iend(),
+            Editable::Indexed(rewrite, _) => rewrite.relocate_post_iend(),
+        }
+    }
+    /// Writes the edited file, logging the edit if it is being recorded,
+    /// which needs it loaded. Either way an unchanged file isn't written
+    /// back over itself.
+    fn write(
+        self,
+        output: &Output,
+        engine_options: &EngineOptions,
+        recording: Option<Recording>,
+    ) -> Result<()> {
+        match self {
+            Editable::Loaded(mut png) => output.write_png(&mut png, engine_options, recording),
+            Editable::Indexed(rewrite, mut source) => {
+                assert!(recording.is_none(), "recorded edits are loaded");
+                timings::end_edit();
+                if output.skips_unchanged(rewrite.is_modified()) {
+                    return Ok(());
+                }
+                check_structure(rewrite.structure_violations(), engine_options)?;
+                output.write_with(engine_options, move |mut f| {
+                    rewrite.write_to(&mut source, &mut f)
+                })
+            }
+        }
+    }
+}
+
+/// A file to copy chunk data out of. A lenient parse loads it whole to
+/// skip stray bytes; otherwise it is only indexed and the data is read
+/// from the file when it is copied, so a huge payload is never held in
+/// memory.
+enum Payloads {
+    Loaded(Vec<u8>, Vec<(ChunkType, Range<usize>)>),
+    Indexed(Rewrite, File),
+}
+
+impl Payloads {
+    fn from_file(mut f: File, options: &ParseOptions) -> Result<Self> {
+        if options.lenient {
+            let mut buffer = Vec::new();
+            Timed(&mut f).read_to_end(&mut buffer)?;
+            return Payloads::from_bytes(buffer, options);
+        }
+        let rewrite = Rewrite::index(io::BufReader::new(&mut f), options)?;
+        Ok(Payloads::Indexed(rewrite, f))
+    }
+    /// The payloads of a png already in memory, such as one given as text.
+    fn from_bytes(buffer: Vec<u8>, options: &ParseOptions) -> Result<Self> {
+        let chunks = Png::parse_borrowed_with(&buffer, options)?
+            .iter()
+            .map(|c| {
+                let start = c.offset() + 8;
+                (*c.chunk_type(), start..start + c.data().len())
+            })
+            .collect();
+        Ok(Payloads::Loaded(buffer, chunks))
+    }
+    fn chunk_types(&self) -> Vec<ChunkType> {
+        match self {
+            Payloads::Loaded(_, chunks) => chunks.iter().map(|(t, _)| *t).collect(),
+            Payloads::Indexed(rewrite, _) => (0..rewrite.chunk_count())
+                .filter_map(|i| rewrite.chunk_type(i).copied())
+                .collect(),
+        }
+    }
+    /// The length of the chunk at `index`, which must be in range.
+    fn chunk_length(&self, index: usize) -> u32 {
+        match self {
+            Payloads::Loaded(_, chunks) => chunks[index].1.len() as u32,
+            Payloads::Indexed(rewrite, _) => rewrite
+                .chunk_length(index)
+                .expect("indexed chunks are in the file"),
+        }
+    }
+    /// The data of the chunk at `index`, which must be in range.
+    fn reader(&mut self, index: usize) -> io::Result<Box<dyn Read + '_>> {
+        match self {
+            Payloads::Loaded(buffer, chunks) => Ok(Box::new(&buffer[chunks[index].1.clone()])),
+            Payloads::Indexed(rewrite, f) => Ok(Box::new(
+                rewrite
+                    .chunk_data_reader(index, f)?
+                    .expect("indexed chunks are in the file"),
+            )),
+        }
+    }
+}
+
+/// `options` for a command that only looks at chunk types and sizes and
+/// so needn't compute CRCs. A lenient parse still does, as it tells chunks
+/// from junk by them.
+fn without_crc_checks(options: ParseOptions) -> ParseOptions {
+    ParseOptions {
+        ignore_crc: options.ignore_crc || !(options.lenient || options.fix_lengths),
+        ..options
+    }
+}
+
+/// The payload of the chunk at `index` with the transforms its envelope
+/// records undone, or `None` if it isn't in an envelope.
+fn unwrap_envelope(payloads: &mut Payloads, index: usize) -> Result<Option<Vec<u8>>> {
+    let mut head = Vec::new();
+    payloads
+        .reader(index)?
+        .take(transform::MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    if !transform::is_envelope(&head) {
+        return Ok(None);
+    }
+    let mut envelope = Vec::new();
+    payloads.reader(index)?.read_to_end(&mut envelope)?;
+    let payload = Registry::default().unwrap(&envelope).context(
+        "failed to undo the transforms in the envelope; --keep-envelope prints it as stored",
+    )?;
+    Ok(Some(payload))
+}
+
+/// The IDAT chunks, their compressed size and how the stream was
+/// compressed, for `info` and `print --data`.
+fn image_data_line(image_data: &ImageData, human_readable: bool) -> String {
+    let chunks = match image_data.chunks {
+        1 => "1 IDAT chunk".to_string(),
+        n => format!("{n} IDAT chunks"),
+    };
+    let header = match &image_data.header {
+        Ok(header) => header.to_string(),
+        Err(e) => e.to_string(),
+    };
+    format!(
+        "{chunks}, {} compressed, {header}",
+        format_size(image_data.compressed, human_readable)
+    )
+}
+
+/// What decode prints: `unwrapped` if there is one, otherwise the chunk's
+/// data as stored.
+fn payload_reader<'a>(
+    payloads: &'a mut Payloads,
+    index: usize,
+    unwrapped: &'a Option<Vec<u8>>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    match unwrapped {
+        Some(data) => Ok(Box::new(data.as_slice())),
+        None => payloads.reader(index),
     }
-    Ok(Png::try_from(buffer.as_slice())?)
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Whether everything `reader` yields is UTF-8, read a buffer at a time.
+fn is_utf8(mut reader: impl Read) -> io::Result<bool> {
+    let mut buffer = vec![0; 64 * 1024];
+    // Bytes of a character split across two reads, moved to the front
+    let mut carry = 0;
+    loop {
+        let read = reader.read(&mut buffer[carry..])?;
+        if read == 0 {
+            return Ok(carry == 0);
+        }
+        let end = carry + read;
+        match std::str::from_utf8(&buffer[..end]) {
+            Ok(_) => carry = 0,
+            Err(e) if e.error_len().is_none() => {
+                buffer.copy_within(e.valid_up_to()..end, 0);
+                carry = end - e.valid_up_to();
+            }
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+/// Prints decoded text. When stdout is a terminal, control characters in
+/// it are escaped so they can't act on the terminal, unless `--no-sanitize`;
+/// piped output is left as it is.
+struct TextOut {
+    sanitize: bool,
+    escaped: usize,
+    limit: OutputLimit,
+}
+
+impl TextOut {
+    fn new(no_sanitize: bool, max_output_bytes: Option<u64>) -> Self {
+        Self {
+            sanitize: !no_sanitize && io::stdout().is_terminal(),
+            escaped: 0,
+            limit: OutputLimit::new(max_output_bytes),
+        }
+    }
+    fn println(&mut self, text: &str) {
+        let text = match self.sanitize {
+            true => {
+                let (text, escaped) = sanitize::sanitize(text);
+                self.escaped += escaped;
+                Cow::Owned(text)
+            }
+            false => Cow::Borrowed(text),
+        };
+        let part = self.limit.take(text.as_bytes(), true);
+        if !part.is_empty() || (text.is_empty() && !self.limit.is_truncated()) {
+            println!("{}", String::from_utf8_lossy(part));
+        }
+    }
+    /// Says how many characters were escaped, if any, and whether the
+    /// output was cut off.
+    fn finish(self) -> Result<()> {
+        if self.escaped > 0 {
+            eprintln!(
+                "Note: escaped {} control characters, pass --no-sanitize to print them as they are",
+                self.escaped
+            );
+        }
+        self.limit.finish()
+    }
+}
+
+/// `survivability` as text: a heading line, then a line per tool.
+fn advice_lines(report: &SurvivabilityReport) -> Vec<String> {
+    let mut lines = vec![format!("{} {}:", report.chunk_type, report.position)];
+    for verdict in &report.verdicts {
+        let reason = verdict
+            .reason
+            .map(|r| format!(" - {r}"))
+            .unwrap_or_default();
+        lines.push(format!(
+            "  {}: {}{reason}",
+            verdict.tool,
+            verdict.fate.as_str()
+        ));
+    }
+    lines
+}
+
+fn main() {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if args.input_format == FileFormat::Base64 {
+        input::read_base64();
+    }
+    if args.version_json {
+        // `exclusive` only covers arguments, not a subcommand after it
+        if let Some(name) = matches.subcommand_name() {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("the argument '--version-json' cannot be used with '{name}'"),
+                )
+                .exit()
+        }
+        println!("{}", version::version_json());
+        return;
+    }
+    let json = args.json;
+    let started = args.timings.then(|| {
+        timings::enable();
+        Instant::now()
+    });
+    let result = match batch_files(&mut args) {
+        Ok(Some(files)) => run_batch(&matches, &args, &files),
+        Ok(None) => match context::describe(&matches, None) {
+            Some(doing) => run_checked(args).context(doing),
+            None => run_checked(args),
+        },
+        Err(e) => Err(e),
+    };
+    if let Some(started) = started {
+        print_timings(started, json);
+    }
+    if let Err(e) = result {
+        if !context::is_reported(e.as_ref()) {
+            eprintln!("{}", context::report(e.as_ref()));
+        }
+        exit(context::exit_code(e.as_ref()))
+    }
+}
+
+thread_local! {
+    /// With `--timings`, the last JSON document the command printed, held
+    /// back for `print_timings` to add to, and whether it goes to stderr.
+    static HELD_JSON: RefCell<Option<(serde_json::Value, bool)>> = const { RefCell::new(None) };
+}
+
+/// Prints a command's `--json` output, on stderr if `to_stderr`. With
+/// `--timings` the last document is held back until `print_timings` adds
+/// the timings to it.
+pub fn print_json(document: serde_json::Value, to_stderr: bool) {
+    let document = match timings::is_enabled() {
+        true => HELD_JSON.replace(Some((document, to_stderr))),
+        false => Some((document, to_stderr)),
+    };
+    if let Some((document, to_stderr)) = document {
+        emit_json(&document, to_stderr);
+    }
+}
+
+fn emit_json(document: &serde_json::Value, to_stderr: bool) {
+    match to_stderr {
+        true => eprintln!("{document}"),
+        false => println!("{document}"),
+    }
+}
+
+/// `--timings`. With `--json` the timings go in a `timings` field of the
+/// command's output, which is wrapped as `{"output": ...}` first if it
+/// isn't an object, or on their own on stderr if there is no output.
+fn print_timings(started: Instant, json: bool) {
+    let total = started.elapsed();
+    let t = timings::take();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    if json {
+        let timings = serde_json::json!({
+            "read_ms": ms(t.read),
+            "signature_ms": ms(t.signature),
+            "parse_ms": ms(t.parse),
+            "chunks": t.chunks,
+            "crc_ms": ms(t.crc),
+            "mutation_ms": t.mutation.map(ms),
+            "serialize_ms": ms(t.serialize),
+            "write_ms": ms(t.write),
+            "total_ms": ms(total),
+        });
+        let (mut document, to_stderr) = match HELD_JSON.take() {
+            Some((serde_json::Value::Object(document), to_stderr)) => (document, to_stderr),
+            Some((output, to_stderr)) => (
+                serde_json::Map::from_iter([("output".into(), output)]),
+                to_stderr,
+            ),
+            None => (serde_json::Map::new(), true),
+        };
+        document.insert("timings".into(), timings);
+        emit_json(&document.into(), to_stderr);
+        return;
+    }
+    let mutation = match t.mutation {
+        Some(mutation) => format!("{:>10.3}", ms(mutation)),
+        None => format!("{:>10}", "-"),
+    };
+    eprintln!("Timings (ms):");
+    eprintln!("  read       {:>10.3}", ms(t.read));
+    eprintln!("  signature  {:>10.3}", ms(t.signature));
+    eprintln!("  parse      {:>10.3}  ({} chunks)", ms(t.parse), t.chunks);
+    eprintln!("  crc        {:>10.3}", ms(t.crc));
+    eprintln!("  mutation   {mutation}");
+    eprintln!("  serialize  {:>10.3}", ms(t.serialize));
+    eprintln!("  write      {:>10.3}", ms(t.write));
+    eprintln!("  total      {:>10.3}", ms(total));
+}
+
+/// With `--files-from`, or on Windows a file argument with wildcards,
+/// takes the file argument out of `args` and returns it followed by the
+/// listed files, with wildcards expanded on Windows as a shell would have.
+fn batch_files(args: &mut Args) -> Result<Option<Vec<PathBuf>>> {
+    if let Some(command) = args.command.as_mut() {
+        command.unbind_file()?;
+    }
+    let Some((file, batch)) = args.command.as_mut().and_then(Commands::batch) else {
+        return Ok(None);
+    };
+    let expand = cfg!(windows);
+    if batch.files_from.is_none()
+        && !(expand && file.as_deref().is_some_and(platform::has_wildcard))
+    {
+        return Ok(None);
+    }
+    let mut files: Vec<PathBuf> = file.take().into_iter().collect();
+    if let Some(list) = &batch.files_from {
+        let mut bytes = Vec::new();
+        if list == Path::new("-") {
+            io::stdin().lock().read_to_end(&mut bytes)?;
+        } else {
+            bytes =
+                fs::read(list).with_context(|| format!("failed to read \"{}\"", list.display()))?;
+        }
+        files.extend(args::parse_file_list(&bytes, batch.null));
+    }
+    if expand {
+        files = platform::expand_wildcards(files);
+    }
+    Ok(Some(files))
+}
+
+/// Runs the command once per file, reporting each failure and carrying on.
+fn run_batch(matches: &ArgMatches, args: &Args, files: &[PathBuf]) -> Result<()> {
+    let mut failed = 0;
+    for file in files {
+        let result = try_open_png_file(file).map_err(Error::from).and_then(|_| {
+            let mut args = args.clone();
+            let (slot, batch) = args
+                .command
+                .as_mut()
+                .and_then(Commands::batch)
+                .expect("a batch command");
+            *slot = Some(file.clone());
+            batch.labelled = true;
+            let doing = context::describe(matches, Some(file)).expect("a subcommand");
+            run_checked(args).context(doing)
+        });
+        if let Err(e) = result {
+            if !context::is_reported(e.as_ref()) {
+                eprintln!("{}", context::report(e.as_ref()));
+            }
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        eprintln!("{failed} of {} files failed", files.len());
+        return Err(context::Reported.into());
+    }
+    Ok(())
+}
+
+/// `run`, and for a command that edits a png, appending the edit to the
+/// audit log and checking the render of what it wrote, if asked to.
+fn run_checked(args: Args) -> Result<()> {
+    #[cfg(feature = "render-check")]
+    let verify_render = args.verify_render;
+    #[cfg(not(feature = "render-check"))]
+    let verify_render = false;
+    let Some(edit) = args.command.as_ref().and_then(Commands::edited) else {
+        return run(args);
+    };
+    if args.audit_log.is_none() && !verify_render {
+        return run(args);
+    }
+    let command = edit.command;
+    let input = edit.input.clone();
+    // None for stdout, or a data URL, which isn't a png to check
+    let written = match edit.output {
+        _ if args.output_format == FileFormat::Base64 => None,
+        Some(output) if output == Path::new("-") => None,
+        output => Some(output.unwrap_or(&input).clone()),
+    };
+    let best_effort = args.audit_best_effort;
+    let audit_log = match args.audit_log.as_deref().map(AuditLog::open) {
+        Some(Err(e)) if best_effort => {
+            eprintln!("Warning: {}", context::one_line(e.as_ref()));
+            None
+        }
+        log => log.transpose()?,
+    };
+    // Logged as the output is written, while the input is still locked
+    let _logging =
+        audit_log.map(|log| audit::begin(log, best_effort, command, &input, written.as_deref()));
+    #[cfg(feature = "render-check")]
+    if verify_render {
+        return run_verifying_render(args, &input, written.as_deref());
+    }
+    run(args)
+}
+
+/// `run`, then checking that the png written to `written`, or to stdout
+/// for `None`, shows the same image as `input` did.
+#[cfg(feature = "render-check")]
+fn run_verifying_render(args: Args, input: &Path, written: Option<&Path>) -> Result<()> {
+    // An input that can't be read is left for the command to report
+    let Ok(original) = fs::read(input) else {
+        return run(args);
+    };
+    // What was at another output before, to tell whether the command wrote it
+    let previous = written.filter(|path| *path != input).map(fs::read);
+    run(args)?;
+    let Some(path) = written else {
+        eprintln!("Note: --verify-render can't check a png written to stdout");
+        return Ok(());
+    };
+    let Ok(edited) = fs::read(path) else {
+        // Nothing was written, as with --dry-run and --output
+        return Ok(());
+    };
+    let unchanged = match previous {
+        Some(previous) => previous.is_ok_and(|previous| previous == edited),
+        None => edited == original,
+    };
+    if unchanged {
+        return Ok(());
+    }
+    verify_render_of(path, &original, &edited)
+}
+
+/// Decodes the file as it was and as written to `path` and fails if they
+/// don't show the same image.
+#[cfg(feature = "render-check")]
+fn verify_render_of(path: &Path, original: &[u8], edited: &[u8]) -> Result<()> {
+    use pngme::render::{self, RenderError, Side};
+
+    match render::compare(original, edited) {
+        Ok(()) => Ok(()),
+        Err(RenderError::Decode {
+            side: Side::Original,
+            source,
+        }) => {
+            eprintln!("Note: --verify-render skipped, the original doesn't decode: {source}");
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| {
+            format!(
+                "--verify-render: \"{}\" shows a different image than before the edit",
+                path.display()
+            )
+        }),
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let options = ParseOptions {
+        lenient: args.lenient,
+        max_chunk_size: args.max_chunk_size,
+        max_file_size: args.max_file_size.unwrap_or(u64::MAX),
+        max_chunks: args.max_chunks,
+        max_total_decompressed: args.max_decompressed.unwrap_or(u64::MAX),
+        ..Default::default()
+    };
+    let engine_options = &EngineOptions {
+        backup: args.backup.clone(),
+        lenient: args.lenient,
+        follow_symlinks: !args.no_follow_symlinks,
+        preserve_timestamps: args.preserve_timestamps,
+        allow_invalid: args.allow_invalid,
+        ..Default::default()
+    };
+    let lock_timeout = Duration::from_secs(args.lock_timeout);
+    let undo_log = args.undo_log.as_deref().map(UndoLog::new);
+    let record = |command, before: &Png| {
+        undo_log.as_ref().map(|log| Recording {
+            log,
+            command,
+            before: before.clone(),
+        })
+    };
+    let size = |bytes: usize| format_size(bytes as u64, args.human_readable);
+    // With --relocate-post-iend, moves chunks after IEND ahead of it just
+    // before an edit is written, so indices shown earlier still hold
+    let relocate = |moved: usize, output: &Output| {
+        if moved > 0 && !args.quiet && !args.json {
+            status!(output, "Moved {moved} chunks from after IEND to before it");
+        }
+        moved
+    };
+    // Changing a critical chunk can invalidate unknown chunks that aren't
+    // marked safe to copy, which the spec has an editor drop
+    let drop_unsafe_to_copy = |png: &mut Png, changed: &ChunkType| {
+        if changed.is_critical() {
+            for chunk in png.drop_unsafe_to_copy() {
+                eprintln!(
+                    "Warning: dropped {}, which isn't safe to copy once {changed} changes",
+                    chunk.chunk_type()
+                );
+            }
+        }
+    };
     match args.command {
         Some(val) => match val {
+            Commands::Init {
+                file,
+                width,
+                height,
+                color,
+                fill,
+                force,
+            } => {
+                if !force && file.exists() {
+                    return Err(format!(
+                        "{} already exists, pass --force to overwrite it",
+                        file.display()
+                    )
+                    .into());
+                }
+                let mut builder = PngBuilder::new(width, height).color_type(color);
+                if let Some(fill) = fill {
+                    builder = builder.fill(&fill);
+                }
+                write_png_file(&file, &builder.build()?, engine_options)?;
+            }
             Commands::Encode {
                 file,
                 chunktype,
                 message,
                 output_path,
+                extra,
+                output,
+                chunks,
+                kv,
+                max_growth,
+                force,
+                corrupt_crc,
+                redundant,
+                types,
+                type_hex,
+                conventional,
+                transforms,
+                auto,
+                input_encoding,
+                template,
+                allow_missing,
+                verbose,
+                batch,
+            } => {
+                let file = file.expect("clap requires a file without --files-from");
+                // Each line of the summary says which file it's about
+                let label = match batch.labelled {
+                    true => format!("{}: ", file.display()),
+                    false => String::new(),
+                };
+                // With --redundant or --type-hex the message comes first, so
+                // there's one positional argument fewer
+                let message_only = redundant.is_some() || type_hex.is_some();
+                if !extra.is_empty() || (message_only && message.is_some()) {
+                    let first = if message_only { &chunktype } else { &message };
+                    let words: Vec<String> =
+                        [first.clone(), message.clone().filter(|_| message_only)]
+                            .into_iter()
+                            .flatten()
+                            .chain(output_path.iter().map(|p| p.display().to_string()))
+                            .chain(extra)
+                            .collect();
+                    return Err(format!(
+                        "Too many arguments: the message must be one argument, so quote it, as in \"{}\", and give the output with -o",
+                        words.join(" ")
+                    )
+                    .into());
+                }
+                if let Some(path) = &output_path {
+                    eprintln!(
+                        "Warning: giving the output after the message is deprecated; use -o \"{0}\". If \"{0}\" is part of the message, quote the whole message",
+                        path.display()
+                    );
+                }
+                let options = ParseOptions {
+                    any_chunk_type: type_hex.is_some(),
+                    ..options
+                };
+                // Read before the edit, so the placeholders describe the
+                // file as it was
+                let env = |name: &str| std::env::var(name).ok();
+                let original = if template {
+                    let bytes = fs::read(&file)
+                        .with_context(|| format!("failed to read \"{}\"", file.display()))?;
+                    Some((FileFacts::new(&file, &bytes), message_template::now(&env)?))
+                } else {
+                    None
+                };
+                let decode = |chunk_type: Option<ChunkType>, message: String| {
+                    let describe = |e: &dyn std::fmt::Display| match chunk_type {
+                        Some(chunk_type) => format!("The message for {chunk_type}: {e}"),
+                        None => format!("The message: {e}"),
+                    };
+                    let message = match &original {
+                        Some((file, now)) => {
+                            let values = message_template::Values {
+                                file,
+                                now: *now,
+                                env: &env,
+                                allow_missing,
+                            };
+                            message_template::expand(&message, &values).map_err(|e| describe(&e))?
+                        }
+                        None => message,
+                    };
+                    input_encoding.decode(message).map_err(|e| describe(&e))
+                };
+                let mut new_chunks = Vec::new();
+                if let Some(copies) = redundant {
+                    // With --redundant the only positional argument is the message
+                    let (Some(message), None) = (&chunktype, &message) else {
+                        return Err(
+                            "With --redundant give only the message, and --type for the chunk types"
+                                .into(),
+                        );
+                    };
+                    if let Some(duplicate) = types
+                        .iter()
+                        .enumerate()
+                        .find_map(|(i, t)| types[..i].contains(t).then_some(t))
+                    {
+                        return Err(format!(
+                            "--type {duplicate} is given more than once, and each copy needs a type of its own"
+                        )
+                        .into());
+                    }
+                    let types = if types.is_empty() {
+                        redundant::default_types(copies as usize)?
+                    } else if types.len() == copies as usize {
+                        types
+                    } else {
+                        return Err(format!(
+                            "--redundant {copies} needs {copies} --type values, not {}",
+                            types.len()
+                        )
+                        .into());
+                    };
+                    let message = decode(None, message.clone())?;
+                    new_chunks.extend(redundant::encode(&message, &types)?);
+                } else if let Some(chunk_type) = type_hex {
+                    // As with --redundant, the only positional argument is the message
+                    let (Some(message), None) = (chunktype, &message) else {
+                        return Err("With --type-hex give only the message".into());
+                    };
+                    new_chunks.push(Chunk::new(chunk_type, decode(Some(chunk_type), message)?));
+                } else {
+                    match (chunktype, message) {
+                        (Some(chunktype), Some(message)) => {
+                            let chunk_type = ChunkType::from_str(&chunktype)?;
+                            new_chunks
+                                .push(Chunk::new(chunk_type, decode(Some(chunk_type), message)?));
+                        }
+                        (None, None) if !chunks.is_empty() || !kv.is_empty() => {}
+                        _ => {
+                            return Err(
+                                "Give the chunk type and the message, or the chunks with --chunk or --kv"
+                                    .into(),
+                            );
+                        }
+                    }
+                }
+                for (chunk_type, message) in chunks.into_iter().chain(kv) {
+                    new_chunks.push(Chunk::new(chunk_type, decode(Some(chunk_type), message)?));
+                }
+                // What --auto chose for each new chunk
+                let mut choices = Vec::new();
+                if !transforms.is_empty() || auto {
+                    let registry = Registry::default();
+                    let chain = transforms
+                        .iter()
+                        .map(|name| registry.id(name))
+                        .collect::<std::result::Result<Vec<u8>, _>>()?;
+                    if auto && chain.contains(&transform::DEFLATE) {
+                        return Err(
+                            "--auto decides whether to deflate, so don't also pass --transform deflate"
+                                .into(),
+                        );
+                    }
+                    for chunk in &mut new_chunks {
+                        let data = if auto {
+                            let wrapped = registry.wrap_auto(chunk.data(), &chain)?;
+                            let envelope = wrapped.envelope.clone();
+                            choices.push((*chunk.chunk_type(), wrapped));
+                            envelope
+                        } else {
+                            registry.wrap(chunk.data(), &chain)?
+                        };
+                        *chunk = Chunk::new(*chunk.chunk_type(), data);
+                    }
+                }
+                if let Some(corruption) = corrupt_crc {
+                    for chunk in &mut new_chunks {
+                        let wrong = corruption.apply(chunk.crc());
+                        if wrong == chunk.crc() {
+                            return Err(format!(
+                                "--corrupt-crc {} is the correct CRC for {}",
+                                format_crc(wrong),
+                                chunk.chunk_type()
+                            )
+                            .into());
+                        }
+                        eprintln!(
+                            "Warning: writing {} with a deliberately wrong CRC {} (correct is {})",
+                            chunk.chunk_type(),
+                            format_crc(wrong),
+                            format_crc(chunk.crc())
+                        );
+                        *chunk = Chunk::with_crc(*chunk.chunk_type(), chunk.data().to_vec(), wrong);
+                    }
+                }
+                let output = Output::resolve(&file, output.or(output_path), args.output_format);
+                let lock = lock_png_file(&file, lock_timeout)?;
+                let mut png = Editable::from_file(
+                    lock.try_clone()?,
+                    &options,
+                    undo_log.is_some() && output.in_place(),
+                )?;
+                let recording = png.loaded().and_then(|before| record("encode", before));
+                let encode_options = EncodeOptions {
+                    conventional,
+                    force,
+                    max_growth,
+                    ..EncodeOptions::default()
+                };
+                let (encoded, changes) = png.encode(new_chunks, encode_options).map_err(|e| {
+                    match e {
+                        MessageError::NotUnique(_) => {
+                            format!("{e}; pass --force to add another anyway")
+                        }
+                        MessageError::TooMuchGrowth { growth, max_growth } => format!(
+                            "The file would grow by {growth:.1}%, above --max-growth {max_growth}%; pass --force to encode anyway"
+                        ),
+                        e => e.to_string(),
+                    }
+                })?;
+                for duplicate in &encoded.duplicates {
+                    eprintln!(
+                        "Warning: adding a second {}, though {duplicate}",
+                        duplicate.chunk_type
+                    );
+                }
+                let report = encoded.report;
+                let growth = report.growth_percent();
+                if growth > GROWTH_WARNING_PERCENT {
+                    eprintln!(
+                        "Warning: the new chunks grow the file by {growth:.1}%, which makes the payload conspicuous"
+                    );
+                }
+                if args.json {
+                    let changes: Vec<_> = changes.iter().map(|c| c.to_json()).collect();
+                    let choices: Vec<_> = choices
+                        .iter()
+                        .map(|(chunk_type, wrapped)| {
+                            serde_json::json!({
+                                "chunk_type": chunk_type.to_string(),
+                                "compressed": wrapped.compressed,
+                                "uncompressed": wrapped.plain_len,
+                                "deflated": wrapped.deflated_len,
+                            })
+                        })
+                        .collect();
+                    output.json(serde_json::json!({
+                        "changes": changes,
+                        "auto": choices,
+                        "before": report.before,
+                        "after": report.after,
+                        "payload": report.payload,
+                        "growth_percent": growth,
+                        "payload_percent": report.payload_percent(),
+                    }));
+                } else if !args.quiet {
+                    for change in &changes {
+                        status!(output, "{label}{}", change.line(size));
+                    }
+                    for (chunk_type, wrapped) in &choices {
+                        let saved = wrapped.saved();
+                        if wrapped.compressed {
+                            status!(
+                                output,
+                                "{label}Compressed {chunk_type}: {} -> {} (saved {})",
+                                size(wrapped.plain_len),
+                                size(wrapped.deflated_len),
+                                size(saved as usize)
+                            );
+                        } else if saved > 0 {
+                            status!(
+                                output,
+                                "{label}Stored {chunk_type} uncompressed: compressing would save only {}",
+                                size(saved as usize)
+                            );
+                        } else {
+                            status!(
+                                output,
+                                "{label}Stored {chunk_type} uncompressed: compressing doesn't shrink it"
+                            );
+                        }
+                    }
+                    if verbose {
+                        for change in &changes {
+                            // After IDAT if there is one before the chunk
+                            let position = if (0..change.index)
+                                .any(|i| png.chunk_type(i) == Some(ChunkType::IDAT))
+                            {
+                                Position::AfterIdat
+                            } else {
+                                Position::BeforeIdat
+                            };
+                            let report = survival::survivability(&change.chunk_type, position);
+                            for line in advice_lines(&report) {
+                                status!(output, "{label}{line}");
+                            }
+                        }
+                    }
+                    status!(
+                        output,
+                        "{label}Size: {} -> {} (+{growth:.1}%), payload is {:.1}% of the file",
+                        size(report.before),
+                        size(report.after),
+                        report.payload_percent()
+                    );
+                }
+                if args.relocate_post_iend {
+                    relocate(png.relocate_post_iend(), &output);
+                }
+                png.write(&output, engine_options, recording)?;
+            }
+            Commands::Decode {
+                file,
+                chunktype: None,
+                raw,
+                keyword: Some(keyword),
+                nth,
+                ignore_case,
+                pretty,
+                no_sanitize,
+                output_encoding,
+                max_output_bytes,
+                ..
+            } => {
+                let png = png_from_file(&file, &options)?;
+                let mut entries = text::find_text(&png, &keyword, ignore_case);
+                if entries.is_empty() {
+                    return Err(DecodeError::Missing(keyword).into());
+                }
+                for entry in &mut entries {
+                    let value =
+                        output_encoding.encode(std::mem::take(&mut entry.value).into_bytes());
+                    entry.value = String::from_utf8(value).expect("encoded text is ASCII");
+                }
+                if let Some(nth) = nth {
+                    let count = entries.len();
+                    let Some(entry) = entries.into_iter().nth(nth as usize - 1) else {
+                        return Err(format!(
+                            "--nth {nth} is out of range, {keyword} has {count} entries"
+                        )
+                        .into());
+                    };
+                    entries = vec![entry];
+                }
+                if raw {
+                    if entries.len() > 1 {
+                        return Err(format!(
+                            "{keyword} has {} entries, pick one with --nth to use --raw",
+                            entries.len()
+                        )
+                        .into());
+                    }
+                    let mut limit = OutputLimit::new(max_output_bytes);
+                    platform::write_stdout(limit.take(entries[0].value.as_bytes(), false))?;
+                    limit.finish()?;
+                } else {
+                    let mut out = TextOut::new(no_sanitize, max_output_bytes);
+                    for entry in entries {
+                        match preview::pretty(entry.value.as_bytes()).filter(|_| pretty) {
+                            Some(pretty) => out.println(&pretty),
+                            None => out.println(&entry.value),
+                        }
+                    }
+                    out.finish()?;
+                }
+            }
+            Commands::Decode {
+                file,
+                raw,
+                redundant: true,
+                pretty,
+                no_sanitize,
+                output_encoding,
+                max_output_bytes,
+                ..
+            } => {
+                let png = png_from_file(&file, &options)?;
+                let mut recovered = redundant::decode(&png)?;
+                recovered.payload = output_encoding.encode(recovered.payload);
+                if recovered.surviving < recovered.written {
+                    eprintln!(
+                        "Warning: only {}/{} redundant copies survive",
+                        recovered.surviving, recovered.written
+                    );
+                }
+                if raw {
+                    let mut limit = OutputLimit::new(max_output_bytes);
+                    platform::write_stdout(limit.take(&recovered.payload, false))?;
+                    limit.finish()?;
+                } else {
+                    let mut out = TextOut::new(no_sanitize, max_output_bytes);
+                    match preview::pretty(&recovered.payload).filter(|_| pretty) {
+                        Some(pretty) => out.println(&pretty),
+                        None => out.println(&String::from_utf8_lossy(&recovered.payload)),
+                    }
+                    out.finish()?;
+                }
+            }
+            Commands::Decode {
+                file,
+                chunktype,
+                type_hex,
+                select,
+                raw,
+                output,
+                pretty,
+                no_sanitize,
+                keep_envelope,
+                auto_inflate,
+                max_inflate,
+                output_encoding,
+                max_output_bytes,
+                ..
+            } => {
+                let options = ParseOptions {
+                    any_chunk_type: type_hex.is_some(),
+                    ..options
+                };
+                let chunktype = chunktype.or(type_hex);
+                let mut payloads = match input::read_text(&file)? {
+                    Some(png) => Payloads::from_bytes(png, &options)?,
+                    None => Payloads::from_file(open_png_file(&file)?, &options)?,
+                };
+                let types = payloads.chunk_types();
+                let iend = types.iter().position(|t| *t == ChunkType::IEND);
+                let found = match (&select, &chunktype) {
+                    (Some(selector), _) => (0..types.len())
+                        .find(|&i| selector.matches(i, &types[i], payloads.chunk_length(i))),
+                    (None, Some(chunk_type)) => types.iter().position(|t| t == chunk_type),
+                    (None, None) => unreachable!("clap requires a chunk type without --keyword"),
+                };
+                let Some(found) = found else {
+                    let missing = match chunktype {
+                        Some(chunk_type) => chunk_type.to_string(),
+                        None => "a chunk matching --select".to_string(),
+                    };
+                    return Err(DecodeError::Missing(missing).into());
+                };
+                let chunktype = types[found].to_string();
+                if iend.is_some_and(|iend| found > iend) {
+                    eprintln!("Note: {chunktype} was found after IEND, where decoders ignore it");
+                }
+                let mut unwrapped = if keep_envelope {
+                    None
+                } else {
+                    unwrap_envelope(&mut payloads, found)?
+                };
+                if auto_inflate {
+                    // Two bytes tell zlib and gzip apart from everything
+                    // else, so other payloads needn't be read in full
+                    let mut head = Vec::new();
+                    payload_reader(&mut payloads, found, &unwrapped)?
+                        .take(2)
+                        .read_to_end(&mut head)?;
+                    if Magic::detect(&head).is_some() {
+                        let mut data = Vec::new();
+                        payload_reader(&mut payloads, found, &unwrapped)?.read_to_end(&mut data)?;
+                        match analysis::inflate(&data, max_inflate) {
+                            Some((magic, Ok(inflated))) => {
+                                eprintln!(
+                                    "Note: inflated {chunktype} from a {} of {} to {}",
+                                    magic.name(),
+                                    size(data.len()),
+                                    size(inflated.len())
+                                );
+                                unwrapped = Some(inflated);
+                            }
+                            Some((magic, Err(e))) => eprintln!(
+                                "Warning: {chunktype} looks like a {} but {e}; printing it as stored",
+                                magic.name()
+                            ),
+                            None => {}
+                        }
+                    }
+                }
+                if output_encoding != PayloadEncoding::Raw {
+                    // Encoded, the payload is text whatever it holds
+                    let mut data = Vec::new();
+                    payload_reader(&mut payloads, found, &unwrapped)?.read_to_end(&mut data)?;
+                    unwrapped = Some(output_encoding.encode(data));
+                }
+                // The Windows console only takes UTF-8, so check before
+                // writing anything there rather than fail partway through
+                let console = cfg!(windows) && io::stdout().is_terminal();
+                if output.is_none()
+                    && (!raw || console)
+                    && !is_utf8(payload_reader(&mut payloads, found, &unwrapped)?)?
+                {
+                    if !raw {
+                        return Err(DecodeError::NotUtf8 {
+                            chunk_type: chunktype,
+                            len: match &unwrapped {
+                                Some(data) => data.len() as u64,
+                                None => payloads.chunk_length(found).into(),
+                            },
+                        }
+                        .into());
+                    }
+                    return Err(
+                        "Refusing to write binary data to the console, redirect it to a file"
+                            .into(),
+                    );
+                }
+                let length = match &unwrapped {
+                    Some(data) => data.len() as u64,
+                    None => u64::from(payloads.chunk_length(found)),
+                };
+                let mut reader = payload_reader(&mut payloads, found, &unwrapped)?;
+                let mut out = TextOut::new(no_sanitize, max_output_bytes);
+                if pretty || (!raw && output.is_none() && out.sanitize) {
+                    // Text for a terminal is small enough to hold at once
+                    let mut data = Vec::new();
+                    reader.read_to_end(&mut data)?;
+                    match preview::pretty(&data).filter(|_| pretty) {
+                        Some(pretty) => out.println(&pretty),
+                        None => out.println(&String::from_utf8_lossy(&data)),
+                    }
+                    out.finish()?;
+                } else if let Some(path) = output {
+                    // Not limited: a file doesn't end up in a log
+                    write_output(&path, engine_options, |f| {
+                        io::copy(&mut reader, f).map(drop)
+                    })?;
+                } else if let Some(room) = out.limit.remaining() {
+                    // Only what can be printed is read, plus enough to find
+                    // where the last whole character ends
+                    let mut data = Vec::new();
+                    reader.take(room + 4).read_to_end(&mut data)?;
+                    let mut limit = out.limit;
+                    let part = limit.take(&data, !raw);
+                    limit.skip(length - data.len() as u64);
+                    let mut stdout = io::stdout().lock();
+                    stdout.write_all(part)?;
+                    if !raw && !part.is_empty() {
+                        stdout.write_all(b"\n")?;
+                    }
+                    stdout.flush()?;
+                    limit.finish()?;
+                } else {
+                    let mut stdout = io::BufWriter::with_capacity(64 * 1024, io::stdout().lock());
+                    io::copy(&mut reader, &mut stdout)?;
+                    if !raw {
+                        stdout.write_all(b"\n")?;
+                    }
+                    stdout.flush()?;
+                }
+            }
+            Commands::Remove {
+                file,
+                chunktype,
+                type_hex,
+                index,
+                force,
+                redundant,
+                types_file,
+                select,
+                output,
+            } => {
+                let options = ParseOptions {
+                    any_chunk_type: type_hex.is_some(),
+                    ..options
+                };
+                let chunktype = chunktype.or(type_hex);
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let types = match &types_file {
+                    Some(list) => {
+                        let text = fs::read_to_string(list)
+                            .map_err(|e| format!("{}: {e}", list.display()))?;
+                        let types = parse_type_list(&text).map_err(|errors| {
+                            let lines: Vec<_> = errors
+                                .iter()
+                                .map(|error| format!("{}: {error}", list.display()))
+                                .collect();
+                            lines.join("\n")
+                        })?;
+                        if let Some(critical) = types.iter().find(|t| t.is_critical())
+                            && !force
+                        {
+                            return Err(
+                                format!("Refusing to remove {critical} without --force").into()
+                            );
+                        }
+                        types
+                    }
+                    None => Vec::new(),
+                };
+                let lock = lock_png_file(&file, lock_timeout)?;
+                // Copies are recognized by their contents, so those must be loaded
+                let load = (undo_log.is_some() && output.in_place()) || redundant;
+                let mut png = Editable::from_file(lock.try_clone()?, &options, load)?;
+                let recording = png.loaded().and_then(|before| record("remove", before));
+                // Positions of the chunks to remove, in order
+                let mut indices = Vec::new();
+                if redundant {
+                    let loaded = png.loaded().expect("loaded above");
+                    indices.extend(
+                        (0..loaded.chunk_count())
+                            .filter(|&i| redundant::is_copy(&loaded.chunks()[i])),
+                    );
+                    if indices.is_empty() {
+                        return Err(redundant::RedundantError::NoCopies.into());
+                    }
+                } else if let Some(index) = index {
+                    let Some(chunk_type) = png.chunk_type(index) else {
+                        return Err(format!(
+                            "Index {index} is out of range, the png has {} chunks",
+                            png.chunk_count()
+                        )
+                        .into());
+                    };
+                    if !force && [ChunkType::IHDR, ChunkType::IEND].contains(&chunk_type) {
+                        return Err(
+                            format!("Refusing to remove {chunk_type} without --force").into()
+                        );
+                    }
+                    indices.push(index);
+                } else if types_file.is_some() {
+                    indices.extend((0..png.chunk_count()).filter(|&i| {
+                        png.chunk_type(i)
+                            .is_some_and(|chunk_type| types.contains(&chunk_type))
+                    }));
+                } else if let Some(selector) = &select {
+                    for index in 0..png.chunk_count() {
+                        let (Some(chunk_type), Some(length)) =
+                            (png.chunk_type(index), png.chunk_length(index))
+                        else {
+                            continue;
+                        };
+                        if !selector.matches(index, &chunk_type, length) {
+                            continue;
+                        }
+                        if chunk_type.is_critical() && !force {
+                            return Err(
+                                format!("Refusing to remove {chunk_type} without --force").into()
+                            );
+                        }
+                        indices.push(index);
+                    }
+                    if indices.is_empty() {
+                        eprintln!("No chunk matches --select");
+                    }
+                } else if let Some(chunktype) = chunktype {
+                    let first =
+                        (0..png.chunk_count()).find(|&i| png.chunk_type(i) == Some(chunktype));
+                    match first {
+                        Some(index) => indices.push(index),
+                        None => eprintln!("{} wasnt found in the png", chunktype),
+                    }
+                }
+                let before = png.total_size();
+                let changes: Vec<ChunkChange> = indices
+                    .iter()
+                    .map(|&i| png.removal(i).expect("index is in range"))
+                    .collect();
+                for &index in indices.iter().rev() {
+                    png.remove_chunk_at(index);
+                }
+                let summary = Summary {
+                    changes,
+                    before,
+                    after: png.total_size(),
+                };
+                if args.json {
+                    output.json(summary.to_json());
+                } else if !args.quiet {
+                    for line in summary.lines(size) {
+                        output.status(line);
+                    }
+                }
+                if types_file.is_some() && !args.json {
+                    for chunk_type in &types {
+                        let count = summary
+                            .changes
+                            .iter()
+                            .filter(|c| c.chunk_type == *chunk_type)
+                            .count();
+                        status!(output, "{chunk_type}: {count} removed");
+                    }
+                }
+                if args.relocate_post_iend {
+                    relocate(png.relocate_post_iend(), &output);
+                }
+                let engine_options = &EngineOptions {
+                    allow_invalid: engine_options.allow_invalid || force,
+                    ..engine_options.clone()
+                };
+                png.write(&output, engine_options, recording)?;
+            }
+            Commands::Print { file, data } => {
+                let buffer = read_png_file(&file)?;
+                let chunks = chunks_of(&file, &buffer, &options)?;
+                println!("{}", Listing(&chunks));
+                if data {
+                    let image_data = idat::summarize(chunks.iter().copied());
+                    let first_idat = chunks
+                        .iter()
+                        .position(|c| *c.chunk_type() == ChunkType::IDAT);
+                    for (index, chunk) in chunks.iter().enumerate() {
+                        let known = KnownChunk::parse(*chunk);
+                        let summary = match (known.label(), Structured::detect(chunk.data())) {
+                            _ if *chunk.chunk_type() == ChunkType::IDAT => match &image_data {
+                                Some(image_data) if first_idat == Some(index) => format!(
+                                    "Image data: {}",
+                                    image_data_line(image_data, args.human_readable)
+                                ),
+                                _ => "Image data, continued".to_string(),
+                            },
+                            (Some(label), _) => format!("{label}: {known}"),
+                            (None, Some(structured)) => structured.summary(),
+                            (None, None) => analysis::analyze(chunk.data()).guess.to_string(),
+                        };
+                        println!("Chunk {}: {summary}", index + 1);
+                    }
+                }
+            }
+            Commands::List {
+                file,
+                format,
+                no_header,
+                digest,
+                long_digest,
+                select,
             } => {
-                let mut png = png_from_file(&file)?;
-                let chunk = Chunk::new(
-                    ChunkType::from_str(&chunktype)?,
-                    message.as_bytes().to_vec(),
+                let options = without_crc_checks(options);
+                let buffer = read_png_file(&file)?;
+                let chunks = chunks_of(&file, &buffer, &options)?;
+                let selected = |index: usize, chunk: &ChunkRef| {
+                    select
+                        .as_ref()
+                        .is_none_or(|s| s.matches(index, chunk.chunk_type(), chunk.length()))
+                };
+                // Only worked out when asked for, as it reads every byte
+                let digest_of = |chunk: &ChunkRef| {
+                    digest.map(|algorithm| match long_digest {
+                        true => algorithm.hex(chunk.data()),
+                        false => algorithm.short_hex(chunk.data()),
+                    })
+                };
+                let delimited = match format {
+                    Some(ListFormat::Template(_)) if digest.is_some() => {
+                        return Err(
+                            "--digest can't be used with a --format template, use {data_sha256}"
+                                .into(),
+                        );
+                    }
+                    Some(ListFormat::Template(template)) => {
+                        for (index, chunk) in chunks
+                            .iter()
+                            .enumerate()
+                            .filter(|(index, chunk)| selected(*index, chunk))
+                        {
+                            let fields = ChunkFields {
+                                index,
+                                offset: chunk.offset(),
+                                chunk: *chunk,
+                            };
+                            println!("{}", template.render(&fields));
+                        }
+                        return Ok(());
+                    }
+                    Some(ListFormat::Delimited(delimited)) => Some(delimited),
+                    None => None,
+                };
+                let post_iend = chunks
+                    .iter()
+                    .position(|c| *c.chunk_type() == ChunkType::IEND)
+                    .map_or(chunks.len(), |iend| iend + 1);
+                let rows = chunks
+                    .iter()
+                    .map(|chunk| (chunk, chunk.offset()))
+                    .enumerate()
+                    .filter(|(index, (chunk, _))| selected(*index, chunk));
+                match OutputFormat::new(args.json, delimited) {
+                    OutputFormat::Delimited(delimited) => {
+                        if !no_header {
+                            let mut columns = LIST_COLUMNS.to_vec();
+                            columns.extend(digest.map(|algorithm| algorithm.name()));
+                            println!("{}", delimited.record(&columns));
+                        }
+                        for (index, (chunk, offset)) in rows {
+                            let chunk_type = chunk.chunk_type();
+                            let mut record = vec![
+                                index.to_string(),
+                                chunk_type.to_string(),
+                                chunk.length().to_string(),
+                                format_crc(chunk.crc()),
+                                offset.to_string(),
+                                chunk_type.is_critical().to_string(),
+                                chunk_type.is_safe_to_copy().to_string(),
+                            ];
+                            record.extend(digest_of(chunk));
+                            println!("{}", delimited.record(&record));
+                        }
+                    }
+                    OutputFormat::Json => {
+                        let chunks: Vec<_> = rows
+                            .map(|(index, (chunk, offset))| {
+                                let mut row = serde_json::json!({
+                                    "index": index,
+                                    "type": chunk.chunk_type().to_string(),
+                                    "length": chunk.length(),
+                                    "crc_hex": format_crc(chunk.crc()),
+                                    "offset": offset,
+                                    "critical": chunk.chunk_type().is_critical(),
+                                    "safe_to_copy": chunk.chunk_type().is_safe_to_copy(),
+                                    "after_iend": index >= post_iend,
+                                });
+                                if let (Some(algorithm), Some(hex)) = (digest, digest_of(chunk)) {
+                                    row[algorithm.name()] = hex.into();
+                                }
+                                row
+                            })
+                            .collect();
+                        print_json(chunks.into(), false);
+                    }
+                    OutputFormat::Text => {
+                        for (index, (chunk, offset)) in rows {
+                            let flag = if index >= post_iend {
+                                "  after IEND"
+                            } else {
+                                ""
+                            };
+                            let digest = match digest_of(chunk) {
+                                Some(hex) => format!("  {hex}"),
+                                None => String::new(),
+                            };
+                            println!(
+                                "{index:>5}  {offset:>10}  0x{offset:08x}  {}  {:>16}  {}{digest}{flag}",
+                                chunk.chunk_type(),
+                                size(chunk.length() as usize),
+                                format_crc(chunk.crc())
+                            );
+                        }
+                    }
+                }
+            }
+            Commands::Stats { file, table } => {
+                let options = without_crc_checks(options);
+                let buffer = read_png_file(&file)?;
+                let chunks = chunks_of(&file, &buffer, &options)?;
+                let file_size = buffer.len();
+                // Each type in the order it first appears: count and bytes
+                // taken up, headers and CRCs included
+                let mut totals: Vec<(ChunkType, usize, usize)> = Vec::new();
+                for chunk in &chunks {
+                    let bytes = chunk.serialized_len();
+                    match totals.iter_mut().find(|(t, ..)| t == chunk.chunk_type()) {
+                        Some((_, count, total)) => {
+                            *count += 1;
+                            *total += bytes;
+                        }
+                        None => totals.push((*chunk.chunk_type(), 1, bytes)),
+                    }
+                }
+                let percent = |bytes: usize| bytes as f64 * 100.0 / file_size as f64;
+                match OutputFormat::new(args.json, table.format) {
+                    OutputFormat::Delimited(delimited) => {
+                        if !table.no_header {
+                            println!("{}", delimited.record(&STATS_COLUMNS));
+                        }
+                        for (chunk_type, count, total) in totals {
+                            println!(
+                                "{}",
+                                delimited.record(&[
+                                    chunk_type.to_string(),
+                                    count.to_string(),
+                                    total.to_string(),
+                                    format!("{:.2}", percent(total)),
+                                ])
+                            );
+                        }
+                    }
+                    OutputFormat::Json => {
+                        let types: Vec<_> = totals
+                            .iter()
+                            .map(|(chunk_type, count, total)| {
+                                serde_json::json!({
+                                    "type": chunk_type.to_string(),
+                                    "count": count,
+                                    "total_bytes": total,
+                                    "percent": percent(*total),
+                                })
+                            })
+                            .collect();
+                        print_json(types.into(), false);
+                    }
+                    OutputFormat::Text => {
+                        for (chunk_type, count, total) in totals {
+                            println!(
+                                "{chunk_type}  {count:>5}  {:>16}  {:>5.1}%",
+                                size(total),
+                                percent(total)
+                            );
+                        }
+                    }
+                }
+            }
+            Commands::Patch {
+                file,
+                chunktype,
+                offset,
+                bytes,
+                replace,
+                output,
+            } => {
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let lock = lock_png_file(&file, lock_timeout)?;
+                let mut png = png_from_reader(&lock, &options)?;
+                let recording = record("patch", &png);
+                let Some(index) = png
+                    .chunks()
+                    .iter()
+                    .position(|c| *c.chunk_type() == chunktype)
+                else {
+                    return Err(format!("{chunktype} wasnt found in the png").into());
+                };
+                let end = offset.saturating_add(replace.unwrap_or(bytes.len()));
+                png.splice_raw(index, offset..end, &bytes)?;
+                let chunk = &png.chunks()[index];
+                status!(
+                    output,
+                    "Patched {chunktype} at index {index}: {} replaced by {}, CRC now {}",
+                    size(end - offset),
+                    size(bytes.len()),
+                    format_crc(chunk.crc())
                 );
-                png.append_chunk(chunk);
-                let out_path;
-                if let Some(path) = output_path {
-                    out_path = path
+                drop_unsafe_to_copy(&mut png, &chunktype);
+                if args.relocate_post_iend {
+                    relocate(png.relocate_post_iend(), &output);
+                }
+                output.write_png(&mut png, engine_options, recording)?;
+            }
+            Commands::Redact {
+                file,
+                chunktype,
+                fill,
+                pattern,
+                force,
+                output,
+            } => {
+                if chunktype.is_critical() && !force {
+                    return Err(format!("Refusing to redact {chunktype} without --force").into());
+                }
+                let pattern = pattern.unwrap_or_else(|| vec![fill.unwrap_or(0)]);
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let lock = lock_png_file(&file, lock_timeout)?;
+                let mut png = png_from_reader(&lock, &options)?;
+                let recording = record("redact", &png);
+                let indices: Vec<usize> = (0..png.chunk_count())
+                    .filter(|&index| *png.chunks()[index].chunk_type() == chunktype)
+                    .collect();
+                if indices.is_empty() {
+                    return Err(format!("{chunktype} wasnt found in the png").into());
+                }
+                for index in indices {
+                    png.redact_at(index, &pattern);
+                    let chunk = &png.chunks()[index];
+                    status!(
+                        output,
+                        "Redacted {chunktype} at index {index}: {} overwritten, CRC now {}",
+                        size(chunk.data().len()),
+                        format_crc(chunk.crc())
+                    );
+                }
+                drop_unsafe_to_copy(&mut png, &chunktype);
+                if args.relocate_post_iend {
+                    relocate(png.relocate_post_iend(), &output);
+                }
+                output.write_png(&mut png, engine_options, recording)?;
+            }
+            Commands::Strip {
+                file,
+                unsafe_only,
+                select,
+                output,
+                batch,
+            } => {
+                let file = file.expect("clap requires a file without --files-from");
+                let label = match batch.labelled {
+                    true => format!("{}: ", file.display()),
+                    false => String::new(),
+                };
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let lock = lock_png_file(&file, lock_timeout)?;
+                let mut png = png_from_reader(&lock, &options)?;
+                let recording = record("strip", &png);
+                let removed = match &select {
+                    Some(selector) => png.strip_selected(selector, unsafe_only),
+                    None if unsafe_only => png.drop_unsafe_to_copy(),
+                    None => png.strip_ancillary(),
+                };
+                for chunk in &removed {
+                    status!(output, "{label}{} is removed", chunk.chunk_type());
+                }
+                if args.relocate_post_iend {
+                    relocate(png.relocate_post_iend(), &output);
+                }
+                output.write_png(&mut png, engine_options, recording)?;
+            }
+            Commands::Inspect { file, chunktype } => {
+                let buffer = read_png_file(&file)?;
+                let chunks = chunks_of(&file, &buffer, &options)?;
+                let Some(chunk) = chunks.iter().find(|c| *c.chunk_type() == chunktype) else {
+                    return Err(format!("{} wasnt found in the png", chunktype).into());
+                };
+                let analysis = analysis::analyze(chunk.data());
+                if args.json {
+                    print_json(
+                        serde_json::json!({
+                            "length": analysis.len,
+                            "entropy": analysis.entropy,
+                            "printable": analysis.printable,
+                            "histogram": analysis.histogram,
+                            "magic": analysis.magic.map(|m| m.name()),
+                            "guess": analysis.guess.to_string(),
+                        }),
+                        false,
+                    );
                 } else {
-                    out_path = file
+                    println!("Length: {}", size(analysis.len));
+                    println!("Entropy: {:.3} bits/byte", analysis.entropy);
+                    println!("Printable: {:.1}%", analysis.printable * 100.0);
+                    let max = analysis.histogram.iter().copied().max().unwrap_or(0).max(1);
+                    for (bucket, n) in analysis.histogram.iter().enumerate() {
+                        let bar = "#".repeat(n * 40 / max);
+                        println!(
+                            "  {:02x}-{:02x} {n:>8} {bar}",
+                            bucket * 16,
+                            bucket * 16 + 15
+                        );
+                    }
+                    print!("Guess: {}", analysis.guess);
+                    if analysis.magic == Some(Magic::Zlib) {
+                        print!(
+                            " (found 0x{:02X} 0x{:02X} header)",
+                            chunk.data()[0],
+                            chunk.data()[1]
+                        );
+                    }
+                    println!();
                 }
-                let mut f = File::create(out_path)?;
-                f.write_all(&png.as_bytes())?;
             }
-            Commands::Decode { file, chunktype } => {
-                let png = png_from_file(&file)?;
-                if let Some(val) = png.chunk_by_type(&chunktype) {
-                    println!("{}", val.data_as_string().unwrap());
+            Commands::Info {
+                file,
+                palette,
+                inflate_check,
+            } => {
+                let buffer = read_png_file(&file)?;
+                let chunks = chunks_of(&file, &buffer, &options)?;
+                let chunk_by_type =
+                    |chunk_type: ChunkType| chunks.iter().find(|c| *c.chunk_type() == chunk_type);
+                let Some(ihdr) = chunk_by_type(ChunkType::IHDR) else {
+                    return Err(format!("{} has no IHDR chunk", file.display()).into());
+                };
+                let ihdr = Ihdr::parse(ihdr.data())?;
+                let known: Vec<_> = chunks
+                    .iter()
+                    .map(|chunk| KnownChunk::parse(*chunk))
+                    .enumerate()
+                    .filter(|(_, known)| known.label().is_some())
+                    .collect();
+                let image_data = idat::summarize(chunks.iter().copied());
+                // Counted, not kept, so memory use doesn't grow with the image
+                let inflated = match &image_data {
+                    Some(_) if inflate_check => {
+                        let idats = chunks
+                            .iter()
+                            .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+                            .map(|c| c.data());
+                        Some(idat::inflated_len(idats, u64::MAX).map_err(|e| e.to_string()))
+                    }
+                    _ => None,
+                };
+                let expected = ihdr.image_data_len();
+                let colors = if palette {
+                    let Some(plte) = chunk_by_type(PLTE) else {
+                        return Err(format!("{} has no PLTE chunk", file.display()).into());
+                    };
+                    let alpha = chunk_by_type(TRNS).map(|c| c.data()).unwrap_or(&[]);
+                    let colors = Palette::parse(plte.data())?.colors().to_vec();
+                    // Entries without a tRNS value are fully opaque
+                    let alpha = (0..colors.len()).map(|i| alpha.get(i).copied().unwrap_or(255));
+                    Some(colors.into_iter().zip(alpha).collect::<Vec<_>>())
+                } else {
+                    None
+                };
+                if args.json {
+                    let mut info = serde_json::json!({
+                        "width": ihdr.width,
+                        "height": ihdr.height,
+                        "bit_depth": ihdr.bit_depth,
+                        "color_type": ihdr.color_type,
+                        "interlace": ihdr.interlace,
+                        "chunks": chunks.len(),
+                    });
+                    info["known_chunks"] = known
+                        .iter()
+                        .map(|(index, known)| {
+                            serde_json::json!({
+                                "index": index,
+                                "chunk_type": chunks[*index].chunk_type().to_string(),
+                                "label": known.label(),
+                                "data": known.to_json(),
+                            })
+                        })
+                        .collect();
+                    if let Some(image_data) = &image_data {
+                        info["image_data"] = serde_json::json!({
+                            "chunks": image_data.chunks,
+                            "compressed": image_data.compressed,
+                            "zlib": match &image_data.header {
+                                Ok(header) => serde_json::json!({
+                                    "method": header.method,
+                                    "window_size": header.window_size,
+                                    "preset_dictionary": header.preset_dictionary,
+                                    "level": header.level,
+                                }),
+                                Err(e) => serde_json::json!({ "error": e.to_string() }),
+                            },
+                            "expected_inflated": expected,
+                        });
+                        match &inflated {
+                            Some(Ok(len)) => info["image_data"]["inflated"] = (*len).into(),
+                            Some(Err(e)) => info["image_data"]["inflate_error"] = e.as_str().into(),
+                            None => {}
+                        }
+                    }
+                    if let Some(colors) = &colors {
+                        info["palette"] = colors
+                            .iter()
+                            .map(|([r, g, b], a)| serde_json::json!([r, g, b, a]))
+                            .collect();
+                    }
+                    print_json(info, false);
                 } else {
-                    eprintln!("{} wasnt found in the png", chunktype)
+                    println!("Dimensions: {}x{}", ihdr.width, ihdr.height);
+                    println!("Bit depth: {}", ihdr.bit_depth);
+                    println!(
+                        "Color type: {} ({})",
+                        ihdr.color_type,
+                        ihdr.color_type_name()
+                    );
+                    let interlace = if ihdr.interlace == 1 { "Adam7" } else { "none" };
+                    println!("Interlace: {interlace}");
+                    println!("Chunks: {}", chunks.len());
+                    if let Some(image_data) = &image_data {
+                        println!(
+                            "Image data: {}",
+                            image_data_line(image_data, args.human_readable)
+                        );
+                    }
+                    match (&inflated, expected) {
+                        (Some(Ok(len)), Some(expected)) => {
+                            let verdict = if *len == expected { "as" } else { "but" };
+                            println!(
+                                "Inflated: {}, {verdict} IHDR calls for {}",
+                                format_size(*len, args.human_readable),
+                                format_size(expected, args.human_readable)
+                            );
+                        }
+                        (Some(Ok(len)), None) => {
+                            println!("Inflated: {}", format_size(*len, args.human_readable))
+                        }
+                        (Some(Err(e)), _) => println!("Inflated: fails, {e}"),
+                        (None, _) => {}
+                    }
+                    for (_, known) in &known {
+                        println!("{}: {known}", known.label().unwrap_or_default());
+                    }
+                    let swatch = io::stdout().is_terminal();
+                    for (index, ([r, g, b], a)) in colors.iter().flatten().enumerate() {
+                        print!("{index:>5}  #{r:02x}{g:02x}{b:02x}  alpha {a:>3}");
+                        if swatch {
+                            print!("  \x1b[48;2;{r};{g};{b}m    \x1b[0m");
+                        }
+                        println!();
+                    }
                 }
             }
-            Commands::Remove { file, chunktype } => {
-                let mut png = png_from_file(&file)?;
-                match png.remove_first_chunk(&chunktype) {
-                    Some(_) => println!("{chunktype} is removed"),
-                    None => {
-                        eprintln!("{} wasnt found in the png", chunktype)
+            Commands::Frames {
+                action: FramesAction::Extract { file, dir },
+            } => {
+                let png = png_from_file(&file, &options)?;
+                let Some(ihdr) = png.chunk_by_type("IHDR") else {
+                    return Err(format!("{} has no IHDR chunk", file.display()).into());
+                };
+                let ihdr = Ihdr::try_from(ihdr)?;
+                let frames = apng::frames(&png).with_context(|| file.display().to_string())?;
+                fs::create_dir_all(&dir).with_context(|| dir.display().to_string())?;
+                let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+                let mut written = Vec::new();
+                for (n, frame) in frames.iter().enumerate() {
+                    let path = dir.join(format!("{stem}-{:03}.png", n + 1));
+                    write_png_file(&path, &frame.to_png(&ihdr, &png), engine_options)?;
+                    let control = &frame.control;
+                    if args.json {
+                        written.push(serde_json::json!({
+                            "frame": n + 1,
+                            "path": path.display().to_string(),
+                            "width": control.width,
+                            "height": control.height,
+                            "x_offset": control.x_offset,
+                            "y_offset": control.y_offset,
+                        }));
+                    } else if !args.quiet {
+                        println!(
+                            "Wrote frame {} ({}x{} at {},{}) to \"{}\"",
+                            n + 1,
+                            control.width,
+                            control.height,
+                            control.x_offset,
+                            control.y_offset,
+                            path.display()
+                        );
                     }
                 }
-                let mut f = File::create(file)?;
-                f.write_all(&png.as_bytes())?;
+                if args.json {
+                    print_json(written.into(), false);
+                }
+            }
+            Commands::Tags {
+                action:
+                    Some(TagsAction::Set {
+                        file,
+                        keyword,
+                        value,
+                        output,
+                    }),
+                ..
+            } => {
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let lock = lock_png_file(&file, lock_timeout)?;
+                let mut png = png_from_reader(&lock, &options)?;
+                let recording = record("tags set", &png);
+                let duplicates = text::set_text(&mut png, &keyword, &value)?;
+                if duplicates > 0 {
+                    status!(
+                        output,
+                        "Set the first {keyword} entry; {duplicates} later duplicates keep their old values"
+                    );
+                }
+                if args.relocate_post_iend {
+                    relocate(png.relocate_post_iend(), &output);
+                }
+                output.write_png(&mut png, engine_options, recording)?;
+            }
+            Commands::Tags {
+                action:
+                    Some(TagsAction::Remove {
+                        file,
+                        keyword,
+                        output,
+                    }),
+                ..
+            } => {
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let lock = lock_png_file(&file, lock_timeout)?;
+                let mut png = png_from_reader(&lock, &options)?;
+                let recording = record("tags remove", &png);
+                let removed = text::remove_text(&mut png, &keyword);
+                if removed.is_empty() {
+                    return Err(format!("{keyword} wasnt found in the png").into());
+                }
+                status!(output, "Removed {} {keyword} entries", removed.len());
+                if args.relocate_post_iend {
+                    relocate(png.relocate_post_iend(), &output);
+                }
+                output.write_png(&mut png, engine_options, recording)?;
             }
-            Commands::Print { file } => {
-                let png = png_from_file(&file)?;
-                println!("{}", png);
+            Commands::Tags {
+                action: None,
+                file,
+                keyword,
+            } => {
+                let file = file.expect("clap requires a file without a subcommand");
+                let buffer = read_png_file(&file)?;
+                let chunks = chunks_of(&file, &buffer, &options)?;
+                let mut entries = Vec::new();
+                for entry in text::text_entries_in(chunks) {
+                    match entry {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => eprintln!("Skipping {e}"),
+                    }
+                }
+                if let Some(keyword) = &keyword {
+                    entries.retain(|e| &e.keyword == keyword);
+                }
+                if args.json {
+                    let entries: Vec<_> = entries
+                        .iter()
+                        .map(|e| {
+                            serde_json::json!({
+                                "keyword": e.keyword,
+                                "value": e.value,
+                                "chunk_type": e.chunk_type.to_string(),
+                                "chunk_index": e.index,
+                                "compressed": e.compressed,
+                                "language": e.language,
+                                "translated_keyword": e.translated_keyword,
+                            })
+                        })
+                        .collect();
+                    print_json(entries.into(), false);
+                } else if keyword.is_some() {
+                    for entry in &entries {
+                        println!("{}", entry.value);
+                    }
+                } else {
+                    for entry in &entries {
+                        let compressed = if entry.compressed { ", compressed" } else { "" };
+                        println!(
+                            "{}: {}  ({}{compressed})",
+                            entry.keyword, entry.value, entry.chunk_type
+                        );
+                    }
+                }
+                if keyword.is_some() && entries.is_empty() {
+                    return Err(context::Reported.into());
+                }
+            }
+            Commands::Repair {
+                file,
+                fix_lengths,
+                output,
+            } => {
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let options = ParseOptions {
+                    ignore_crc: true,
+                    fix_lengths,
+                    ..options
+                };
+                let mut lock = lock_png_file(&file, lock_timeout)?;
+                let mut buffer = Vec::new();
+                Timed(&mut lock).read_to_end(&mut buffer)?;
+                let lengths = if fix_lengths {
+                    find_length_mismatches(&buffer, &options)?
+                } else {
+                    Vec::new()
+                };
+                let mut png = Png::parse_with(&buffer, &options)?;
+                let recording = record("repair", &png);
+                for mismatch in &lengths {
+                    status!(
+                        output,
+                        "Fixed length of {} at offset {}: declared {} -> {}",
+                        mismatch.chunk_type,
+                        mismatch.offset,
+                        mismatch.declared,
+                        mismatch.actual
+                    );
+                }
+                let broken: Vec<usize> = (0..png.chunks().len())
+                    .filter(|&i| png.chunks()[i].crc() != png.chunks()[i].computed_crc())
+                    .collect();
+                for &index in &broken {
+                    let chunk = &png.chunks()[index];
+                    status!(
+                        output,
+                        "Fixed CRC of {} at index {index}: {} -> {}",
+                        chunk.chunk_type(),
+                        format_crc(chunk.crc()),
+                        format_crc(chunk.computed_crc())
+                    );
+                    let fixed = Chunk::new(*chunk.chunk_type(), chunk.data().to_vec());
+                    png.replace_chunk_at(index, fixed);
+                }
+                // Only a --lenient parse keeps any
+                let stray = png.drop_stray_bytes();
+                for stray in &stray {
+                    status!(
+                        output,
+                        "Dropped {} stray bytes at offset {}",
+                        stray.bytes().len(),
+                        stray.offset()
+                    );
+                }
+                let relocated =
+                    args.relocate_post_iend && relocate(png.relocate_post_iend(), &output) > 0;
+                let unchanged =
+                    broken.is_empty() && lengths.is_empty() && stray.is_empty() && !relocated;
+                if unchanged {
+                    status!(output, "{}: no CRC errors found", file.display());
+                }
+                // An --output elsewhere is written even when there is nothing to fix
+                if !unchanged || !output.in_place() {
+                    output.write_png(&mut png, engine_options, recording)?;
+                }
+            }
+            Commands::Salvage {
+                file,
+                decode,
+                write_recovered,
+            } => {
+                let PartialParse { png, error, offset } = match input::read_text(&file)? {
+                    Some(png) => Png::from_reader_partial(png.as_slice(), &options),
+                    None => Png::from_reader_partial(open_png_file(&file)?, &options),
+                };
+                let has_iend = png
+                    .chunks()
+                    .iter()
+                    .any(|c| *c.chunk_type() == ChunkType::IEND);
+                // With --decode stdout is for the message
+                let say = |line: &dyn std::fmt::Display| {
+                    if decode.is_some() {
+                        eprintln!("{line}");
+                    } else {
+                        println!("{line}");
+                    }
+                };
+                let reason = match &error {
+                    Some(ReadError::Invalid(e)) => Some(e.kind().to_string()),
+                    Some(ReadError::Io(e)) => Some(e.to_string()),
+                    None if !has_iend => Some("the file ends without IEND".to_string()),
+                    None => None,
+                };
+                if args.json {
+                    let stopped = reason
+                        .as_ref()
+                        .map(|reason| serde_json::json!({ "offset": offset, "reason": reason }));
+                    let report = serde_json::json!({
+                        "file": file.to_string_lossy(),
+                        "chunks": png.chunk_count(),
+                        "recovered_bytes": offset,
+                        "stopped": stopped,
+                    });
+                    print_json(report, decode.is_some());
+                } else {
+                    say(&format_args!(
+                        "Recovered {} chunks, {}",
+                        png.chunk_count(),
+                        size(offset)
+                    ));
+                    match &reason {
+                        Some(reason) => say(&format_args!(
+                            "Stopped at offset {offset} (0x{offset:x}): {reason}"
+                        )),
+                        None => say(&"The whole file is intact"),
+                    }
+                }
+                if let Some(path) = write_recovered {
+                    if png.chunk_count() == 0 {
+                        return Err("No chunks were recovered, so there is nothing to write".into());
+                    }
+                    // Stray bytes are left out, so the result is all chunks
+                    let mut chunks = png.chunks().to_vec();
+                    if !has_iend {
+                        chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+                    }
+                    let recovered = Png::from_chunks(chunks);
+                    // What was recovered is written as it is, even if the
+                    // damage took IHDR or left chunks after IEND
+                    let engine_options = &EngineOptions {
+                        allow_invalid: true,
+                        ..engine_options.clone()
+                    };
+                    write_png_file(&path, &recovered, engine_options)?;
+                    let added = if has_iend { "" } else { ", with an IEND added" };
+                    let line = format_args!(
+                        "Wrote {} chunks to \"{}\"{added}",
+                        recovered.chunk_count(),
+                        path.display()
+                    );
+                    if args.json {
+                        eprintln!("{line}");
+                    } else {
+                        say(&line);
+                    }
+                }
+                if let Some(chunk_type) = decode {
+                    let Some(chunk) = png.chunks().iter().find(|c| *c.chunk_type() == chunk_type)
+                    else {
+                        return Err(DecodeError::Missing(chunk_type.to_string()).into());
+                    };
+                    let Ok(message) = chunk.data_as_string() else {
+                        return Err(DecodeError::NotUtf8 {
+                            chunk_type: chunk_type.to_string(),
+                            len: chunk.data().len() as u64,
+                        }
+                        .into());
+                    };
+                    println!("{message}");
+                }
+            }
+            Commands::Verify {
+                file,
+                strict,
+                batch: _,
+            } => {
+                let file = file.expect("clap requires a file without --files-from");
+                let buffer = read_png_file(&file)?;
+                let findings = verify::verify(&buffer, &options);
+                let errors = findings
+                    .iter()
+                    .filter(|f| f.severity() == Severity::Error)
+                    .count();
+                let warnings = findings.len() - errors;
+                let failed = errors > 0 || (strict && warnings > 0);
+                if args.json {
+                    let findings: Vec<_> = findings
+                        .iter()
+                        .map(|f| {
+                            serde_json::json!({
+                                "severity": f.severity().as_str(),
+                                "code": f.kind.code(),
+                                "chunk_index": f.chunk_index,
+                                "chunk_type": f.chunk_type.map(|t| t.to_string()),
+                                "offset": f.offset,
+                                "message": f.message,
+                            })
+                        })
+                        .collect();
+                    print_json(
+                        serde_json::json!({
+                            "file": file.to_string_lossy(),
+                            "findings": findings,
+                            "summary": {
+                                "errors": errors,
+                                "warnings": warnings,
+                                "ok": !failed,
+                            },
+                        }),
+                        false,
+                    );
+                } else {
+                    for f in &findings {
+                        println!(
+                            "{}[{}]: {}",
+                            f.severity().as_str(),
+                            f.kind.code(),
+                            f.message
+                        );
+                    }
+                    if findings.is_empty() {
+                        println!("{}: OK", file.display());
+                    } else {
+                        println!("{}: {errors} errors, {warnings} warnings", file.display());
+                    }
+                }
+                if failed {
+                    return Err(context::Reported.into());
+                }
+            }
+            Commands::Watch {
+                dir,
+                chunk_type,
+                message_file,
+                once,
+                debounce,
+            } => {
+                let data = fs::read(&message_file)
+                    .map_err(|e| format!("{}: {e}", message_file.display()))?;
+                let rule = watch::Rule {
+                    chunk: Chunk::new(chunk_type, data),
+                    options,
+                    engine_options: engine_options.clone(),
+                    lock_timeout,
+                };
+                if once {
+                    let (added, failed) = watch::run_once(&dir, &rule)?;
+                    println!("{added} files updated, {failed} failed");
+                    if failed > 0 {
+                        return Err(context::Reported.into());
+                    }
+                } else {
+                    let stop = Arc::new(AtomicBool::new(false));
+                    let handler_stop = stop.clone();
+                    ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed))?;
+                    watch::watch(&dir, &rule, Duration::from_millis(debounce), &stop)?;
+                }
+            }
+            Commands::Apply {
+                rules,
+                file,
+                dry_run,
+                output,
+            } => {
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let source =
+                    fs::read_to_string(&rules).map_err(|e| format!("{}: {e}", rules.display()))?;
+                let rules =
+                    Rules::parse(&source).map_err(|e| format!("{}: {e}", rules.display()))?;
+                // A dry run only reads, so it doesn't wait for other editors
+                let lock = if dry_run {
+                    None
+                } else {
+                    Some(lock_png_file(&file, lock_timeout)?)
+                };
+                let mut png = match &lock {
+                    Some(lock) => png_from_reader(lock, &options)?,
+                    None => png_from_file(&file, &options)?,
+                };
+                let recording = record("apply", &png);
+                let reports = rules.apply(&mut png)?;
+                for (number, (step, (removed, added))) in
+                    rules.steps.iter().zip(reports).enumerate()
+                {
+                    status!(
+                        output,
+                        "{}. {step}: {removed} removed, {added} added",
+                        number + 1
+                    );
+                }
+                if dry_run {
+                    status!(output, "Dry run, {} left unchanged", file.display());
+                } else {
+                    if args.relocate_post_iend {
+                        relocate(png.relocate_post_iend(), &output);
+                    }
+                    output.write_png(&mut png, engine_options, recording)?;
+                }
+            }
+            Commands::Optimize {
+                file,
+                dry_run,
+                remove,
+                no_strip_trailing,
+                no_drop_empty,
+                no_dedup,
+                no_merge_idat,
+                output,
+            } => {
+                let output = Output::resolve(&file, output.output, args.output_format);
+                let optimize_options = OptimizeOptions {
+                    strip_trailing: !no_strip_trailing,
+                    remove,
+                    drop_empty: !no_drop_empty,
+                    dedup: !no_dedup,
+                    merge_idat: !no_merge_idat,
+                    max_idat_len: options.max_chunk_size,
+                };
+                let lock = if dry_run {
+                    None
+                } else {
+                    Some(lock_png_file(&file, lock_timeout)?)
+                };
+                let mut png = match &lock {
+                    Some(lock) => png_from_reader(lock, &options)?,
+                    None => png_from_file(&file, &options)?,
+                };
+                let recording = record("optimize", &png);
+                let before = png.total_size();
+                let savings = optimize::optimize(&mut png, &optimize_options)?;
+                if args.json {
+                    let passes: Vec<_> = savings
+                        .iter()
+                        .map(|s| {
+                            serde_json::json!({
+                                "pass": s.pass.to_string(),
+                                "chunks": s.chunks,
+                                "bytes": s.bytes,
+                            })
+                        })
+                        .collect();
+                    output.json(serde_json::json!({
+                        "passes": passes,
+                        "before": before,
+                        "after": png.total_size(),
+                        "dry_run": dry_run,
+                    }));
+                } else {
+                    for saving in &savings {
+                        status!(
+                            output,
+                            "{}: {} chunks, {} saved",
+                            saving.pass,
+                            saving.chunks,
+                            size(saving.bytes)
+                        );
+                    }
+                    status!(
+                        output,
+                        "Total: {} saved, {} -> {}",
+                        size(before - png.total_size()),
+                        size(before),
+                        size(png.total_size())
+                    );
+                }
+                if dry_run {
+                    if !args.json {
+                        status!(output, "Dry run, {} left unchanged", file.display());
+                    }
+                } else {
+                    if args.relocate_post_iend {
+                        relocate(png.relocate_post_iend(), &output);
+                    }
+                    // write_png would say it's unchanged, which --json can't have
+                    if png.is_modified() || !output.in_place() {
+                        output.write_png(&mut png, engine_options, recording)?;
+                    }
+                }
+            }
+            Commands::Scan {
+                dir,
+                write_baseline,
+                baseline,
+            } => {
+                let mut manifest = Manifest::default();
+                for path in watch::find_pngs(&dir)? {
+                    let png = png_from_file(&path, &options)
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    let relative = path.strip_prefix(&dir).unwrap_or(&path);
+                    manifest.insert(relative.to_string_lossy().replace('\\', "/"), &png);
+                }
+                if let Some(target) = write_baseline {
+                    let json = serde_json::to_string_pretty(&manifest)?;
+                    fs::write(&target, json + "\n")
+                        .map_err(|e| format!("{}: {e}", target.display()))?;
+                    println!(
+                        "Recorded {} files in {}",
+                        manifest.files.len(),
+                        target.display()
+                    );
+                    return Ok(());
+                }
+                let source = baseline.expect("clap requires --baseline without --write-baseline");
+                let text = fs::read_to_string(&source)
+                    .map_err(|e| format!("{}: {e}", source.display()))?;
+                let recorded: Manifest = serde_json::from_str(&text)
+                    .map_err(|e| format!("{}: {e}", source.display()))?;
+                let findings = recorded.compare(&manifest);
+                let area = |change: &baseline::ChunkChange| {
+                    if change.is_pixel_data() {
+                        "pixel data"
+                    } else {
+                        "metadata"
+                    }
+                };
+                if args.json {
+                    let findings: Vec<_> = findings
+                        .iter()
+                        .map(|f| {
+                            let changes: Vec<_> = match &f.kind {
+                                baseline::FindingKind::Changed(changes) => changes
+                                    .iter()
+                                    .map(|c| {
+                                        serde_json::json!({
+                                            "change": c.kind.as_str(),
+                                            "index": c.index,
+                                            "type": c.chunk_type,
+                                            "pixel_data": c.is_pixel_data(),
+                                        })
+                                    })
+                                    .collect(),
+                                _ => Vec::new(),
+                            };
+                            serde_json::json!({
+                                "path": f.path,
+                                "status": f.kind.as_str(),
+                                "changes": changes,
+                            })
+                        })
+                        .collect();
+                    print_json(serde_json::json!({ "findings": findings }), false);
+                } else {
+                    for finding in &findings {
+                        match &finding.kind {
+                            baseline::FindingKind::Changed(changes) => {
+                                for change in changes {
+                                    println!(
+                                        "{}: {} {} at index {} ({})",
+                                        finding.path,
+                                        change.kind.as_str(),
+                                        change.chunk_type,
+                                        change.index,
+                                        area(change)
+                                    );
+                                }
+                            }
+                            kind => println!("{}: {}", finding.path, kind.as_str()),
+                        }
+                    }
+                    if findings.is_empty() {
+                        println!("{} files match the baseline", manifest.files.len());
+                    }
+                }
+                if !findings.is_empty() {
+                    return Err(context::Reported.into());
+                }
+            }
+            Commands::Undo { file } => {
+                let Some(log) = &undo_log else {
+                    return Err(
+                        "undo needs --undo-log DIR, the log the edits were recorded in".into(),
+                    );
+                };
+                let mut buffer = Vec::new();
+                Timed(lock_png_file(&file, lock_timeout)?).read_to_end(&mut buffer)?;
+                let mut png = Png::parse_with(&buffer, &options)?;
+                let record = log.undo(&file, &buffer, &mut png)?;
+                write_png_file(&file, &png, engine_options)?;
+                log.pop(&file)?;
+                println!(
+                    "Undid {} from {}: {} chunks restored, {} removed",
+                    record.command,
+                    record.id,
+                    record.restored(),
+                    record.dropped()
+                );
+            }
+            Commands::Shell { file } => {
+                let png = png_from_file(&file, &options)?;
+                let engine = Engine::from_png(file, png, engine_options.clone());
+                shell::run(shell::Session::new(engine))?;
+            }
+            Commands::SelfTest => {
+                if !self_test::run() {
+                    return Err(context::Reported.into());
+                }
+            }
+            Commands::Advise {
+                chunk_type,
+                position,
+            } => {
+                let report = survival::survivability(&chunk_type, position);
+                if args.json {
+                    let verdicts: Vec<_> = report
+                        .verdicts
+                        .iter()
+                        .map(|v| {
+                            serde_json::json!({
+                                "tool": v.tool,
+                                "fate": v.fate.as_str(),
+                                "reason": v.reason,
+                            })
+                        })
+                        .collect();
+                    print_json(
+                        serde_json::json!({
+                            "chunk_type": chunk_type.to_string(),
+                            "position": position.as_str(),
+                            "worst": report.worst().as_str(),
+                            "verdicts": verdicts,
+                        }),
+                        false,
+                    );
+                } else {
+                    for line in advice_lines(&report) {
+                        println!("{line}");
+                    }
+                }
+            }
+            Commands::ChunkType { types } => {
+                let mut invalid = false;
+                let mut reports = Vec::new();
+                for (i, name) in types.iter().enumerate() {
+                    let chunk_type = match ChunkType::from_str(name) {
+                        Ok(chunk_type) => chunk_type,
+                        Err(e) => {
+                            eprintln!("{name}: {e}");
+                            invalid = true;
+                            continue;
+                        }
+                    };
+                    let standard = standard::lookup(&chunk_type);
+                    // What bit 5 (lowercase) of each byte means when clear and when set
+                    let properties = [
+                        ("critical", "ancillary"),
+                        ("public", "private"),
+                        ("reserved bit valid", "reserved bit invalid"),
+                        ("unsafe to copy", "safe to copy"),
+                    ];
+                    if args.json {
+                        reports.push(serde_json::json!({
+                            "type": name,
+                            "bytes": chunk_type.bytes(),
+                            "critical": chunk_type.is_critical(),
+                            "public": chunk_type.is_public(),
+                            "reserved_bit_valid": chunk_type.is_reserved_bit_valid(),
+                            "safe_to_copy": chunk_type.is_safe_to_copy(),
+                            "standard": standard.is_some(),
+                            "description": standard.map(|s| s.description),
+                        }));
+                        continue;
+                    }
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{name}");
+                    for (byte, (clear, set)) in chunk_type.bytes().iter().zip(properties) {
+                        let bit = byte >> 5 & 1;
+                        let meaning = if bit == 0 { clear } else { set };
+                        println!(
+                            "  {}  0x{byte:02x}  bit 5 = {bit}  {meaning}",
+                            *byte as char
+                        );
+                    }
+                    match standard {
+                        Some(s) => println!("  standard: {}", s.description),
+                        None => println!("  not a registered chunk type"),
+                    }
+                }
+                if args.json {
+                    print_json(reports.into(), false);
+                }
+                if invalid {
+                    return Err(context::Reported.into());
+                }
+            }
+            Commands::Explain { code } => {
+                let Some(kind) = FindingKind::from_code(&code) else {
+                    let codes: Vec<_> = FindingKind::ALL
+                        .iter()
+                        .map(|kind| format!("  {}", kind.code()))
+                        .collect();
+                    return Err(format!(
+                        "Unknown code '{code}', the codes are:\n{}",
+                        codes.join("\n")
+                    )
+                    .into());
+                };
+                if args.json {
+                    print_json(
+                        serde_json::json!({
+                            "code": kind.code(),
+                            "severity": kind.severity().as_str(),
+                            "explanation": kind.explanation(),
+                        }),
+                        false,
+                    );
+                } else {
+                    println!("{} ({})", kind.code(), kind.severity().as_str());
+                    println!();
+                    println!("{}", kind.explanation());
+                }
+            }
+            #[cfg(feature = "render-check")]
+            Commands::ComparePixels {
+                first,
+                second,
+                ignore_alpha,
+                tolerance,
+            } => {
+                use pngme::render::{self, PixelDifference, PixelOptions};
+                let decode = |path: &Path| -> Result<render::Rendering> {
+                    let bytes = fs::read(path).with_context(|| path.display().to_string())?;
+                    render::render(&bytes)
+                        .with_context(|| format!("{} doesn't decode", path.display()))
+                };
+                let options = PixelOptions {
+                    ignore_alpha,
+                    tolerance,
+                };
+                let difference =
+                    render::compare_pixels(&decode(&first)?, &decode(&second)?, &options);
+                let (first, second) = (first.display(), second.display());
+                if args.json {
+                    let json = match &difference {
+                        None => serde_json::json!({ "identical": true }),
+                        Some(PixelDifference::Dimensions {
+                            first: (w1, h1),
+                            second: (w2, h2),
+                        }) => serde_json::json!({
+                            "identical": false,
+                            "dimensions": [[w1, h1], [w2, h2]],
+                        }),
+                        Some(PixelDifference::Pixels {
+                            at: (x, y),
+                            first,
+                            second,
+                            differing,
+                            ..
+                        }) => serde_json::json!({
+                            "identical": false,
+                            "first_difference": { "x": x, "y": y, "values": [first, second] },
+                            "differing_pixels": differing,
+                        }),
+                    };
+                    print_json(json, false);
+                } else {
+                    match &difference {
+                        None => println!("identical"),
+                        Some(PixelDifference::Dimensions {
+                            first: (w1, h1),
+                            second: (w2, h2),
+                        }) => println!("{first} is {w1}x{h1} but {second} is {w2}x{h2}"),
+                        Some(PixelDifference::Pixels {
+                            at: (x, y),
+                            first: a,
+                            second: b,
+                            differing,
+                            sixteen_bit,
+                        }) => {
+                            let rgba =
+                                |[r, g, b, a]: &[u16; 4]| format!("rgba({r}, {g}, {b}, {a})");
+                            let depth = if *sixteen_bit { " (16-bit)" } else { "" };
+                            println!(
+                                "different at pixel ({x}, {y}): {first} has {} and {second} has {}{depth}",
+                                rgba(a),
+                                rgba(b)
+                            );
+                            match differing {
+                                1 => println!("1 pixel differs"),
+                                n => println!("{n} pixels differ"),
+                            }
+                        }
+                    }
+                }
+                if difference.is_some() {
+                    return Err(context::Reported.into());
+                }
             }
         },
         None => todo!(),
@@ -0,0 +1,579 @@
+//! Hiding a message in a chunk and reading it back, in one call each.
+//! `pngme encode` adds its chunks through `encode_chunks`, so with the
+//! options it was given a message goes where the CLI puts it, by default
+//! just before IEND, and is refused where the CLI refuses it.
+//!
+//! ```
+//! use pngme::builder::PngBuilder;
+//! use pngme::message::EncodeOptions;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut png = PngBuilder::new(1, 1).build()?;
+//! png.encode_message("ruSt", b"hidden message", EncodeOptions::default())?;
+//! assert_eq!(png.decode_message("ruSt")?, Some(b"hidden message".to_vec()));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::chunk::Chunk;
+use crate::chunk_type::{ChunkType, ChunkTypeError};
+use crate::png::{Png, SizeReport};
+use crate::rewrite::Rewrite;
+use crate::standard;
+use crate::survival::Position;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// Whether a chunk type with the reserved bit set, a lowercase third
+/// letter, may be written. The spec reserves it, and decoders are free to
+/// treat such a chunk as an error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReservedTypes {
+    /// What the CLI does.
+    #[default]
+    Allow,
+    Refuse,
+}
+
+/// How `encode_chunks` adds chunks. The defaults are `pngme encode`'s
+/// without any options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub position: Position,
+    /// Put each chunk where the spec has chunks of its type go, e.g. tEXt
+    /// before IDAT, instead of at `position`, as `--conventional` does.
+    pub conventional: bool,
+    /// Overwrite the first chunk of the type, where it is, rather than add
+    /// another. With no chunk of the type yet, one is added at `position`.
+    pub replace: bool,
+    pub reserved: ReservedTypes,
+    /// Add a second chunk of a type the spec allows only one of, such as
+    /// tIME, and grow the file past `max_growth`, as `--force` does.
+    pub force: bool,
+    /// Refuse to grow the file by more than this percentage, as
+    /// `--max-growth` does.
+    pub max_growth: Option<u32>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            position: Position::AfterIdat,
+            conventional: false,
+            replace: false,
+            reserved: ReservedTypes::Allow,
+            force: false,
+            max_growth: None,
+        }
+    }
+}
+
+/// A second chunk of a type the spec allows only one of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Duplicate {
+    pub chunk_type: ChunkType,
+    /// Index of the chunk of the type the file already has, or `None` if
+    /// the type was given more than once.
+    pub existing: Option<usize>,
+}
+
+impl Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.existing {
+            Some(index) => write!(f, "the file already has one at chunk {index}"),
+            None => write!(f, "it's given more than once"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageError {
+    InvalidType(ChunkTypeError),
+    /// The type has the reserved bit set and `ReservedTypes::Refuse` was given.
+    Reserved(ChunkType),
+    /// `Position::BeforeIdat` in a file without image data.
+    NoIdat,
+    /// A chunk would be a second of its type, which the spec doesn't
+    /// allow, and `force` wasn't given.
+    NotUnique(Duplicate),
+    /// The file would grow by more than `max_growth` percent, and `force`
+    /// wasn't given.
+    TooMuchGrowth {
+        growth: f64,
+        max_growth: u32,
+    },
+}
+
+impl Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::InvalidType(e) => write!(f, "Invalid chunk type: {e}"),
+            MessageError::Reserved(chunk_type) => write!(
+                f,
+                "{chunk_type} has the reserved bit set (a lowercase third letter)"
+            ),
+            MessageError::NoIdat => write!(f, "There is no IDAT chunk to put the message before"),
+            MessageError::NotUnique(duplicate) => write!(
+                f,
+                "There may be only one {} and {duplicate}",
+                duplicate.chunk_type
+            ),
+            MessageError::TooMuchGrowth { growth, max_growth } => write!(
+                f,
+                "The file would grow by {growth:.1}%, above the maximum of {max_growth}%"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MessageError::InvalidType(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ChunkTypeError> for MessageError {
+    fn from(e: ChunkTypeError) -> Self {
+        MessageError::InvalidType(e)
+    }
+}
+
+/// A file chunks can be encoded into: loaded whole as a `Png`, or only
+/// indexed as a `Rewrite`.
+pub trait ChunkList {
+    fn chunk_count(&self) -> usize;
+    fn type_at(&self, index: usize) -> Option<ChunkType>;
+    /// Length of the chunk at `index` with its header and CRC.
+    fn size_at(&self, index: usize) -> Option<usize>;
+    fn total_size(&self) -> usize;
+    fn insert_at(&mut self, index: usize, chunk: Chunk);
+    fn replace_at(&mut self, index: usize, chunk: Chunk);
+}
+
+impl ChunkList for Png {
+    fn chunk_count(&self) -> usize {
+        Png::chunk_count(self)
+    }
+    fn type_at(&self, index: usize) -> Option<ChunkType> {
+        self.chunks().get(index).map(|c| *c.chunk_type())
+    }
+    fn size_at(&self, index: usize) -> Option<usize> {
+        self.chunks().get(index).map(|c| c.length() as usize + 12)
+    }
+    fn total_size(&self) -> usize {
+        Png::total_size(self)
+    }
+    fn insert_at(&mut self, index: usize, chunk: Chunk) {
+        self.insert_chunk(index, chunk);
+    }
+    fn replace_at(&mut self, index: usize, chunk: Chunk) {
+        self.replace_chunk_at(index, chunk);
+    }
+}
+
+impl ChunkList for Rewrite {
+    fn chunk_count(&self) -> usize {
+        Rewrite::chunk_count(self)
+    }
+    fn type_at(&self, index: usize) -> Option<ChunkType> {
+        self.chunk_type(index).copied()
+    }
+    fn size_at(&self, index: usize) -> Option<usize> {
+        self.chunk_length(index).map(|length| length as usize + 12)
+    }
+    fn total_size(&self) -> usize {
+        Rewrite::total_size(self)
+    }
+    fn insert_at(&mut self, index: usize, chunk: Chunk) {
+        self.insert_chunk(index, chunk);
+    }
+    fn replace_at(&mut self, index: usize, chunk: Chunk) {
+        self.replace_chunk_at(index, chunk);
+    }
+}
+
+/// What `encode_chunks` did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoded {
+    pub report: SizeReport,
+    /// Where each chunk is after the edit, in the order they were given.
+    pub indices: Vec<usize>,
+    /// The chunks `force` let through that the spec allows only one of.
+    pub duplicates: Vec<Duplicate>,
+}
+
+/// Adds each of `chunks` to `file` as `opts` say. Every check is made
+/// before anything is added, so on error the file is left as it was.
+pub fn encode_chunks<F: ChunkList + ?Sized>(
+    file: &mut F,
+    chunks: Vec<Chunk>,
+    opts: EncodeOptions,
+) -> Result<Encoded, MessageError> {
+    let find = |file: &F, chunk_type: ChunkType| {
+        (0..file.chunk_count()).find(|&index| file.type_at(index) == Some(chunk_type))
+    };
+    if opts.reserved == ReservedTypes::Refuse
+        && let Some(chunk) = chunks
+            .iter()
+            .find(|c| !c.chunk_type().is_reserved_bit_valid())
+    {
+        return Err(MessageError::Reserved(*chunk.chunk_type()));
+    }
+    if opts.position == Position::BeforeIdat
+        && !opts.conventional
+        && find(file, ChunkType::IDAT).is_none()
+    {
+        return Err(MessageError::NoIdat);
+    }
+    let mut duplicates = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let chunk_type = *chunk.chunk_type();
+        // Replacing never makes a second chunk of a type
+        if opts.replace || !standard::is_unique(&chunk_type) {
+            continue;
+        }
+        let existing = find(file, chunk_type);
+        if existing.is_none() && !chunks[..i].iter().any(|c| *c.chunk_type() == chunk_type) {
+            continue;
+        }
+        let duplicate = Duplicate {
+            chunk_type,
+            existing,
+        };
+        if !opts.force {
+            return Err(MessageError::NotUnique(duplicate));
+        }
+        duplicates.push(duplicate);
+    }
+    let before = file.total_size();
+    let mut after = before;
+    for (i, chunk) in chunks.iter().enumerate() {
+        // The first chunk of the type when this one is added, which an
+        // earlier one may have replaced
+        let replaced = match opts.replace {
+            true => chunks[..i]
+                .iter()
+                .rev()
+                .find(|c| c.chunk_type() == chunk.chunk_type())
+                .map(|c| c.length() as usize + 12)
+                .or_else(|| find(file, *chunk.chunk_type()).and_then(|i| file.size_at(i))),
+            false => None,
+        };
+        after = after + chunk.length() as usize + 12 - replaced.unwrap_or(0);
+    }
+    let report = SizeReport {
+        before,
+        after,
+        payload: chunks.iter().map(|c| c.data().len()).sum(),
+    };
+    if let Some(max_growth) = opts.max_growth
+        && !opts.force
+        && after > before
+        && report.growth_percent() > max_growth as f64
+    {
+        return Err(MessageError::TooMuchGrowth {
+            growth: report.growth_percent(),
+            max_growth,
+        });
+    }
+    let mut indices: Vec<usize> = Vec::new();
+    for chunk in chunks {
+        let chunk_type = *chunk.chunk_type();
+        if opts.replace
+            && let Some(index) = find(file, chunk_type)
+        {
+            file.replace_at(index, chunk);
+            indices.push(index);
+            continue;
+        }
+        let index = if opts.conventional {
+            let types: Vec<ChunkType> = (0..file.chunk_count())
+                .filter_map(|i| file.type_at(i))
+                .collect();
+            standard::conventional_index(&types, &chunk_type)
+        } else {
+            match opts.position {
+                Position::BeforeIdat => find(file, ChunkType::IDAT).expect("checked above"),
+                // Where `Png::append_chunks` puts it
+                Position::AfterIdat => find(file, ChunkType::IEND).unwrap_or(file.chunk_count()),
+                Position::AfterIend => file.chunk_count(),
+            }
+        };
+        // Chunks added earlier at or after this one have moved down
+        for earlier in indices.iter_mut().filter(|i| **i >= index) {
+            *earlier += 1;
+        }
+        file.insert_at(index, chunk);
+        indices.push(index);
+    }
+    Ok(Encoded {
+        report,
+        indices,
+        duplicates,
+    })
+}
+
+impl Png {
+    /// Stores `payload` in a chunk of `chunk_type`, through `encode_chunks`.
+    /// On error the file is left as it was.
+    pub fn encode_message(
+        &mut self,
+        chunk_type: &str,
+        payload: &[u8],
+        opts: EncodeOptions,
+    ) -> Result<(), MessageError> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        encode_chunks(self, vec![Chunk::new(chunk_type, payload.to_vec())], opts)?;
+        Ok(())
+    }
+    /// The data of the first chunk of `chunk_type`, or `None` if there isn't one.
+    pub fn decode_message(&self, chunk_type: &str) -> Result<Option<Vec<u8>>, MessageError> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        Ok(self
+            .chunks()
+            .iter()
+            .find(|c| *c.chunk_type() == chunk_type)
+            .map(|c| c.data().to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+
+    fn png() -> Png {
+        PngBuilder::new(1, 1).build().unwrap()
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn at(position: Position) -> EncodeOptions {
+        EncodeOptions {
+            position,
+            ..EncodeOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_default_matches_append() {
+        let mut encoded = png();
+        encoded
+            .encode_message("ruSt", b"hello", EncodeOptions::default())
+            .unwrap();
+        let mut appended = png();
+        appended.append_chunks([Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"hello".to_vec(),
+        )]);
+        assert_eq!(encoded.as_bytes(), appended.as_bytes());
+        assert_eq!(types(&encoded), ["IHDR", "IDAT", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_position() {
+        let mut png = png();
+        png.encode_message("ruSa", b"a", at(Position::BeforeIdat))
+            .unwrap();
+        png.encode_message("ruSb", b"b", at(Position::AfterIend))
+            .unwrap();
+        assert_eq!(types(&png), ["IHDR", "ruSa", "IDAT", "IEND", "ruSb"]);
+
+        let mut no_idat = Png::from_chunks(vec![png.chunks()[0].clone()]);
+        assert_eq!(
+            no_idat.encode_message("ruSt", b"", at(Position::BeforeIdat)),
+            Err(MessageError::NoIdat)
+        );
+        assert_eq!(no_idat.chunk_count(), 1);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut png = png();
+        png.encode_message("ruSt", b"one", EncodeOptions::default())
+            .unwrap();
+        png.encode_message("ruSt", b"two", EncodeOptions::default())
+            .unwrap();
+        assert_eq!(png.decode_message("ruSt").unwrap(), Some(b"one".to_vec()));
+
+        let replace = EncodeOptions {
+            replace: true,
+            // Ignored when there is a chunk to replace
+            position: Position::AfterIend,
+            ..EncodeOptions::default()
+        };
+        png.encode_message("ruSt", b"three", replace).unwrap();
+        assert_eq!(types(&png), ["IHDR", "IDAT", "ruSt", "ruSt", "IEND"]);
+        assert_eq!(png.decode_message("ruSt").unwrap(), Some(b"three".to_vec()));
+
+        // Nothing to replace, so added at the position
+        png.encode_message("ruSx", b"new", replace).unwrap();
+        assert_eq!(types(&png).last().unwrap(), "ruSx");
+    }
+
+    #[test]
+    fn test_reserved_types() {
+        let mut png = png();
+        png.encode_message("rust", b"", EncodeOptions::default())
+            .unwrap();
+        let refuse = EncodeOptions {
+            reserved: ReservedTypes::Refuse,
+            ..EncodeOptions::default()
+        };
+        let error = png.encode_message("ruby", b"", refuse).unwrap_err();
+        assert_eq!(
+            error,
+            MessageError::Reserved(ChunkType::from_str("ruby").unwrap())
+        );
+        png.encode_message("ruBy", b"", refuse).unwrap();
+        assert_eq!(types(&png), ["IHDR", "IDAT", "rust", "ruBy", "IEND"]);
+    }
+
+    #[test]
+    fn test_conventional() {
+        let mut png = png();
+        let conventional = EncodeOptions {
+            conventional: true,
+            ..EncodeOptions::default()
+        };
+        png.encode_message("tEXt", b"Title\0x", conventional)
+            .unwrap();
+        assert_eq!(types(&png), ["IHDR", "tEXt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_unique_types() {
+        let mut png = png();
+        let time_type = ChunkType::from_str("tIME").unwrap();
+        let time = |second| Chunk::new(time_type, vec![7, 234, 1, 1, 0, 0, second]);
+        let encoded = encode_chunks(&mut png, vec![time(0)], EncodeOptions::default()).unwrap();
+        assert_eq!(encoded.indices, [2]);
+        assert!(encoded.duplicates.is_empty());
+
+        let before = png.as_bytes();
+        let error = encode_chunks(&mut png, vec![time(1)], EncodeOptions::default()).unwrap_err();
+        let duplicate = Duplicate {
+            chunk_type: time_type,
+            existing: Some(2),
+        };
+        assert_eq!(error, MessageError::NotUnique(duplicate));
+        assert_eq!(
+            error.to_string(),
+            "There may be only one tIME and the file already has one at chunk 2"
+        );
+        assert_eq!(png.as_bytes(), before);
+
+        let mut fresh = self::png();
+        let error = encode_chunks(&mut fresh, vec![time(0), time(1)], EncodeOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "There may be only one tIME and it's given more than once"
+        );
+
+        let force = EncodeOptions {
+            force: true,
+            ..EncodeOptions::default()
+        };
+        let encoded = encode_chunks(&mut png, vec![time(1)], force).unwrap();
+        assert_eq!(encoded.indices, [3]);
+        assert_eq!(encoded.duplicates, [duplicate]);
+
+        // Replacing the one there is is fine
+        let replace = EncodeOptions {
+            replace: true,
+            ..EncodeOptions::default()
+        };
+        encode_chunks(&mut png, vec![time(2), time(3)], replace).unwrap();
+        assert_eq!(png.chunks()[2].data()[6], 3);
+    }
+
+    #[test]
+    fn test_max_growth() {
+        let mut png = png();
+        let size = png.total_size();
+        let limited = EncodeOptions {
+            max_growth: Some(50),
+            ..EncodeOptions::default()
+        };
+        let payload = vec![0; size];
+        let error = png.encode_message("ruSt", &payload, limited).unwrap_err();
+        assert!(matches!(
+            error,
+            MessageError::TooMuchGrowth { max_growth: 50, .. }
+        ));
+        assert_eq!(png.total_size(), size);
+
+        png.encode_message("ruSt", &payload[..size / 4], limited)
+            .unwrap();
+        let forced = EncodeOptions {
+            force: true,
+            ..limited
+        };
+        png.encode_message("ruSt", &payload, forced).unwrap();
+        // Replacing something the same size doesn't grow the file
+        let replace = EncodeOptions {
+            replace: true,
+            ..limited
+        };
+        png.encode_message("ruSt", &payload[..size / 4], replace)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_matches_png() {
+        use crate::png::ParseOptions;
+        let bytes = png().as_bytes();
+        let chunks = || {
+            vec![
+                Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"a\0b".to_vec()),
+                Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"c".to_vec()),
+            ]
+        };
+        let conventional = EncodeOptions {
+            conventional: true,
+            ..EncodeOptions::default()
+        };
+        for opts in [
+            EncodeOptions::default(),
+            conventional,
+            at(Position::AfterIend),
+        ] {
+            let mut png = png();
+            let mut rewrite = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap();
+            let encoded = encode_chunks(&mut png, chunks(), opts).unwrap();
+            assert_eq!(
+                encode_chunks(&mut rewrite, chunks(), opts).unwrap(),
+                encoded
+            );
+            let mut output = Vec::new();
+            rewrite
+                .write_to(&mut std::io::Cursor::new(&bytes), &mut output)
+                .unwrap();
+            assert_eq!(output, png.as_bytes(), "{opts:?}");
+            for (chunk, &index) in chunks().iter().zip(&encoded.indices) {
+                assert_eq!(png.chunks()[index], *chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_types() {
+        let mut png = png();
+        for bad in ["ru5t", "rus", "rusty"] {
+            assert!(matches!(
+                png.encode_message(bad, b"", EncodeOptions::default()),
+                Err(MessageError::InvalidType(_))
+            ));
+            assert!(png.decode_message(bad).is_err());
+        }
+        assert_eq!(png.decode_message("ruSt").unwrap(), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! Messages with placeholders filled in as they're encoded, as `encode
+//! --template` does, e.g. `build={env:BUILD_ID} file={filename}`:
+//!
+//! - `{env:VAR}`, the environment variable `VAR`
+//! - `{filename}`, `{filesize}` and `{sha256}`, of the file as it was
+//!   before the edit
+//! - `{now}` or `{now:FORMAT}`, the time in UTC, with `%Y`, `%m`, `%d`,
+//!   `%H`, `%M`, `%S`, `%s` (seconds since the epoch) and `%%`. Without a
+//!   format it's `%Y-%m-%dT%H:%M:%SZ`. If `SOURCE_DATE_EPOCH` is set it's
+//!   that time instead of the clock, so builds can be reproduced
+//!
+//! `{filename}` depends on the name of the file, so the same file under
+//! another name gets a different message.
+//!
+//! `{{` and `}}` are literal braces.
+
+use crate::digest::sha256_hex;
+use crate::format::Utc;
+use std::fmt::{self, Display, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Every placeholder, as the error for an unknown one lists them.
+pub const PLACEHOLDERS: [&str; 5] = ["env:VAR", "filename", "filesize", "sha256", "now"];
+
+/// What `{now}` is written as without a format.
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageTemplateError {
+    UnknownPlaceholder(String),
+    /// `{env:VAR}` for a variable that isn't set, or isn't UTF-8.
+    MissingEnv(String),
+    /// A `%` in a `{now:...}` format that isn't one of the ones known.
+    BadTimeFormat(String),
+    /// `SOURCE_DATE_EPOCH` set to something other than a number of seconds.
+    BadSourceDateEpoch(String),
+    UnclosedBrace,
+    UnmatchedBrace,
+}
+
+impl Display for MessageTemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageTemplateError::UnknownPlaceholder(name) => write!(
+                f,
+                "Unknown placeholder '{{{name}}}', the placeholders are {}",
+                PLACEHOLDERS.join(", ")
+            ),
+            MessageTemplateError::MissingEnv(name) => write!(
+                f,
+                "The environment variable {name} isn't set; pass --allow-missing to leave it empty"
+            ),
+            MessageTemplateError::BadTimeFormat(spec) => write!(
+                f,
+                "'{spec}' isn't a time format, use %Y, %m, %d, %H, %M, %S, %s or %%"
+            ),
+            MessageTemplateError::BadSourceDateEpoch(value) => write!(
+                f,
+                "SOURCE_DATE_EPOCH is '{value}', not a number of seconds since the epoch"
+            ),
+            MessageTemplateError::UnclosedBrace => write!(f, "A '{{' is never closed"),
+            MessageTemplateError::UnmatchedBrace => {
+                write!(f, "A '}}' has no '{{', write '}}}}' for a literal one")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MessageTemplateError {}
+
+/// What the placeholders about the file stand for, taken before it's edited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileFacts {
+    /// The last component of the path.
+    pub filename: String,
+    pub filesize: u64,
+    /// As 64 lowercase hex digits.
+    pub sha256: String,
+}
+
+impl FileFacts {
+    /// The facts about `bytes`, as read from `path`.
+    pub fn new(path: &Path, bytes: &[u8]) -> Self {
+        FileFacts {
+            filename: path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            filesize: bytes.len() as u64,
+            sha256: sha256_hex(bytes),
+        }
+    }
+}
+
+/// Everything a placeholder can stand for.
+pub struct Values<'a> {
+    pub file: &'a FileFacts,
+    pub now: SystemTime,
+    /// Looks up an environment variable, `std::env::var(name).ok()` but
+    /// for tests.
+    pub env: &'a dyn Fn(&str) -> Option<String>,
+    /// Leave `{env:VAR}` empty for a variable that isn't set, rather than fail.
+    pub allow_missing: bool,
+}
+
+/// The time `{now}` stands for: `SOURCE_DATE_EPOCH` if `env` has it, or
+/// else the clock.
+pub fn now(env: &dyn Fn(&str) -> Option<String>) -> Result<SystemTime, MessageTemplateError> {
+    match env("SOURCE_DATE_EPOCH") {
+        Some(value) => value
+            .parse()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+            .map_err(|_| MessageTemplateError::BadSourceDateEpoch(value)),
+        None => Ok(SystemTime::now()),
+    }
+}
+
+/// `template` with every placeholder replaced.
+pub fn expand(template: &str, values: &Values) -> Result<String, MessageTemplateError> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(at) = rest.find(['{', '}']) {
+        out.push_str(&rest[..at]);
+        let (brace, after) = rest[at..].split_at(1);
+        if let Some(after) = after.strip_prefix(brace) {
+            out.push_str(brace);
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            return Err(MessageTemplateError::UnmatchedBrace);
+        }
+        let end = after.find('}').ok_or(MessageTemplateError::UnclosedBrace)?;
+        let placeholder = &after[..end];
+        match placeholder.split_once(':') {
+            Some(("env", name)) => match (values.env)(name) {
+                Some(value) => out.push_str(&value),
+                None if values.allow_missing => {}
+                None => return Err(MessageTemplateError::MissingEnv(name.to_string())),
+            },
+            Some(("now", format)) => format_time(&mut out, format, Utc::from(values.now))?,
+            None if placeholder == "now" => {
+                format_time(&mut out, DEFAULT_TIME_FORMAT, Utc::from(values.now))?
+            }
+            None if placeholder == "filename" => out.push_str(&values.file.filename),
+            None if placeholder == "filesize" => {
+                let _ = write!(out, "{}", values.file.filesize);
+            }
+            None if placeholder == "sha256" => out.push_str(&values.file.sha256),
+            _ => {
+                return Err(MessageTemplateError::UnknownPlaceholder(
+                    placeholder.to_string(),
+                ));
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Writes `time` to `out` as `format` says.
+fn format_time(out: &mut String, format: &str, time: Utc) -> Result<(), MessageTemplateError> {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('Y') => write!(out, "{:04}", time.year),
+            Some('m') => write!(out, "{:02}", time.month),
+            Some('d') => write!(out, "{:02}", time.day),
+            Some('H') => write!(out, "{:02}", time.hour),
+            Some('M') => write!(out, "{:02}", time.minute),
+            Some('S') => write!(out, "{:02}", time.second),
+            Some('s') => write!(out, "{}", time.unix),
+            Some('%') => write!(out, "%"),
+            Some(other) => return Err(MessageTemplateError::BadTimeFormat(format!("%{other}"))),
+            None => return Err(MessageTemplateError::BadTimeFormat("%".to_string())),
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_with(template: &str, allow_missing: bool) -> Result<String, MessageTemplateError> {
+        let file = FileFacts::new(Path::new("images/a.png"), b"abc");
+        let env = |name: &str| (name == "BUILD_ID").then(|| "42".to_string());
+        let values = Values {
+            file: &file,
+            now: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            env: &env,
+            allow_missing,
+        };
+        expand(template, &values)
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            expand_with(
+                "build={env:BUILD_ID} file={filename} size={filesize}",
+                false
+            )
+            .unwrap(),
+            "build=42 file=a.png size=3"
+        );
+        assert_eq!(
+            expand_with("{sha256}", false).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            expand_with("{now} {now:%Y%m%d} {now:%s} {now:100%%}", false).unwrap(),
+            "2023-11-14T22:13:20Z 20231114 1700000000 100%"
+        );
+        assert_eq!(
+            expand_with("{{env:BUILD_ID}} }}", false),
+            Ok("{env:BUILD_ID} }".to_string())
+        );
+        assert_eq!(expand_with("plain text", false).unwrap(), "plain text");
+    }
+
+    #[test]
+    fn test_errors() {
+        let fails = |template: &str| expand_with(template, false).unwrap_err();
+        assert_eq!(
+            fails("{env:GIT_SHA}"),
+            MessageTemplateError::MissingEnv("GIT_SHA".into())
+        );
+        assert_eq!(expand_with("[{env:GIT_SHA}]", true).unwrap(), "[]");
+        assert_eq!(
+            fails("{file}"),
+            MessageTemplateError::UnknownPlaceholder("file".into())
+        );
+        assert_eq!(
+            fails("{filename:x}"),
+            MessageTemplateError::UnknownPlaceholder("filename:x".into())
+        );
+        assert_eq!(
+            fails("{now:%Y-%q}"),
+            MessageTemplateError::BadTimeFormat("%q".into())
+        );
+        assert_eq!(
+            fails("{now:%}"),
+            MessageTemplateError::BadTimeFormat("%".into())
+        );
+        assert_eq!(
+            fails("{}"),
+            MessageTemplateError::UnknownPlaceholder("".into())
+        );
+        assert_eq!(fails("a {filename"), MessageTemplateError::UnclosedBrace);
+        assert_eq!(fails("a } b"), MessageTemplateError::UnmatchedBrace);
+        // Unknown placeholders fail even with --allow-missing
+        assert!(expand_with("{nope}", true).is_err());
+    }
+
+    #[test]
+    fn test_source_date_epoch() {
+        let env = |name: &str| (name == "SOURCE_DATE_EPOCH").then(|| "1700000000".to_string());
+        assert_eq!(
+            now(&env),
+            Ok(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        let env = |_: &str| Some("yesterday".to_string());
+        assert_eq!(
+            now(&env),
+            Err(MessageTemplateError::BadSourceDateEpoch("yesterday".into()))
+        );
+        assert!(now(&|_| None).is_ok());
+    }
+}
@@ -0,0 +1,241 @@
+//! Lossless, chunk-level size reduction: each pass drops or joins chunks
+//! without touching the image data itself.
+
+use crate::chunk_type::ChunkType;
+use crate::png::{ParseOptions, Png};
+
+/// Which passes to run. `Default` runs all of them and removes no extra types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    pub strip_trailing: bool,
+    /// Ancillary chunk types to remove entirely.
+    pub remove: Vec<ChunkType>,
+    pub drop_empty: bool,
+    pub dedup: bool,
+    pub merge_idat: bool,
+    /// Largest IDAT that merging may produce.
+    pub max_idat_len: u32,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            strip_trailing: true,
+            remove: Vec::new(),
+            drop_empty: true,
+            dedup: true,
+            merge_idat: true,
+            max_idat_len: ParseOptions::SPEC_MAX_CHUNK_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    StripTrailing,
+    Remove,
+    DropEmpty,
+    Dedup,
+    MergeIdat,
+}
+
+impl std::fmt::Display for Pass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Pass::StripTrailing => "strip data after IEND",
+            Pass::Remove => "remove listed chunk types",
+            Pass::DropEmpty => "drop empty ancillary chunks",
+            Pass::Dedup => "drop duplicate ancillary chunks",
+            Pass::MergeIdat => "merge adjacent IDAT chunks",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// What one pass did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saving {
+    pub pass: Pass,
+    /// Chunks removed or merged away.
+    pub chunks: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptimizeError(pub ChunkType);
+
+impl std::fmt::Display for OptimizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is critical and can't be removed losslessly", self.0)
+    }
+}
+
+impl std::error::Error for OptimizeError {}
+
+/// Runs the passes enabled in `options` in a fixed order, trailing data
+/// first and IDAT merging last, and reports what each one saved.
+pub fn optimize(png: &mut Png, options: &OptimizeOptions) -> Result<Vec<Saving>, OptimizeError> {
+    if let Some(critical) = options.remove.iter().find(|t| t.is_critical()) {
+        return Err(OptimizeError(*critical));
+    }
+    let mut savings = Vec::new();
+    let mut run = |pass: Pass, png: &mut Png, f: &mut dyn FnMut(&mut Png) -> usize| {
+        let before = png.total_size();
+        let chunks = f(png);
+        savings.push(Saving {
+            pass,
+            chunks,
+            bytes: before - png.total_size(),
+        });
+    };
+    if options.strip_trailing {
+        run(Pass::StripTrailing, png, &mut |png| {
+            png.truncate_after_iend().0.len()
+        });
+    }
+    if !options.remove.is_empty() {
+        run(Pass::Remove, png, &mut |png| {
+            png.remove_matching(|c| options.remove.contains(c.chunk_type()))
+                .len()
+        });
+    }
+    if options.drop_empty {
+        run(Pass::DropEmpty, png, &mut |png| {
+            png.remove_empty_ancillary().len()
+        });
+    }
+    if options.dedup {
+        run(Pass::Dedup, png, &mut |png| {
+            png.remove_duplicate_ancillary().len()
+        });
+    }
+    if options.merge_idat {
+        run(Pass::MergeIdat, png, &mut |png| {
+            png.merge_idat(options.max_idat_len)
+        });
+    }
+    Ok(savings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn chunk(name: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+    }
+
+    /// A 4x4 image with its IDAT split in three, a duplicate and an empty
+    /// ancillary chunk, and a chunk after IEND.
+    fn bloated() -> Png {
+        let png = PngBuilder::new(4, 4).build().unwrap();
+        let mut chunks = png.chunks().to_vec();
+        let idat = chunks.remove(1);
+        let data = idat.data();
+        let third = data.len() / 3;
+        chunks.splice(
+            1..1,
+            [
+                chunk("tEXt", b"Comment\0hi"),
+                chunk("IDAT", &data[..third]),
+                chunk("IDAT", &data[third..2 * third]),
+                chunk("IDAT", &data[2 * third..]),
+                chunk("tEXt", b"Comment\0hi"),
+                chunk("ruSt", b""),
+                chunk("prVt", b"private"),
+            ],
+        );
+        chunks.push(chunk("ruSt", b"after IEND"));
+        Png::from_chunks(chunks)
+    }
+
+    fn pixels(png: &Png) -> Vec<u8> {
+        let decoder = png::Decoder::new(std::io::Cursor::new(png.as_bytes()));
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut buf).unwrap();
+        buf
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_optimize_all_passes() {
+        let mut png = bloated();
+        let expected = pixels(&png);
+        let before = png.total_size();
+        let options = OptimizeOptions {
+            remove: vec![ChunkType::from_str("prVt").unwrap()],
+            ..OptimizeOptions::default()
+        };
+        let savings = optimize(&mut png, &options).unwrap();
+        let counts: Vec<(Pass, usize)> = savings.iter().map(|s| (s.pass, s.chunks)).collect();
+        assert_eq!(
+            counts,
+            [
+                (Pass::StripTrailing, 1),
+                (Pass::Remove, 1),
+                (Pass::DropEmpty, 1),
+                (Pass::Dedup, 1),
+                (Pass::MergeIdat, 2),
+            ]
+        );
+        // Each merge saves a chunk's 12 bytes of framing
+        assert_eq!(savings[4].bytes, 24);
+        let total: usize = savings.iter().map(|s| s.bytes).sum();
+        assert_eq!(before - png.total_size(), total);
+        assert_eq!(types(&png), ["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(pixels(&png), expected);
+    }
+
+    #[test]
+    fn test_passes_can_be_turned_off() {
+        let mut png = bloated();
+        let options = OptimizeOptions {
+            strip_trailing: false,
+            drop_empty: false,
+            dedup: false,
+            ..OptimizeOptions::default()
+        };
+        let savings = optimize(&mut png, &options).unwrap();
+        assert_eq!(savings.len(), 1);
+        assert_eq!(types(&bloated()).len() - types(&png).len(), 2);
+        assert_eq!(pixels(&png), pixels(&bloated()));
+    }
+
+    #[test]
+    fn test_merge_respects_max_len() {
+        let mut png = bloated();
+        let lengths = |png: &Png| -> Vec<u32> {
+            png.chunks()
+                .iter()
+                .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+                .map(|c| c.length())
+                .collect()
+        };
+        let split = lengths(&png);
+        // Room for the first two but not all three
+        assert_eq!(png.merge_idat(split[0] + split[1]), 1);
+        assert_eq!(lengths(&png), [split[0] + split[1], split[2]]);
+    }
+
+    #[test]
+    fn test_refuses_to_remove_critical() {
+        let options = OptimizeOptions {
+            remove: vec![ChunkType::IDAT],
+            ..OptimizeOptions::default()
+        };
+        assert_eq!(
+            optimize(&mut bloated(), &options),
+            Err(OptimizeError(ChunkType::IDAT))
+        );
+    }
+}
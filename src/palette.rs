@@ -0,0 +1,173 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::Ihdr;
+
+pub const PLTE: ChunkType = ChunkType::literal(*b"PLTE");
+pub const TRNS: ChunkType = ChunkType::literal(*b"tRNS");
+
+/// The colors of a PLTE chunk, in index order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    NotPlte,
+    /// The data isn't a whole number of RGB triples.
+    BadLength(usize),
+    Empty,
+    TooManyEntries(usize),
+}
+
+impl std::fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::NotPlte => write!(f, "Not a PLTE chunk"),
+            PaletteError::BadLength(len) => {
+                write!(f, "PLTE length {len} is not a multiple of 3")
+            }
+            PaletteError::Empty => write!(f, "PLTE has no entries"),
+            PaletteError::TooManyEntries(n) => {
+                write!(f, "PLTE has {n} entries, more than the maximum of 256")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl Palette {
+    pub fn parse(data: &[u8]) -> Result<Palette, PaletteError> {
+        if !data.len().is_multiple_of(3) {
+            return Err(PaletteError::BadLength(data.len()));
+        }
+        let colors: Vec<[u8; 3]> = data
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        match colors.len() {
+            0 => Err(PaletteError::Empty),
+            n if n > 256 => Err(PaletteError::TooManyEntries(n)),
+            _ => Ok(Palette { colors }),
+        }
+    }
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+}
+
+impl TryFrom<&Chunk> for Palette {
+    type Error = PaletteError;
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if *chunk.chunk_type() != PLTE {
+            return Err(PaletteError::NotPlte);
+        }
+        Palette::parse(chunk.data())
+    }
+}
+
+/// Problems with how the palette fits the rest of the image: the header's
+/// color type and bit depth, and any tRNS alpha values. Each is a message.
+/// `plte` and `trns` are the raw chunk data, if those chunks are present.
+pub fn check(ihdr: &Ihdr, plte: Option<&[u8]>, trns: Option<&[u8]>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let palette = match plte.map(Palette::parse) {
+        None => {
+            if ihdr.color_type == Ihdr::INDEXED {
+                problems.push("Indexed image has no PLTE chunk".to_string());
+            }
+            return problems;
+        }
+        Some(Err(e)) => {
+            problems.push(e.to_string());
+            return problems;
+        }
+        Some(Ok(palette)) => palette,
+    };
+    match ihdr.color_type {
+        Ihdr::INDEXED => {
+            let max = 1usize.checked_shl(ihdr.bit_depth.into()).unwrap_or(0);
+            if palette.len() > max {
+                problems.push(format!(
+                    "PLTE has {} entries but bit depth {} allows at most {max}",
+                    palette.len(),
+                    ihdr.bit_depth
+                ));
+            }
+            if let Some(trns) = trns
+                && trns.len() > palette.len()
+            {
+                problems.push(format!(
+                    "tRNS has {} alpha values but PLTE only has {} entries",
+                    trns.len(),
+                    palette.len()
+                ));
+            }
+        }
+        0 | 4 => problems.push(format!(
+            "PLTE is not allowed with color type {} ({})",
+            ihdr.color_type,
+            ihdr.color_type_name()
+        )),
+        _ => {}
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr(color_type: u8, bit_depth: u8) -> Ihdr {
+        Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            compression: 0,
+            filter: 0,
+            interlace: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        let chunk = Chunk::new(PLTE, vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 9, 9, 9]);
+        let palette = Palette::try_from(&chunk).unwrap();
+        assert_eq!(palette.len(), 4);
+        assert_eq!(palette.colors()[1], [0, 255, 0]);
+    }
+
+    #[test]
+    fn test_malformed_palette() {
+        assert_eq!(Palette::parse(&[1, 2]), Err(PaletteError::BadLength(2)));
+        assert_eq!(Palette::parse(&[]), Err(PaletteError::Empty));
+        assert_eq!(
+            Palette::parse(&[0; 257 * 3]),
+            Err(PaletteError::TooManyEntries(257))
+        );
+        let chunk = Chunk::new(TRNS, vec![0, 0, 0]);
+        assert_eq!(Palette::try_from(&chunk), Err(PaletteError::NotPlte));
+    }
+
+    #[test]
+    fn test_check() {
+        let four = [0; 12];
+        assert!(check(&ihdr(3, 2), Some(&four), Some(&[0, 128])).is_empty());
+        assert!(check(&ihdr(2, 8), Some(&four), None).is_empty());
+        assert!(check(&ihdr(2, 8), None, None).is_empty());
+
+        assert_eq!(check(&ihdr(3, 1), Some(&four), None).len(), 1);
+        assert_eq!(check(&ihdr(3, 2), Some(&four), Some(&[0; 5])).len(), 1);
+        assert_eq!(check(&ihdr(3, 8), None, None).len(), 1);
+        assert_eq!(check(&ihdr(0, 8), Some(&four), None).len(), 1);
+        assert_eq!(check(&ihdr(3, 8), Some(&[0; 4]), None).len(), 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! Workarounds for things the shell or OS does differently on Windows. The
+//! helpers themselves are portable so they're tested everywhere; only their
+//! callers check the platform.
+
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes raw bytes to stdout. Rust never puts stdout in text mode, so
+/// redirected output is byte for byte, but the Windows console only accepts
+/// UTF-8 and would fail partway through anything else.
+pub fn write_stdout(bytes: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    if cfg!(windows) && stdout.is_terminal() && std::str::from_utf8(bytes).is_err() {
+        return Err(io::Error::other(
+            "Refusing to write binary data to the console, redirect it to a file",
+        ));
+    }
+    stdout.write_all(bytes)?;
+    stdout.flush()
+}
+
+/// A path from raw bytes, such as a list written by `find -print0`. Unix
+/// paths are any bytes; elsewhere they're taken as UTF-8.
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Matches `name` against a pattern where `*` is any run of characters and
+/// `?` is exactly one.
+pub fn wildcard_match(pattern: &str, name: &str, ignore_case: bool) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let same = |a: char, b: char| {
+        if ignore_case {
+            a.to_lowercase().eq(b.to_lowercase())
+        } else {
+            a == b
+        }
+    };
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` if the rest stops matching
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || same(c, name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    p = star + 1;
+                    n = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Expands `*` and `?` in the last component of each path into the
+/// matching file names, sorted, the way a Unix shell would before we ever
+/// see them. cmd.exe and PowerShell pass patterns through as they are.
+///
+/// Only for arguments already known to be paths: a message or chunk type
+/// with a `*` in it is meant literally, and quoting can't say so on Windows.
+/// Like a shell, a path that matches nothing is kept literally.
+pub fn expand_wildcards(paths: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        match path.to_str().and_then(expand_one) {
+            Some(matches) => expanded.extend(matches),
+            None => expanded.push(path),
+        }
+    }
+    expanded
+}
+
+/// Whether `path` has a pattern for `expand_wildcards` to expand.
+pub fn has_wildcard(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.contains(['*', '?']))
+}
+
+fn expand_one(arg: &str) -> Option<Vec<PathBuf>> {
+    let path = Path::new(arg);
+    let pattern = path.file_name()?.to_str()?;
+    if !pattern.contains(['*', '?']) {
+        return None;
+    }
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+    let mut names: Vec<String> = fs::read_dir(dir.unwrap_or(Path::new(".")))
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        // Hidden files only match a pattern that asks for them
+        .filter(|name| !name.starts_with('.') || pattern.starts_with('.'))
+        .filter(|name| wildcard_match(pattern, name, cfg!(windows)))
+        .collect();
+    if names.is_empty() {
+        return None;
+    }
+    names.sort();
+    Some(
+        names
+            .into_iter()
+            .map(|name| match dir {
+                Some(dir) => dir.join(name),
+                None => name.into(),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.png", "a.png", false));
+        assert!(wildcard_match("a?c*", "abc", false));
+        assert!(wildcard_match("*a*b", "xaxxab", false));
+        assert!(!wildcard_match("*.png", "a.png.bak", false));
+        assert!(!wildcard_match("?", "", false));
+        assert!(!wildcard_match("*.png", "A.PNG", false));
+        assert!(wildcard_match("*.png", "A.PNG", true));
+    }
+
+    #[test]
+    fn test_expand_wildcards() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["b.png", "a.png", "c.txt", ".hidden.png"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let pattern = dir.path().join("*.png");
+        let none = dir.path().join("*.gif");
+        let plain = dir.path().join("c.txt");
+        assert!(has_wildcard(&pattern) && !has_wildcard(&plain));
+        let expanded = expand_wildcards([pattern, none.clone(), plain.clone()]);
+        assert_eq!(
+            expanded,
+            [
+                dir.path().join("a.png"),
+                dir.path().join("b.png"),
+                none,
+                plain
+            ]
+        );
+    }
+
+    #[test]
+    fn test_only_paths_are_expanded() {
+        use crate::commands::{Args, Commands};
+        use clap::Parser;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["a.png", "b.png"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let pattern = dir.path().join("*.png");
+        let message = dir.path().join("*").to_str().unwrap().to_string();
+        let mut args = Args::try_parse_from([
+            "pngme",
+            "encode",
+            pattern.to_str().unwrap(),
+            "ruSt",
+            &message,
+            "--kv",
+            "ruSu:*",
+        ])
+        .unwrap();
+        // What main does with the file of a batch command on Windows
+        let (file, _) = args.command.as_mut().and_then(Commands::batch).unwrap();
+        let files = expand_wildcards(file.take());
+        assert_eq!(files, [dir.path().join("a.png"), dir.path().join("b.png")]);
+        let Some(Commands::Encode {
+            message: Some(kept),
+            kv,
+            ..
+        }) = args.command
+        else {
+            panic!("not encode")
+        };
+        assert_eq!(kept, message);
+        assert_eq!(kv[0].1, "*");
+    }
+}
@@ -1,29 +1,471 @@
 #![allow(unused, non_snake_case)]
 
-use crate::chunk::{Chunk, InvalidChunk};
+use crate::analysis::KnownFormat;
+use crate::chunk::{Chunk, ChunkRef, CrcMismatch, InvalidChunk, X25};
 use crate::chunk_type::ChunkType;
+use crate::format::{format_crc, format_size};
+use crate::selector::ChunkSelector;
+use crate::standard;
+use crate::text;
+use crate::timings::{self, Phase, Timed};
+use std::io::Read;
+use std::ops::Range;
 use std::str::FromStr;
 
+#[derive(Debug, Clone)]
 pub struct Png {
     chunks: Vec<Chunk>,
+    stray: Vec<StrayBytes>,
+    /// Whether anything changed since parsing or the last `mark_clean`.
+    modified: bool,
+}
+
+/// Two files are equal if they'd be written out the same, whether or not
+/// either was edited to get there.
+impl PartialEq for Png {
+    fn eq(&self, other: &Self) -> bool {
+        self.chunks == other.chunks && self.stray == other.stray
+    }
+}
+
+/// Bytes that a lenient parse had to skip to find the next valid chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrayBytes {
+    index: usize,
+    offset: usize,
+    bytes: Vec<u8>,
+}
+
+impl StrayBytes {
+    /// Index of the chunk these bytes precede, or the chunk count if they trail the file.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    /// Position of the first stray byte in the original file.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Skip over bytes that don't form a valid chunk, resuming at the next
+    /// position where a chunk with a matching CRC starts. A chunk whose type
+    /// isn't letters but whose CRC matches is kept, as `any_chunk_type` would,
+    /// where a chunk is expected to start; as with `fix_lengths`, not at
+    /// every byte skipped.
+    pub lenient: bool,
+    /// Largest chunk length accepted, checked before anything is allocated.
+    pub max_chunk_size: u32,
+    /// Accept chunks whose stored CRC doesn't match, keeping the stored value,
+    /// and don't spend time computing CRCs at all. `Chunk::verify_crc` and
+    /// `Png::verify_all_crcs` check them afterwards.
+    pub ignore_crc: bool,
+    /// When a chunk's declared length doesn't lead to a matching CRC, look for
+    /// the length that does and read the chunk with that instead. Only tried
+    /// where a chunk is expected to start, not at every byte a lenient parse
+    /// skips.
+    pub fix_lengths: bool,
+    /// Largest input accepted, in bytes. The streaming parser stops before
+    /// reading a chunk that would end past it.
+    pub max_file_size: u64,
+    /// Most chunks accepted. Unlimited by default; the CLI sets
+    /// `--max-chunks`, as a small file of empty chunks holds millions.
+    pub max_chunks: usize,
+    /// Most bytes the zTXt, iTXt and iCCP chunks may inflate to between them.
+    /// Anything below `u64::MAX` makes parsing inflate those chunks to check.
+    pub max_total_decompressed: u64,
+    /// Accept chunk types whose bytes aren't all ASCII letters, as
+    /// `ChunkType::from_bytes_unchecked` makes them.
+    pub any_chunk_type: bool,
+}
+
+impl ParseOptions {
+    /// The spec caps chunk lengths at 2^31 - 1.
+    pub const SPEC_MAX_CHUNK_SIZE: u32 = i32::MAX as u32;
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            lenient: false,
+            max_chunk_size: Self::SPEC_MAX_CHUNK_SIZE,
+            ignore_crc: false,
+            fix_lengths: false,
+            max_file_size: u64::MAX,
+            max_chunks: usize::MAX,
+            max_total_decompressed: u64::MAX,
+            any_chunk_type: false,
+        }
+    }
+}
+
+/// What the chunks parsed so far have used of `max_chunks` and
+/// `max_total_decompressed`.
+#[derive(Default)]
+struct Budget {
+    chunks: usize,
+    decompressed: u64,
+}
+
+impl Budget {
+    fn charge(
+        &mut self,
+        chunk_type: &ChunkType,
+        data: &[u8],
+        options: &ParseOptions,
+    ) -> Result<(), InvalidChunk> {
+        if self.chunks >= options.max_chunks {
+            return Err(InvalidChunk::TooManyChunks {
+                max: options.max_chunks,
+            });
+        }
+        self.chunks += 1;
+        let max = options.max_total_decompressed;
+        if max != u64::MAX {
+            self.decompressed += text::inflated_len(chunk_type, data, max - self.decompressed);
+            if self.decompressed > max {
+                return Err(InvalidChunk::TooMuchDecompressed { max });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A parse failure and the file offset of the chunk it happened in.
+#[derive(Debug)]
+pub struct ParseError {
+    offset: usize,
+    kind: InvalidChunk,
+}
+
+impl ParseError {
+    pub fn new(offset: usize, kind: InvalidChunk) -> Self {
+        Self { offset, kind }
+    }
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn kind(&self) -> &InvalidChunk {
+        &self.kind
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at offset {} (0x{:x})",
+            self.kind, self.offset, self.offset
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    /// Whether parsing stopped at one of the `ParseOptions` limits rather
+    /// than at something wrong with the input.
+    pub fn is_limit(&self) -> bool {
+        self.kind.is_limit()
+    }
+}
+
+/// How much a file grew when chunks were added to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeReport {
+    /// File size in bytes before the new chunks.
+    pub before: usize,
+    pub after: usize,
+    /// Data bytes carried by the new chunks, excluding their headers and CRCs.
+    pub payload: usize,
+}
+
+impl SizeReport {
+    /// Size increase as a percentage of the original file.
+    pub fn growth_percent(&self) -> f64 {
+        (self.after - self.before) as f64 * 100.0 / self.before as f64
+    }
+    /// Share of the resulting file taken up by the payload, as a percentage.
+    pub fn payload_percent(&self) -> f64 {
+        self.payload as f64 * 100.0 / self.after as f64
+    }
+}
+
+/// The chunks `Png::from_reader_partial` read before it had to stop.
+#[derive(Debug)]
+pub struct PartialParse {
+    /// Every chunk read intact, in order.
+    pub png: Png,
+    /// Why reading stopped early, or `None` if the whole stream was read.
+    pub error: Option<ReadError>,
+    /// Where the intact part ends: the offset of the chunk that couldn't be
+    /// read, or the length of the stream.
+    pub offset: usize,
+}
+
+impl PartialParse {
+    /// The file, or the error if reading stopped early.
+    pub fn into_result(self) -> Result<Png, ReadError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.png),
+        }
+    }
+}
+
+/// Errors from `Png::from_reader`, which can fail on I/O as well as on content.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(std::io::Error),
+    Invalid(ParseError),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "{e}"),
+            ReadError::Invalid(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl ReadError {
+    pub fn is_limit(&self) -> bool {
+        matches!(self, ReadError::Invalid(e) if e.is_limit())
+    }
+}
+
+impl From<std::io::Error> for ReadError {
+    fn from(value: std::io::Error) -> Self {
+        ReadError::Io(value)
+    }
+}
+
+impl From<ParseError> for ReadError {
+    fn from(value: ParseError) -> Self {
+        ReadError::Invalid(value)
+    }
+}
+
+/// Errors from `Png::splice_raw`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpliceError {
+    NoChunk {
+        index: usize,
+        count: usize,
+    },
+    /// The range doesn't lie within the chunk's `len` data bytes.
+    BadRange {
+        range: Range<usize>,
+        len: usize,
+    },
+    /// The data would grow past the spec's maximum chunk length.
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for SpliceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpliceError::NoChunk { index, count } => {
+                write!(f, "Chunk index {index} is out of range for {count} chunks")
+            }
+            SpliceError::BadRange { range, len } => write!(
+                f,
+                "Bytes {}..{} are outside the chunk's {}",
+                range.start,
+                range.end,
+                format_size(*len as u64, false)
+            ),
+            SpliceError::TooLarge(len) => write!(
+                f,
+                "The chunk would hold {len} bytes, more than the maximum of {}",
+                ParseOptions::SPEC_MAX_CHUNK_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpliceError {}
+
+/// What makes a png too broken for most tools to open: IHDR must come
+/// first and there must be an IEND. Chunks after IEND are allowed, as
+/// readers stop at IEND and edits keep them where they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructureViolation {
+    /// The first chunk, if there is one, isn't IHDR.
+    IhdrNotFirst { found: Option<ChunkType> },
+    /// There's no IEND to end the image.
+    NoIend,
+}
+
+impl std::fmt::Display for StructureViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StructureViolation::IhdrNotFirst { found: Some(found) } => {
+                write!(f, "The first chunk is {found}, not IHDR")
+            }
+            StructureViolation::IhdrNotFirst { found: None } => write!(f, "There are no chunks"),
+            StructureViolation::NoIend => write!(f, "There is no IEND chunk"),
+        }
+    }
+}
+
+/// Why `Png::as_bytes_validated` refused: every violation, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidStructure(pub Vec<StructureViolation>);
+
+impl std::fmt::Display for InvalidStructure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, violation) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{violation}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidStructure {}
+
+/// What's wrong with the structure of a png whose chunks have these types,
+/// in order. An empty file violates only once.
+pub fn structure_violations<'a>(
+    types: impl IntoIterator<Item = &'a ChunkType>,
+) -> Vec<StructureViolation> {
+    let mut types = types.into_iter();
+    let Some(&first) = types.next() else {
+        return vec![StructureViolation::IhdrNotFirst { found: None }];
+    };
+    let mut violations = Vec::new();
+    if first != ChunkType::IHDR {
+        violations.push(StructureViolation::IhdrNotFirst { found: Some(first) });
+    }
+    if first != ChunkType::IEND && !types.any(|t| *t == ChunkType::IEND) {
+        violations.push(StructureViolation::NoIend);
+    }
+    violations
 }
 
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Self { chunks }
+        Self {
+            chunks,
+            stray: Vec::new(),
+            modified: false,
+        }
+    }
+    /// Whether a chunk or stray byte was added, removed or changed since the
+    /// file was parsed or built, or since `mark_clean`. A parse that fixed
+    /// chunk lengths counts as a change. An edit that finds
+    /// nothing to do, such as removing duplicates from a file without any,
+    /// doesn't count.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+    /// Forgets about earlier changes, e.g. once they've been written out.
+    pub fn mark_clean(&mut self) {
+        self.modified = false;
     }
 
+    /// The signature written before the chunks. Parsing rejects anything other
+    /// than the standard one, so this is always `STANDARD_HEADER`.
+    pub fn header(&self) -> [u8; 8] {
+        Self::STANDARD_HEADER
+    }
+    /// Every parsed chunk in file order, IHDR and IEND included. Stray bytes
+    /// from a lenient parse aren't chunks and are in `stray_bytes` instead.
+    ///
+    /// There is no `chunks_mut`, because stray bytes are tracked by chunk
+    /// index; change the list with `insert_chunk`, `remove_chunk_at`,
+    /// `replace_chunk_at`, `swap_chunks` or `retain` so they stay in place.
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
     }
+    /// Same as `chunks().len()`.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+    /// True if there are no chunks at all, not even IHDR.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+    pub fn stray_bytes(&self) -> &[StrayBytes] {
+        &self.stray
+    }
+    /// Forgets any bytes skipped by a lenient parse so they aren't written back out.
+    pub fn drop_stray_bytes(&mut self) -> Vec<StrayBytes> {
+        self.modified |= !self.stray.is_empty();
+        std::mem::take(&mut self.stray)
+    }
     pub fn chunk_by_type(&self, chunk_name: &str) -> Option<&Chunk> {
         let chunk_type = ChunkType::from_str(chunk_name).ok()?;
         self.chunks.iter().find(|&x| *x.chunk_type() == chunk_type)
     }
+    /// Adds `chunk` after every other chunk, IEND included.
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.chunks.push(chunk);
+        // Inserting rather than pushing keeps trailing stray bytes at the end
+        self.insert_chunk(self.chunks.len(), chunk);
+    }
+    /// Adds `chunk` just before IEND, or at the end if there is no IEND, so
+    /// decoders don't skip it as trailing data.
+    pub fn insert_before_iend(&mut self, chunk: Chunk) {
+        match self
+            .chunks
+            .iter()
+            .position(|c| *c.chunk_type() == ChunkType::IEND)
+        {
+            Some(iend) => self.insert_chunk(iend, chunk),
+            // Inserting rather than pushing keeps trailing stray bytes at the end
+            None => self.insert_chunk(self.chunks.len(), chunk),
+        }
+    }
+    /// Adds `chunk` where the spec has chunks of its type go, e.g. gAMA
+    /// before PLTE or tRNS between PLTE and IDAT, and returns its index.
+    /// See `standard::placement`.
+    pub fn insert_conventional(&mut self, chunk: Chunk) -> usize {
+        let index = standard::conventional_index(
+            self.chunks.iter().map(Chunk::chunk_type),
+            chunk.chunk_type(),
+        );
+        self.insert_chunk(index, chunk);
+        index
+    }
+    /// Like `insert_conventional`, except that a chunk the spec allows only
+    /// one of, such as tIME, takes the place of the one already there,
+    /// which is returned along with the index.
+    pub fn insert_or_replace(&mut self, chunk: Chunk) -> (usize, Option<Chunk>) {
+        let existing = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type() == chunk.chunk_type());
+        match existing.filter(|_| standard::is_unique(chunk.chunk_type())) {
+            Some(index) => (index, self.replace_chunk_at(index, chunk)),
+            None => (self.insert_conventional(chunk), None),
+        }
+    }
+    /// Adds every chunk in turn before IEND and reports how the file size
+    /// changed.
+    pub fn append_chunks(&mut self, chunks: impl IntoIterator<Item = Chunk>) -> SizeReport {
+        let before = self.total_size();
+        let mut payload = 0;
+        for chunk in chunks {
+            payload += chunk.data().len();
+            self.insert_before_iend(chunk);
+        }
+        SizeReport {
+            before,
+            after: self.total_size(),
+            payload,
+        }
     }
     pub fn remove_first_chunk(&mut self, chunk_name: &str) -> Option<Chunk> {
         let chunk_type = ChunkType::from_str(chunk_name).ok()?;
@@ -31,59 +473,790 @@ impl Png {
             .chunks
             .iter()
             .position(|x| *x.chunk_type() == chunk_type)?;
-        Some(self.chunks.remove(pos))
+        self.remove_chunk_at(pos)
     }
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let chunk_bytes: Vec<u8> = self.chunks.iter().flat_map(|x| x.as_bytes()).collect();
-        Png::STANDARD_HEADER
+    pub fn remove_chunk_at(&mut self, index: usize) -> Option<Chunk> {
+        if index < self.chunks.len() {
+            for stray in self.stray.iter_mut().filter(|s| s.index > index) {
+                stray.index -= 1;
+            }
+            self.modified = true;
+            Some(self.chunks.remove(index))
+        } else {
+            None
+        }
+    }
+    /// Inserts `chunk` so that it ends up at `index`, shifting later chunks back.
+    /// Stray bytes stay attached to the chunk they preceded.
+    ///
+    /// Panics if `index` is greater than the number of chunks.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.chunks.insert(index, chunk);
+        for stray in self.stray.iter_mut().filter(|s| s.index >= index) {
+            stray.index += 1;
+        }
+        self.modified = true;
+    }
+    /// Every chunk whose stored CRC doesn't match its contents, with its
+    /// index. Only a file parsed with `ParseOptions::ignore_crc` or built
+    /// from chunks made with `Chunk::with_crc` has any.
+    pub fn verify_all_crcs(&self) -> Vec<(usize, CrcMismatch)> {
+        self.chunks
             .iter()
-            .chain(chunk_bytes.iter())
-            .copied()
+            .enumerate()
+            .filter_map(|(index, chunk)| chunk.verify_crc().err().map(|e| (index, e)))
             .collect()
     }
+    /// Swaps the chunk at `index` for `chunk`, returning the old one.
+    pub fn replace_chunk_at(&mut self, index: usize, chunk: Chunk) -> Option<Chunk> {
+        let slot = self.chunks.get_mut(index)?;
+        self.modified = true;
+        Some(std::mem::replace(slot, chunk))
+    }
+    /// Replaces `byte_range` of the data of the chunk at `chunk_index` with
+    /// `replacement`, which needn't be the same length, and recomputes the
+    /// chunk's length and CRC. Nothing checks that the result still makes
+    /// sense for the chunk's type.
+    pub fn splice_raw(
+        &mut self,
+        chunk_index: usize,
+        byte_range: Range<usize>,
+        replacement: &[u8],
+    ) -> Result<(), SpliceError> {
+        let count = self.chunks.len();
+        let chunk = self.chunks.get(chunk_index).ok_or(SpliceError::NoChunk {
+            index: chunk_index,
+            count,
+        })?;
+        let len = chunk.data().len();
+        if byte_range.start > byte_range.end || byte_range.end > len {
+            return Err(SpliceError::BadRange {
+                range: byte_range,
+                len,
+            });
+        }
+        let new_len = len - byte_range.len() + replacement.len();
+        if new_len > ParseOptions::SPEC_MAX_CHUNK_SIZE as usize {
+            return Err(SpliceError::TooLarge(new_len));
+        }
+        self.chunks[chunk_index]
+            .data_mut()
+            .splice(byte_range, replacement.iter().copied());
+        self.modified = true;
+        Ok(())
+    }
+    /// Overwrites the data of the chunk at `index` with `pattern` repeated,
+    /// keeping its type and length, and recomputes its CRC, so the file's
+    /// size and every chunk's offset stay the same. Returns the old chunk.
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` is empty.
+    pub fn redact_at(&mut self, index: usize, pattern: &[u8]) -> Option<Chunk> {
+        assert!(!pattern.is_empty(), "redacting needs a byte to fill with");
+        let chunk = self.chunks.get(index)?;
+        let data = pattern.iter().copied().cycle().take(chunk.data().len());
+        let redacted = Chunk::new(*chunk.chunk_type(), data.collect());
+        self.replace_chunk_at(index, redacted)
+    }
+    /// Exchanges the chunks at `a` and `b`. Stray bytes stay at their
+    /// positions in the file rather than following either chunk.
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn swap_chunks(&mut self, a: usize, b: usize) {
+        self.chunks.swap(a, b);
+        self.modified |= a != b;
+    }
+    /// Keeps only the chunks for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Chunk) -> bool) {
+        self.remove_matching(|c| !keep(c));
+    }
+    pub(crate) fn remove_matching(
+        &mut self,
+        mut predicate: impl FnMut(&Chunk) -> bool,
+    ) -> Vec<Chunk> {
+        let matches: Vec<bool> = self.chunks.iter().map(&mut predicate).collect();
+        if !matches.contains(&true) {
+            return Vec::new();
+        }
+        // In one pass rather than a `remove_chunk_at` per match, which is
+        // quadratic in a file of many small chunks. `kept_before[i]` is how
+        // many chunks in front of chunk i stay, so where stray bytes in
+        // front of it end up
+        let mut kept_before = Vec::with_capacity(matches.len() + 1);
+        let mut kept = Vec::with_capacity(self.chunks.len());
+        let mut removed = Vec::new();
+        for (chunk, matched) in std::mem::take(&mut self.chunks).into_iter().zip(matches) {
+            kept_before.push(kept.len());
+            if matched {
+                removed.push(chunk);
+            } else {
+                kept.push(chunk);
+            }
+        }
+        kept_before.push(kept.len());
+        for stray in &mut self.stray {
+            stray.index = kept_before[stray.index];
+        }
+        self.chunks = kept;
+        self.modified = true;
+        removed
+    }
+    /// Removes every ancillary chunk, keeping only the critical ones.
+    pub fn strip_ancillary(&mut self) -> Vec<Chunk> {
+        self.remove_matching(|c| !c.chunk_type().is_critical())
+    }
+    /// Removes unknown ancillary chunks that aren't marked safe to copy, which
+    /// is what the spec asks of an editor that changes critical chunks.
+    /// Registered standard chunks are kept regardless of their copy bit.
+    pub fn drop_unsafe_to_copy(&mut self) -> Vec<Chunk> {
+        self.remove_matching(|c| is_unsafe_to_copy(c.chunk_type()))
+    }
+    /// What `strip_ancillary`, or `drop_unsafe_to_copy` with `unsafe_only`,
+    /// would remove, narrowed to the chunks `selector` selects.
+    pub fn strip_selected(&mut self, selector: &ChunkSelector, unsafe_only: bool) -> Vec<Chunk> {
+        // Chunks are visited once each in order, so this is the index each
+        // had before any were removed
+        let mut index = 0;
+        self.remove_matching(|c| {
+            let chunk_type = c.chunk_type();
+            let selected = selector.matches(index, chunk_type, c.length());
+            index += 1;
+            selected
+                && match unsafe_only {
+                    true => is_unsafe_to_copy(chunk_type),
+                    false => !chunk_type.is_critical(),
+                }
+        })
+    }
+    /// Removes ancillary chunks identical in type and data to an earlier one.
+    pub fn remove_duplicate_ancillary(&mut self) -> Vec<Chunk> {
+        let mut seen: Vec<Chunk> = Vec::new();
+        self.remove_matching(|c| {
+            if c.chunk_type().is_critical() {
+                false
+            } else if seen.contains(c) {
+                true
+            } else {
+                seen.push(c.clone());
+                false
+            }
+        })
+    }
+    /// Removes ancillary chunks with no data.
+    pub fn remove_empty_ancillary(&mut self) -> Vec<Chunk> {
+        self.remove_matching(|c| !c.chunk_type().is_critical() && c.data().is_empty())
+    }
+    /// Position of the first IEND chunk, which ends the image as far as
+    /// decoders are concerned.
+    pub fn iend_index(&self) -> Option<usize> {
+        self.chunks
+            .iter()
+            .position(|c| *c.chunk_type() == ChunkType::IEND)
+    }
+    /// The valid chunks after the first IEND, which some watermarking tools
+    /// append and decoders ignore. They are the tail of `chunks()` and are
+    /// written back where they were unless moved by `relocate_post_iend`.
+    pub fn post_iend_chunks(&self) -> &[Chunk] {
+        match self.iend_index() {
+            Some(iend) => &self.chunks[iend + 1..],
+            None => &[],
+        }
+    }
+    /// Moves every chunk after the first IEND to just before it, keeping
+    /// their order, so decoders and other tools see them. Stray bytes stay
+    /// after IEND. Returns how many chunks moved.
+    pub fn relocate_post_iend(&mut self) -> usize {
+        let Some(mut iend) = self.iend_index() else {
+            return 0;
+        };
+        let moved = self.chunks.len() - iend - 1;
+        for _ in 0..moved {
+            let chunk = self.remove_chunk_at(iend + 1).unwrap();
+            self.insert_chunk(iend, chunk);
+            iend += 1;
+        }
+        moved
+    }
+    /// Drops every chunk and stray byte after the first IEND, which decoders
+    /// ignore. Returns the chunks and how many stray bytes went.
+    pub fn truncate_after_iend(&mut self) -> (Vec<Chunk>, usize) {
+        let Some(iend) = self.iend_index() else {
+            return (Vec::new(), 0);
+        };
+        let chunks = self.chunks.split_off(iend + 1);
+        let stray = self.stray.iter().filter(|s| s.index > iend);
+        let stray_len = stray.map(|s| s.bytes.len()).sum();
+        self.stray.retain(|s| s.index <= iend);
+        self.modified |= !chunks.is_empty() || stray_len > 0;
+        (chunks, stray_len)
+    }
+    /// Joins each run of adjacent IDAT chunks into as few as fit in
+    /// `max_len` bytes each. The image data is one zlib stream however it is
+    /// split, so this doesn't change the pixels. Runs broken up by stray
+    /// bytes are left as they are. Returns how many chunks were merged away.
+    ///
+    /// Panics if `max_len` is zero.
+    pub fn merge_idat(&mut self, max_len: u32) -> usize {
+        assert!(max_len > 0);
+        let mut merged = 0;
+        let mut index = 0;
+        while index + 1 < self.chunks.len() {
+            let (current, next) = (&self.chunks[index], &self.chunks[index + 1]);
+            let fits = current.length() as u64 + next.length() as u64 <= max_len as u64;
+            let adjacent = !self.stray.iter().any(|s| s.index == index + 1);
+            if *current.chunk_type() == ChunkType::IDAT
+                && *next.chunk_type() == ChunkType::IDAT
+                && fits
+                && adjacent
+            {
+                let next = self.remove_chunk_at(index + 1).unwrap();
+                let mut data = self.chunks[index].data().to_vec();
+                data.extend_from_slice(next.data());
+                self.chunks[index] = Chunk::new(ChunkType::IDAT, data);
+                merged += 1;
+            } else {
+                index += 1;
+            }
+        }
+        merged
+    }
+    /// Where each chunk starts in the serialized file, counting the signature
+    /// and any stray bytes. For an unmodified parse these are the original offsets.
+    pub fn chunk_offsets(&self) -> Vec<usize> {
+        let mut offset = Png::STANDARD_HEADER.len();
+        let mut stray = self.stray.iter().peekable();
+        let mut offsets = Vec::with_capacity(self.chunks.len());
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            while let Some(s) = stray.next_if(|s| s.index <= index) {
+                offset += s.bytes.len();
+            }
+            offsets.push(offset);
+            offset += chunk.length() as usize + 12;
+        }
+        offsets
+    }
+    /// Length of `as_bytes()`, stray and trailing bytes included, without
+    /// serializing anything.
+    pub fn total_size(&self) -> usize {
+        let chunks: usize = self.chunks.iter().map(Chunk::serialized_len).sum();
+        let stray: usize = self.stray.iter().map(|s| s.bytes.len()).sum();
+        Png::STANDARD_HEADER.len() + chunks + stray
+    }
+    /// Serializes the png as it is, valid or not.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        timings::time(Phase::Serialize, || self.serialize())
+    }
+    pub fn structure_violations(&self) -> Vec<StructureViolation> {
+        structure_violations(self.chunks.iter().map(Chunk::chunk_type))
+    }
+    /// `as_bytes`, unless IHDR isn't first or there's no IEND.
+    pub fn as_bytes_validated(&self) -> Result<Vec<u8>, InvalidStructure> {
+        match self.structure_violations() {
+            violations if violations.is_empty() => Ok(self.as_bytes()),
+            violations => Err(InvalidStructure(violations)),
+        }
+    }
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.total_size());
+        bytes.extend_from_slice(&Png::STANDARD_HEADER);
+        let mut stray = self.stray.iter().peekable();
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            while let Some(s) = stray.next_if(|s| s.index <= index) {
+                bytes.extend_from_slice(&s.bytes);
+            }
+            bytes.extend_from_slice(&chunk.as_bytes());
+        }
+        for s in stray {
+            bytes.extend_from_slice(&s.bytes);
+        }
+        bytes
+    }
 }
 
 impl std::fmt::Display for Png {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Header: {:?} ", Png::STANDARD_HEADER);
-        for (index, chunk) in self.chunks.iter().enumerate() {
-            writeln!(f, "Chunk: {}", index + 1)?;
-            writeln!(f, "    Length: {} ", chunk.length())?;
-            writeln!(f, "    Chunk type: {} ", chunk.chunk_type())?;
-            writeln!(f, "    Chunk Data: {:?} ", chunk.data_as_string())?;
-            writeln!(f, "    CRC: {} ", chunk.crc())?;
+        let chunks = self.chunks.iter().map(ChunkRef::from);
+        write_listing(f, self.chunk_offsets().into_iter().zip(chunks))
+    }
+}
+
+/// What `Png`'s `Display` prints, for chunks from `Png::parse_borrowed`.
+pub struct Listing<'a>(pub &'a [ChunkRef<'a>]);
+
+impl std::fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_listing(f, self.0.iter().map(|chunk| (chunk.offset(), *chunk)))
+    }
+}
+
+/// The signature, then each chunk and where it starts in the file.
+fn write_listing<'a>(
+    f: &mut std::fmt::Formatter<'_>,
+    chunks: impl Iterator<Item = (usize, ChunkRef<'a>)>,
+) -> std::fmt::Result {
+    write!(f, "Header: {:?} ", Png::STANDARD_HEADER)?;
+    for (index, (offset, chunk)) in chunks.enumerate() {
+        writeln!(f, "Chunk: {}", index + 1)?;
+        writeln!(f, "    Offset: {offset} (0x{offset:x}) ")?;
+        writeln!(
+            f,
+            "    Length: {} ",
+            format_size(u64::from(chunk.length()), false)
+        )?;
+        writeln!(f, "    Chunk type: {} ", chunk.chunk_type())?;
+        writeln!(f, "    Chunk Data: {:?} ", chunk.data_as_str())?;
+        writeln!(f, "    CRC: {} ", format_crc(chunk.crc()))?;
+    }
+    Ok(())
+}
+
+/// A chunk whose length field is wrong, found by `recover_length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthMismatch {
+    /// Where the chunk's length field is in the file.
+    pub offset: usize,
+    pub chunk_type: ChunkType,
+    pub declared: u32,
+    /// The length at which the stored CRC matches the chunk's contents.
+    pub actual: u32,
+}
+
+impl std::fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at offset {} (0x{:x}): declared length {}, actual {}",
+            self.chunk_type, self.offset, self.offset, self.declared, self.actual
+        )
+    }
+}
+
+/// Unknown ancillary chunks not marked safe to copy; registered standard
+/// chunks are kept regardless of their copy bit.
+fn is_unsafe_to_copy(chunk_type: &ChunkType) -> bool {
+    !chunk_type.is_critical() && !chunk_type.is_safe_to_copy() && !standard::is_standard(chunk_type)
+}
+
+/// For a chunk at `offset` that doesn't parse, finds a length other than the
+/// declared one at which the four bytes that follow are a matching CRC of
+/// its type and data, and either the file ends or another chunk type comes
+/// next. This catches tools that wrote the wrong length but a CRC over the
+/// real data. The search is linear in the bytes left in the file.
+pub fn recover_length(
+    value: &[u8],
+    offset: usize,
+    options: &ParseOptions,
+) -> Option<LengthMismatch> {
+    let header = value.get(offset..offset.checked_add(8)?)?;
+    let declared = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&header[4..]).unwrap()).ok()?;
+    let body = &value[offset + 8..];
+    let longest = body
+        .len()
+        .checked_sub(4)?
+        .min(options.max_chunk_size as usize);
+    let mut digest = X25.digest();
+    digest.update(&header[4..]);
+    for actual in 0..=longest {
+        let stored = u32::from_be_bytes(body[actual..actual + 4].try_into().unwrap());
+        if actual != declared as usize && digest.clone().finalize() == stored {
+            let next = offset + 12 + actual;
+            let plausible = next == value.len()
+                || value
+                    .get(next + 4..next + 8)
+                    .is_some_and(|t| t.iter().all(u8::is_ascii_alphabetic));
+            if plausible {
+                return Some(LengthMismatch {
+                    offset,
+                    chunk_type,
+                    declared,
+                    actual: actual as u32,
+                });
+            }
+        }
+        if actual < longest {
+            digest.update(&body[actual..actual + 1]);
         }
-        Ok(())
     }
+    None
 }
 
-impl TryFrom<&[u8]> for Png {
-    type Error = InvalidChunk;
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let header_size = Self::STANDARD_HEADER.len();
-        if value[..header_size] != Self::STANDARD_HEADER {
-            Err(InvalidChunk::Header)?
-        }
-        let mut value_slice = &value[header_size..];
-        let mut chunks = Vec::new();
-        while !value_slice.is_empty() {
-            if value_slice.len() < 4 {
+pub(crate) struct StrayRange {
+    pub(crate) index: usize,
+    pub(crate) offset: usize,
+    pub(crate) end: usize,
+}
+
+pub(crate) fn chunk_at<'a>(
+    value: &'a [u8],
+    offset: usize,
+    options: &ParseOptions,
+) -> Result<ChunkRef<'a>, InvalidChunk> {
+    ChunkRef::parse_with(chunk_bytes_at(value, offset, options)?, offset, options)
+}
+
+/// `chunk_at` where a chunk is expected to start, as `ChunkRef::parse_expected`
+/// reads it.
+pub(crate) fn expected_chunk_at<'a>(
+    value: &'a [u8],
+    offset: usize,
+    options: &ParseOptions,
+) -> Result<ChunkRef<'a>, InvalidChunk> {
+    ChunkRef::parse_expected(chunk_bytes_at(value, offset, options)?, offset, options)
+}
+
+/// The bytes of the chunk at `offset`, as far as its length field says.
+fn chunk_bytes_at<'a>(
+    value: &'a [u8],
+    offset: usize,
+    options: &ParseOptions,
+) -> Result<&'a [u8], InvalidChunk> {
+    let value_slice = &value[offset..];
+    if value_slice.len() < 4 {
+        Err(InvalidChunk::Truncated {
+            needed: 4,
+            got: value_slice.len(),
+        })?
+    }
+    let length = u32::from_be_bytes([
+        value_slice[0],
+        value_slice[1],
+        value_slice[2],
+        value_slice[3],
+    ]);
+    if length > options.max_chunk_size {
+        Err(InvalidChunk::TooLarge {
+            length,
+            max: options.max_chunk_size,
+        })?
+    }
+    let len = length as usize;
+    // Subtracting instead of adding keeps this from overflowing on 32-bit targets
+    if value_slice.len() - 4 < len || value_slice.len() - 4 - len < 8 {
+        Err(InvalidChunk::Truncated {
+            needed: len.saturating_add(12),
+            got: value_slice.len(),
+        })?
+    }
+    Ok(&value_slice[..len + 12])
+}
+
+pub(crate) fn check_signature(value: &[u8]) -> Result<(), ParseError> {
+    let Some(found) = value.first_chunk::<8>() else {
+        return Err(ParseError::new(0, InvalidChunk::Header));
+    };
+    if *found != Png::STANDARD_HEADER {
+        let kind = InvalidChunk::InvalidSignature {
+            found: *found,
+            detected: KnownFormat::detect(value),
+        };
+        return Err(ParseError::new(0, kind));
+    }
+    Ok(())
+}
+
+/// Reads and checks the signature at the start of a stream. When it's
+/// wrong, up to 4 more bytes are read to tell what the stream is instead.
+pub(crate) fn read_signature<R: Read>(reader: &mut R) -> Result<(), ReadError> {
+    let mut header = [0; 12];
+    let filled = read_up_to(reader, &mut header[..8])?;
+    if header[..filled] == Png::STANDARD_HEADER {
+        return Ok(());
+    }
+    let extra = if filled == 8 {
+        read_up_to(reader, &mut header[8..])?
+    } else {
+        0
+    };
+    check_signature(&header[..filled + extra])?;
+    Ok(())
+}
+
+pub(crate) struct Scanned<'a> {
+    pub(crate) chunks: Vec<ChunkRef<'a>>,
+    pub(crate) stray: Vec<StrayRange>,
+    /// Chunks read with a corrected length, with `fix_lengths`.
+    pub(crate) fixed: Vec<LengthMismatch>,
+}
+
+pub(crate) fn scan<'a>(value: &'a [u8], options: &ParseOptions) -> Result<Scanned<'a>, ParseError> {
+    timings::time(Phase::Signature, || check_signature(value))?;
+    if value.len() as u64 > options.max_file_size {
+        let kind = InvalidChunk::FileTooLarge {
+            max: options.max_file_size,
+        };
+        return Err(ParseError::new(0, kind));
+    }
+    let scanned = timings::time(Phase::Parse, || scan_chunks(value, options))?;
+    timings::count_chunks(scanned.chunks.len());
+    Ok(scanned)
+}
+
+fn scan_chunks<'a>(value: &'a [u8], options: &ParseOptions) -> Result<Scanned<'a>, ParseError> {
+    let mut offset = Png::STANDARD_HEADER.len();
+    let mut chunks = Vec::new();
+    let mut stray = Vec::new();
+    let mut fixed = Vec::new();
+    let mut stray_start = None;
+    let mut budget = Budget::default();
+    while offset < value.len() {
+        let mut found = if stray_start.is_none() {
+            expected_chunk_at(value, offset, options)
+        } else {
+            chunk_at(value, offset, options)
+        };
+        // With `ignore_crc` a wrong length can still read as a chunk, so
+        // look at the CRC here rather than rely on the parse failing
+        let crc_matches = |chunk: &ChunkRef| {
+            let end = chunk.offset() + 8 + chunk.data().len();
+            X25.checksum(&value[chunk.offset() + 4..end]) == chunk.crc()
+        };
+        if options.fix_lengths
+            && stray_start.is_none()
+            && !found.as_ref().is_ok_and(crc_matches)
+            && let Some(mismatch) = recover_length(value, offset, options)
+        {
+            let end = offset + 8 + mismatch.actual as usize;
+            found = Ok(ChunkRef::with_length(value, offset, end));
+            fixed.push(mismatch);
+        }
+        match found {
+            Ok(chunk) => {
+                if let Some(start) = stray_start.take() {
+                    stray.push(StrayRange {
+                        index: chunks.len(),
+                        offset: start,
+                        end: offset,
+                    });
+                }
+                budget
+                    .charge(chunk.chunk_type(), chunk.data(), options)
+                    .map_err(|e| ParseError::new(offset, e))?;
+                offset += chunk.length() as usize + 12;
+                chunks.push(chunk);
+            }
+            Err(_) if options.lenient => {
+                stray_start.get_or_insert(offset);
+                offset += 1;
+            }
+            // A few trailing bytes that can't hold a length field are tolerated,
+            // and kept so the file still serializes to the same bytes
+            Err(_) if value.len() - offset < 4 => {
+                stray_start = Some(offset);
                 break;
             }
-            let len = u32::from_be_bytes([
-                value_slice[0],
-                value_slice[1],
-                value_slice[2],
-                value_slice[3],
-            ]) as usize;
-            if value_slice.len() < len + 12 {
-                Err(InvalidChunk::Data)?
+            Err(e) => return Err(ParseError::new(offset, e)),
+        }
+    }
+    if let Some(start) = stray_start {
+        stray.push(StrayRange {
+            index: chunks.len(),
+            offset: start,
+            end: value.len(),
+        });
+    }
+    Ok(Scanned {
+        chunks,
+        stray,
+        fixed,
+    })
+}
+
+/// Every chunk in `value` whose length field is wrong, as `fix_lengths`
+/// would read it.
+pub fn find_length_mismatches(
+    value: &[u8],
+    options: &ParseOptions,
+) -> Result<Vec<LengthMismatch>, ParseError> {
+    let options = ParseOptions {
+        fix_lengths: true,
+        ..options.clone()
+    };
+    Ok(scan(value, &options)?.fixed)
+}
+
+impl Png {
+    /// Splits a PNG file into borrowed chunks without copying any chunk data.
+    pub fn parse_borrowed(value: &[u8]) -> Result<Vec<ChunkRef<'_>>, ParseError> {
+        Self::parse_borrowed_with(value, &ParseOptions::default())
+    }
+    /// Like `parse_borrowed`, but any bytes skipped in lenient mode are discarded.
+    pub fn parse_borrowed_with<'a>(
+        value: &'a [u8],
+        options: &ParseOptions,
+    ) -> Result<Vec<ChunkRef<'a>>, ParseError> {
+        Ok(scan(value, options)?.chunks)
+    }
+    pub fn parse_with(value: &[u8], options: &ParseOptions) -> Result<Png, ParseError> {
+        let Scanned {
+            chunks,
+            stray,
+            fixed,
+        } = scan(value, options)?;
+        Ok(Self {
+            chunks: chunks.into_iter().map(ChunkRef::to_owned).collect(),
+            stray: stray
+                .into_iter()
+                .map(|s| StrayBytes {
+                    index: s.index,
+                    offset: s.offset,
+                    bytes: value[s.offset..s.end].to_vec(),
+                })
+                .collect(),
+            // Written out, chunks read with a fixed length differ from the input
+            modified: !fixed.is_empty(),
+        })
+    }
+}
+
+impl Png {
+    pub fn from_reader<R: Read>(reader: R) -> Result<Png, ReadError> {
+        Self::from_reader_with(reader, &ParseOptions::default())
+    }
+    /// Parses chunk by chunk from a stream. Each chunk's buffer grows only as
+    /// its bytes actually arrive, so a huge declared length on a short stream
+    /// fails with a truncation error instead of a huge allocation.
+    pub fn from_reader_with<R: Read>(reader: R, options: &ParseOptions) -> Result<Png, ReadError> {
+        Self::from_reader_partial(reader, options).into_result()
+    }
+    /// Like `from_reader_with`, but keeps the chunks read before an error
+    /// instead of dropping them, for getting what can be had out of a
+    /// truncated or damaged file. With `lenient` or `fix_lengths` the input
+    /// is parsed as a whole, so an error leaves no chunks.
+    pub fn from_reader_partial<R: Read>(reader: R, options: &ParseOptions) -> PartialParse {
+        let mut reader = Timed(reader);
+        if options.lenient || options.fix_lengths {
+            // Resynchronising and fixing lengths need to look ahead, so take
+            // the whole input
+            let mut buffer = Vec::new();
+            let parsed = reader
+                .read_to_end(&mut buffer)
+                .map_err(ReadError::from)
+                .and_then(|_| Ok(Self::parse_with(&buffer, options)?));
+            return match parsed {
+                Ok(png) => PartialParse {
+                    png,
+                    error: None,
+                    offset: buffer.len(),
+                },
+                Err(error) => PartialParse {
+                    png: Png::from_chunks(Vec::new()),
+                    offset: match &error {
+                        ReadError::Invalid(e) => e.offset(),
+                        ReadError::Io(_) => 0,
+                    },
+                    error: Some(error),
+                },
+            };
+        }
+        if let Err(error) = timings::time(Phase::Signature, || read_signature(&mut reader)) {
+            return PartialParse {
+                png: Png::from_chunks(Vec::new()),
+                error: Some(error),
+                offset: 0,
+            };
+        }
+        let partial = timings::time(Phase::Parse, || Self::read_chunks(reader, options));
+        timings::count_chunks(partial.png.chunk_count());
+        partial
+    }
+    fn read_chunks<R: Read>(reader: R, options: &ParseOptions) -> PartialParse {
+        let mut partial = PartialParse {
+            png: Png::from_chunks(Vec::new()),
+            error: None,
+            offset: Png::STANDARD_HEADER.len(),
+        };
+        if let Err(error) = Self::read_chunks_into(reader, options, &mut partial) {
+            partial.error = Some(error);
+        }
+        partial
+    }
+    /// Reads chunks into `partial` until the stream ends or one fails,
+    /// keeping `partial.offset` at the end of the last one read.
+    fn read_chunks_into<R: Read>(
+        mut reader: R,
+        options: &ParseOptions,
+        partial: &mut PartialParse,
+    ) -> Result<(), ReadError> {
+        let PartialParse {
+            png: Png { chunks, stray, .. },
+            offset: read_to,
+            ..
+        } = partial;
+        let mut budget = Budget::default();
+        loop {
+            let offset = *read_to;
+            let mut length_bytes = [0; 4];
+            // As in `try_from`, a few trailing bytes that can't hold a length are tolerated
+            let filled = read_up_to(&mut reader, &mut length_bytes)?;
+            if filled < length_bytes.len() {
+                if filled > 0 {
+                    stray.push(StrayBytes {
+                        index: chunks.len(),
+                        offset,
+                        bytes: length_bytes[..filled].to_vec(),
+                    });
+                }
+                *read_to += filled;
+                break;
+            }
+            let length = u32::from_be_bytes(length_bytes);
+            if length > options.max_chunk_size {
+                let kind = InvalidChunk::TooLarge {
+                    length,
+                    max: options.max_chunk_size,
+                };
+                Err(ParseError::new(offset, kind))?
             }
-            let chunk = Chunk::try_from(&value_slice[..len + 12])?;
-            chunks.push(chunk);
-            value_slice = &value_slice[len + 12..]
+            if (offset + 12) as u64 + u64::from(length) > options.max_file_size {
+                let kind = InvalidChunk::FileTooLarge {
+                    max: options.max_file_size,
+                };
+                Err(ParseError::new(offset, kind))?
+            }
+            let mut buffer = length_bytes.to_vec();
+            reader
+                .by_ref()
+                .take(length as u64 + 8)
+                .read_to_end(&mut buffer)?;
+            if buffer.len() != length as usize + 12 {
+                let kind = InvalidChunk::Truncated {
+                    needed: length as usize + 12,
+                    got: buffer.len(),
+                };
+                Err(ParseError::new(offset, kind))?
+            }
+            let chunk = ChunkRef::parse_expected(&buffer, offset, options)
+                .map_err(|e| ParseError::new(offset, e))?;
+            budget
+                .charge(chunk.chunk_type(), chunk.data(), options)
+                .map_err(|e| ParseError::new(offset, e))?;
+            chunks.push(chunk.to_owned());
+            *read_to += buffer.len();
         }
-        Ok(Self { chunks })
+        Ok(())
+    }
+}
+
+/// Fills as much of `buf` as the reader can provide, returning how much that was.
+pub(crate) fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = ParseError;
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::parse_with(value, &ParseOptions::default())
     }
 }
 
@@ -92,6 +1265,7 @@ mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::{ChunkType, ChunkTypeError};
+    use crate::rewrite::Rewrite;
     use std::convert::TryFrom;
     use std::str::FromStr;
 
@@ -207,6 +1381,60 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let count = png.chunks().len();
+        png.insert_before_iend(chunk_from_strings("TeSt", "Message").unwrap());
+        let types = chunk_types(&png);
+        assert_eq!(types[count - 1..], ["TeSt", "IEND"]);
+
+        png.append_chunk(chunk_from_strings("TeSt", "Last").unwrap());
+        let types = chunk_types(&png);
+        assert_eq!(types[count - 1..], ["TeSt", "IEND", "TeSt"]);
+    }
+
+    #[test]
+    fn test_insert_conventional_for_each_standard_type() {
+        for standard in standard::STANDARD_CHUNKS {
+            let mut png = Png::from_chunks(
+                ["IHDR", "IDAT", "IEND"]
+                    .iter()
+                    .map(|t| chunk_from_strings(t, "").unwrap())
+                    .collect(),
+            );
+            let index = png.insert_conventional(chunk_from_strings(standard.name, "").unwrap());
+            let expected = match standard.name {
+                "IHDR" => ["IHDR", "IHDR", "IDAT", "IEND"],
+                "IDAT" | "fdAT" => ["IHDR", "IDAT", standard.name, "IEND"],
+                "IEND" => ["IHDR", "IDAT", "IEND", "IEND"],
+                name => ["IHDR", name, "IDAT", "IEND"],
+            };
+            assert_eq!(chunk_types(&png), expected, "{}", standard.name);
+            assert_eq!(chunk_types(&png)[index], standard.name);
+        }
+    }
+
+    #[test]
+    fn test_insert_conventional_orders_around_plte() {
+        let mut png = Png::from_chunks(
+            ["IHDR", "PLTE", "IDAT", "IEND"]
+                .iter()
+                .map(|t| chunk_from_strings(t, "").unwrap())
+                .collect(),
+        );
+        for name in ["tRNS", "gAMA", "tEXt", "ruSt", "sRGB", "fdAT", "RUST"] {
+            png.insert_conventional(chunk_from_strings(name, "").unwrap());
+        }
+        assert_eq!(
+            chunk_types(&png),
+            [
+                "IHDR", "gAMA", "sRGB", "PLTE", "tRNS", "tEXt", "ruSt", "IDAT", "fdAT", "RUST",
+                "IEND"
+            ]
+        );
+    }
+
     #[test]
     fn test_remove_first_chunk() {
         let mut png = testing_png();
@@ -216,6 +1444,466 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunk_at() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "one").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "two").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "three").unwrap());
+
+        let removed = png.remove_chunk_at(4).unwrap();
+        assert_eq!(removed.data(), b"two");
+
+        let remaining: Vec<&[u8]> = png.chunks()[3..].iter().map(|c| c.data()).collect();
+        assert_eq!(remaining, [&b"one"[..], &b"three"[..]]);
+        assert!(png.remove_chunk_at(5).is_none());
+    }
+
+    fn mixed_png() -> Png {
+        Png::from_chunks(
+            [
+                "IHDR", "gAMA", "ruSt", "ruST", "tEXt", "IDAT", "prIV", "IEND",
+            ]
+            .iter()
+            .map(|t| chunk_from_strings(t, "data").unwrap())
+            .collect(),
+        )
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_modified_by_every_mutator() {
+        let chunk = || chunk_from_strings("ruSt", "new").unwrap();
+        type Edit = (&'static str, fn(&mut Png));
+        let edits: [Edit; 16] = [
+            ("append_chunk", |p| {
+                p.append_chunk(chunk_from_strings("ruSt", "x").unwrap())
+            }),
+            ("append_chunks", |p| {
+                p.append_chunks([chunk_from_strings("ruSt", "x").unwrap()]);
+            }),
+            ("remove_first_chunk", |p| {
+                p.remove_first_chunk("ruSt");
+            }),
+            ("remove_chunk_at", |p| {
+                p.remove_chunk_at(0);
+            }),
+            ("insert_chunk", |p| {
+                p.insert_chunk(1, chunk_from_strings("ruSt", "x").unwrap())
+            }),
+            ("replace_chunk_at", |p| {
+                p.replace_chunk_at(2, chunk_from_strings("ruSt", "x").unwrap());
+            }),
+            ("splice_raw", |p| p.splice_raw(2, 0..1, b"D").unwrap()),
+            ("swap_chunks", |p| p.swap_chunks(1, 2)),
+            ("retain", |p| p.retain(|c| c.chunk_type().is_critical())),
+            ("strip_ancillary", |p| {
+                p.strip_ancillary();
+            }),
+            ("drop_unsafe_to_copy", |p| {
+                p.drop_unsafe_to_copy();
+            }),
+            ("remove_duplicate_ancillary", |p| {
+                p.append_chunk(chunk_from_strings("ruSt", "data").unwrap());
+                p.mark_clean();
+                p.remove_duplicate_ancillary();
+            }),
+            ("remove_empty_ancillary", |p| {
+                p.append_chunk(chunk_from_strings("ruSt", "").unwrap());
+                p.mark_clean();
+                p.remove_empty_ancillary();
+            }),
+            ("relocate_post_iend", |p| {
+                p.insert_chunk(p.chunk_count(), chunk_from_strings("ruSt", "x").unwrap());
+                p.mark_clean();
+                p.relocate_post_iend();
+            }),
+            ("truncate_after_iend", |p| {
+                p.insert_chunk(p.chunk_count(), chunk_from_strings("ruSt", "x").unwrap());
+                p.mark_clean();
+                p.truncate_after_iend();
+            }),
+            ("merge_idat", |p| {
+                p.insert_chunk(6, chunk_from_strings("IDAT", "more").unwrap());
+                p.mark_clean();
+                p.merge_idat(1024);
+            }),
+        ];
+        for (name, edit) in edits {
+            let mut png = mixed_png();
+            assert!(!png.is_modified());
+            edit(&mut png);
+            assert!(png.is_modified(), "{name}");
+            png.mark_clean();
+            assert!(!png.is_modified(), "{name}");
+        }
+
+        let bytes = mixed_png().as_bytes();
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        assert!(!png.is_modified());
+        // Edits with nothing to do
+        png.remove_duplicate_ancillary();
+        png.remove_empty_ancillary();
+        png.relocate_post_iend();
+        png.truncate_after_iend();
+        png.merge_idat(1024);
+        png.swap_chunks(1, 1);
+        png.drop_stray_bytes();
+        assert!(png.remove_first_chunk("abCd").is_none());
+        assert!(png.replace_chunk_at(99, chunk()).is_none());
+        assert!(png.splice_raw(99, 0..0, b"").is_err());
+        assert!(!png.is_modified());
+        // Equality is about content, not history
+        png.swap_chunks(1, 2);
+        png.swap_chunks(1, 2);
+        assert!(png.is_modified());
+        assert_eq!(png, mixed_png());
+
+        let mut bytes = bytes;
+        bytes.splice(8..8, *b"junk");
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let mut png = Png::parse_with(&bytes, &options).unwrap();
+        assert!(!png.is_modified());
+        png.drop_stray_bytes();
+        assert!(png.is_modified());
+    }
+
+    #[test]
+    fn test_splice_raw() {
+        let mut png = testing_png();
+        // "I am another chunk": overwrite, shrink and grow in the middle
+        png.splice_raw(1, 5..12, b"ANOTHER").unwrap();
+        assert_eq!(png.chunks()[1].data(), b"I am ANOTHER chunk");
+        png.splice_raw(1, 5..13, b"").unwrap();
+        assert_eq!(png.chunks()[1].data(), b"I am chunk");
+        png.splice_raw(1, 5..5, b"a spliced ").unwrap();
+        assert_eq!(png.chunks()[1].data(), b"I am a spliced chunk");
+
+        let chunk = &png.chunks()[1];
+        assert_eq!(chunk.length(), 20);
+        assert_eq!(chunk.crc(), chunk.computed_crc());
+        assert_eq!(Png::try_from(png.as_bytes().as_slice()).unwrap(), png);
+    }
+
+    #[test]
+    fn test_redact_at() {
+        let mut png = testing_png();
+        let before = png.as_bytes();
+        let old = png.redact_at(1, b"xyz").unwrap();
+        assert_eq!(old.data(), b"I am another chunk");
+        let chunk = &png.chunks()[1];
+        assert_eq!(chunk.data(), b"xyzxyzxyzxyzxyzxyz");
+        assert_eq!(chunk.chunk_type(), old.chunk_type());
+        assert_eq!(chunk.crc(), chunk.computed_crc());
+        assert_ne!(chunk.crc(), old.crc());
+        assert_eq!(png.as_bytes().len(), before.len());
+        assert_eq!(
+            png.chunk_offsets(),
+            Png::try_from(&before[..]).unwrap().chunk_offsets()
+        );
+        assert!(png.is_modified());
+
+        png.redact_at(0, &[0]);
+        assert!(png.chunks()[0].data().iter().all(|&b| b == 0));
+        assert_eq!(png.redact_at(3, &[0]), None);
+    }
+
+    #[test]
+    fn test_replaced_chunk_serializes_its_own_length() {
+        let mut png = testing_png();
+        let chunk_type = *png.chunks()[1].chunk_type();
+        png.replace_chunk_at(1, Chunk::new(chunk_type, b"short".to_vec()));
+        png.splice_raw(1, 5..5, b" and then longer").unwrap();
+        let chunk = &png.chunks()[1];
+        assert_eq!(chunk.length(), 21);
+        let bytes = chunk.as_bytes();
+        assert_eq!(bytes[..4], 21u32.to_be_bytes());
+        assert_eq!(bytes.len(), chunk.serialized_len());
+        assert_eq!(Chunk::try_from(bytes.as_slice()).unwrap(), *chunk);
+    }
+
+    #[test]
+    fn test_splice_raw_checks_range() {
+        let mut png = testing_png();
+        let before = png.clone();
+        assert_eq!(
+            png.splice_raw(3, 0..0, b"x"),
+            Err(SpliceError::NoChunk { index: 3, count: 3 })
+        );
+        let len = png.chunks()[1].data().len();
+        assert_eq!(
+            png.splice_raw(1, len..len + 1, b"x"),
+            Err(SpliceError::BadRange {
+                range: len..len + 1,
+                len
+            })
+        );
+        #[allow(clippy::reversed_empty_ranges)]
+        let backwards = 4..2;
+        assert!(png.splice_raw(1, backwards, b"").is_err());
+        assert_eq!(png, before);
+        // Appending at the very end is allowed
+        png.splice_raw(1, len..len, b"!").unwrap();
+        assert!(png.chunks()[1].data().ends_with(b"chunk!"));
+    }
+
+    #[test]
+    fn test_drop_unsafe_to_copy() {
+        let mut png = mixed_png();
+        let removed: Vec<String> = png
+            .drop_unsafe_to_copy()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(removed, ["ruST", "prIV"]);
+        // gAMA is unsafe to copy but a known standard chunk
+        assert_eq!(
+            chunk_types(&png),
+            ["IHDR", "gAMA", "ruSt", "tEXt", "IDAT", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_strip_ancillary() {
+        let mut png = mixed_png();
+        assert_eq!(png.strip_ancillary().len(), 5);
+        assert_eq!(chunk_types(&png), ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_selected() {
+        let mut png = mixed_png();
+        let private = ChunkSelector::from_str("private").unwrap();
+        let removed: Vec<String> = png
+            .strip_selected(&private, false)
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(removed, ["ruSt", "ruST", "prIV"]);
+        assert_eq!(chunk_types(&png), ["IHDR", "gAMA", "tEXt", "IDAT", "IEND"]);
+
+        let mut png = mixed_png();
+        let index = ChunkSelector::from_str("index=2").unwrap();
+        assert_eq!(png.strip_selected(&index, true).len(), 0);
+        assert_eq!(png.strip_selected(&index, false).len(), 1);
+        assert_eq!(chunk_types(&png).len(), 7);
+    }
+
+    #[test]
+    fn test_total_size_matches_bytes() {
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let empty = chunk_from_strings("ruSt", "").unwrap();
+        assert_eq!(empty.serialized_len(), 12);
+        let mut after_iend = testing_png();
+        after_iend.insert_chunk(after_iend.chunk_count(), empty.clone());
+        let mut trailing = testing_png().as_bytes();
+        trailing.extend_from_slice(b"\0\0");
+        let pngs = [
+            testing_png(),
+            Png::from_chunks(Vec::new()),
+            mixed_png(),
+            after_iend,
+            // Stray bytes between chunks
+            Png::parse_with(&with_junk(5, 2).0, &lenient).unwrap(),
+            Png::try_from(trailing.as_slice()).unwrap(),
+        ];
+        for mut png in pngs {
+            assert_eq!(png.total_size(), png.as_bytes().len(), "{png}");
+            for chunk in png.chunks() {
+                assert_eq!(chunk.serialized_len(), chunk.as_bytes().len());
+            }
+            png.append_chunk(empty.clone());
+            assert_eq!(png.total_size(), png.as_bytes().len(), "{png}");
+        }
+    }
+
+    #[test]
+    fn test_append_chunks_report() {
+        let mut png = testing_png();
+        let before = png.as_bytes().len();
+        let chunks = [
+            chunk_from_strings("ruSt", "hello").unwrap(),
+            chunk_from_strings("ruSt", "").unwrap(),
+        ];
+        let report = png.append_chunks(chunks);
+        assert_eq!(report.before, before);
+        assert_eq!(report.after, before + 17 + 12);
+        assert_eq!(report.payload, 5);
+        assert_eq!(png.chunks().len(), 5);
+    }
+
+    #[test]
+    fn test_jpeg_signature() {
+        let jpeg = [0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10, 0x4a, 0x46, 0x49, 0x46];
+        let err = Png::try_from(&jpeg[..]).unwrap_err();
+        let found = [0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10, 0x4a, 0x46];
+        let kind = InvalidChunk::InvalidSignature {
+            found,
+            detected: Some(KnownFormat::Jpeg),
+        };
+        assert_eq!(*err.kind(), kind);
+        assert_eq!(err.offset(), 0);
+        assert!(
+            err.to_string()
+                .starts_with("input is a JPEG (FF D8 FF), not a PNG")
+        );
+
+        let err = Png::from_reader(&jpeg[..]).unwrap_err();
+        assert!(matches!(err, ReadError::Invalid(ref e) if *e.kind() == kind));
+    }
+
+    #[test]
+    fn test_signature_format_hints() {
+        let cases: [(&[u8], &str); 7] = [
+            (b"GIF89a\x01\x00\x01\x00", "GIF (47 49 46 38 39 61)"),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", "WebP (52 49 46 46)"),
+            (b"BM\x3a\x00\x00\x00\x00\x00", "BMP (42 4D)"),
+            (b"II*\x00\x08\x00\x00\x00", "TIFF (49 49 2A 00)"),
+            (b"%PDF-1.7\n%", "PDF (25 50 44 46 2D)"),
+            (b"PK\x03\x04\x14\x00\x00\x00", "ZIP (50 4B 03 04)"),
+            (&[0xff, 0xd8, 0xff, 0xdb, 0, 0x43, 0, 0], "JPEG (FF D8 FF)"),
+        ];
+        for (bytes, hint) in cases {
+            let expected = format!("input is a {hint}, not a PNG");
+            let err = Png::try_from(bytes).unwrap_err();
+            assert!(err.to_string().starts_with(&expected), "{err}");
+            // The stream readers read past the signature to tell a WebP
+            let err = Png::from_reader(bytes).unwrap_err();
+            assert!(err.to_string().starts_with(&expected), "{err}");
+            let err = Rewrite::index(bytes, &ParseOptions::default()).unwrap_err();
+            assert!(err.to_string().starts_with(&expected), "{err}");
+        }
+
+        let bytes = b"plain text file";
+        let err = Png::try_from(&bytes[..]).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            InvalidChunk::InvalidSignature { detected: None, .. }
+        ));
+        let message = err.to_string();
+        assert!(message.contains("expected signature 89 50 4e 47 0d 0a 1a 0a"));
+        assert!(message.contains("found 70 6c 61 69 6e 20 74 65"));
+    }
+
+    #[test]
+    fn test_partial_parse_keeps_the_intact_prefix() {
+        let bytes = testing_png().as_bytes();
+        let offsets = testing_png().chunk_offsets();
+        let options = ParseOptions::default();
+
+        // Cut in the middle of the second chunk
+        let cut = &bytes[..offsets[1] + 10];
+        let partial = Png::from_reader_partial(cut, &options);
+        assert_eq!(partial.png.chunks(), &testing_png().chunks()[..1]);
+        assert_eq!(partial.offset, offsets[1]);
+        let Some(ReadError::Invalid(e)) = &partial.error else {
+            panic!("{:?}", partial.error);
+        };
+        assert!(matches!(e.kind(), InvalidChunk::Truncated { .. }));
+        assert_eq!(e.offset(), offsets[1]);
+        assert!(Png::from_reader(cut).is_err());
+
+        // Cut between chunks, which reads fine but leaves chunks out
+        let partial = Png::from_reader_partial(&bytes[..offsets[2]], &options);
+        assert!(partial.error.is_none());
+        assert_eq!(partial.png.chunk_count(), 2);
+        assert_eq!(partial.offset, offsets[2]);
+
+        let partial = Png::from_reader_partial(&bytes[..], &options);
+        assert_eq!(partial.offset, bytes.len());
+        assert_eq!(partial.into_result().unwrap().as_bytes(), bytes);
+
+        let partial = Png::from_reader_partial(&bytes[..4], &options);
+        assert_eq!((partial.png.chunk_count(), partial.offset), (0, 0));
+        assert!(partial.error.is_some());
+    }
+
+    #[test]
+    fn test_truncated_signature() {
+        let bytes = &Png::STANDARD_HEADER[..5];
+        assert_eq!(
+            *Png::try_from(bytes).unwrap_err().kind(),
+            InvalidChunk::Header
+        );
+        assert!(matches!(
+            Png::from_reader(bytes).unwrap_err(),
+            ReadError::Invalid(ref e) if *e.kind() == InvalidChunk::Header
+        ));
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(testing_png().header(), Png::STANDARD_HEADER);
+    }
+
+    #[test]
+    fn test_insert_and_replace_chunk() {
+        let (bytes, _) = with_junk(3, 1);
+        let mut png = Png::parse_with(
+            &bytes,
+            &ParseOptions {
+                lenient: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        png.insert_chunk(1, chunk_from_strings("ruSt", "new").unwrap());
+        assert_eq!(chunk_types(&png), ["FrSt", "ruSt", "miDl", "LASt"]);
+        // The junk still precedes miDl
+        assert_eq!(png.stray_bytes()[0].index(), 2);
+
+        let old = png
+            .replace_chunk_at(1, chunk_from_strings("ruSt", "newer").unwrap())
+            .unwrap();
+        assert_eq!(old.data(), b"new");
+        assert_eq!(png.chunks()[1].data(), b"newer");
+        assert!(png.replace_chunk_at(9, old).is_none());
+    }
+
+    #[test]
+    fn test_ignore_crc_keeps_bad_chunks() {
+        let mut chunks = testing_chunks();
+        let bad = &chunks[1];
+        chunks[1] = Chunk::with_crc(*bad.chunk_type(), bad.data().to_vec(), bad.crc() ^ 1);
+        let bytes = Png::from_chunks(chunks.clone()).as_bytes();
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+
+        let options = ParseOptions {
+            ignore_crc: true,
+            ..Default::default()
+        };
+        let png = Png::parse_with(&bytes, &options).unwrap();
+        assert_eq!(png.chunks(), chunks);
+        assert_eq!(png.as_bytes(), bytes);
+        let png = Png::from_reader_with(bytes.as_slice(), &options).unwrap();
+        assert_eq!(png.chunks(), chunks);
+        // Checking afterwards finds what the parse let through
+        let mismatches = png.verify_all_crcs();
+        assert_eq!(
+            mismatches,
+            [(
+                1,
+                CrcMismatch {
+                    stored: chunks[1].crc(),
+                    computed: chunks[1].computed_crc()
+                }
+            )]
+        );
+        assert!(testing_png().verify_all_crcs().is_empty());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -230,6 +1918,637 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_as_bytes_validated() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.as_bytes_validated().unwrap(), PNG_FILE);
+
+        let ihdr = png.remove_chunk_at(0).unwrap();
+        let first = *png.chunks()[0].chunk_type();
+        let iend = png.remove_chunk_at(png.chunk_count() - 1).unwrap();
+        assert_eq!(
+            png.as_bytes_validated(),
+            Err(InvalidStructure(vec![
+                StructureViolation::IhdrNotFirst { found: Some(first) },
+                StructureViolation::NoIend,
+            ]))
+        );
+        // The unchecked path still writes whatever it's given
+        assert_eq!(png.as_bytes().len(), png.total_size());
+        png.insert_chunk(0, ihdr);
+        assert_eq!(png.structure_violations(), [StructureViolation::NoIend]);
+        // Chunks may follow IEND
+        png.insert_chunk(png.chunk_count(), iend);
+        png.insert_chunk(png.chunk_count(), testing_chunks().remove(0));
+        assert_eq!(png.structure_violations(), []);
+
+        let empty = Png::from_chunks(Vec::new());
+        assert_eq!(
+            empty.as_bytes_validated().unwrap_err().to_string(),
+            "There are no chunks"
+        );
+        let rewrite = Rewrite::index(&PNG_FILE[..], &ParseOptions::default()).unwrap();
+        assert_eq!(rewrite.structure_violations(), []);
+    }
+
+    #[test]
+    fn test_parse_borrowed_matches_owned() {
+        let owned = Png::try_from(&PNG_FILE[..]).unwrap();
+        let borrowed = Png::parse_borrowed(&PNG_FILE).unwrap();
+        assert_eq!(owned.chunks().len(), borrowed.len());
+        for (chunk, chunk_ref) in owned.chunks().iter().zip(&borrowed) {
+            assert_eq!(chunk.chunk_type(), chunk_ref.chunk_type());
+            assert_eq!(chunk.length(), chunk_ref.length());
+            assert_eq!(chunk.data(), chunk_ref.data());
+            assert_eq!(chunk.crc(), chunk_ref.crc());
+            assert_eq!(chunk.as_bytes(), ChunkRef::to_owned(*chunk_ref).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_parse_borrowed_offsets() {
+        let borrowed = Png::parse_borrowed(&PNG_FILE).unwrap();
+        assert_eq!(borrowed[0].offset(), 8);
+        // IHDR is 13 bytes of data plus 12 bytes of framing
+        assert_eq!(borrowed[1].offset(), 8 + 25);
+        for pair in borrowed.windows(2) {
+            assert_eq!(
+                pair[1].offset(),
+                pair[0].offset() + pair[0].length() as usize + 12
+            );
+        }
+    }
+
+    fn with_junk(junk_len: usize, position: usize) -> (Vec<u8>, usize) {
+        let clean = testing_png().as_bytes();
+        let chunk_ends: Vec<usize> = Png::parse_borrowed(&clean)
+            .unwrap()
+            .iter()
+            .map(|c| c.offset())
+            .chain([clean.len()])
+            .collect();
+        let at = chunk_ends[position];
+        let mut bytes = clean[..at].to_vec();
+        bytes.extend((0..junk_len).map(|i| 0xA0 + i as u8));
+        bytes.extend_from_slice(&clean[at..]);
+        (bytes, at)
+    }
+
+    #[test]
+    fn test_lenient_skips_junk() {
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        // Before the first chunk, between chunks, and after the last one
+        for position in 0..=3 {
+            for junk_len in 1..=16 {
+                let (bytes, at) = with_junk(junk_len, position);
+                if position < 3 || junk_len >= 4 {
+                    assert!(Png::try_from(bytes.as_slice()).is_err());
+                }
+
+                let png = Png::parse_with(&bytes, &lenient).unwrap();
+                assert_eq!(png.chunks().len(), 3);
+                assert_eq!(png.stray_bytes().len(), 1);
+                let stray = &png.stray_bytes()[0];
+                assert_eq!(stray.index(), position);
+                assert_eq!(stray.offset(), at);
+                assert_eq!(stray.bytes().len(), junk_len);
+                assert_eq!(png.as_bytes(), bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_garbled_chunk_type() {
+        let found = [0x00, 0x41, 0x42, 0x43];
+        let garbled = |crc_delta: u32| {
+            let crc = crate::testutil::crc(&found, b"abc").wrapping_add(crc_delta);
+            let mut bytes = crate::testutil::minimal_rgb(1, 1);
+            // Before IEND, the last 12 bytes
+            let at = bytes.len() - 12;
+            bytes.splice(at..at, crate::testutil::raw_chunk(&found, b"abc", crc));
+            (bytes, at)
+        };
+        let (bytes, at) = garbled(0);
+        let error = Png::try_from(bytes.as_slice()).unwrap_err();
+        assert_eq!(error.offset(), at);
+        assert_eq!(
+            *error.kind(),
+            InvalidChunk::Type {
+                found,
+                error: ChunkTypeError::InvalidByte {
+                    position: 0,
+                    byte: 0
+                }
+            }
+        );
+        assert!(Png::from_reader(bytes.as_slice()).is_err());
+
+        // Lenient parsing keeps it as it is, since its CRC vouches for it
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let png = Png::parse_with(&bytes, &lenient).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        assert!(png.stray_bytes().is_empty());
+        let chunk = &png.chunks()[2];
+        assert_eq!(chunk.chunk_type().bytes(), found);
+        assert_eq!(chunk.chunk_type().to_string(), "\\x00ABC");
+        assert_eq!(chunk.data(), b"abc");
+        assert_eq!(png.as_bytes(), bytes);
+        let png = Png::from_reader_with(bytes.as_slice(), &lenient).unwrap();
+        assert_eq!(png.chunks()[2].chunk_type().bytes(), found);
+
+        // Without a right CRC it's only bytes to skip
+        let (bytes, _) = garbled(1);
+        let png = Png::parse_with(&bytes, &lenient).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.stray_bytes()[0].bytes().len(), 15);
+    }
+
+    #[test]
+    fn test_lenient_scan_of_junk_stays_linear() {
+        // Every fourth offset reads as a 64 KiB chunk with a garbled type.
+        // Checking each one's CRC while skipping them took seconds per
+        // 256 KiB; only the first, where a chunk is expected, is checked
+        let mut bytes = crate::testutil::minimal_rgb(2, 2);
+        let at = bytes.len() - 12;
+        bytes.splice(at..at, [0, 0, 0xff, 0xff].repeat(64 * 1024));
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        let png = Png::parse_with(&bytes, &lenient).unwrap();
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(5),
+            "{:?}",
+            start.elapsed()
+        );
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.stray_bytes()[0].bytes().len(), 256 * 1024);
+        let start = std::time::Instant::now();
+        crate::verify::verify(&bytes, &ParseOptions::default());
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    /// The testing png with miDl's length field off by `delta`, and the
+    /// offset of that chunk.
+    fn with_wrong_length(delta: i32) -> (Vec<u8>, usize) {
+        let mut bytes = testing_png().as_bytes();
+        let offset = Png::parse_borrowed(&bytes).unwrap()[1].offset();
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let declared = length.checked_add_signed(delta).unwrap();
+        bytes[offset..offset + 4].copy_from_slice(&declared.to_be_bytes());
+        (bytes, offset)
+    }
+
+    #[test]
+    fn test_fix_lengths() {
+        let actual = testing_chunks()[1].length();
+        for delta in [-2, 2] {
+            let (bytes, offset) = with_wrong_length(delta);
+            assert!(Png::try_from(bytes.as_slice()).is_err());
+            // Without fix_lengths, lenient parsing keeps the chunk as stray bytes
+            let lenient = ParseOptions {
+                lenient: true,
+                ..Default::default()
+            };
+            let png = Png::parse_with(&bytes, &lenient).unwrap();
+            assert_eq!(png.chunks().len(), 2);
+            assert_eq!(png.as_bytes(), bytes);
+
+            let declared = actual.checked_add_signed(delta).unwrap();
+            let expected = LengthMismatch {
+                offset,
+                chunk_type: ChunkType::from_str("miDl").unwrap(),
+                declared,
+                actual,
+            };
+            for ignore_crc in [false, true] {
+                let options = ParseOptions {
+                    ignore_crc,
+                    fix_lengths: true,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    find_length_mismatches(&bytes, &options).unwrap(),
+                    [expected]
+                );
+                let png = Png::parse_with(&bytes, &options).unwrap();
+                assert_eq!(png, testing_png(), "{delta} {ignore_crc}");
+            }
+            assert!(
+                expected
+                    .to_string()
+                    .ends_with(&format!("declared length {declared}, actual {actual}"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_recover_length_needs_a_matching_crc() {
+        let (mut bytes, offset) = with_wrong_length(2);
+        // Damage the data too, so no length gives a matching CRC
+        bytes[offset + 8] ^= 1;
+        assert_eq!(
+            recover_length(&bytes, offset, &ParseOptions::default()),
+            None
+        );
+        let options = ParseOptions {
+            fix_lengths: true,
+            ..Default::default()
+        };
+        assert!(find_length_mismatches(&bytes, &options).is_err());
+        assert_eq!(
+            find_length_mismatches(&testing_png().as_bytes(), &options).unwrap(),
+            []
+        );
+    }
+
+    #[test]
+    fn test_drop_stray_bytes() {
+        let (bytes, _) = with_junk(5, 1);
+        let mut png = Png::parse_with(
+            &bytes,
+            &ParseOptions {
+                lenient: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(png.drop_stray_bytes().len(), 1);
+        assert_eq!(png.as_bytes(), testing_png().as_bytes());
+    }
+
+    #[test]
+    fn test_stray_bytes_follow_removed_chunks() {
+        let (bytes, _) = with_junk(3, 2);
+        let mut png = Png::parse_with(
+            &bytes,
+            &ParseOptions {
+                lenient: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        png.remove_chunk_at(0);
+        assert_eq!(png.stray_bytes()[0].index(), 1);
+        let reparsed = Png::parse_with(
+            &png.as_bytes(),
+            &ParseOptions {
+                lenient: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(reparsed.stray_bytes()[0].index(), 1);
+    }
+
+    #[test]
+    fn test_chunk_count_includes_critical_chunks() {
+        let png = mixed_png();
+        assert_eq!(png.chunk_count(), 8);
+        assert_eq!(png.chunks()[0].chunk_type(), &ChunkType::IHDR);
+        assert_eq!(png.chunks()[7].chunk_type(), &ChunkType::IEND);
+        assert!(!png.is_empty());
+        assert!(Png::from_chunks(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_retain_and_swap() {
+        let mut png = mixed_png();
+        png.retain(|c| c.chunk_type().is_public());
+        assert_eq!(chunk_types(&png), ["IHDR", "gAMA", "tEXt", "IDAT", "IEND"]);
+        png.swap_chunks(1, 2);
+        assert_eq!(chunk_types(&png), ["IHDR", "tEXt", "gAMA", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_retain_keeps_stray_bytes_in_place() {
+        let (bytes, _) = with_junk(3, 2);
+        let mut png = Png::parse_with(
+            &bytes,
+            &ParseOptions {
+                lenient: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let first = *png.chunks()[0].chunk_type();
+        png.retain(|c| *c.chunk_type() != first);
+        assert_eq!(png.stray_bytes()[0].index(), 1);
+    }
+
+    #[test]
+    fn test_remove_matching_is_one_pass() {
+        // Stray bytes in front of the first, a middle and a removed chunk
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        for (len, at) in [(2, 0), (3, 2), (1, 3)] {
+            let (bytes, _) = with_junk(len, at);
+            let original = Png::parse_with(&bytes, &lenient).unwrap();
+            let mut expected = original.clone();
+            for index in (0..expected.chunk_count()).rev() {
+                if !expected.chunks()[index].chunk_type().is_critical() {
+                    expected.remove_chunk_at(index);
+                }
+            }
+            let mut png = original.clone();
+            let removed = png.strip_ancillary();
+            assert_eq!(png, expected, "{at}");
+            assert_eq!(png.as_bytes(), expected.as_bytes(), "{at}");
+            assert_eq!(
+                removed.len(),
+                original.chunk_count() - expected.chunk_count()
+            );
+            assert!(png.is_modified());
+        }
+
+        let empty = chunk_from_strings("emPt", "").unwrap();
+        let mut chunks = testing_png().chunks().to_vec();
+        chunks.extend(std::iter::repeat_n(empty.clone(), 99_000));
+        let mut png = Png::from_chunks(chunks);
+        let start = std::time::Instant::now();
+        let removed = png.remove_matching(|c| c.chunk_type() == empty.chunk_type());
+        assert_eq!(removed.len(), 99_000);
+        assert_eq!(png, testing_png());
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_post_iend_chunks() {
+        let mut chunks = mixed_png().chunks().to_vec();
+        chunks.push(chunk_from_strings("wmRk", "first").unwrap());
+        chunks.push(chunk_from_strings("wmRk", "second").unwrap());
+        let bytes = Png::from_chunks(chunks).as_bytes();
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.iend_index(), Some(7));
+        assert_eq!(png.post_iend_chunks().len(), 2);
+        assert_eq!(png.post_iend_chunks()[1].data(), b"second");
+        assert_eq!(png.as_bytes(), bytes);
+
+        assert_eq!(png.relocate_post_iend(), 2);
+        assert!(png.post_iend_chunks().is_empty());
+        assert_eq!(chunk_types(&png)[7..], ["wmRk", "wmRk", "IEND"]);
+        assert_eq!(png.chunks()[7].data(), b"first");
+        assert_eq!(png.relocate_post_iend(), 0);
+        assert!(testing_png().post_iend_chunks().is_empty());
+    }
+
+    fn with_declared_length(length: u32) -> Vec<u8> {
+        let mut bytes = testing_png().as_bytes();
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(b"RuSt tiny payload");
+        bytes
+    }
+
+    #[test]
+    fn test_adversarial_lengths_fail_promptly() {
+        for length in [u32::MAX, 0x8000_0000, 0x7FFF_FFFF, 0x0100_0000, 100] {
+            let bytes = with_declared_length(length);
+            assert!(Png::try_from(bytes.as_slice()).is_err());
+            assert!(Png::from_reader(bytes.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_lengths_above_spec_max_rejected() {
+        let bytes = with_declared_length(u32::MAX);
+        assert!(matches!(
+            Png::try_from(bytes.as_slice()).map_err(|e| e.kind().clone()),
+            Err(InvalidChunk::TooLarge {
+                length: u32::MAX,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Png::from_reader(bytes.as_slice()),
+            Err(ReadError::Invalid(ParseError {
+                kind: InvalidChunk::TooLarge { .. },
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_max_chunk_size_option() {
+        let options = ParseOptions {
+            max_chunk_size: 19,
+            ..Default::default()
+        };
+        // "I am the last chunk" is 19 bytes, the first chunk is 20
+        let bytes = testing_png().as_bytes();
+        let err = Png::parse_with(&bytes, &options).unwrap_err();
+        assert_eq!(err.offset(), 8);
+        assert!(matches!(
+            err.kind(),
+            InvalidChunk::TooLarge {
+                length: 20,
+                max: 19
+            }
+        ));
+        assert!(Png::from_reader_with(bytes.as_slice(), &options).is_err());
+    }
+
+    /// Parses `bytes` both ways, checking they fail alike, and returns
+    /// what they failed with.
+    fn limit_error(bytes: &[u8], options: &ParseOptions) -> (usize, InvalidChunk) {
+        let err = Png::parse_with(bytes, options).unwrap_err();
+        let Err(ReadError::Invalid(streamed)) = Png::from_reader_with(bytes, options) else {
+            panic!("the streaming parser should fail the same way");
+        };
+        assert_eq!(streamed.kind(), err.kind());
+        assert!(err.is_limit());
+        (err.offset(), err.kind().clone())
+    }
+
+    #[test]
+    fn test_max_file_size_option() {
+        let bytes = testing_png().as_bytes();
+        let options = ParseOptions {
+            max_file_size: bytes.len() as u64,
+            ..Default::default()
+        };
+        assert!(Png::parse_with(&bytes, &options).is_ok());
+        assert!(Png::from_reader_with(bytes.as_slice(), &options).is_ok());
+        let options = ParseOptions {
+            max_file_size: bytes.len() as u64 - 1,
+            ..Default::default()
+        };
+        let (_, kind) = limit_error(&bytes, &options);
+        assert_eq!(
+            kind,
+            InvalidChunk::FileTooLarge {
+                max: bytes.len() as u64 - 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_max_chunks_option() {
+        let bytes = testing_png().as_bytes();
+        let options = ParseOptions {
+            max_chunks: 2,
+            ..Default::default()
+        };
+        let (offset, kind) = limit_error(&bytes, &options);
+        assert_eq!(kind, InvalidChunk::TooManyChunks { max: 2 });
+        assert_eq!(offset, testing_png().chunk_offsets()[2]);
+    }
+
+    #[test]
+    fn test_many_empty_chunks() {
+        // 200,000 empty chunks are only 2.4 MB
+        let empty = Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new()).as_bytes();
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        for _ in 0..200_000 {
+            bytes.extend_from_slice(&empty);
+        }
+        let capped = ParseOptions {
+            max_chunks: 100_000,
+            ..Default::default()
+        };
+        let (offset, kind) = limit_error(&bytes, &capped);
+        assert_eq!(kind, InvalidChunk::TooManyChunks { max: 100_000 });
+        assert_eq!(offset, 8 + 100_000 * 12);
+        let Err(ReadError::Invalid(e)) = Rewrite::index(bytes.as_slice(), &capped) else {
+            panic!("indexing should stop at the cap too");
+        };
+        assert_eq!(e.offset(), offset);
+
+        let started = std::time::Instant::now();
+        let png = Png::parse_with(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!(png.chunk_count(), 200_000);
+        let rewrite = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap();
+        assert_eq!(rewrite.chunk_count(), 200_000);
+        // Generous, for debug builds on slow machines
+        assert!(started.elapsed().as_secs() < 10, "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_max_total_decompressed_option() {
+        let mut png = testing_png();
+        // Two zTXt chunks of 600 zeros each, so 1200 bytes between them
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(text::deflate(&[0; 600]));
+        for _ in 0..2 {
+            png.append_chunk(Chunk::new(text::ZTXT, data.clone()));
+        }
+        let bytes = png.as_bytes();
+        let options = |max| ParseOptions {
+            max_total_decompressed: max,
+            ..Default::default()
+        };
+        assert!(Png::parse_with(&bytes, &options(1200)).is_ok());
+        assert!(Png::from_reader_with(bytes.as_slice(), &options(1200)).is_ok());
+        let (offset, kind) = limit_error(&bytes, &options(1199));
+        assert_eq!(kind, InvalidChunk::TooMuchDecompressed { max: 1199 });
+        assert_eq!(offset, png.chunk_offsets()[4]);
+        let (offset, _) = limit_error(&bytes, &options(599));
+        assert_eq!(offset, png.chunk_offsets()[3]);
+    }
+
+    #[test]
+    fn test_from_reader_matches_try_from() {
+        let png = Png::from_reader(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_truncated_input_never_panics() {
+        for len in 0..PNG_FILE.len() {
+            let _ = Png::try_from(&PNG_FILE[..len]);
+            let _ = Png::from_reader(&PNG_FILE[..len]);
+            let _ = Png::parse_with(
+                &PNG_FILE[..len],
+                &ParseOptions {
+                    lenient: true,
+                    ..Default::default()
+                },
+            );
+        }
+        assert!(Png::try_from(&PNG_FILE[..5]).is_err());
+    }
+
+    #[test]
+    fn test_display_with_binary_chunk_data() {
+        let mut png = testing_png();
+        let chunk_type = ChunkType::from_str("BiNy").unwrap();
+        png.append_chunk(Chunk::new(chunk_type, vec![0x80, 0x00, 0xff]));
+        let _png_string = format!("{}", png);
+    }
+
+    #[test]
+    fn test_chunk_offsets() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let offsets = png.chunk_offsets();
+        // IHDR at 8 with 13 bytes of data, then sRGB with 1 byte
+        assert_eq!(offsets[1], 8 + 12 + 13);
+        assert_eq!(offsets[2], 8 + 12 + 13 + 12 + 1);
+        let borrowed: Vec<usize> = Png::parse_borrowed(&PNG_FILE)
+            .unwrap()
+            .iter()
+            .map(|c| c.offset())
+            .collect();
+        assert_eq!(offsets, borrowed);
+    }
+
+    #[test]
+    fn test_chunk_offsets_with_stray_bytes() {
+        let (bytes, at) = with_junk(7, 2);
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let png = Png::parse_with(&bytes, &lenient).unwrap();
+        let borrowed: Vec<usize> = Png::parse_borrowed_with(&bytes, &lenient)
+            .unwrap()
+            .iter()
+            .map(|c| c.offset())
+            .collect();
+        assert_eq!(png.chunk_offsets()[2], at + 7);
+        assert_eq!(png.chunk_offsets(), borrowed);
+    }
+
+    #[test]
+    fn test_listing_matches_display() {
+        let (bytes, _) = with_junk(7, 2);
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        for (bytes, options) in [(&PNG_FILE[..], ParseOptions::default()), (&bytes, lenient)] {
+            let png = Png::parse_with(bytes, &options).unwrap();
+            let chunks = Png::parse_borrowed_with(bytes, &options).unwrap();
+            assert_eq!(Listing(&chunks).to_string(), png.to_string());
+        }
+    }
+
+    #[test]
+    fn test_parse_error_offset() {
+        let mut bytes = testing_png().as_bytes();
+        let offsets = Png::parse_borrowed(&bytes)
+            .unwrap()
+            .iter()
+            .map(|c| c.offset())
+            .collect::<Vec<_>>();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let err = Png::try_from(bytes.as_slice()).unwrap_err();
+        assert!(matches!(err.kind(), InvalidChunk::Crc { .. }));
+        assert_eq!(err.offset(), offsets[2]);
+        assert!(
+            err.to_string()
+                .contains(&format!("at offset {}", offsets[2]))
+        );
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -0,0 +1,122 @@
+//! Recognising structured text in chunk data, so `decode --pretty` and
+//! `print --data` can show it readably. Anything that isn't recognised is
+//! left to the caller to show as it is; nothing here fails.
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Structured {
+    /// A JSON object or array. Bare strings and numbers are left as text.
+    Json(Value),
+    /// One `key=value` pair per line, in order.
+    KeyValue(Vec<(String, String)>),
+}
+
+impl Structured {
+    pub fn detect(data: &[u8]) -> Option<Structured> {
+        let text = std::str::from_utf8(data).ok()?.trim();
+        if text.starts_with(['{', '[']) {
+            return serde_json::from_str(text).ok().map(Structured::Json);
+        }
+        let pairs: Option<Vec<_>> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (key, value) = line.split_once('=')?;
+                let key = key.trim();
+                let valid = !key.is_empty() && !key.contains(char::is_whitespace);
+                valid.then(|| (key.to_string(), value.trim().to_string()))
+            })
+            .collect();
+        pairs
+            .filter(|pairs| !pairs.is_empty())
+            .map(Structured::KeyValue)
+    }
+    /// Indented JSON, or the pairs with their values lined up.
+    pub fn pretty(&self) -> String {
+        match self {
+            Structured::Json(value) => {
+                serde_json::to_string_pretty(value).expect("a parsed Value always serializes")
+            }
+            Structured::KeyValue(pairs) => {
+                let width = pairs.iter().map(|(k, _)| k.chars().count()).max();
+                let width = width.unwrap_or(0);
+                let lines: Vec<String> = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{key:<width$} = {value}"))
+                    .collect();
+                lines.join("\n")
+            }
+        }
+    }
+    /// One line saying what the data holds, e.g. `JSON object with keys: a, b`.
+    pub fn summary(&self) -> String {
+        let keys = |keys: Vec<&str>| {
+            if keys.is_empty() {
+                "no keys".to_string()
+            } else {
+                format!("keys: {}", keys.join(", "))
+            }
+        };
+        match self {
+            Structured::Json(Value::Object(map)) => {
+                format!(
+                    "JSON object with {}",
+                    keys(map.keys().map(String::as_str).collect())
+                )
+            }
+            Structured::Json(Value::Array(items)) => match items.len() {
+                1 => "JSON array of 1 item".to_string(),
+                n => format!("JSON array of {n} items"),
+            },
+            Structured::Json(_) => "JSON value".to_string(),
+            Structured::KeyValue(pairs) => format!(
+                "key=value pairs with {}",
+                keys(pairs.iter().map(|(k, _)| k.as_str()).collect())
+            ),
+        }
+    }
+}
+
+/// `data` made readable if it's structured, otherwise `None`.
+pub fn pretty(data: &[u8]) -> Option<String> {
+    Structured::detect(data).map(|s| s.pretty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let data = br#"{"b":1,"a":[true,null]}"#;
+        assert_eq!(
+            pretty(data).unwrap(),
+            "{\n  \"a\": [\n    true,\n    null\n  ],\n  \"b\": 1\n}"
+        );
+        let summary = Structured::detect(data).unwrap().summary();
+        assert_eq!(summary, "JSON object with keys: a, b");
+        let summary = Structured::detect(b" [1, 2] ").unwrap().summary();
+        assert_eq!(summary, "JSON array of 2 items");
+    }
+
+    #[test]
+    fn test_key_value() {
+        let data = b"name=ferris\nlong_key = a=b\n\n";
+        assert_eq!(pretty(data).unwrap(), "name     = ferris\nlong_key = a=b");
+        let summary = Structured::detect(data).unwrap().summary();
+        assert_eq!(summary, "key=value pairs with keys: name, long_key");
+    }
+
+    #[test]
+    fn test_unstructured() {
+        assert_eq!(pretty(br#"{"a": 1"#), None);
+        assert_eq!(pretty(b"[not json"), None);
+        assert_eq!(pretty(b"plain text"), None);
+        assert_eq!(pretty(b"a=1\nno pair here"), None);
+        assert_eq!(pretty(b"two words=1"), None);
+        assert_eq!(pretty(b"42"), None);
+        assert_eq!(pretty(b""), None);
+        assert_eq!(pretty(&[b'{', 0xff, b'}']), None);
+    }
+}
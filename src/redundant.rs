@@ -0,0 +1,225 @@
+//! One payload stored in several chunks of different types, so it survives
+//! an optimizer that strips some of them.
+//!
+//! Every copy starts with a header: `MAGIC`, a version byte, the number of
+//! copies written, and the payload's length and CRC-32, both big endian.
+//! The CRC catches a damaged copy, not a forged one.
+
+use crate::chunk::{Chunk, X25};
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+pub const MAGIC: [u8; 4] = *b"pmRD";
+/// The header version this writes, and the only one it reads.
+pub const VERSION: u8 = 1;
+const HEADER_LEN: usize = 14;
+
+/// Copies get the default types `pmRa`, `pmRb` and so on, one per letter.
+pub const MAX_COPIES: usize = 26;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedundantError {
+    BadCopies(usize),
+    /// Copies must be ancillary, private and safe to copy, or optimizers
+    /// and editors would be right to drop them all.
+    BadType(ChunkType),
+    DuplicateType(ChunkType),
+    TooLarge(usize),
+    NoCopies,
+    /// Every copy found failed its CRC check; holds how many there were.
+    AllDamaged(usize),
+}
+
+impl std::fmt::Display for RedundantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedundantError::BadCopies(n) => {
+                write!(f, "Can write 1 to {MAX_COPIES} copies, not {n}")
+            }
+            RedundantError::BadType(t) => write!(
+                f,
+                "{t} can't hold a copy: it must be ancillary, private and safe to copy, like pmRa"
+            ),
+            RedundantError::DuplicateType(t) => write!(f, "{t} is given more than once"),
+            RedundantError::TooLarge(len) => {
+                write!(f, "A {len} byte payload is too large to store")
+            }
+            RedundantError::NoCopies => write!(f, "There are no redundant copies in the png"),
+            RedundantError::AllDamaged(n) => {
+                write!(f, "All {n} redundant copies are damaged")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RedundantError {}
+
+/// `count` distinct chunk types that can each hold a copy.
+pub fn default_types(count: usize) -> Result<Vec<ChunkType>, RedundantError> {
+    if count == 0 || count > MAX_COPIES {
+        return Err(RedundantError::BadCopies(count));
+    }
+    Ok((b'a'..)
+        .take(count)
+        .map(|last| ChunkType::try_from([b'p', b'm', b'R', last]).unwrap())
+        .collect())
+}
+
+fn check_types(types: &[ChunkType]) -> Result<(), RedundantError> {
+    if types.is_empty() || types.len() > MAX_COPIES {
+        return Err(RedundantError::BadCopies(types.len()));
+    }
+    for (i, t) in types.iter().enumerate() {
+        if t.is_critical() || t.is_public() || !t.is_reserved_bit_valid() || !t.is_safe_to_copy() {
+            return Err(RedundantError::BadType(*t));
+        }
+        if types[..i].contains(t) {
+            return Err(RedundantError::DuplicateType(*t));
+        }
+    }
+    Ok(())
+}
+
+/// A chunk of each of `types` holding `payload`.
+pub fn encode(payload: &[u8], types: &[ChunkType]) -> Result<Vec<Chunk>, RedundantError> {
+    check_types(types)?;
+    let len = u32::try_from(payload.len()).map_err(|_| RedundantError::TooLarge(payload.len()))?;
+    let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+    data.extend_from_slice(&MAGIC);
+    data.push(VERSION);
+    data.push(types.len() as u8);
+    data.extend_from_slice(&len.to_be_bytes());
+    data.extend_from_slice(&X25.checksum(payload).to_be_bytes());
+    data.extend_from_slice(payload);
+    Ok(types.iter().map(|t| Chunk::new(*t, data.clone())).collect())
+}
+
+/// Whether `chunk` is a copy written by `encode`, intact or not.
+pub fn is_copy(chunk: &Chunk) -> bool {
+    !chunk.chunk_type().is_critical() && chunk.data().starts_with(&MAGIC)
+}
+
+/// The payload in a copy's data, if its header is readable and the CRC
+/// matches, with the number of copies it says were written.
+fn read_copy(data: &[u8]) -> Option<(&[u8], usize)> {
+    let header = data.get(..HEADER_LEN)?;
+    if header[..4] != MAGIC || header[4] != VERSION {
+        return None;
+    }
+    let len = u32::from_be_bytes(header[6..10].try_into().unwrap()) as usize;
+    let crc = u32::from_be_bytes(header[10..14].try_into().unwrap());
+    let payload = &data[HEADER_LEN..];
+    (payload.len() == len && X25.checksum(payload) == crc).then_some((payload, header[5] as usize))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovered {
+    pub payload: Vec<u8>,
+    /// Intact copies of the payload still in the file.
+    pub surviving: usize,
+    /// How many copies were written.
+    pub written: usize,
+}
+
+/// Finds an intact copy in `png`, and counts how many copies of the same
+/// payload survive.
+pub fn decode(png: &Png) -> Result<Recovered, RedundantError> {
+    let copies: Vec<&Chunk> = png.chunks().iter().filter(|c| is_copy(c)).collect();
+    let intact: Vec<(&[u8], usize)> = copies.iter().filter_map(|c| read_copy(c.data())).collect();
+    let Some(&(payload, written)) = intact.first() else {
+        return Err(if copies.is_empty() {
+            RedundantError::NoCopies
+        } else {
+            RedundantError::AllDamaged(copies.len())
+        });
+    };
+    Ok(Recovered {
+        payload: payload.to_vec(),
+        surviving: intact.iter().filter(|(p, _)| *p == payload).count(),
+        written,
+    })
+}
+
+/// Removes every copy, intact or damaged, and returns them.
+pub fn remove(png: &mut Png) -> Vec<Chunk> {
+    let mut removed = Vec::new();
+    png.retain(|chunk| {
+        if is_copy(chunk) {
+            removed.push(chunk.clone());
+            false
+        } else {
+            true
+        }
+    });
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use std::str::FromStr;
+
+    fn png_with_copies(payload: &[u8], count: usize) -> Png {
+        let mut png = PngBuilder::new(1, 1).build().unwrap();
+        png.append_chunks(encode(payload, &default_types(count).unwrap()).unwrap());
+        png
+    }
+
+    #[test]
+    fn test_default_types() {
+        let types = default_types(3).unwrap();
+        let names: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+        assert_eq!(names, ["pmRa", "pmRb", "pmRc"]);
+        assert!(check_types(&default_types(MAX_COPIES).unwrap()).is_ok());
+        assert_eq!(default_types(0), Err(RedundantError::BadCopies(0)));
+        assert_eq!(default_types(27), Err(RedundantError::BadCopies(27)));
+    }
+
+    #[test]
+    fn test_types_are_checked() {
+        for name in ["IDAT", "tEXt", "ruST", "prIV"] {
+            let t = ChunkType::from_str(name).unwrap();
+            assert_eq!(
+                encode(b"x", &[t]),
+                Err(RedundantError::BadType(t)),
+                "{name}"
+            );
+        }
+        let t = ChunkType::from_str("abCd").unwrap();
+        assert_eq!(encode(b"x", &[t, t]), Err(RedundantError::DuplicateType(t)));
+    }
+
+    #[test]
+    fn test_decode_survives_missing_and_damaged_copies() {
+        let mut png = png_with_copies(b"keep me", 3);
+        let recovered = decode(&png).unwrap();
+        assert_eq!(recovered.payload, b"keep me");
+        assert_eq!((recovered.surviving, recovered.written), (3, 3));
+
+        // An optimizer drops the first copy, and another is damaged
+        let first = png.chunks().iter().position(is_copy).unwrap();
+        png.remove_chunk_at(first);
+        let second = png.chunks().iter().position(is_copy).unwrap();
+        let damaged = png.chunks()[second].clone();
+        let mut data = damaged.data().to_vec();
+        *data.last_mut().unwrap() ^= 1;
+        png.replace_chunk_at(second, Chunk::new(*damaged.chunk_type(), data));
+        let recovered = decode(&png).unwrap();
+        assert_eq!(recovered.payload, b"keep me");
+        assert_eq!((recovered.surviving, recovered.written), (1, 3));
+
+        assert_eq!(remove(&mut png).len(), 2);
+        assert_eq!(decode(&png), Err(RedundantError::NoCopies));
+    }
+
+    #[test]
+    fn test_all_damaged() {
+        let mut png = png_with_copies(b"", 1);
+        let index = png.chunks().iter().position(is_copy).unwrap();
+        let chunk = png.chunks()[index].clone();
+        let truncated = chunk.data()[..HEADER_LEN - 1].to_vec();
+        png.replace_chunk_at(index, Chunk::new(*chunk.chunk_type(), truncated));
+        assert_eq!(decode(&png), Err(RedundantError::AllDamaged(1)));
+    }
+}
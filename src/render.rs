@@ -0,0 +1,423 @@
+//! Checking that an edit left the image as it was. Both files are decoded
+//! with the `png` crate, palettes and tRNS expanded, and the pixels
+//! compared byte for byte, so a change to IDAT, PLTE or tRNS that alters
+//! what is shown is caught, while one that only re-encodes the same
+//! pixels is not. Only the first frame of an animation is compared.
+//!
+//! Embedding in the pixels themselves changes them on purpose, so this is
+//! for edits that only touch chunks.
+//!
+//! `compare_pixels` is looser, for comparing files from different
+//! encoders: it only asks whether the pixels look the same, however they're
+//! stored, optionally within a tolerance.
+
+use png::{BitDepth, ColorType, Decoder, DecodingError, Transformations};
+use std::fmt::{self, Display};
+use std::io::Cursor;
+
+/// An image as a viewer would get it: 8 or 16 bits per channel, with any
+/// palette and tRNS applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendering {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: BitDepth,
+    pub pixels: Vec<u8>,
+}
+
+impl Rendering {
+    /// e.g. `2x2 Rgba 8-bit`.
+    fn layout(&self) -> String {
+        format!(
+            "{}x{} {:?} {}-bit",
+            self.width, self.height, self.color_type, self.bit_depth as u8
+        )
+    }
+}
+
+pub fn render(bytes: &[u8]) -> Result<Rendering, DecodingError> {
+    let mut decoder = Decoder::new(Cursor::new(bytes));
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    // Too big to address is as good as too big to decode
+    let size = reader
+        .output_buffer_size()
+        .ok_or(DecodingError::LimitsExceeded)?;
+    let mut pixels = vec![0; size];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+    Ok(Rendering {
+        width: info.width,
+        height: info.height,
+        color_type: info.color_type,
+        bit_depth: info.bit_depth,
+        pixels,
+    })
+}
+
+/// Which of the two files being compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Original,
+    Output,
+}
+
+impl Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Side::Original => "original",
+            Side::Output => "output",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    Decode {
+        side: Side,
+        source: DecodingError,
+    },
+    /// The dimensions, color type or bit depth differ.
+    Layout {
+        original: String,
+        output: String,
+    },
+    Pixels {
+        /// How many bytes of the pixel data differ, of how many.
+        differing: usize,
+        total: usize,
+        /// The first pixel that differs, as (x, y).
+        first: (u32, u32),
+    },
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Decode { side, source } => {
+                write!(f, "the {side} doesn't decode: {source}")
+            }
+            RenderError::Layout { original, output } => {
+                write!(f, "the output is {output} where the original is {original}")
+            }
+            RenderError::Pixels {
+                differing,
+                total,
+                first: (x, y),
+            } => write!(
+                f,
+                "{differing} of {total} bytes of pixel data differ, the first at pixel ({x}, {y})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes both files and checks they show the same image.
+pub fn compare(original: &[u8], output: &[u8]) -> Result<(), RenderError> {
+    let decode = |bytes, side| render(bytes).map_err(|source| RenderError::Decode { side, source });
+    let original = decode(original, Side::Original)?;
+    let output = decode(output, Side::Output)?;
+    if original.layout() != output.layout() {
+        return Err(RenderError::Layout {
+            original: original.layout(),
+            output: output.layout(),
+        });
+    }
+    let differing = original
+        .pixels
+        .iter()
+        .zip(&output.pixels)
+        .filter(|(a, b)| a != b)
+        .count();
+    let Some(first) = original
+        .pixels
+        .iter()
+        .zip(&output.pixels)
+        .position(|(a, b)| a != b)
+    else {
+        return Ok(());
+    };
+    let pixel_size = original.pixels.len() / (original.width as usize * original.height as usize);
+    let pixel = (first / pixel_size) as u32;
+    Err(RenderError::Pixels {
+        differing,
+        total: original.pixels.len(),
+        first: (pixel % original.width, pixel / original.width),
+    })
+}
+
+/// How `compare_pixels` decides two pixels are the same.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PixelOptions {
+    /// Compare color only, so transparency changes don't count.
+    pub ignore_alpha: bool,
+    /// How far apart two channel values may be and still count as equal,
+    /// on the scale of the deeper image: 255 or 65535 for full.
+    pub tolerance: u16,
+}
+
+/// Where `compare_pixels` found two images to differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PixelDifference {
+    Dimensions {
+        first: (u32, u32),
+        second: (u32, u32),
+    },
+    Pixels {
+        /// The first pixel that differs, as (x, y).
+        at: (u32, u32),
+        /// Its RGBA values in each image.
+        first: [u16; 4],
+        second: [u16; 4],
+        /// How many pixels differ in all.
+        differing: usize,
+        /// Whether the values are 16-bit rather than 8-bit.
+        sixteen_bit: bool,
+    },
+}
+
+impl Rendering {
+    /// Every pixel as RGBA, whatever the color type, with opaque alpha
+    /// where there's none. 8-bit samples are scaled to 16 bits if
+    /// `sixteen_bit`, so 0xff becomes 0xffff.
+    fn rgba(&self, sixteen_bit: bool) -> Vec<[u16; 4]> {
+        let (samples, max): (Vec<u16>, u16) = match self.bit_depth {
+            BitDepth::Sixteen => (
+                self.pixels
+                    .chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    .collect(),
+                u16::MAX,
+            ),
+            _ if sixteen_bit => (
+                self.pixels.iter().map(|&b| u16::from(b) * 257).collect(),
+                u16::MAX,
+            ),
+            _ => (self.pixels.iter().map(|&b| u16::from(b)).collect(), 255),
+        };
+        samples
+            .chunks_exact(self.color_type.samples())
+            .map(|p| match *p {
+                [g] => [g, g, g, max],
+                [g, a] => [g, g, g, a],
+                [r, g, b] => [r, g, b, max],
+                [r, g, b, a] => [r, g, b, a],
+                _ => unreachable!("a pixel has 1 to 4 samples"),
+            })
+            .collect()
+    }
+}
+
+/// Whether two images show the same pixels, whatever color type and bit
+/// depth each is stored in: gray, palette and color pixels that look the
+/// same are equal, as are 8- and 16-bit ones.
+pub fn compare_pixels(
+    first: &Rendering,
+    second: &Rendering,
+    options: &PixelOptions,
+) -> Option<PixelDifference> {
+    if (first.width, first.height) != (second.width, second.height) {
+        return Some(PixelDifference::Dimensions {
+            first: (first.width, first.height),
+            second: (second.width, second.height),
+        });
+    }
+    let sixteen_bit = first.bit_depth == BitDepth::Sixteen || second.bit_depth == BitDepth::Sixteen;
+    let channels = if options.ignore_alpha { 3 } else { 4 };
+    let same = |a: &[u16; 4], b: &[u16; 4]| {
+        (0..channels).all(|c| a[c].abs_diff(b[c]) <= options.tolerance)
+    };
+    let (a, b) = (first.rgba(sixteen_bit), second.rgba(sixteen_bit));
+    let mut differing = a
+        .iter()
+        .zip(&b)
+        .enumerate()
+        .filter(|(_, (a, b))| !same(a, b));
+    let (index, (a_pixel, b_pixel)) = differing.next()?;
+    let index = index as u32;
+    Some(PixelDifference::Pixels {
+        at: (index % first.width, index / first.width),
+        first: *a_pixel,
+        second: *b_pixel,
+        differing: differing.count() + 1,
+        sixteen_bit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{ColorType as BuilderColor, PngBuilder};
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use std::str::FromStr;
+
+    fn filled(fill: &[u8]) -> Png {
+        PngBuilder::new(3, 2)
+            .color_type(BuilderColor::Rgba)
+            .fill(fill)
+            .build()
+            .unwrap()
+    }
+
+    /// 2x1, a black pixel then a white one from a two-color palette.
+    fn indexed() -> Png {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_palette(vec![0, 0, 0, 255, 255, 255]);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 1]).unwrap();
+        writer.finish().unwrap();
+        Png::try_from(bytes.as_slice()).unwrap()
+    }
+
+    fn replace(png: &mut Png, chunk_type: &str, data: Vec<u8>) {
+        let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+        let index = png
+            .chunks()
+            .iter()
+            .position(|c| *c.chunk_type() == chunk_type)
+            .unwrap();
+        png.replace_chunk_at(index, Chunk::new(chunk_type, data));
+    }
+
+    #[test]
+    fn test_chunk_edits_render_the_same() {
+        let original = filled(&[255, 0, 0, 128]);
+        let mut edited = original.clone();
+        edited.append_chunks(vec![Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"hello".to_vec(),
+        )]);
+        edited.strip_ancillary();
+        compare(&original.as_bytes(), &edited.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_idat_corruption_is_caught() {
+        let original = filled(&[255, 0, 0, 128]);
+        // Image data that decodes cleanly, to different pixels
+        let other = filled(&[255, 0, 0, 129]);
+        let mut edited = original.clone();
+        replace(
+            &mut edited,
+            "IDAT",
+            other.chunk_by_type("IDAT").unwrap().data().to_vec(),
+        );
+        let error = compare(&original.as_bytes(), &edited.as_bytes()).unwrap_err();
+        assert!(matches!(
+            error,
+            RenderError::Pixels {
+                differing: 6,
+                total: 24,
+                first: (0, 0)
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "6 of 24 bytes of pixel data differ, the first at pixel (0, 0)"
+        );
+
+        // Image data that no longer decodes at all
+        let mut edited = original.clone();
+        replace(&mut edited, "IDAT", vec![0x78, 0x9c, 0xff, 0xff]);
+        let error = compare(&original.as_bytes(), &edited.as_bytes()).unwrap_err();
+        assert!(matches!(
+            error,
+            RenderError::Decode {
+                side: Side::Output,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_palette_and_transparency_changes_are_caught() {
+        let original = indexed();
+        let mut edited = original.clone();
+        replace(&mut edited, "PLTE", vec![0, 0, 0, 255, 255, 254]);
+        let error = compare(&original.as_bytes(), &edited.as_bytes()).unwrap_err();
+        assert!(matches!(error, RenderError::Pixels { .. }), "{error}");
+
+        // Making a palette entry transparent adds an alpha channel
+        let mut edited = original.clone();
+        let idat = edited.chunks().len() - 2;
+        edited.insert_chunk(
+            idat,
+            Chunk::new(ChunkType::from_str("tRNS").unwrap(), vec![0]),
+        );
+        let error = compare(&original.as_bytes(), &edited.as_bytes()).unwrap_err();
+        assert!(matches!(error, RenderError::Layout { .. }), "{error}");
+    }
+
+    #[test]
+    fn test_pixels_compare_across_representations() {
+        let options = PixelOptions::default();
+        // Black then white, from a palette and as plain RGB
+        let indexed = render(&indexed().as_bytes()).unwrap();
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Sixteen);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255])
+            .unwrap();
+        writer.finish().unwrap();
+        let rgb16 = render(&bytes).unwrap();
+        assert_eq!(compare_pixels(&indexed, &rgb16, &options), None);
+
+        let opaque = render(&filled(&[10, 20, 30, 255]).as_bytes()).unwrap();
+        let translucent = render(&filled(&[10, 20, 32, 128]).as_bytes()).unwrap();
+        assert_eq!(
+            compare_pixels(&opaque, &translucent, &options),
+            Some(PixelDifference::Pixels {
+                at: (0, 0),
+                first: [10, 20, 30, 255],
+                second: [10, 20, 32, 128],
+                differing: 6,
+                sixteen_bit: false,
+            })
+        );
+        let loose = PixelOptions {
+            ignore_alpha: true,
+            tolerance: 2,
+        };
+        assert_eq!(compare_pixels(&opaque, &translucent, &loose), None);
+
+        let wider = render(&PngBuilder::new(4, 2).build().unwrap().as_bytes()).unwrap();
+        assert_eq!(
+            compare_pixels(&opaque, &wider, &options),
+            Some(PixelDifference::Dimensions {
+                first: (3, 2),
+                second: (4, 2)
+            })
+        );
+    }
+
+    #[test]
+    fn test_layout_changes_are_caught() {
+        let original = filled(&[0, 0, 0, 255]);
+        let wider = PngBuilder::new(4, 2).build().unwrap();
+        let error = compare(&original.as_bytes(), &wider.as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the output is 4x2 Rgb 8-bit where the original is 3x2 Rgba 8-bit"
+        );
+        let error = compare(b"not a png", &original.as_bytes()).unwrap_err();
+        assert!(error.to_string().starts_with("the original doesn't decode"));
+    }
+}
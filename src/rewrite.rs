@@ -0,0 +1,535 @@
+//! Editing a file without loading it. `Rewrite::index` reads only where each
+//! chunk is, and `write_to` copies the untouched chunks straight from the
+//! input, so adding a small chunk to a huge image costs about one pass of I/O
+//! and a fixed amount of memory.
+
+use crate::chunk::{Chunk, InvalidChunk, X25};
+use crate::chunk_type::ChunkType;
+use crate::png::{
+    self, ParseError, ParseOptions, Png, ReadError, SizeReport, StructureViolation,
+    structure_violations,
+};
+use crate::standard;
+use crate::timings::{self, Phase, Timed};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Where a chunk of the input is, as found by `Rewrite::index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexedChunk {
+    chunk_type: ChunkType,
+    offset: usize,
+    length: u32,
+}
+
+impl IndexedChunk {
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    /// Byte offset of the chunk's length field within the input.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+    fn end(&self) -> usize {
+        self.offset + self.length as usize + 12
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+    Original(IndexedChunk),
+    New(Chunk),
+}
+
+impl Entry {
+    fn chunk_type(&self) -> &ChunkType {
+        match self {
+            Entry::Original(c) => c.chunk_type(),
+            Entry::New(c) => c.chunk_type(),
+        }
+    }
+    fn length(&self) -> u32 {
+        match self {
+            Entry::Original(c) => c.length(),
+            Entry::New(c) => c.length(),
+        }
+    }
+    fn serialized_len(&self) -> usize {
+        self.length() as usize + 12
+    }
+}
+
+/// A list of chunk edits to apply to a file while copying it. The methods
+/// mirror `Png`'s, and the output is byte for byte what `Png` would write.
+#[derive(Debug, Clone)]
+pub struct Rewrite {
+    entries: Vec<Entry>,
+    /// The few bytes after the last chunk that a strict parse tolerates.
+    trailing: std::ops::Range<usize>,
+    /// Whether any chunk was added, removed or moved since indexing.
+    modified: bool,
+}
+
+impl Rewrite {
+    /// Reads through a PNG stream, checking each chunk as `Png::from_reader_with`
+    /// would but keeping only its position. Chunk data is read once to check
+    /// the CRC and then dropped; with `ignore_crc` it is still read, as the
+    /// reader may not be able to seek.
+    ///
+    /// Skipping stray bytes needs the whole input at hand, so this always
+    /// parses strictly and `options.lenient` is ignored; use `Png` for that.
+    /// `max_chunks` is enforced as by `Png`.
+    pub fn index<R: Read>(reader: R, options: &ParseOptions) -> Result<Self, ReadError> {
+        let mut reader = Timed(reader);
+        timings::time(Phase::Signature, || png::read_signature(&mut reader))?;
+        let rewrite = timings::time(Phase::Parse, || Self::index_chunks(reader, options))?;
+        timings::count_chunks(rewrite.chunk_count());
+        Ok(rewrite)
+    }
+    fn index_chunks<R: Read>(mut reader: R, options: &ParseOptions) -> Result<Self, ReadError> {
+        let mut offset = Png::STANDARD_HEADER.len();
+        let mut entries = Vec::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let mut head = [0; 8];
+            let filled = png::read_up_to(&mut reader, &mut head)?;
+            if filled < 4 {
+                return Ok(Self {
+                    entries,
+                    trailing: offset..offset + filled,
+                    modified: false,
+                });
+            }
+            if entries.len() >= options.max_chunks {
+                let kind = InvalidChunk::TooManyChunks {
+                    max: options.max_chunks,
+                };
+                Err(ParseError::new(offset, kind))?
+            }
+            let length = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+            if length > options.max_chunk_size {
+                let kind = InvalidChunk::TooLarge {
+                    length,
+                    max: options.max_chunk_size,
+                };
+                Err(ParseError::new(offset, kind))?
+            }
+            let truncated = |got| {
+                let kind = InvalidChunk::Truncated {
+                    needed: length as usize + 12,
+                    got,
+                };
+                ParseError::new(offset, kind)
+            };
+            if filled < head.len() {
+                Err(truncated(filled))?
+            }
+            let bytes = [head[4], head[5], head[6], head[7]];
+            let chunk_type = if options.any_chunk_type {
+                ChunkType::from_bytes_unchecked(bytes)
+            } else {
+                ChunkType::try_from(bytes).map_err(|error| {
+                    ParseError::new(
+                        offset,
+                        InvalidChunk::Type {
+                            found: bytes,
+                            error,
+                        },
+                    )
+                })?
+            };
+
+            let mut digest = X25.digest();
+            digest.update(&head[4..]);
+            let mut remaining = length as usize;
+            while remaining > 0 {
+                let want = remaining.min(buffer.len());
+                let got = png::read_up_to(&mut reader, &mut buffer[..want])?;
+                if got < want {
+                    Err(truncated(8 + length as usize - remaining + got))?
+                }
+                timings::time(Phase::Crc, || digest.update(&buffer[..got]));
+                remaining -= got;
+            }
+            let mut crc = [0; 4];
+            let got = png::read_up_to(&mut reader, &mut crc)?;
+            if got < crc.len() {
+                Err(truncated(8 + length as usize + got))?
+            }
+            let computed = timings::time(Phase::Crc, || digest.finalize());
+            let stored = u32::from_be_bytes(crc);
+            if !options.ignore_crc && computed != stored {
+                Err(ParseError::new(
+                    offset,
+                    InvalidChunk::Crc { stored, computed },
+                ))?
+            }
+            let chunk = IndexedChunk {
+                chunk_type,
+                offset,
+                length,
+            };
+            offset = chunk.end();
+            entries.push(Entry::Original(chunk));
+        }
+    }
+    pub fn chunk_count(&self) -> usize {
+        self.entries.len()
+    }
+    /// Whether the output would differ from the input, as
+    /// `Png::is_modified` says of a loaded file.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+    pub fn chunk_type(&self, index: usize) -> Option<&ChunkType> {
+        self.entries.get(index).map(Entry::chunk_type)
+    }
+    pub fn structure_violations(&self) -> Vec<StructureViolation> {
+        structure_violations(self.entries.iter().map(Entry::chunk_type))
+    }
+    /// Data length of the chunk at `index`.
+    pub fn chunk_length(&self, index: usize) -> Option<u32> {
+        self.entries.get(index).map(Entry::length)
+    }
+    /// The data of the chunk at `index`, read straight from `input`, which
+    /// must be the stream that was indexed. Nothing is buffered, so a huge
+    /// payload can be copied out in bounded memory. `None` if `index` is out
+    /// of range or the chunk was added rather than indexed.
+    pub fn chunk_data_reader<'a, R: Read + Seek>(
+        &self,
+        index: usize,
+        input: &'a mut R,
+    ) -> io::Result<Option<io::Take<&'a mut R>>> {
+        let Some(Entry::Original(chunk)) = self.entries.get(index) else {
+            return Ok(None);
+        };
+        input.seek(SeekFrom::Start(chunk.offset as u64 + 8))?;
+        Ok(Some(input.take(u64::from(chunk.length))))
+    }
+    /// Adds `chunk` after every other chunk, as `Png::append_chunk` does.
+    pub fn append_chunk(&mut self, chunk: Chunk) {
+        self.entries.push(Entry::New(chunk));
+        self.modified = true;
+    }
+    /// Adds `chunk` just before IEND, or at the end if there is no IEND, as
+    /// `Png::insert_before_iend` does.
+    pub fn insert_before_iend(&mut self, chunk: Chunk) {
+        let iend = self
+            .entries
+            .iter()
+            .position(|e| *e.chunk_type() == ChunkType::IEND);
+        let index = iend.unwrap_or(self.entries.len());
+        self.entries.insert(index, Entry::New(chunk));
+        self.modified = true;
+    }
+    /// Adds `chunk` where the spec has chunks of its type go, as
+    /// `Png::insert_conventional` does, and returns its index.
+    pub fn insert_conventional(&mut self, chunk: Chunk) -> usize {
+        let index = standard::conventional_index(
+            self.entries.iter().map(Entry::chunk_type),
+            chunk.chunk_type(),
+        );
+        self.entries.insert(index, Entry::New(chunk));
+        self.modified = true;
+        index
+    }
+    /// Adds `chunk` at `index`, as `Png::insert_chunk` does.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.entries.insert(index, Entry::New(chunk));
+        self.modified = true;
+    }
+    /// Puts `chunk` in place of the chunk at `index`, returning the type of
+    /// the chunk it replaced.
+    pub fn replace_chunk_at(&mut self, index: usize, chunk: Chunk) -> Option<ChunkType> {
+        let slot = self.entries.get_mut(index)?;
+        self.modified = true;
+        Some(*std::mem::replace(slot, Entry::New(chunk)).chunk_type())
+    }
+    /// Adds every chunk in turn before IEND and reports how the file size
+    /// changed.
+    pub fn append_chunks(&mut self, chunks: impl IntoIterator<Item = Chunk>) -> SizeReport {
+        let before = self.total_size();
+        let mut payload = 0;
+        for chunk in chunks {
+            payload += chunk.data().len();
+            self.insert_before_iend(chunk);
+        }
+        SizeReport {
+            before,
+            after: self.total_size(),
+            payload,
+        }
+    }
+    /// Drops the chunk at `index`, returning its type.
+    pub fn remove_chunk_at(&mut self, index: usize) -> Option<ChunkType> {
+        if index < self.entries.len() {
+            self.modified = true;
+            Some(*self.entries.remove(index).chunk_type())
+        } else {
+            None
+        }
+    }
+    /// Drops the first chunk of type `chunk_name`, returning its index.
+    pub fn remove_first_chunk(&mut self, chunk_name: &str) -> Option<usize> {
+        let chunk_type: ChunkType = chunk_name.parse().ok()?;
+        let index = self
+            .entries
+            .iter()
+            .position(|e| *e.chunk_type() == chunk_type)?;
+        self.entries.remove(index);
+        self.modified = true;
+        Some(index)
+    }
+    /// Moves every chunk after the first IEND to just before it, as
+    /// `Png::relocate_post_iend` does. Returns how many chunks moved.
+    pub fn relocate_post_iend(&mut self) -> usize {
+        let Some(iend) = self
+            .entries
+            .iter()
+            .position(|e| *e.chunk_type() == ChunkType::IEND)
+        else {
+            return 0;
+        };
+        let after = self.entries.split_off(iend + 1);
+        let moved = after.len();
+        self.entries.splice(iend..iend, after);
+        self.modified |= moved > 0;
+        moved
+    }
+    /// Length of the output, without writing anything.
+    pub fn total_size(&self) -> usize {
+        let chunks: usize = self.entries.iter().map(Entry::serialized_len).sum();
+        Png::STANDARD_HEADER.len() + chunks + self.trailing.len()
+    }
+    /// Writes the edited file to `output`, copying unchanged chunks from
+    /// `input`, which must be the stream that was indexed. Runs of chunks
+    /// that were adjacent in the input are copied in one go.
+    pub fn write_to<R: Read + Seek, W: Write>(
+        &self,
+        input: &mut R,
+        output: &mut W,
+    ) -> io::Result<()> {
+        timings::time(Phase::Serialize, || self.copy(input, output))
+    }
+    fn copy<R: Read + Seek, W: Write>(&self, input: &mut R, output: &mut W) -> io::Result<()> {
+        let expected = self.trailing.end as u64;
+        if input.seek(SeekFrom::End(0))? != expected {
+            return Err(io::Error::other(
+                "The input changed size since it was indexed",
+            ));
+        }
+        output.write_all(&Png::STANDARD_HEADER)?;
+        // The source range waiting to be copied, extended while chunks stay contiguous
+        let mut run: Option<std::ops::Range<usize>> = None;
+        let mut flush = |run: &mut Option<std::ops::Range<usize>>, output: &mut W| {
+            if let Some(range) = run.take() {
+                input.seek(SeekFrom::Start(range.start as u64))?;
+                let len = range.len() as u64;
+                if io::copy(&mut input.by_ref().take(len), output)? != len {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+            }
+            Ok(())
+        };
+        for entry in &self.entries {
+            match entry {
+                Entry::Original(chunk) => match &mut run {
+                    Some(range) if range.end == chunk.offset => range.end = chunk.end(),
+                    _ => {
+                        flush(&mut run, output)?;
+                        run = Some(chunk.offset..chunk.end());
+                    }
+                },
+                Entry::New(chunk) => {
+                    flush(&mut run, output)?;
+                    output.write_all(&chunk.as_bytes())?;
+                }
+            }
+        }
+        match &mut run {
+            Some(range) if range.end == self.trailing.start => range.end = self.trailing.end,
+            _ => {
+                flush(&mut run, output)?;
+                run = Some(self.trailing.clone());
+            }
+        }
+        flush(&mut run, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.into())
+    }
+
+    fn source() -> Vec<u8> {
+        PngBuilder::new(4, 4)
+            .with_chunk(chunk("tEXt", "Title\0x"))
+            .with_chunk(chunk("ruSt", "hello"))
+            .build()
+            .unwrap()
+            .as_bytes()
+    }
+
+    fn rewritten(bytes: &[u8], edit: impl FnOnce(&mut Rewrite)) -> Vec<u8> {
+        let mut rewrite = Rewrite::index(bytes, &ParseOptions::default()).unwrap();
+        edit(&mut rewrite);
+        let mut output = Vec::new();
+        rewrite
+            .write_to(&mut Cursor::new(bytes), &mut output)
+            .unwrap();
+        assert_eq!(output.len(), rewrite.total_size());
+        assert_eq!(rewrite.is_modified(), output != bytes);
+        output
+    }
+
+    #[test]
+    fn test_unchanged_copy() {
+        let bytes = source();
+        assert_eq!(rewritten(&bytes, |_| {}), bytes);
+    }
+
+    #[test]
+    fn test_append_matches_png() {
+        let bytes = source();
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        let report = png.append_chunks([chunk("ruSt", "one"), chunk("abCd", "two")]);
+        let mut rewrite_report = None;
+        let output = rewritten(&bytes, |r| {
+            rewrite_report = Some(r.append_chunks([chunk("ruSt", "one"), chunk("abCd", "two")]));
+        });
+        assert_eq!(output, png.as_bytes());
+        assert_eq!(rewrite_report, Some(report));
+    }
+
+    #[test]
+    fn test_remove_matches_png() {
+        let bytes = source();
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        let text = png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "tEXt");
+        png.remove_first_chunk("tEXt");
+        let removed = *png.chunks()[2].chunk_type();
+        png.remove_chunk_at(2);
+        let output = rewritten(&bytes, |r| {
+            assert_eq!(r.remove_first_chunk("tEXt"), text);
+            assert_eq!(r.remove_chunk_at(2), Some(removed));
+        });
+        assert_eq!(output, png.as_bytes());
+    }
+
+    #[test]
+    fn test_insert_conventional_matches_png() {
+        let bytes = source();
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        let added = [chunk("gAMA", "\0\0\0\x01"), chunk("ruSt", "new")];
+        let indices: Vec<usize> = added
+            .iter()
+            .map(|c| png.insert_conventional(c.clone()))
+            .collect();
+        let output = rewritten(&bytes, |r| {
+            for (chunk, index) in added.iter().zip(&indices) {
+                assert_eq!(r.insert_conventional(chunk.clone()), *index);
+            }
+        });
+        assert_eq!(output, png.as_bytes());
+    }
+
+    #[test]
+    fn test_insert_and_replace_match_png() {
+        let bytes = source();
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        png.insert_chunk(1, chunk("ruSa", "a"));
+        let replaced = *png.chunks()[3].chunk_type();
+        png.replace_chunk_at(3, chunk("ruSb", "b"));
+        let output = rewritten(&bytes, |r| {
+            r.insert_chunk(1, chunk("ruSa", "a"));
+            assert_eq!(r.replace_chunk_at(3, chunk("ruSb", "b")), Some(replaced));
+            assert_eq!(r.replace_chunk_at(9, chunk("ruSc", "c")), None);
+        });
+        assert_eq!(output, png.as_bytes());
+    }
+
+    #[test]
+    fn test_trailing_bytes_kept() {
+        let mut bytes = source();
+        bytes.extend_from_slice(b"xy");
+        assert_eq!(rewritten(&bytes, |_| {}), bytes);
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        png.append_chunk(chunk("ruSt", "more"));
+        assert_eq!(
+            rewritten(&bytes, |r| r.append_chunk(chunk("ruSt", "more"))),
+            png.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_relocate_post_iend_matches_png() {
+        let mut bytes = source();
+        bytes.extend_from_slice(&chunk("wmRk", "after").as_bytes());
+        bytes.extend_from_slice(b"xy");
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.relocate_post_iend(), 1);
+        let output = rewritten(&bytes, |r| assert_eq!(r.relocate_post_iend(), 1));
+        assert_eq!(output, png.as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_data_reader() {
+        let bytes = source();
+        let mut rewrite = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap();
+        let mut input = Cursor::new(bytes.as_slice());
+        let mut data = Vec::new();
+        let reader = rewrite.chunk_data_reader(3, &mut input).unwrap();
+        reader.unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello");
+
+        rewrite.insert_before_iend(chunk("ruSt", "new"));
+        assert!(rewrite.chunk_data_reader(4, &mut input).unwrap().is_none());
+        assert!(rewrite.chunk_data_reader(9, &mut input).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_index_rejects_what_png_rejects() {
+        let mut bytes = source();
+        let offset = Png::parse_borrowed(&bytes).unwrap()[2].offset();
+        bytes[offset + 8] ^= 1;
+        let err = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap_err();
+        let ReadError::Invalid(err) = err else {
+            panic!("{err}")
+        };
+        assert!(matches!(err.kind(), InvalidChunk::Crc { .. }));
+        assert_eq!(err.offset(), offset);
+
+        let options = ParseOptions {
+            ignore_crc: true,
+            ..Default::default()
+        };
+        assert!(Rewrite::index(bytes.as_slice(), &options).is_ok());
+        let truncated = &source()[..offset + 10];
+        assert!(Rewrite::index(truncated, &ParseOptions::default()).is_err());
+        assert!(Rewrite::index(&b"GIF89a"[..], &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_changed_input_is_refused() {
+        let bytes = source();
+        let rewrite = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap();
+        let shorter = &bytes[..bytes.len() - 1];
+        assert!(
+            rewrite
+                .write_to(&mut Cursor::new(shorter), &mut Vec::new())
+                .is_err()
+        );
+    }
+}
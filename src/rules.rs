@@ -0,0 +1,422 @@
+//! Rule files: an ordered list of chunk operations written in TOML and
+//! applied to a `Png` in one go.
+//!
+//! ```toml
+//! [[step]]
+//! remove = ["eXIf"]
+//!
+//! [[step]]
+//! add = [{ type = "tEXt", keyword = "Software", value = "mytool" }]
+//!
+//! [[step]]
+//! dedup = true
+//! ```
+//!
+//! Each `[[step]]` holds exactly one operation, and each operation is a thin
+//! layer over the `Png` and `text` functions of the same name.
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::standard;
+use crate::text::{self, ITXT, TextEntry, TextErrorKind, ZTXT};
+use serde::{Deserialize, Deserializer, de::Error as _};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    #[serde(rename = "step", default)]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Step {
+    /// Remove every chunk of each listed type.
+    Remove(#[serde(deserialize_with = "chunk_types")] Vec<ChunkType>),
+    /// `Png::strip_ancillary` or `Png::drop_unsafe_to_copy`.
+    Strip(StripMode),
+    /// Append each chunk, before IEND. Standard chunks go where the spec
+    /// has them, and one the spec allows only one of replaces the existing one.
+    Add(Vec<NewChunk>),
+    /// `text::set_text`.
+    SetText { keyword: String, value: String },
+    /// `text::remove_text`.
+    RemoveText(String),
+    /// Remove ancillary chunks identical in type and data to an earlier one.
+    /// Critical chunks are left alone, as repeated IDATs are still image data.
+    Dedup(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StripMode {
+    Ancillary,
+    UnsafeToCopy,
+}
+
+/// A chunk for `add`: text chunks take a keyword and value, others take data.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RawNewChunk")]
+pub enum NewChunk {
+    Text {
+        chunk_type: ChunkType,
+        keyword: String,
+        value: String,
+    },
+    Data {
+        chunk_type: ChunkType,
+        data: String,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawNewChunk {
+    #[serde(rename = "type", deserialize_with = "chunk_type")]
+    chunk_type: ChunkType,
+    keyword: Option<String>,
+    value: Option<String>,
+    data: Option<String>,
+}
+
+impl TryFrom<RawNewChunk> for NewChunk {
+    type Error = String;
+    fn try_from(raw: RawNewChunk) -> Result<Self, Self::Error> {
+        let chunk_type = raw.chunk_type;
+        match (text::is_text_type(&chunk_type), raw) {
+            (
+                true,
+                RawNewChunk {
+                    keyword: Some(keyword),
+                    value: Some(value),
+                    data: None,
+                    ..
+                },
+            ) => Ok(NewChunk::Text {
+                chunk_type,
+                keyword,
+                value,
+            }),
+            (true, _) => Err(format!("{chunk_type} takes a keyword and a value")),
+            (
+                false,
+                RawNewChunk {
+                    keyword: None,
+                    value: None,
+                    data: Some(data),
+                    ..
+                },
+            ) => Ok(NewChunk::Data { chunk_type, data }),
+            (false, _) => Err(format!("{chunk_type} takes data")),
+        }
+    }
+}
+
+/// Deserializes one chunk type name, so an invalid one is reported at its
+/// own position rather than at the enclosing table.
+struct TypeName(ChunkType);
+
+impl<'de> Deserialize<'de> for TypeName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        ChunkType::from_str(&name)
+            .map(TypeName)
+            .map_err(|e| D::Error::custom(format!("'{name}': {e}")))
+    }
+}
+
+fn chunk_type<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ChunkType, D::Error> {
+    Ok(TypeName::deserialize(deserializer)?.0)
+}
+
+fn chunk_types<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ChunkType>, D::Error> {
+    let names = Vec::<TypeName>::deserialize(deserializer)?;
+    Ok(names.into_iter().map(|name| name.0).collect())
+}
+
+#[derive(Debug)]
+pub enum RulesError {
+    /// The rules file isn't valid. The message includes the offending line.
+    Parse(toml::de::Error),
+    /// A step couldn't build the chunk it was asked to add.
+    Step { step: usize, kind: TextErrorKind },
+}
+
+impl std::fmt::Display for RulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RulesError::Parse(e) => write!(f, "{e}"),
+            RulesError::Step { step, kind } => write!(f, "Step {}: {kind}", step + 1),
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+impl NewChunk {
+    pub fn to_chunk(&self) -> Result<Chunk, TextErrorKind> {
+        match self {
+            NewChunk::Text {
+                chunk_type,
+                keyword,
+                value,
+            } => {
+                let mut entry = TextEntry::new(keyword, value);
+                if entry.chunk_type != *chunk_type {
+                    entry.chunk_type = *chunk_type;
+                    let itxt = *chunk_type == ITXT;
+                    entry.language = itxt.then(String::new);
+                    entry.translated_keyword = itxt.then(String::new);
+                }
+                entry.compressed = *chunk_type == ZTXT;
+                entry.to_chunk()
+            }
+            NewChunk::Data { chunk_type, data } => {
+                Ok(Chunk::new(*chunk_type, data.clone().into_bytes()))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |types: &mut dyn Iterator<Item = String>| types.collect::<Vec<_>>().join(", ");
+        match self {
+            Step::Remove(types) => write!(
+                f,
+                "remove {}",
+                list(&mut types.iter().map(|t| t.to_string()))
+            ),
+            Step::Strip(StripMode::Ancillary) => write!(f, "strip ancillary chunks"),
+            Step::Strip(StripMode::UnsafeToCopy) => write!(f, "strip unsafe-to-copy chunks"),
+            Step::Add(chunks) => write!(
+                f,
+                "add {}",
+                list(&mut chunks.iter().map(|c| match c {
+                    NewChunk::Text {
+                        chunk_type,
+                        keyword,
+                        ..
+                    } => format!("{chunk_type} {keyword}"),
+                    NewChunk::Data { chunk_type, .. } => chunk_type.to_string(),
+                }))
+            ),
+            Step::SetText { keyword, value } => write!(f, "set {keyword} to {value:?}"),
+            Step::RemoveText(keyword) => write!(f, "remove text entries for {keyword}"),
+            Step::Dedup(true) => write!(f, "remove duplicate ancillary chunks"),
+            Step::Dedup(false) => write!(f, "keep duplicate chunks"),
+        }
+    }
+}
+
+impl Step {
+    /// Applies the step, returning how many chunks it removed and added.
+    pub fn apply(&self, png: &mut Png) -> Result<(usize, usize), TextErrorKind> {
+        let removed = match self {
+            Step::Remove(types) => png.remove_matching(|c| types.contains(c.chunk_type())),
+            Step::Strip(StripMode::Ancillary) => png.strip_ancillary(),
+            Step::Strip(StripMode::UnsafeToCopy) => png.drop_unsafe_to_copy(),
+            Step::Add(chunks) => {
+                let chunks = chunks
+                    .iter()
+                    .map(NewChunk::to_chunk)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let added = chunks.len();
+                let mut replaced = 0;
+                for chunk in chunks {
+                    // Text and other standard chunks go where the spec has them
+                    if standard::is_standard(chunk.chunk_type()) {
+                        replaced += png.insert_or_replace(chunk).1.is_some() as usize;
+                    } else {
+                        png.insert_before_iend(chunk);
+                    }
+                }
+                return Ok((replaced, added));
+            }
+            Step::SetText { keyword, value } => {
+                let before = png.chunk_count();
+                text::set_text(png, keyword, value)?;
+                return Ok((0, png.chunk_count() - before));
+            }
+            Step::RemoveText(keyword) => text::remove_text(png, keyword),
+            Step::Dedup(false) => Vec::new(),
+            Step::Dedup(true) => png.remove_duplicate_ancillary(),
+        };
+        Ok((removed.len(), 0))
+    }
+}
+
+impl Rules {
+    pub fn parse(source: &str) -> Result<Rules, RulesError> {
+        toml::from_str(source).map_err(RulesError::Parse)
+    }
+    /// Applies every step in order. On error `png` may be partly changed,
+    /// so callers that write it out should only do so on success.
+    pub fn apply(&self, png: &mut Png) -> Result<Vec<(usize, usize)>, RulesError> {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(step, s)| s.apply(png).map_err(|kind| RulesError::Step { step, kind }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.into())
+    }
+
+    fn fixture() -> Png {
+        PngBuilder::new(1, 1)
+            .with_chunk(chunk("eXIf", "exif"))
+            .with_chunk(chunk("ruSt", "one"))
+            .with_chunk(chunk("ruSt", "one"))
+            .with_chunk(chunk("ruSt", "two"))
+            .build()
+            .unwrap()
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_multi_step_application() {
+        let rules = Rules::parse(
+            r#"
+            [[step]]
+            remove = ["eXIf"]
+
+            [[step]]
+            add = [
+                { type = "tEXt", keyword = "Software", value = "mytool" },
+                { type = "abCd", data = "raw" },
+            ]
+
+            [[step]]
+            dedup = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rules.steps.len(), 3);
+        let mut png = fixture();
+        let reports = rules.apply(&mut png).unwrap();
+        assert_eq!(reports, [(1, 0), (0, 2), (1, 0)]);
+        assert!(!types(&png).contains(&"eXIf".to_string()));
+        let entries: Vec<_> = text::text_entries(&png).into_iter().flatten().collect();
+        assert_eq!(entries[0].keyword, "Software");
+        assert_eq!(entries[0].value, "mytool");
+        let rust: Vec<_> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "ruSt")
+            .map(|c| c.data())
+            .collect();
+        assert_eq!(rust, [b"one", b"two"]);
+        assert_eq!(types(&png).last().unwrap(), "IEND");
+    }
+
+    #[test]
+    fn test_text_steps() {
+        let rules = Rules::parse(
+            r#"
+            [[step]]
+            set-text = { keyword = "Title", value = "café" }
+            [[step]]
+            add = [{ type = "zTXt", keyword = "Comment", value = "squashed" }]
+            [[step]]
+            remove-text = "Title"
+            [[step]]
+            strip = "unsafe-to-copy"
+            "#,
+        )
+        .unwrap();
+        let mut png = fixture();
+        assert_eq!(
+            rules.apply(&mut png).unwrap(),
+            [(0, 1), (0, 1), (1, 0), (0, 0)]
+        );
+        let entries: Vec<_> = text::text_entries(&png).into_iter().flatten().collect();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].compressed);
+        assert_eq!(entries[0].value, "squashed");
+    }
+
+    #[test]
+    fn test_adding_a_unique_chunk_replaces_it() {
+        let rules = Rules::parse(
+            r#"
+            [[step]]
+            add = [{ type = "eXIf", data = "new exif" }, { type = "sPLT", data = "a" }]
+            [[step]]
+            add = [{ type = "sPLT", data = "b" }]
+            "#,
+        )
+        .unwrap();
+        let mut png = fixture();
+        let before = png.chunk_count();
+        // eXIf takes the old one's place, the second sPLT is added
+        assert_eq!(rules.apply(&mut png).unwrap(), [(1, 2), (0, 1)]);
+        assert_eq!(png.chunk_count(), before + 2);
+        let exif: Vec<_> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "eXIf")
+            .map(|c| c.data())
+            .collect();
+        assert_eq!(exif, [b"new exif"]);
+    }
+
+    fn parse_error(source: &str) -> String {
+        Rules::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_unknown_keys_report_their_line() {
+        let message = parse_error("[[step]]\nremove = [\"eXIf\"]\n\n[[step]]\nfrobnicate = true\n");
+        assert!(message.contains("line 5"), "{message}");
+        assert!(message.contains("frobnicate"), "{message}");
+
+        let message =
+            parse_error("[[step]]\nadd = [{ type = \"ruSt\", data = \"x\", colour = 1 }]\n");
+        assert!(message.contains("line 2"), "{message}");
+        assert!(message.contains("colour"), "{message}");
+
+        let message = parse_error("[[step]]\ndedup = true\n[settings]\n");
+        assert!(message.contains("line 3"), "{message}");
+    }
+
+    #[test]
+    fn test_invalid_values_report_their_line() {
+        let message = parse_error("[[step]]\n\nremove = [\"eX1f\"]\n");
+        assert!(message.contains("line 3"), "{message}");
+        assert!(message.contains("Byte 2"), "{message}");
+
+        let message = parse_error("[[step]]\nadd = [{ type = \"tEXt\", data = \"x\" }]\n");
+        assert!(message.contains("takes a keyword and a value"), "{message}");
+        let message = parse_error("[[step]]\nstrip = \"everything\"\n");
+        assert!(message.contains("everything"), "{message}");
+    }
+
+    #[test]
+    fn test_step_errors_name_the_step() {
+        let rules = Rules::parse(
+            "[[step]]\ndedup = false\n[[step]]\nset-text = { keyword = \"\", value = \"x\" }\n",
+        )
+        .unwrap();
+        let err = rules.apply(&mut fixture()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Step 2: {}", TextErrorKind::BadKeyword)
+        );
+    }
+}
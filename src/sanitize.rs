@@ -0,0 +1,85 @@
+//! Making chunk text safe to print to a terminal. Chunk data comes from
+//! whoever made the file, and control characters in it can move the cursor,
+//! recolor or retitle the terminal, or hide what was printed before them.
+
+/// Whether `c` is shown escaped: C0 controls other than tab and newline,
+/// DEL, and the C1 controls, which some terminals act on like ESC sequences.
+fn needs_escape(c: char) -> bool {
+    c != '\n' && c != '\t' && c.is_control()
+}
+
+/// `text` with every control character that could act on a terminal
+/// written out as an escape instead, e.g. ESC as `\x1b`, and how many were
+/// escaped. Escaping the ESC that starts a CSI or OSC sequence leaves the
+/// rest of it as plain text. Newlines and tabs are kept, and so is a CR
+/// ending a CRLF line; a lone CR, which would let later text overwrite the
+/// line, is escaped.
+pub fn sanitize(text: &str) -> (String, usize) {
+    let mut out = String::with_capacity(text.len());
+    let mut escaped = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let crlf = c == '\r' && chars.peek() == Some(&'\n');
+        if needs_escape(c) && !crlf {
+            escaped += 1;
+            // Every control character is below U+00A0, so two digits do
+            out.push_str(&format!("\\x{:02x}", c as u32));
+        } else {
+            out.push(c);
+        }
+    }
+    (out, escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text_is_unchanged() {
+        let text = "Hello, wörld!\n\tindented\r\nCRLF line\n";
+        assert_eq!(sanitize(text), (text.to_string(), 0));
+        assert_eq!(sanitize(""), (String::new(), 0));
+    }
+
+    #[test]
+    fn test_csi_sequences() {
+        assert_eq!(
+            sanitize("\x1b[31mred\x1b[0m"),
+            ("\\x1b[31mred\\x1b[0m".to_string(), 2)
+        );
+        // Clear the screen and move home
+        assert_eq!(
+            sanitize("\x1b[2J\x1b[H"),
+            ("\\x1b[2J\\x1b[H".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn test_osc_sequences() {
+        // Set the window title, ended by BEL and by ST
+        assert_eq!(
+            sanitize("\x1b]0;pwned\x07"),
+            ("\\x1b]0;pwned\\x07".to_string(), 2)
+        );
+        assert_eq!(
+            sanitize("\x1b]8;;http://x\x1b\\link"),
+            ("\\x1b]8;;http://x\\x1b\\link".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn test_bare_controls() {
+        assert_eq!(sanitize("a\x1bb"), ("a\\x1bb".to_string(), 1));
+        assert_eq!(sanitize("\x00\x08\x7f"), ("\\x00\\x08\\x7f".to_string(), 3));
+        // C1 CSI, the one-character form of ESC [
+        assert_eq!(sanitize("\u{9b}31m"), ("\\x9b31m".to_string(), 1));
+    }
+
+    #[test]
+    fn test_lone_cr() {
+        assert_eq!(sanitize("rm -rf /\rls"), ("rm -rf /\\x0dls".to_string(), 1));
+        assert_eq!(sanitize("end\r"), ("end\\x0d".to_string(), 1));
+        assert_eq!(sanitize("a\r\nb"), ("a\r\nb".to_string(), 0));
+    }
+}
@@ -0,0 +1,234 @@
+//! Chunk selectors: a small expression picking out chunks by what they
+//! are, e.g. `private,larger-than=4096`. Predicates are separated by
+//! commas and a chunk is selected when all of them hold:
+//!
+//! - `type=ruSt`: the chunk's type
+//! - `index=3`: its position, as shown by `list`
+//! - `critical`, `ancillary`: the case of the type's first letter
+//! - `public`, `private`: the case of its second letter
+//! - `larger-than=1024`: more than this many bytes of data
+
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Predicate {
+    Type(ChunkType),
+    Index(usize),
+    Critical,
+    Ancillary,
+    Public,
+    Private,
+    LargerThan(u32),
+}
+
+impl Predicate {
+    fn matches(&self, index: usize, chunk_type: &ChunkType, length: u32) -> bool {
+        match *self {
+            Predicate::Type(t) => t == *chunk_type,
+            Predicate::Index(i) => i == index,
+            Predicate::Critical => chunk_type.is_critical(),
+            Predicate::Ancillary => !chunk_type.is_critical(),
+            Predicate::Public => chunk_type.is_public(),
+            Predicate::Private => !chunk_type.is_public(),
+            Predicate::LargerThan(bytes) => length > bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSelector {
+    predicates: Vec<Predicate>,
+}
+
+impl ChunkSelector {
+    pub fn predicates(&self) -> &[Predicate] {
+        &self.predicates
+    }
+    /// Whether the chunk at `index`, of `chunk_type` with `length` bytes of
+    /// data, is selected. Taking these rather than a `Chunk` lets a file
+    /// that is only indexed be matched without reading any data.
+    pub fn matches(&self, index: usize, chunk_type: &ChunkType, length: u32) -> bool {
+        self.predicates
+            .iter()
+            .all(|p| p.matches(index, chunk_type, length))
+    }
+    /// The positions of the chunks of `png` that are selected, in order.
+    pub fn select(&self, png: &Png) -> Vec<usize> {
+        png.chunks()
+            .iter()
+            .enumerate()
+            .filter(|(index, chunk)| self.matches(*index, chunk.chunk_type(), chunk.length()))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// A selector that doesn't parse, pointing at the token at fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorError {
+    pub expression: String,
+    /// Where the bad token starts in the expression, in characters.
+    pub start: usize,
+    pub len: usize,
+    pub message: String,
+}
+
+impl Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        writeln!(f, "  {}", self.expression)?;
+        write!(
+            f,
+            "  {}{}",
+            " ".repeat(self.start),
+            "^".repeat(self.len.max(1))
+        )
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+fn parse_predicate(token: &str) -> Result<Predicate, String> {
+    let (name, value) = match token.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (token.trim(), None),
+    };
+    let number = |value: Option<&str>| -> Result<u64, String> {
+        let value = value.ok_or_else(|| format!("'{name}' needs a number, e.g. {name}=3"))?;
+        value
+            .parse()
+            .map_err(|_| format!("'{value}' isn't a number"))
+    };
+    let flag = |predicate| match value {
+        Some(_) => Err(format!("'{name}' doesn't take a value")),
+        None => Ok(predicate),
+    };
+    match name {
+        "type" => {
+            let value = value.ok_or("'type' needs a chunk type, e.g. type=ruSt")?;
+            ChunkType::from_str(value)
+                .map(Predicate::Type)
+                .map_err(|e| format!("'{value}' isn't a chunk type: {e}"))
+        }
+        "index" => Ok(Predicate::Index(number(value)? as usize)),
+        "larger-than" => {
+            let bytes = number(value)?;
+            u32::try_from(bytes)
+                .map(Predicate::LargerThan)
+                .map_err(|_| format!("{bytes} is more than a chunk can hold"))
+        }
+        "critical" => flag(Predicate::Critical),
+        "ancillary" => flag(Predicate::Ancillary),
+        "public" => flag(Predicate::Public),
+        "private" => flag(Predicate::Private),
+        "" => Err("Empty predicate".to_string()),
+        _ => Err(format!(
+            "Unknown predicate '{name}', expected type=, index=, larger-than=, \
+             critical, ancillary, public or private"
+        )),
+    }
+}
+
+impl FromStr for ChunkSelector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut predicates = Vec::new();
+        let mut start = 0;
+        for token in s.split(',') {
+            let len = token.chars().count();
+            match parse_predicate(token) {
+                Ok(predicate) => predicates.push(predicate),
+                Err(message) => {
+                    // Point at the token without the spaces around it
+                    let skipped = token.chars().take_while(|c| c.is_whitespace()).count();
+                    return Err(SelectorError {
+                        expression: s.to_string(),
+                        start: start + skipped.min(len),
+                        len: token.trim().chars().count(),
+                        message,
+                    });
+                }
+            }
+            start += len + 1;
+        }
+        Ok(ChunkSelector { predicates })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use crate::chunk::Chunk;
+
+    fn selector(s: &str) -> ChunkSelector {
+        ChunkSelector::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            selector("type=ruSt, index=3,larger-than=1024").predicates(),
+            [
+                Predicate::Type(ChunkType::from_str("ruSt").unwrap()),
+                Predicate::Index(3),
+                Predicate::LargerThan(1024),
+            ]
+        );
+        assert_eq!(
+            selector("critical,ancillary,public,private").predicates(),
+            [
+                Predicate::Critical,
+                Predicate::Ancillary,
+                Predicate::Public,
+                Predicate::Private,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_point_at_the_token() {
+        let error = ChunkSelector::from_str("private,larger-than=big").unwrap_err();
+        assert_eq!(error.start, 8);
+        assert_eq!(error.len, 15);
+        assert_eq!(
+            error.to_string(),
+            "'big' isn't a number\n  private,larger-than=big\n          ^^^^^^^^^^^^^^^"
+        );
+        let error = ChunkSelector::from_str("private, shiny").unwrap_err();
+        assert_eq!((error.start, error.len), (9, 5));
+        assert!(error.message.starts_with("Unknown predicate 'shiny'"));
+        let error = ChunkSelector::from_str("private,").unwrap_err();
+        assert_eq!((error.start, error.len), (8, 0));
+        assert!(error.to_string().ends_with("\n          ^"));
+        for bad in ["", "type", "type=ru5t", "index", "index=-1", "private=1"] {
+            assert!(ChunkSelector::from_str(bad).is_err(), "{bad}");
+        }
+        assert!(ChunkSelector::from_str("larger-than=4294967296").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let chunk = |t: &str, len: usize| Chunk::new(ChunkType::from_str(t).unwrap(), vec![0; len]);
+        let png = PngBuilder::new(1, 1)
+            .with_chunk(chunk("ruSt", 10))
+            .with_chunk(chunk("ruSt", 5000))
+            .with_chunk(chunk("tEXt", 5000))
+            .build()
+            .unwrap();
+        // IHDR, IDAT, the three above, IEND
+        assert_eq!(selector("type=ruSt").select(&png), [2, 3]);
+        assert_eq!(selector("index=4").select(&png), [4]);
+        assert_eq!(selector("private").select(&png), [2, 3]);
+        assert_eq!(selector("ancillary").select(&png), [2, 3, 4]);
+        assert_eq!(selector("critical").select(&png), [0, 1, 5]);
+        assert_eq!(selector("private,larger-than=4096").select(&png), [3]);
+        assert_eq!(selector("public,larger-than=4096").select(&png), [4]);
+        assert_eq!(selector("larger-than=5000").select(&png), [0usize; 0]);
+        assert_eq!(selector("type=ruSt,index=4").select(&png), [0usize; 0]);
+    }
+}
@@ -0,0 +1,133 @@
+//! `pngme self-test`: an end-to-end run through the library entirely in
+//! memory, to catch a broken build or platform before it touches real files.
+
+use pngme::builder::PngBuilder;
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::format::format_crc;
+use pngme::png::{ParseOptions, Png};
+use pngme::rewrite::Rewrite;
+use pngme::text::{self, TextEntry, ZTXT};
+use pngme::verify;
+use std::io::Cursor;
+use std::str::FromStr;
+
+const PAYLOAD: &[u8] = b"pngme self-test \x00\xff payload";
+
+/// The CRC every IEND chunk has, straight from the PNG spec's examples, so a
+/// miscompiled or wrong-endian CRC can't agree with itself and pass.
+const IEND_CRC: u32 = 0xae42_6082;
+
+/// Runs one step, prints its outcome and passes its result along.
+fn check<T>(name: &str, step: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    let result = step();
+    match &result {
+        Ok(_) => println!("PASS  {name}"),
+        Err(e) => println!("FAIL  {name}: {e}"),
+    }
+    result
+}
+
+fn ensure(condition: bool, message: &str) -> Result<(), String> {
+    if condition {
+        Ok(())
+    } else {
+        Err(message.to_string())
+    }
+}
+
+/// Runs every step, stopping at the first failure, and says whether all passed.
+pub fn run() -> bool {
+    let result = steps();
+    if result.is_ok() {
+        println!("All steps passed");
+    }
+    result.is_ok()
+}
+
+fn steps() -> Result<(), String> {
+    let mut png = check("build", || {
+        PngBuilder::new(4, 4).build().map_err(|e| e.to_string())
+    })?;
+
+    let chunk_type = ChunkType::from_str("ruSt").map_err(|e| e.to_string())?;
+    let comment = TextEntry {
+        chunk_type: ZTXT,
+        compressed: true,
+        ..TextEntry::new("Comment", "squeezed through zlib and back")
+    };
+    check("encode", || {
+        let chunks = vec![
+            Chunk::new(chunk_type, PAYLOAD.to_vec()),
+            comment.to_chunk().map_err(|e| e.to_string())?,
+        ];
+        let report = png.append_chunks(chunks);
+        ensure(report.payload > PAYLOAD.len(), "payload size not counted")
+    })?;
+
+    let bytes = check("serialize", || {
+        let bytes = png.as_bytes();
+        ensure(bytes.starts_with(&Png::STANDARD_HEADER), "bad signature")?;
+        ensure(
+            bytes.len() == png.total_size(),
+            "total_size() disagrees with as_bytes()",
+        )?;
+        Ok(bytes)
+    })?;
+
+    let parsed = check("parse", || {
+        Png::try_from(bytes.as_slice()).map_err(|e| e.to_string())
+    })?;
+
+    check("decode", || {
+        let chunk = parsed
+            .chunks()
+            .iter()
+            .find(|c| *c.chunk_type() == chunk_type)
+            .ok_or("payload chunk missing")?;
+        ensure(chunk.data() == PAYLOAD, "payload changed")?;
+        let values: Vec<String> = text::find_text(&parsed, "Comment", false)
+            .into_iter()
+            .map(|e| e.value)
+            .collect();
+        ensure(values == [comment.value.clone()], "compressed text changed")
+    })?;
+
+    check("crc", || {
+        for chunk in parsed.chunks() {
+            ensure(
+                chunk.crc() == chunk.computed_crc(),
+                &format!("{} has a wrong CRC", chunk.chunk_type()),
+            )?;
+        }
+        let iend = Chunk::new(ChunkType::IEND, Vec::new());
+        ensure(
+            iend.crc() == IEND_CRC,
+            &format!(
+                "IEND CRC is {}, not {}",
+                format_crc(iend.crc()),
+                format_crc(IEND_CRC)
+            ),
+        )?;
+        let findings = verify::verify(&bytes, &ParseOptions::default());
+        ensure(findings.is_empty(), "verify reported problems")
+    })?;
+
+    check("compare", || {
+        ensure(parsed == png, "parsed chunks differ from the original")?;
+        ensure(parsed.as_bytes() == bytes, "re-serialized bytes differ")?;
+        let original = PngBuilder::new(4, 4).build().map_err(|e| e.to_string())?;
+        let original = original.as_bytes();
+        let mut rewrite = Rewrite::index(original.as_slice(), &ParseOptions::default())
+            .map_err(|e| e.to_string())?;
+        rewrite.append_chunks(png.chunks()[png.chunk_count() - 3..png.chunk_count() - 1].to_vec());
+        let mut copied = Vec::new();
+        rewrite
+            .write_to(&mut Cursor::new(&original), &mut copied)
+            .map_err(|e| e.to_string())?;
+        ensure(
+            copied == bytes,
+            "copy-through rewrite differs from the in-memory one",
+        )
+    })
+}
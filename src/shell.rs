@@ -0,0 +1,315 @@
+//! `pngme shell`: a prompt for looking around a file and removing chunks,
+//! with the removals staged in memory until `write`.
+
+use crate::{Result, context};
+use pngme::chunk_type::ChunkType;
+use pngme::engine::Engine;
+use pngme::format::{format_crc, format_size};
+use pngme::png::Png;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const HELP: &str = "\
+list             every chunk with its index, type, length and CRC
+show <index>     a chunk's details and a preview of its data
+hex <index>      a chunk's data as a hex dump
+decode <type>    the data of the first chunk of a type, as text
+remove <index>   stage removing a chunk
+undo             unstage the last removal
+write [path]     write the staged changes, over the file or to path
+quit             leave, discarding anything not written
+help             this list";
+
+/// The file being explored and the edits staged on it.
+pub struct Session {
+    engine: Engine,
+    /// The file as it was before each staged edit, newest last.
+    history: Vec<Png>,
+}
+
+/// What to do after a command.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    Print(String),
+    Quit,
+}
+
+/// Splits a command line on whitespace, keeping a double-quoted run,
+/// which may contain spaces, as one word.
+pub fn tokenize(line: &str) -> std::result::Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err("Unterminated quote".to_string());
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Up to 16 bytes per line: offset, hex, and the printable ones as text.
+fn hex_dump(data: &[u8]) -> String {
+    let lines: Vec<String> = data
+        .chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("{:08x}  {:<47}  {text}", row * 16, hex.join(" "))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+impl Session {
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            history: Vec::new(),
+        }
+    }
+    /// How many edits are staged and not yet written.
+    pub fn staged(&self) -> usize {
+        self.history.len()
+    }
+    pub fn prompt(&self) -> String {
+        let name = self
+            .engine
+            .path()
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        match self.staged() {
+            0 => format!("{name}> "),
+            n => format!("{name} [{n} staged]> "),
+        }
+    }
+    fn index(&self, word: Option<&String>) -> std::result::Result<usize, String> {
+        let word = word.ok_or("Give a chunk index, as shown by list")?;
+        let index: usize = word
+            .parse()
+            .map_err(|_| format!("'{word}' isn't a chunk index"))?;
+        if index >= self.engine.png().chunk_count() {
+            return Err(format!(
+                "Index {index} is out of range, the png has {} chunks",
+                self.engine.png().chunk_count()
+            ));
+        }
+        Ok(index)
+    }
+    /// Runs one command line. Errors are the message to show; the session
+    /// is unchanged by a command that fails.
+    pub fn execute(&mut self, line: &str) -> std::result::Result<Step, String> {
+        let words = tokenize(line)?;
+        let Some((command, args)) = words.split_first() else {
+            return Ok(Step::Print(String::new()));
+        };
+        let size = |bytes: usize| format_size(bytes as u64, false);
+        let output = match command.as_str() {
+            "help" => HELP.to_string(),
+            "list" => {
+                let lines: Vec<String> = self
+                    .engine
+                    .png()
+                    .chunks()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, chunk)| {
+                        format!(
+                            "{index:>5}  {}  {:>16}  {}",
+                            chunk.chunk_type(),
+                            size(chunk.length() as usize),
+                            format_crc(chunk.crc())
+                        )
+                    })
+                    .collect();
+                lines.join("\n")
+            }
+            "show" => {
+                let chunk = &self.engine.png().chunks()[self.index(args.first())?];
+                let preview = match std::str::from_utf8(chunk.data()) {
+                    Ok(text) if text.chars().count() > 200 => {
+                        format!("{}...", text.chars().take(200).collect::<String>())
+                    }
+                    Ok(text) => text.to_string(),
+                    Err(_) => "(binary, see hex)".to_string(),
+                };
+                format!(
+                    "Type: {}\nLength: {}\nCRC: {}\nData: {preview}",
+                    chunk.chunk_type(),
+                    size(chunk.length() as usize),
+                    format_crc(chunk.crc())
+                )
+            }
+            "hex" => hex_dump(self.engine.png().chunks()[self.index(args.first())?].data()),
+            "decode" => {
+                let name = args.first().ok_or("Give a chunk type")?;
+                let chunk = self
+                    .engine
+                    .png()
+                    .chunk_by_type(name)
+                    .ok_or_else(|| format!("{name} wasnt found in the png"))?;
+                chunk.data_as_string().map_err(|e| e.to_string())?
+            }
+            "remove" => {
+                let index = self.index(args.first())?;
+                let chunk_type = *self.engine.png().chunks()[index].chunk_type();
+                if [ChunkType::IHDR, ChunkType::IEND].contains(&chunk_type) {
+                    return Err(format!("Refusing to remove {chunk_type}"));
+                }
+                self.history.push(self.engine.png().clone());
+                self.engine.png_mut().remove_chunk_at(index);
+                format!("Staged removing {chunk_type} at index {index}")
+            }
+            "undo" => {
+                let previous = self.history.pop().ok_or("Nothing is staged")?;
+                *self.engine.png_mut() = previous;
+                "Unstaged the last change".to_string()
+            }
+            "write" => {
+                let path = args
+                    .first()
+                    .map_or(self.engine.path(), Path::new)
+                    .to_owned();
+                let saved = match args.first() {
+                    Some(_) => self.engine.save_as(&path),
+                    None => self.engine.save(),
+                };
+                for warning in saved.map_err(|e| context::one_line(&e))? {
+                    eprintln!("Warning: {warning}");
+                }
+                let written = self.history.len();
+                self.history.clear();
+                format!("Wrote {written} staged changes to {}", path.display())
+            }
+            "quit" | "exit" => return Ok(Step::Quit),
+            other => return Err(format!("Unknown command '{other}', try help")),
+        };
+        Ok(Step::Print(output))
+    }
+}
+
+/// Reads commands from stdin until `quit` or end of input.
+pub fn run(mut session: Session) -> Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", session.prompt());
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            break;
+        };
+        match session.execute(&line?) {
+            Ok(Step::Print(output)) if output.is_empty() => {}
+            Ok(Step::Print(output)) => println!("{output}"),
+            Ok(Step::Quit) => break,
+            Err(e) => println!("Error: {e}"),
+        }
+    }
+    if session.staged() > 0 {
+        println!("Discarded {} staged changes", session.staged());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::builder::PngBuilder;
+    use pngme::chunk::Chunk;
+    use pngme::engine::EngineOptions;
+    use std::str::FromStr;
+
+    fn session(dir: &tempfile::TempDir) -> Session {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+        let png = PngBuilder::new(1, 1).with_chunk(chunk).build().unwrap();
+        let path = dir.path().join("a.png");
+        std::fs::write(&path, png.as_bytes()).unwrap();
+        Session::new(Engine::from_png(path, png, EngineOptions::default()))
+    }
+
+    fn print(session: &mut Session, line: &str) -> String {
+        match session.execute(line) {
+            Ok(Step::Print(output)) => output,
+            other => panic!("{line}: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("  write  \"my file.png\" ").unwrap(),
+            ["write", "my file.png"]
+        );
+        assert_eq!(tokenize("decode \"\"").unwrap(), ["decode", ""]);
+        assert!(tokenize("").unwrap().is_empty());
+        assert!(tokenize("write \"open").is_err());
+    }
+
+    #[test]
+    fn test_read_commands() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut session = session(&dir);
+        assert_eq!(print(&mut session, "list").lines().count(), 4);
+        assert!(print(&mut session, "list").contains("    2  ruSt           5 bytes"));
+        assert!(print(&mut session, "show 2").ends_with("Data: hello"));
+        assert_eq!(
+            print(&mut session, "hex 2"),
+            format!("00000000  {:<47}  hello", "68 65 6c 6c 6f")
+        );
+        assert_eq!(print(&mut session, "decode ruSt"), "hello");
+        assert!(session.execute("decode abCd").is_err());
+        assert!(
+            session
+                .execute("show 9")
+                .unwrap_err()
+                .contains("out of range")
+        );
+        assert!(session.execute("frobnicate").is_err());
+        assert_eq!(session.execute("quit"), Ok(Step::Quit));
+    }
+
+    #[test]
+    fn test_staged_edits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut session = session(&dir);
+        let original = std::fs::read(session.engine.path()).unwrap();
+        assert_eq!(session.prompt(), "a.png> ");
+        assert!(session.execute("remove 0").is_err());
+        print(&mut session, "remove 2");
+        assert_eq!(session.prompt(), "a.png [1 staged]> ");
+        assert_eq!(session.engine.png().chunk_count(), 3);
+        print(&mut session, "undo");
+        assert_eq!(session.staged(), 0);
+        assert_eq!(session.engine.png().chunk_count(), 4);
+        assert!(session.execute("undo").is_err());
+
+        print(&mut session, "remove 2");
+        let copy = dir.path().join("copy of a.png");
+        print(&mut session, &format!("write \"{}\"", copy.display()));
+        assert_eq!(session.staged(), 0);
+        assert_eq!(std::fs::read(session.engine.path()).unwrap(), original);
+        let written = Png::try_from(std::fs::read(&copy).unwrap().as_slice()).unwrap();
+        assert_eq!(written.chunk_count(), 3);
+    }
+}
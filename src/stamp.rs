@@ -0,0 +1,175 @@
+//! A png parsed once and then written out again and again with different
+//! chunks added, as a server stamping per-user chunks into the same few
+//! images does.
+//!
+//! The file is kept serialized, behind an `Arc`, along with where new
+//! chunks go in it, so each stamp writes the bytes before that point, the
+//! new chunks and the bytes after it. Nothing is parsed or copied per call
+//! but the output itself, and clones share the one buffer across threads.
+//!
+//! ```
+//! use pngme::builder::PngBuilder;
+//! use pngme::chunk::Chunk;
+//! use pngme::stamp::PngTemplate;
+//!
+//! let template = PngTemplate::new(&PngBuilder::new(64, 64).build().unwrap());
+//! let user = Chunk::new("usEr".parse().unwrap(), b"alice".to_vec());
+//! let stamped = template.stamp(&[user]);
+//! assert_eq!(stamped.len(), template.len() + 17);
+//! ```
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{ParseError, ParseOptions, Png};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PngTemplate {
+    bytes: Arc<[u8]>,
+    /// Where `Png::append_chunks` would put new chunks: just before IEND
+    /// and any stray bytes in front of it, or before trailing stray bytes
+    /// if there's no IEND.
+    insert_at: usize,
+}
+
+impl PngTemplate {
+    pub fn new(png: &Png) -> Self {
+        let index = png
+            .chunks()
+            .iter()
+            .position(|c| *c.chunk_type() == ChunkType::IEND)
+            .unwrap_or(png.chunk_count());
+        let insert_at = match index.checked_sub(1) {
+            Some(last) => png.chunk_offsets()[last] + png.chunks()[last].serialized_len(),
+            None => Png::STANDARD_HEADER.len(),
+        };
+        PngTemplate {
+            bytes: png.as_bytes().into(),
+            insert_at,
+        }
+    }
+    /// Parses `bytes` as `options` say.
+    pub fn parse(bytes: &[u8], options: &ParseOptions) -> Result<Self, ParseError> {
+        Ok(Self::new(&Png::parse_with(bytes, options)?))
+    }
+    /// The file with nothing added.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+    /// The length of `stamp(extra_chunks)`, without writing anything.
+    pub fn stamped_len(&self, extra_chunks: &[Chunk]) -> usize {
+        let extra: usize = extra_chunks.iter().map(Chunk::serialized_len).sum();
+        self.bytes.len() + extra
+    }
+    /// The file with `extra_chunks` added, the same bytes as appending them
+    /// to the parsed png with `Png::append_chunks` and serializing it.
+    pub fn stamp(&self, extra_chunks: &[Chunk]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.stamped_len(extra_chunks));
+        self.stamp_to(extra_chunks, &mut bytes)
+            .expect("writing to a Vec can't fail");
+        bytes
+    }
+    /// Writes what `stamp` returns to `output`, without building it first.
+    pub fn stamp_to<W: Write>(&self, extra_chunks: &[Chunk], output: &mut W) -> io::Result<()> {
+        let (head, tail) = self.bytes.split_at(self.insert_at);
+        output.write_all(head)?;
+        for chunk in extra_chunks {
+            output.write_all(&chunk.as_bytes())?;
+        }
+        output.write_all(tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use std::str::FromStr;
+    use std::thread;
+
+    fn extra() -> Vec<Chunk> {
+        ["one", "two", ""]
+            .iter()
+            .map(|text| {
+                Chunk::new(
+                    ChunkType::from_str("usEr").unwrap(),
+                    text.as_bytes().to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    fn naive(png: &Png, extra_chunks: &[Chunk]) -> Vec<u8> {
+        let mut png = png.clone();
+        png.append_chunks(extra_chunks.to_vec());
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_stamp_matches_appending() {
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let mut pngs = vec![
+            Png::try_from(testutil::apng(3).as_slice()).unwrap(),
+            Png::try_from(testutil::with_text_chunks(&[("Title", "t")]).as_slice()).unwrap(),
+        ];
+        // Stray bytes before IEND and at the end, and a chunk after IEND
+        let mut bytes = testutil::minimal_rgb(2, 2);
+        let iend = bytes.len() - 12;
+        bytes.splice(iend..iend, [1, 2, 3]);
+        bytes.extend(testutil::raw_chunk(
+            b"ruSt",
+            b"late",
+            testutil::crc(b"ruSt", b"late"),
+        ));
+        bytes.extend([9, 9]);
+        let png = Png::parse_with(&bytes, &lenient).unwrap();
+        assert_eq!(png.stray_bytes().len(), 2);
+        pngs.push(png);
+        // No IEND at all, and nothing but the signature
+        let mut png = Png::try_from(testutil::minimal_rgb(1, 1).as_slice()).unwrap();
+        png.remove_first_chunk("IEND");
+        pngs.push(png);
+        pngs.push(Png::from_chunks(Vec::new()));
+
+        for png in &pngs {
+            let template = PngTemplate::new(png);
+            assert_eq!(template.as_bytes(), png.as_bytes());
+            assert_eq!(template.stamp(&[]), png.as_bytes());
+            for count in 1..=3 {
+                let extra = &extra()[..count];
+                let stamped = template.stamp(extra);
+                assert_eq!(stamped, naive(png, extra));
+                assert_eq!(stamped.len(), template.stamped_len(extra));
+            }
+        }
+    }
+
+    #[test]
+    fn test_clones_share_one_buffer_across_threads() {
+        let bytes = testutil::minimal_rgb(8, 8);
+        let template = PngTemplate::parse(&bytes, &ParseOptions::default()).unwrap();
+        let expected = naive(&Png::try_from(bytes.as_slice()).unwrap(), &extra());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let template = template.clone();
+                thread::spawn(move || template.stamp(&extra()))
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+        let clone = template.clone();
+        assert!(std::ptr::eq(clone.as_bytes(), template.as_bytes()));
+        assert!(PngTemplate::parse(&bytes[..20], &ParseOptions::default()).is_err());
+    }
+}
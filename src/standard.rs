@@ -0,0 +1,338 @@
+use crate::chunk_type::ChunkType;
+
+/// A chunk type registered in the PNG specification or its official extensions.
+#[derive(Debug)]
+pub struct StandardChunk {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Where the spec has it go relative to the other chunks.
+    pub placement: Placement,
+    /// Whether the spec allows at most one of it in a file.
+    pub unique: bool,
+}
+
+/// Where a chunk belongs in the file, from the ordering rules of the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// IHDR.
+    First,
+    /// Before PLTE and IDAT, e.g. gAMA and iCCP.
+    BeforePlte,
+    /// PLTE itself.
+    Plte,
+    /// After PLTE but before IDAT, e.g. tRNS.
+    AfterPlte,
+    /// Before IDAT, e.g. pHYs.
+    BeforeIdat,
+    /// IDAT itself.
+    Idat,
+    /// After IDAT, e.g. fdAT.
+    AfterIdat,
+    /// Anywhere between IHDR and IEND, e.g. tIME and text. pngme puts
+    /// these before IDAT, where a reader that stops at the image still
+    /// sees them.
+    Anywhere,
+    /// IEND.
+    Last,
+}
+
+impl Placement {
+    /// Chunks go in order of rank. Those with the same rank may come in
+    /// either order, so a chunk is inserted after the last chunk it may
+    /// follow.
+    fn rank(self) -> u8 {
+        match self {
+            Placement::First => 0,
+            Placement::BeforePlte => 1,
+            Placement::Plte => 2,
+            Placement::AfterPlte | Placement::BeforeIdat | Placement::Anywhere => 3,
+            Placement::Idat => 4,
+            Placement::AfterIdat => 5,
+            Placement::Last => 6,
+        }
+    }
+}
+
+pub const STANDARD_CHUNKS: &[StandardChunk] = &[
+    StandardChunk {
+        name: "IHDR",
+        description: "Image header: dimensions, bit depth and color type",
+        placement: Placement::First,
+        unique: true,
+    },
+    StandardChunk {
+        name: "PLTE",
+        description: "Palette of RGB colors for indexed images",
+        placement: Placement::Plte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "IDAT",
+        description: "Compressed image data",
+        placement: Placement::Idat,
+        unique: false,
+    },
+    StandardChunk {
+        name: "IEND",
+        description: "Marks the end of the file",
+        placement: Placement::Last,
+        unique: true,
+    },
+    StandardChunk {
+        name: "acTL",
+        description: "APNG animation control: frame and loop counts",
+        placement: Placement::BeforeIdat,
+        unique: true,
+    },
+    StandardChunk {
+        name: "cHRM",
+        description: "Primary chromaticities and white point",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "cICP",
+        description: "Coding-independent code points for the color space",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "gAMA",
+        description: "Image gamma",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "iCCP",
+        description: "Embedded ICC color profile",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "mDCV",
+        description: "Mastering display color volume",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "cLLI",
+        description: "Content light level information",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "sBIT",
+        description: "Significant bits per sample",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "sRGB",
+        description: "Image uses the sRGB color space",
+        placement: Placement::BeforePlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "bKGD",
+        description: "Default background color",
+        placement: Placement::AfterPlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "hIST",
+        description: "Palette usage histogram",
+        placement: Placement::AfterPlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "tRNS",
+        description: "Transparency: palette alpha or a transparent color",
+        placement: Placement::AfterPlte,
+        unique: true,
+    },
+    StandardChunk {
+        name: "eXIf",
+        description: "Exif metadata",
+        placement: Placement::BeforeIdat,
+        unique: true,
+    },
+    StandardChunk {
+        name: "fcTL",
+        description: "APNG frame control: size, position and timing",
+        placement: Placement::Anywhere,
+        unique: false,
+    },
+    StandardChunk {
+        name: "pHYs",
+        description: "Physical pixel dimensions",
+        placement: Placement::BeforeIdat,
+        unique: true,
+    },
+    StandardChunk {
+        name: "sPLT",
+        description: "Suggested palette",
+        placement: Placement::BeforeIdat,
+        unique: false,
+    },
+    StandardChunk {
+        name: "fdAT",
+        description: "APNG frame data",
+        placement: Placement::AfterIdat,
+        unique: false,
+    },
+    StandardChunk {
+        name: "tIME",
+        description: "Time of last modification",
+        placement: Placement::Anywhere,
+        unique: true,
+    },
+    StandardChunk {
+        name: "iTXt",
+        description: "International (UTF-8) text, optionally compressed",
+        placement: Placement::Anywhere,
+        unique: false,
+    },
+    StandardChunk {
+        name: "tEXt",
+        description: "Latin-1 text",
+        placement: Placement::Anywhere,
+        unique: false,
+    },
+    StandardChunk {
+        name: "zTXt",
+        description: "Compressed Latin-1 text",
+        placement: Placement::Anywhere,
+        unique: false,
+    },
+    StandardChunk {
+        name: "oFFs",
+        description: "Image offset",
+        placement: Placement::BeforeIdat,
+        unique: true,
+    },
+    StandardChunk {
+        name: "pCAL",
+        description: "Calibration of pixel values",
+        placement: Placement::BeforeIdat,
+        unique: true,
+    },
+    StandardChunk {
+        name: "sCAL",
+        description: "Physical scale of the image subject",
+        placement: Placement::BeforeIdat,
+        unique: true,
+    },
+    StandardChunk {
+        name: "gIFg",
+        description: "GIF graphic control extension",
+        placement: Placement::Anywhere,
+        unique: false,
+    },
+    StandardChunk {
+        name: "gIFx",
+        description: "GIF application extension",
+        placement: Placement::Anywhere,
+        unique: false,
+    },
+    StandardChunk {
+        name: "sTER",
+        description: "Stereo image indicator",
+        placement: Placement::BeforeIdat,
+        unique: true,
+    },
+];
+
+pub fn lookup(chunk_type: &ChunkType) -> Option<&'static StandardChunk> {
+    STANDARD_CHUNKS
+        .iter()
+        .find(|c| c.name.as_bytes() == chunk_type.bytes())
+}
+
+pub fn is_standard(chunk_type: &ChunkType) -> bool {
+    lookup(chunk_type).is_some()
+}
+
+/// Whether `chunk_type` is a standard chunk the spec allows at most one of.
+pub fn is_unique(chunk_type: &ChunkType) -> bool {
+    lookup(chunk_type).is_some_and(|standard| standard.unique)
+}
+
+/// Where `chunk_type` conventionally goes: the spec's placement for a
+/// standard chunk, before IDAT for any other ancillary chunk, and before
+/// IEND, where `encode` puts chunks, for any other critical one.
+pub fn placement(chunk_type: &ChunkType) -> Placement {
+    match lookup(chunk_type) {
+        Some(standard) => standard.placement,
+        None if chunk_type.is_critical() => Placement::AfterIdat,
+        None => Placement::BeforeIdat,
+    }
+}
+
+/// The index at which a chunk of `chunk_type` goes among chunks of
+/// `types`: just before the first that has to come after it, or at the
+/// end if none does.
+pub fn conventional_index<'a>(
+    types: impl IntoIterator<Item = &'a ChunkType>,
+    chunk_type: &ChunkType,
+) -> usize {
+    let rank = placement(chunk_type).rank();
+    let mut count = 0;
+    for (index, existing) in types.into_iter().enumerate() {
+        if placement(existing).rank() > rank {
+            return index;
+        }
+        count = index + 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_table_entries_are_valid_types() {
+        for chunk in STANDARD_CHUNKS {
+            assert!(ChunkType::from_str(chunk.name).is_ok(), "{}", chunk.name);
+        }
+    }
+
+    fn types(names: &[&str]) -> Vec<ChunkType> {
+        names
+            .iter()
+            .map(|name| ChunkType::from_str(name).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_conventional_index() {
+        let index = |existing: &[&str], name: &str| {
+            conventional_index(&types(existing), &ChunkType::from_str(name).unwrap())
+        };
+        let indexed = ["IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "IEND"];
+        assert_eq!(index(&indexed, "cHRM"), 2);
+        assert_eq!(index(&indexed, "PLTE"), 3);
+        assert_eq!(index(&indexed, "bKGD"), 4);
+        assert_eq!(index(&indexed, "tEXt"), 4);
+        assert_eq!(index(&indexed, "ruSt"), 4);
+        assert_eq!(index(&indexed, "fdAT"), 6);
+        assert_eq!(index(&indexed, "RUST"), 6);
+        assert_eq!(index(&indexed, "IEND"), 7);
+        // Before a text chunk that was free to come early
+        assert_eq!(index(&["IHDR", "tEXt", "IDAT", "IEND"], "sRGB"), 1);
+        assert_eq!(index(&[], "tEXt"), 0);
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup(&ChunkType::IDAT).unwrap().name, "IDAT");
+        assert!(is_standard(&ChunkType::from_str("tEXt").unwrap()));
+        assert!(!is_standard(&ChunkType::from_str("ruSt").unwrap()));
+        assert!(is_unique(&ChunkType::from_str("tIME").unwrap()));
+        assert!(is_unique(&ChunkType::IHDR));
+        assert!(!is_unique(&ChunkType::IDAT));
+        assert!(!is_unique(&ChunkType::from_str("tEXt").unwrap()));
+        assert!(!is_unique(&ChunkType::from_str("ruSt").unwrap()));
+    }
+}
@@ -0,0 +1,124 @@
+//! The short account of what `encode` and `remove` changed, printed after
+//! the edit so there's no need to run `list` to check it.
+
+use pngme::chunk_type::ChunkType;
+
+/// A chunk an edit added or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkChange {
+    pub added: bool,
+    /// Where the chunk is after the edit if it was added, or was before it
+    /// if removed, as `list` shows it.
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    /// Whether an added chunk went in ahead of IEND, rather than at the end
+    /// of a file without one.
+    pub before_iend: bool,
+}
+
+impl ChunkChange {
+    /// One line such as `+ ruSt (42 bytes) at index 5 (before IEND)`.
+    pub fn line(&self, size: impl Fn(usize) -> String) -> String {
+        let length = size(self.length as usize);
+        if self.added {
+            let before_iend = if self.before_iend {
+                " (before IEND)"
+            } else {
+                ""
+            };
+            format!(
+                "+ {} ({length}) at index {}{before_iend}",
+                self.chunk_type, self.index
+            )
+        } else {
+            format!("- {} ({length}) from index {}", self.chunk_type, self.index)
+        }
+    }
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "change": if self.added { "added" } else { "removed" },
+            "index": self.index,
+            "chunk_type": self.chunk_type.to_string(),
+            "length": self.length,
+        })
+    }
+}
+
+/// Every chunk an edit added or removed, with the file's size either side.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub changes: Vec<ChunkChange>,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl Summary {
+    /// A line per change, then the file sizes. Nothing if nothing changed.
+    pub fn lines(&self, size: impl Fn(usize) -> String) -> Vec<String> {
+        if self.changes.is_empty() {
+            return Vec::new();
+        }
+        let mut lines: Vec<String> = self.changes.iter().map(|c| c.line(&size)).collect();
+        lines.push(format!(
+            "Size: {} -> {}",
+            size(self.before),
+            size(self.after)
+        ));
+        lines
+    }
+    pub fn to_json(&self) -> serde_json::Value {
+        let changes: Vec<_> = self.changes.iter().map(|c| c.to_json()).collect();
+        serde_json::json!({
+            "changes": changes,
+            "before": self.before,
+            "after": self.after,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn bytes(n: usize) -> String {
+        format!("{n} bytes")
+    }
+
+    #[test]
+    fn test_lines() {
+        let added = ChunkChange {
+            added: true,
+            index: 5,
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            length: 42,
+            before_iend: true,
+        };
+        let removed = ChunkChange {
+            added: false,
+            index: 3,
+            chunk_type: ChunkType::from_str("tEXt").unwrap(),
+            length: 120,
+            before_iend: false,
+        };
+        assert_eq!(
+            added.line(bytes),
+            "+ ruSt (42 bytes) at index 5 (before IEND)"
+        );
+        assert_eq!(removed.line(bytes), "- tEXt (120 bytes) from index 3");
+        let summary = Summary {
+            changes: vec![removed],
+            before: 30466,
+            after: 30334,
+        };
+        assert_eq!(
+            summary.lines(bytes),
+            [
+                "- tEXt (120 bytes) from index 3",
+                "Size: 30466 bytes -> 30334 bytes"
+            ]
+        );
+        assert!(Summary::default().lines(bytes).is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+//! Advice on whether a chunk is likely to survive the tools images commonly
+//! pass through. The behaviors are generalizations, not guarantees: tools
+//! change between versions and most can be configured to keep or strip
+//! more. To teach it about another tool, add an entry to `TOOLS`.
+
+use crate::chunk_type::ChunkType;
+use crate::standard;
+use crate::text::{ITXT, TEXT, ZTXT};
+
+/// Where a chunk sits in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    BeforeIdat,
+    /// After the image data but before IEND, where `encode` puts chunks.
+    AfterIdat,
+    AfterIend,
+}
+
+impl Position {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Position::BeforeIdat => "before IDAT",
+            Position::AfterIdat => "after IDAT",
+            Position::AfterIend => "after IEND",
+        }
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a tool does to a chunk, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fate {
+    Kept,
+    /// Kept in meaning, but its bytes may change.
+    MayBeAltered,
+    Dropped,
+}
+
+impl Fate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Fate::Kept => "kept",
+            Fate::MayBeAltered => "may be altered",
+            Fate::Dropped => "dropped",
+        }
+    }
+}
+
+/// One thing a tool is known to do. A tool's behaviors are tried in order
+/// and the first that applies to a chunk decides its fate; a chunk none of
+/// them apply to is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behavior {
+    /// Drops anything after IEND.
+    DropAfterIend,
+    /// Decodes to pixels and writes a new file, losing every chunk.
+    ReencodePixels,
+    /// Keeps tEXt and iTXt.
+    KeepText,
+    /// Recompresses zTXt, which keeps its text but not its bytes.
+    RecompressZtxt,
+    /// Strips ancillary chunks it doesn't know unless they are safe to copy
+    /// and come before IDAT.
+    StripUnknownUnlessSafeBeforeIdat,
+    /// Writes back only the chunks it knows.
+    DropUnknown,
+}
+
+impl Behavior {
+    /// The fate of a chunk this behavior applies to, and why.
+    pub fn apply(
+        &self,
+        chunk_type: &ChunkType,
+        position: Position,
+    ) -> Option<(Fate, &'static str)> {
+        let unknown = !chunk_type.is_critical() && !standard::is_standard(chunk_type);
+        match self {
+            Behavior::DropAfterIend => (position == Position::AfterIend)
+                .then_some((Fate::Dropped, "anything after IEND is dropped")),
+            Behavior::ReencodePixels => Some((
+                Fate::Dropped,
+                "the image is re-encoded from its pixels, so only pixel-level (LSB) embedding survives",
+            )),
+            Behavior::KeepText => [TEXT, ITXT]
+                .contains(chunk_type)
+                .then_some((Fate::Kept, "tEXt and iTXt are kept")),
+            Behavior::RecompressZtxt => (*chunk_type == ZTXT).then_some((
+                Fate::MayBeAltered,
+                "zTXt may be recompressed, keeping its text but not its bytes",
+            )),
+            Behavior::StripUnknownUnlessSafeBeforeIdat => (unknown
+                && !(chunk_type.is_safe_to_copy() && position == Position::BeforeIdat))
+                .then_some((
+                    Fate::Dropped,
+                    "unknown ancillary chunks are stripped unless safe to copy and placed before IDAT",
+                )),
+            Behavior::DropUnknown => unknown
+                .then_some((Fate::Dropped, "chunks it doesn't recognize aren't written back")),
+        }
+    }
+}
+
+/// A kind of tool and what it is known to do.
+#[derive(Debug)]
+pub struct Tool {
+    pub name: &'static str,
+    pub behaviors: &'static [Behavior],
+}
+
+pub const TOOLS: &[Tool] = &[
+    Tool {
+        name: "lossless optimizers (oxipng, pngcrush)",
+        behaviors: &[
+            Behavior::DropAfterIend,
+            Behavior::StripUnknownUnlessSafeBeforeIdat,
+        ],
+    },
+    Tool {
+        name: "ImageMagick",
+        behaviors: &[
+            Behavior::DropAfterIend,
+            Behavior::KeepText,
+            Behavior::RecompressZtxt,
+            Behavior::DropUnknown,
+        ],
+    },
+    Tool {
+        name: "social media re-encoding",
+        behaviors: &[Behavior::ReencodePixels],
+    },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub tool: &'static str,
+    pub fate: Fate,
+    /// Which behavior decided, unless none applied and the chunk is kept.
+    pub reason: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SurvivabilityReport {
+    pub chunk_type: ChunkType,
+    pub position: Position,
+    /// One per entry in `TOOLS`, in the same order.
+    pub verdicts: Vec<Verdict>,
+}
+
+impl SurvivabilityReport {
+    /// The worst fate across every tool.
+    pub fn worst(&self) -> Fate {
+        self.verdicts
+            .iter()
+            .map(|v| v.fate)
+            .max()
+            .unwrap_or(Fate::Kept)
+    }
+}
+
+/// How a chunk of `chunk_type` at `position` fares with each tool in `TOOLS`.
+pub fn survivability(chunk_type: &ChunkType, position: Position) -> SurvivabilityReport {
+    let verdicts = TOOLS
+        .iter()
+        .map(|tool| {
+            let decided = tool
+                .behaviors
+                .iter()
+                .find_map(|b| b.apply(chunk_type, position));
+            Verdict {
+                tool: tool.name,
+                fate: decided.map_or(Fate::Kept, |(fate, _)| fate),
+                reason: decided.map(|(_, reason)| reason),
+            }
+        })
+        .collect();
+    SurvivabilityReport {
+        chunk_type: *chunk_type,
+        position,
+        verdicts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn fates(name: &str, position: Position) -> Vec<Fate> {
+        let chunk_type = ChunkType::from_str(name).unwrap();
+        survivability(&chunk_type, position)
+            .verdicts
+            .iter()
+            .map(|v| v.fate)
+            .collect()
+    }
+
+    #[test]
+    fn test_unknown_chunks() {
+        use Fate::*;
+        // ruST is unsafe to copy, so optimizers drop it wherever it is
+        assert_eq!(
+            fates("ruST", Position::BeforeIdat),
+            [Dropped, Dropped, Dropped]
+        );
+        assert_eq!(
+            fates("ruST", Position::AfterIdat),
+            [Dropped, Dropped, Dropped]
+        );
+        // Safe to copy survives an optimizer only before IDAT
+        assert_eq!(
+            fates("pmRa", Position::BeforeIdat),
+            [Kept, Dropped, Dropped]
+        );
+        assert_eq!(
+            fates("pmRa", Position::AfterIdat),
+            [Dropped, Dropped, Dropped]
+        );
+    }
+
+    #[test]
+    fn test_text_chunks() {
+        use Fate::*;
+        assert_eq!(fates("tEXt", Position::AfterIdat), [Kept, Kept, Dropped]);
+        assert_eq!(
+            fates("zTXt", Position::AfterIdat),
+            [Kept, MayBeAltered, Dropped]
+        );
+        // After IEND nothing survives
+        assert_eq!(
+            fates("tEXt", Position::AfterIend),
+            [Dropped, Dropped, Dropped]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let chunk_type = ChunkType::from_str("zTXt").unwrap();
+        let report = survivability(&chunk_type, Position::AfterIdat);
+        assert_eq!(report.verdicts.len(), TOOLS.len());
+        assert_eq!(report.verdicts[0].reason, None);
+        assert!(report.verdicts[1].reason.unwrap().contains("recompressed"));
+        assert!(report.verdicts[2].reason.unwrap().contains("LSB"));
+        assert_eq!(report.worst(), Fate::Dropped);
+    }
+}
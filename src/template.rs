@@ -0,0 +1,300 @@
+//! Templates for one line of output per chunk, as used by `list --format`.
+//! A template is text with `{field}` placeholders, optionally `{field:x}` or
+//! `{field:X}` for a number in hex. `\t`, `\n` and `\\` are escapes, and
+//! `{{`/`}}` are literal braces.
+
+use crate::chunk::ChunkRef;
+use crate::digest::Algorithm;
+use std::fmt::Write;
+
+/// Every field a placeholder can name.
+pub const FIELDS: [&str; 8] = [
+    "index",
+    "type",
+    "length",
+    "crc",
+    "offset",
+    "critical",
+    "safe_to_copy",
+    "data_sha256",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Index,
+    Type,
+    Length,
+    Crc,
+    Offset,
+    Critical,
+    SafeToCopy,
+    DataSha256,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        Some(match name {
+            "index" => Field::Index,
+            "type" => Field::Type,
+            "length" => Field::Length,
+            "crc" => Field::Crc,
+            "offset" => Field::Offset,
+            "critical" => Field::Critical,
+            "safe_to_copy" => Field::SafeToCopy,
+            "data_sha256" => Field::DataSha256,
+            _ => return None,
+        })
+    }
+    fn is_number(self) -> bool {
+        matches!(
+            self,
+            Field::Index | Field::Length | Field::Crc | Field::Offset
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Plain,
+    LowerHex,
+    UpperHex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field, Style),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnknownField(String),
+    /// A spec after `:` that isn't `x` or `X`, or one on a field that isn't
+    /// a number.
+    BadSpec {
+        field: String,
+        spec: String,
+    },
+    UnclosedBrace,
+    UnmatchedBrace,
+    UnknownEscape(char),
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::UnknownField(name) => write!(
+                f,
+                "Unknown field '{{{name}}}', the fields are {}",
+                FIELDS.join(", ")
+            ),
+            TemplateError::BadSpec { field, spec } => write!(
+                f,
+                "'{{{field}:{spec}}}' isn't a format, only numbers can be written as :x or :X"
+            ),
+            TemplateError::UnclosedBrace => write!(f, "A '{{' is never closed"),
+            TemplateError::UnmatchedBrace => {
+                write!(f, "A '}}' has no '{{', write '}}}}' for a literal one")
+            }
+            TemplateError::UnknownEscape(c) => write!(f, "Unknown escape '\\{c}'"),
+        }
+    }
+}
+impl std::error::Error for TemplateError {}
+
+/// Where a chunk sits in its file, alongside the chunk itself.
+pub struct ChunkFields<'a> {
+    pub index: usize,
+    pub offset: usize,
+    pub chunk: ChunkRef<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl std::str::FromStr for Template {
+    type Err = TemplateError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => text.push('\t'),
+                    Some('n') => text.push('\n'),
+                    Some('\\') => text.push('\\'),
+                    Some(other) => return Err(TemplateError::UnknownEscape(other)),
+                    None => text.push('\\'),
+                },
+                '}' => {
+                    if chars.next() != Some('}') {
+                        return Err(TemplateError::UnmatchedBrace);
+                    }
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    if let Some(after) = rest.strip_prefix('{') {
+                        text.push('{');
+                        chars = after.chars();
+                        continue;
+                    }
+                    let end = rest.find('}').ok_or(TemplateError::UnclosedBrace)?;
+                    let (name, spec) = match rest[..end].split_once(':') {
+                        Some((name, spec)) => (name, Some(spec)),
+                        None => (&rest[..end], None),
+                    };
+                    let field = Field::from_name(name)
+                        .ok_or_else(|| TemplateError::UnknownField(name.to_string()))?;
+                    let style = match spec {
+                        None => Style::Plain,
+                        Some("x") if field.is_number() => Style::LowerHex,
+                        Some("X") if field.is_number() => Style::UpperHex,
+                        Some(spec) => {
+                            return Err(TemplateError::BadSpec {
+                                field: name.to_string(),
+                                spec: spec.to_string(),
+                            });
+                        }
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field, style));
+                    chars = rest[end + 1..].chars();
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    pub fn render(&self, fields: &ChunkFields) -> String {
+        let mut out = String::new();
+        let chunk = fields.chunk;
+        for part in &self.parts {
+            let (field, style) = match part {
+                Part::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                Part::Field(field, style) => (*field, *style),
+            };
+            let number = match field {
+                Field::Index => fields.index as u64,
+                Field::Length => chunk.length() as u64,
+                Field::Crc => chunk.crc() as u64,
+                Field::Offset => fields.offset as u64,
+                Field::Type => {
+                    out.push_str(&chunk.chunk_type().to_string());
+                    continue;
+                }
+                Field::Critical => {
+                    out.push_str(&chunk.chunk_type().is_critical().to_string());
+                    continue;
+                }
+                Field::SafeToCopy => {
+                    out.push_str(&chunk.chunk_type().is_safe_to_copy().to_string());
+                    continue;
+                }
+                Field::DataSha256 => {
+                    out.push_str(&Algorithm::Sha256.hex(chunk.data()));
+                    continue;
+                }
+            };
+            let _ = match style {
+                Style::Plain => write!(out, "{number}"),
+                Style::LowerHex => write!(out, "{number:x}"),
+                Style::UpperHex => write!(out, "{number:X}"),
+            };
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn render(template: &str) -> String {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"abc".to_vec());
+        let fields = ChunkFields {
+            index: 3,
+            offset: 255,
+            chunk: ChunkRef::from(&chunk),
+        };
+        Template::from_str(template).unwrap().render(&fields)
+    }
+
+    #[test]
+    fn test_fields() {
+        assert_eq!(
+            render("{index} {type} {length} {offset} {critical} {safe_to_copy}"),
+            "3 ruSt 3 255 false true"
+        );
+        assert_eq!(
+            render("{data_sha256}"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(render("{offset:x} {offset:X}"), "ff FF");
+        let chunk = Chunk::new(ChunkType::IEND, Vec::new());
+        let fields = ChunkFields {
+            index: 0,
+            offset: 0,
+            chunk: ChunkRef::from(&chunk),
+        };
+        let template = Template::from_str("{crc} {crc:x}").unwrap();
+        assert_eq!(template.render(&fields), "2923585666 ae426082");
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(render("{index}\\t{type}\\n"), "3\truSt\n");
+        assert_eq!(render("a\\\\b {{index}} }}"), "a\\b {index} }");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Template::from_str("{index} {name}"),
+            Err(TemplateError::UnknownField("name".into()))
+        );
+        assert!(
+            Template::from_str("{name}")
+                .unwrap_err()
+                .to_string()
+                .contains("'{name}'")
+        );
+        assert_eq!(
+            Template::from_str("{type:x}"),
+            Err(TemplateError::BadSpec {
+                field: "type".into(),
+                spec: "x".into()
+            })
+        );
+        assert_eq!(
+            Template::from_str("{index"),
+            Err(TemplateError::UnclosedBrace)
+        );
+        assert_eq!(Template::from_str("a}"), Err(TemplateError::UnmatchedBrace));
+        assert_eq!(
+            Template::from_str("\\q"),
+            Err(TemplateError::UnknownEscape('q'))
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Small PNG files for tests, built the same way every time so golden
+//! outputs stay stable. Behind the `testutil` feature; each function
+//! returns the whole file and panics on arguments it can't build from.
+//!
+//! ```
+//! use pngme::png::Png;
+//! use pngme::testutil;
+//!
+//! let png = Png::try_from(testutil::paletted(16).as_slice()).unwrap();
+//! assert!(png.chunk_by_type("PLTE").is_some());
+//! assert!(Png::try_from(testutil::with_corrupt_crc(1).as_slice()).is_err());
+//! ```
+
+use crate::apng::{ACTL, FCTL, FDAT};
+use crate::builder::PngBuilder;
+use crate::chunk::{Chunk, X25};
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::text::TEXT;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+/// The color of every pixel of `minimal_rgb`.
+pub const RGB_FILL: [u8; 3] = [0x20, 0x80, 0xe0];
+
+/// A chunk's bytes laid out by hand, length, type, data and then `crc`
+/// whether or not it's right, for testing the parser against something
+/// it didn't write itself.
+pub fn raw_chunk(chunk_type: &[u8; 4], data: &[u8], crc: u32) -> Vec<u8> {
+    let length = u32::try_from(data.len()).expect("chunk data fits a u32 length");
+    [&length.to_be_bytes(), chunk_type, data, &crc.to_be_bytes()].concat()
+}
+
+/// The CRC `raw_chunk` needs to be right.
+pub fn crc(chunk_type: &[u8; 4], data: &[u8]) -> u32 {
+    let mut digest = X25.digest();
+    digest.update(chunk_type);
+    digest.update(data);
+    digest.finalize()
+}
+
+/// A `width` x `height` 8-bit RGB image in `RGB_FILL`: IHDR, one IDAT, IEND.
+pub fn minimal_rgb(width: u32, height: u32) -> Vec<u8> {
+    PngBuilder::new(width, height)
+        .fill(&RGB_FILL)
+        .build()
+        .expect("the dimensions are in range")
+        .as_bytes()
+}
+
+/// An 8-bit indexed image one pixel high and `n_colors` wide, pixel `i`
+/// being palette entry `i`, with IHDR, PLTE, IDAT and IEND.
+pub fn paletted(n_colors: usize) -> Vec<u8> {
+    assert!(
+        (1..=256).contains(&n_colors),
+        "a palette has 1 to 256 colors, not {n_colors}"
+    );
+    let palette: Vec<u8> = (0..n_colors)
+        .flat_map(|i| {
+            let i = i as u8;
+            [i, 255 - i, i.wrapping_mul(7)]
+        })
+        .collect();
+    let row: Vec<u8> = (0..n_colors).map(|i| i as u8).collect();
+    Png::from_chunks(vec![
+        ihdr(n_colors as u32, 1, 3),
+        Chunk::new(ChunkType::literal(*b"PLTE"), palette),
+        Chunk::new(ChunkType::IDAT, image_data(&[&row])),
+        Chunk::new(ChunkType::IEND, Vec::new()),
+    ])
+    .as_bytes()
+}
+
+/// A 1x1 `minimal_rgb` with a tEXt chunk for each keyword and text, in
+/// order, after IDAT.
+pub fn with_text_chunks(pairs: &[(&str, &str)]) -> Vec<u8> {
+    let mut png = Png::try_from(minimal_rgb(1, 1).as_slice()).expect("it was just built");
+    for (keyword, text) in pairs {
+        let data = [keyword.as_bytes(), b"\0", text.as_bytes()].concat();
+        png.insert_before_iend(Chunk::new(TEXT, data));
+    }
+    png.as_bytes()
+}
+
+/// `with_text_chunks(&[("Comment", "fixture")])`, which is IHDR, IDAT, tEXt
+/// and IEND, with the CRC of the chunk at `index` inverted.
+pub fn with_corrupt_crc(index: usize) -> Vec<u8> {
+    let png = with_text_chunks(&[("Comment", "fixture")]);
+    let png = Png::try_from(png.as_slice()).expect("it was just built");
+    let chunks = png.chunks();
+    assert!(
+        index < chunks.len(),
+        "there are {} chunks, so no chunk {index}",
+        chunks.len()
+    );
+    let mut bytes = Png::STANDARD_HEADER.to_vec();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let crc = if i == index {
+            !chunk.crc()
+        } else {
+            chunk.crc()
+        };
+        bytes.extend(raw_chunk(&chunk.chunk_type().bytes(), chunk.data(), crc));
+    }
+    bytes
+}
+
+/// A 2x2 `minimal_rgb` cut off after `offset` bytes, or whole if it's
+/// shorter than that.
+pub fn truncated_at(offset: usize) -> Vec<u8> {
+    let mut bytes = minimal_rgb(2, 2);
+    bytes.truncate(offset);
+    bytes
+}
+
+/// A 4x3 RGB APNG of `frames` frames played forever, each 0.1 seconds.
+/// The first is the default image, filled with 10; frame `n` after it is
+/// 2x2 at (1, 1), filled with `10 * (n + 1)`, in one fdAT.
+pub fn apng(frames: u32) -> Vec<u8> {
+    assert!(frames > 0, "an animation needs at least one frame");
+    let mut chunks = vec![
+        ihdr(4, 3, 2),
+        Chunk::new(ACTL, [frames.to_be_bytes(), [0; 4]].concat()),
+    ];
+    let mut sequence = 0u32;
+    for n in 0..frames {
+        let (width, height, offset): (u32, u32, u32) = if n == 0 { (4, 3, 0) } else { (2, 2, 1) };
+        let fctl = [
+            &sequence.to_be_bytes()[..],
+            &width.to_be_bytes(),
+            &height.to_be_bytes(),
+            &u32::to_be_bytes(offset),
+            &u32::to_be_bytes(offset),
+            // 1/10 s, dispose none, blend source
+            &[0, 1, 0, 10, 0, 0],
+        ]
+        .concat();
+        chunks.push(Chunk::new(FCTL, fctl));
+        sequence += 1;
+        let row = vec![(n as u8).wrapping_add(1).wrapping_mul(10); width as usize * 3];
+        let data = image_data(&vec![row.as_slice(); height as usize]);
+        if n == 0 {
+            chunks.push(Chunk::new(ChunkType::IDAT, data));
+        } else {
+            chunks.push(Chunk::new(
+                FDAT,
+                [&sequence.to_be_bytes()[..], &data].concat(),
+            ));
+            sequence += 1;
+        }
+    }
+    chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+/// An 8-bit, non-interlaced IHDR.
+fn ihdr(width: u32, height: u32, color_type: u8) -> Chunk {
+    let data = [
+        &width.to_be_bytes()[..],
+        &height.to_be_bytes(),
+        &[8, color_type, 0, 0, 0],
+    ]
+    .concat();
+    Chunk::new(ChunkType::IHDR, data)
+}
+
+/// `rows` each with filter type 0 in front, compressed.
+fn image_data(rows: &[&[u8]]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rows {
+        encoder
+            .write_all(&[&[0], *row].concat())
+            .expect("writing to a Vec can't fail");
+    }
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::frames;
+    use crate::ihdr::Ihdr;
+    use crate::png::{ParseOptions, ReadError};
+    use crate::verify::{FindingKind, verify};
+
+    fn parse(bytes: &[u8]) -> Png {
+        Png::try_from(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_fixtures_are_valid_and_stable() {
+        let fixtures = [
+            minimal_rgb(3, 2),
+            paletted(1),
+            paletted(256),
+            with_text_chunks(&[("Title", "a"), ("Author", "b")]),
+            apng(1),
+            apng(3),
+        ];
+        for bytes in &fixtures {
+            assert_eq!(verify(bytes, &ParseOptions::default()), []);
+            let png = parse(bytes);
+            let ihdr = Ihdr::try_from(&png.chunks()[0]).unwrap();
+            let parts = png
+                .chunks()
+                .iter()
+                .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+                .map(|c| c.data());
+            assert_eq!(crate::idat::check(&ihdr, parts), None);
+        }
+        assert_eq!(minimal_rgb(3, 2), minimal_rgb(3, 2));
+        assert_eq!(apng(3), apng(3));
+    }
+
+    #[test]
+    fn test_fixture_contents() {
+        let png = parse(&paletted(16));
+        assert_eq!(png.chunk_by_type("PLTE").unwrap().length(), 48);
+
+        let png = parse(&with_text_chunks(&[("Title", "a"), ("Author", "b")]));
+        let texts: Vec<&[u8]> = png
+            .chunks()
+            .iter()
+            .filter(|c| *c.chunk_type() == TEXT)
+            .map(|c| c.data())
+            .collect();
+        assert_eq!(texts, [&b"Title\0a"[..], b"Author\0b"]);
+
+        let png = parse(&apng(3));
+        let frames = frames(&png).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].is_default_image);
+        assert_eq!(
+            (frames[2].control.width, frames[2].control.x_offset),
+            (2, 1)
+        );
+
+        let chunk = raw_chunk(b"ruSt", b"hi", crc(b"ruSt", b"hi"));
+        assert_eq!(
+            chunk,
+            Chunk::new("ruSt".parse().unwrap(), b"hi".to_vec()).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_broken_fixtures_fail_as_designed() {
+        for index in 0..4 {
+            let bytes = with_corrupt_crc(index);
+            let findings = verify(&bytes, &ParseOptions::default());
+            assert!(
+                findings
+                    .iter()
+                    .any(|f| f.kind == FindingKind::CrcMismatch && f.chunk_index == Some(index)),
+                "{index}: {findings:?}"
+            );
+        }
+        let whole = minimal_rgb(2, 2);
+        assert_eq!(truncated_at(whole.len() + 10), whole);
+        for offset in [0, 5, 12, 20, whole.len() - 1] {
+            let bytes = truncated_at(offset);
+            assert_eq!(bytes.len(), offset);
+            assert!(Png::try_from(bytes.as_slice()).is_err(), "{offset}");
+            assert!(matches!(
+                Png::from_reader(bytes.as_slice()),
+                Err(ReadError::Invalid(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,561 @@
+use crate::chunk::{Chunk, ChunkRef};
+use crate::chunk_data::{ChunkDataError, KnownChunk};
+use crate::chunk_type::ChunkType;
+use crate::format::format_size;
+use crate::png::Png;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Read, Write};
+
+/// Compressed text is inflated up to this many bytes, so a small chunk
+/// can't expand into an arbitrarily large allocation.
+pub const MAX_DECOMPRESSED_LEN: u64 = 16 * 1024 * 1024;
+
+/// One keyword/value pair from a tEXt, zTXt or iTXt chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEntry {
+    /// Position of the source chunk in the file.
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub keyword: String,
+    pub value: String,
+    pub compressed: bool,
+    /// Language tag and translated keyword, only present in iTXt.
+    pub language: Option<String>,
+    pub translated_keyword: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextErrorKind {
+    MissingSeparator,
+    BadKeyword,
+    UnknownCompression(u8),
+    BadCompressedData,
+    TooLarge,
+    InvalidUtf8,
+    NulInValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextError {
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub kind: TextErrorKind,
+}
+
+impl std::fmt::Display for TextErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextErrorKind::MissingSeparator => write!(f, "Missing null separator"),
+            TextErrorKind::BadKeyword => write!(f, "Keyword must be 1-79 printable Latin-1 bytes"),
+            TextErrorKind::UnknownCompression(method) => {
+                write!(f, "Unknown compression method {method}")
+            }
+            TextErrorKind::BadCompressedData => write!(f, "Corrupt compressed text"),
+            TextErrorKind::TooLarge => write!(
+                f,
+                "Compressed text inflates past {}",
+                format_size(MAX_DECOMPRESSED_LEN, false)
+            ),
+            TextErrorKind::InvalidUtf8 => write!(f, "Text is not valid UTF-8"),
+            TextErrorKind::NulInValue => write!(f, "Text can't contain a null byte"),
+        }
+    }
+}
+
+impl std::error::Error for TextErrorKind {}
+
+impl std::fmt::Display for TextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at index {}: {}",
+            self.chunk_type, self.index, self.kind
+        )
+    }
+}
+
+impl std::error::Error for TextError {}
+
+pub const TEXT: ChunkType = ChunkType::literal(*b"tEXt");
+pub const ZTXT: ChunkType = ChunkType::literal(*b"zTXt");
+pub const ITXT: ChunkType = ChunkType::literal(*b"iTXt");
+
+/// Whether a chunk of this type carries textual metadata.
+pub fn is_text_type(chunk_type: &ChunkType) -> bool {
+    [TEXT, ZTXT, ITXT].contains(chunk_type)
+}
+
+pub(crate) fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// A keyword is 1-79 printable Latin-1 bytes without leading, trailing or
+/// repeated spaces.
+pub fn is_valid_keyword(keyword: &[u8]) -> bool {
+    (1..=79).contains(&keyword.len())
+        && keyword.iter().all(|&b| (32..=126).contains(&b) || b >= 161)
+        && keyword.first() != Some(&b' ')
+        && keyword.last() != Some(&b' ')
+        && !keyword.windows(2).any(|w| w == b"  ")
+}
+
+/// Encodes as Latin-1, or `None` if some character is outside it.
+pub(crate) fn to_latin1(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+pub(crate) fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(bytes)
+        .expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+pub(crate) fn split_null(bytes: &[u8]) -> Result<(&[u8], &[u8]), TextErrorKind> {
+    let at = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(TextErrorKind::MissingSeparator)?;
+    Ok((&bytes[..at], &bytes[at + 1..]))
+}
+
+pub(crate) fn inflate(method: u8, bytes: &[u8]) -> Result<Vec<u8>, TextErrorKind> {
+    if method != 0 {
+        return Err(TextErrorKind::UnknownCompression(method));
+    }
+    let mut out = Vec::new();
+    ZlibDecoder::new(bytes)
+        .take(MAX_DECOMPRESSED_LEN + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| TextErrorKind::BadCompressedData)?;
+    if out.len() as u64 > MAX_DECOMPRESSED_LEN {
+        return Err(TextErrorKind::TooLarge);
+    }
+    Ok(out)
+}
+
+pub const ICCP: ChunkType = ChunkType::literal(*b"iCCP");
+
+/// The zlib stream in a zTXt, compressed iTXt or iCCP chunk, or `None` for
+/// any other chunk or one too malformed to find it in.
+fn compressed_stream<'a>(chunk_type: &ChunkType, data: &'a [u8]) -> Option<&'a [u8]> {
+    let (_, rest) = split_null(data).ok()?;
+    if *chunk_type == ZTXT || *chunk_type == ICCP {
+        rest.get(1..)
+    } else if *chunk_type == ITXT {
+        let [1, _, rest @ ..] = rest else {
+            return None;
+        };
+        let (_, rest) = split_null(rest).ok()?;
+        split_null(rest).ok().map(|(_, text)| text)
+    } else {
+        None
+    }
+}
+
+/// How many bytes a chunk's compressed stream inflates to, counting no
+/// further than `limit + 1`. A corrupt stream counts what came out before
+/// the corruption, and chunks without one count 0.
+pub(crate) fn inflated_len(chunk_type: &ChunkType, data: &[u8], limit: u64) -> u64 {
+    let Some(stream) = compressed_stream(chunk_type, data) else {
+        return 0;
+    };
+    let mut counted = 0;
+    let mut decoder = ZlibDecoder::new(stream).take(limit.saturating_add(1));
+    let mut buffer = [0; 8192];
+    while let Ok(n @ 1..) = decoder.read(&mut buffer) {
+        counted += n as u64;
+    }
+    counted
+}
+
+impl TextEntry {
+    /// Decodes a text chunk. Returns `None` for chunks of any other type.
+    pub fn parse<'a>(
+        index: usize,
+        chunk: impl Into<ChunkRef<'a>>,
+    ) -> Option<Result<TextEntry, TextError>> {
+        let chunk = chunk.into();
+        let chunk_type = *chunk.chunk_type();
+        if !is_text_type(&chunk_type) {
+            return None;
+        }
+        Some(
+            Self::parse_data(index, chunk_type, chunk.data()).map_err(|kind| TextError {
+                index,
+                chunk_type,
+                kind,
+            }),
+        )
+    }
+
+    fn parse_data(
+        index: usize,
+        chunk_type: ChunkType,
+        data: &[u8],
+    ) -> Result<TextEntry, TextErrorKind> {
+        let (keyword, rest) = split_null(data)?;
+        if !is_valid_keyword(keyword) {
+            return Err(TextErrorKind::BadKeyword);
+        }
+        let mut entry = TextEntry {
+            index,
+            chunk_type,
+            keyword: latin1(keyword),
+            value: String::new(),
+            compressed: false,
+            language: None,
+            translated_keyword: None,
+        };
+        if chunk_type == TEXT {
+            entry.value = latin1(rest);
+        } else if chunk_type == ZTXT {
+            let (&method, text) = rest.split_first().ok_or(TextErrorKind::MissingSeparator)?;
+            entry.value = latin1(&inflate(method, text)?);
+            entry.compressed = true;
+        } else {
+            let [flag, method, rest @ ..] = rest else {
+                return Err(TextErrorKind::MissingSeparator);
+            };
+            let (language, rest) = split_null(rest)?;
+            let (translated, text) = split_null(rest)?;
+            let text = if *flag != 0 {
+                entry.compressed = true;
+                inflate(*method, text)?
+            } else {
+                text.to_vec()
+            };
+            let utf8 =
+                |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| TextErrorKind::InvalidUtf8);
+            entry.value = utf8(text)?;
+            entry.language = Some(latin1(language));
+            entry.translated_keyword = Some(utf8(translated.to_vec())?);
+        }
+        Ok(entry)
+    }
+}
+
+impl TextEntry {
+    /// A new entry for `keyword`, stored as tEXt when the value fits in
+    /// Latin-1 and as uncompressed iTXt otherwise.
+    pub fn new(keyword: &str, value: &str) -> TextEntry {
+        let chunk_type = if to_latin1(value).is_some() {
+            TEXT
+        } else {
+            ITXT
+        };
+        TextEntry {
+            index: 0,
+            chunk_type,
+            keyword: keyword.to_string(),
+            value: value.to_string(),
+            compressed: false,
+            language: (chunk_type == ITXT).then(String::new),
+            translated_keyword: (chunk_type == ITXT).then(String::new),
+        }
+    }
+
+    /// Changes the value, moving a tEXt or zTXt entry to iTXt if the new value
+    /// doesn't fit in Latin-1.
+    pub fn set_value(&mut self, value: &str) {
+        if self.chunk_type != ITXT && to_latin1(value).is_none() {
+            self.chunk_type = ITXT;
+            self.language = Some(String::new());
+            self.translated_keyword = Some(String::new());
+        }
+        self.value = value.to_string();
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk, TextErrorKind> {
+        let keyword = to_latin1(&self.keyword)
+            .filter(|k| is_valid_keyword(k))
+            .ok_or(TextErrorKind::BadKeyword)?;
+        if self.value.contains('\0') {
+            return Err(TextErrorKind::NulInValue);
+        }
+        let mut data = keyword;
+        data.push(0);
+        if self.chunk_type == ITXT {
+            let language = self.language.as_deref().unwrap_or_default();
+            let translated = self.translated_keyword.as_deref().unwrap_or_default();
+            data.extend_from_slice(&[self.compressed as u8, 0]);
+            data.extend_from_slice(&to_latin1(language).ok_or(TextErrorKind::BadKeyword)?);
+            data.push(0);
+            data.extend_from_slice(translated.as_bytes());
+            data.push(0);
+            if self.compressed {
+                data.extend_from_slice(&deflate(self.value.as_bytes()));
+            } else {
+                data.extend_from_slice(self.value.as_bytes());
+            }
+        } else {
+            let value = to_latin1(&self.value).ok_or(TextErrorKind::InvalidUtf8)?;
+            if self.chunk_type == ZTXT {
+                data.push(0);
+                data.extend_from_slice(&deflate(&value));
+            } else {
+                data.extend_from_slice(&value);
+            }
+        }
+        Ok(Chunk::new(self.chunk_type, data))
+    }
+}
+
+/// Sets `keyword` to `value`. The first existing entry is rewritten in place,
+/// keeping its chunk type where the value allows, and any later duplicates
+/// are left as they are. Without an existing entry a new one goes before the
+/// first IDAT. Returns the number of later duplicates, which keep their old
+/// values.
+pub fn set_text(png: &mut Png, keyword: &str, value: &str) -> Result<usize, TextErrorKind> {
+    let mut existing = text_entries(png)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.keyword == keyword);
+    let Some(mut entry) = existing.next() else {
+        png.insert_conventional(TextEntry::new(keyword, value).to_chunk()?);
+        return Ok(0);
+    };
+    let duplicates = existing.count();
+    entry.set_value(value);
+    png.replace_chunk_at(entry.index, entry.to_chunk()?);
+    Ok(duplicates)
+}
+
+fn has_keyword(chunk: &Chunk, keyword: &str) -> bool {
+    TextEntry::parse(0, chunk).is_some_and(|e| e.is_ok_and(|e| e.keyword == keyword))
+}
+
+/// Removes every tEXt, zTXt and iTXt entry stored under `keyword`.
+pub fn remove_text(png: &mut Png, keyword: &str) -> Vec<Chunk> {
+    png.remove_matching(|chunk| has_keyword(chunk, keyword))
+}
+
+/// Every text entry in the file, in file order, including duplicates.
+pub fn text_entries(png: &Png) -> Vec<Result<TextEntry, TextError>> {
+    text_entries_in(png.chunks())
+}
+
+/// As `text_entries`, over chunks in file order, such as those
+/// `Png::parse_borrowed` returns.
+pub fn text_entries_in<'a>(
+    chunks: impl IntoIterator<Item = impl Into<ChunkRef<'a>>>,
+) -> Vec<Result<TextEntry, TextError>> {
+    chunks
+        .into_iter()
+        .map(Into::into)
+        .enumerate()
+        .filter_map(|(index, chunk)| match KnownChunk::parse(chunk) {
+            KnownChunk::Text(entry) => Some(Ok(TextEntry { index, ..entry })),
+            KnownChunk::Malformed(_, ChunkDataError::Text(kind))
+                if is_text_type(chunk.chunk_type()) =>
+            {
+                Some(Err(TextError {
+                    index,
+                    chunk_type: *chunk.chunk_type(),
+                    kind,
+                }))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The readable entries stored under `keyword`, in file order. Keywords are
+/// compared exactly unless `ignore_case` is set.
+pub fn find_text(png: &Png, keyword: &str, ignore_case: bool) -> Vec<TextEntry> {
+    let matches = |k: &str| {
+        if ignore_case {
+            k.to_lowercase() == keyword.to_lowercase()
+        } else {
+            k == keyword
+        }
+    };
+    text_entries(png)
+        .into_iter()
+        .flatten()
+        .filter(|e| matches(&e.keyword))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+
+    fn fixture() -> Png {
+        let ztxt = [b"Comment\0\0".as_slice(), &deflate(b"compressed caf\xe9")].concat();
+        let itxt = [
+            b"Author\0\x01\0en\0Auteur\0".as_slice(),
+            &deflate("Zoë".as_bytes()),
+        ]
+        .concat();
+        PngBuilder::new(1, 1)
+            .with_chunk(Chunk::new(TEXT, b"Software\0pngme".to_vec()))
+            .with_chunk(Chunk::new(ZTXT, ztxt))
+            .with_chunk(Chunk::new(ITXT, itxt))
+            .with_chunk(Chunk::new(TEXT, b"Software\0other".to_vec()))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_all_three_flavors() {
+        let entries: Vec<TextEntry> = text_entries(&fixture())
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let summary: Vec<(&str, &str, bool)> = entries
+            .iter()
+            .map(|e| (e.keyword.as_str(), e.value.as_str(), e.compressed))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Software", "pngme", false),
+                ("Comment", "compressed café", true),
+                ("Author", "Zoë", true),
+                ("Software", "other", false),
+            ]
+        );
+        assert_eq!(entries[0].index, 2);
+        assert_eq!(entries[2].chunk_type, ITXT);
+        assert_eq!(entries[2].language.as_deref(), Some("en"));
+        assert_eq!(entries[2].translated_keyword.as_deref(), Some("Auteur"));
+    }
+
+    #[test]
+    fn test_malformed_text() {
+        let parse = |chunk_type, data: &[u8]| {
+            TextEntry::parse(0, &Chunk::new(chunk_type, data.to_vec()))
+                .unwrap()
+                .unwrap_err()
+                .kind
+        };
+        assert_eq!(
+            parse(TEXT, b"no separator"),
+            TextErrorKind::MissingSeparator
+        );
+        assert_eq!(parse(TEXT, b"\0empty keyword"), TextErrorKind::BadKeyword);
+        assert_eq!(parse(TEXT, b" padded\0x"), TextErrorKind::BadKeyword);
+        assert_eq!(
+            parse(ZTXT, b"k\0\x01abc"),
+            TextErrorKind::UnknownCompression(1)
+        );
+        assert_eq!(
+            parse(ZTXT, b"k\0\0not zlib"),
+            TextErrorKind::BadCompressedData
+        );
+        assert_eq!(parse(ITXT, b"k\0\0\0\0\0\xff"), TextErrorKind::InvalidUtf8);
+        assert!(TextEntry::parse(0, &Chunk::new(ChunkType::IEND, Vec::new())).is_none());
+    }
+
+    #[test]
+    fn test_keyword_validation() {
+        assert!(is_valid_keyword(b"Author"));
+        assert!(is_valid_keyword(b"Creation Time"));
+        assert!(!is_valid_keyword(b""));
+        assert!(!is_valid_keyword(&[b'a'; 80]));
+        assert!(!is_valid_keyword(b"trailing "));
+        assert!(!is_valid_keyword(b"two  spaces"));
+        assert!(!is_valid_keyword(b"tab\there"));
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn values(png: &Png, keyword: &str) -> Vec<(ChunkType, String)> {
+        text_entries(png)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.keyword == keyword)
+            .map(|e| (e.chunk_type, e.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_to_chunk_round_trip() {
+        for entry in text_entries(&fixture()).into_iter().flatten() {
+            let chunk = entry.to_chunk().unwrap();
+            assert_eq!(
+                TextEntry::parse(entry.index, &chunk).unwrap().unwrap(),
+                entry
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_existing_keeps_type_and_position() {
+        let mut png = fixture();
+        let before = types(&png);
+        set_text(&mut png, "Comment", "still latin-1").unwrap();
+        assert_eq!(types(&png), before);
+        assert_eq!(
+            values(&png, "Comment"),
+            [(ZTXT, "still latin-1".to_string())]
+        );
+
+        // zTXt can't hold this, so it becomes iTXt at the same index
+        set_text(&mut png, "Comment", "snowman ☃").unwrap();
+        assert_eq!(values(&png, "Comment"), [(ITXT, "snowman ☃".to_string())]);
+        assert_eq!(png.chunks()[3].chunk_type().to_string(), "iTXt");
+
+        // The later Software duplicate is left alone
+        let (before, old_values) = (types(&png), values(&png, "Software"));
+        assert_eq!(set_text(&mut png, "Software", "new").unwrap(), 1);
+        assert_eq!(types(&png), before);
+        assert_eq!(
+            values(&png, "Software"),
+            [(TEXT, "new".to_string()), old_values[1].clone()]
+        );
+        assert_eq!(png.chunks()[2].chunk_type().to_string(), "tEXt");
+    }
+
+    #[test]
+    fn test_set_new_goes_before_idat() {
+        let mut png = fixture();
+        set_text(&mut png, "Title", "Zoë").unwrap();
+        set_text(&mut png, "Emoji", "🦀").unwrap();
+        let t = types(&png);
+        assert_eq!(&t[..4], ["IHDR", "tEXt", "iTXt", "IDAT"]);
+        assert_eq!(values(&png, "Title"), [(TEXT, "Zoë".to_string())]);
+        assert_eq!(values(&png, "Emoji"), [(ITXT, "🦀".to_string())]);
+        assert_eq!(
+            set_text(&mut png, " bad", "x").unwrap_err(),
+            TextErrorKind::BadKeyword
+        );
+        assert_eq!(
+            set_text(&mut png, "Nul", "a\0b").unwrap_err(),
+            TextErrorKind::NulInValue
+        );
+    }
+
+    #[test]
+    fn test_remove_text() {
+        let mut png = fixture();
+        assert_eq!(remove_text(&mut png, "Software").len(), 2);
+        assert_eq!(types(&png), ["IHDR", "IDAT", "zTXt", "iTXt", "IEND"]);
+        assert!(remove_text(&mut png, "Software").is_empty());
+    }
+
+    #[test]
+    fn test_find_text() {
+        let png = fixture();
+        let values = |keyword, ignore_case| -> Vec<String> {
+            find_text(&png, keyword, ignore_case)
+                .into_iter()
+                .map(|e| e.value)
+                .collect()
+        };
+        assert_eq!(values("Comment", false), ["compressed café"]);
+        assert!(values("comment", false).is_empty());
+        assert_eq!(values("comment", true), ["compressed café"]);
+        assert!(values("Nope", true).is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! Where the time goes, for `--timings`: reading, checking the signature,
+//! parsing chunks, checking CRCs, editing, serializing and writing, each
+//! summed over the thread's work since the last `take`.
+//!
+//! Phases nest, and time spent in an inner phase counts only toward that
+//! one, so reading the input while parsing it counts as reading. Until
+//! `enable` is called each instrumentation point costs one atomic load.
+
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Read,
+    Signature,
+    Parse,
+    Crc,
+    Serialize,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub read: Duration,
+    pub signature: Duration,
+    pub parse: Duration,
+    /// How many chunks were parsed.
+    pub chunks: usize,
+    pub crc: Duration,
+    /// Time from `begin_edit` to `end_edit`, less the phases run in
+    /// between, or `None` if nothing was edited.
+    pub mutation: Option<Duration>,
+    pub serialize: Duration,
+    pub write: Duration,
+}
+
+impl Timings {
+    fn phase(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Read => &mut self.read,
+            Phase::Signature => &mut self.signature,
+            Phase::Parse => &mut self.parse,
+            Phase::Crc => &mut self.crc,
+            Phase::Serialize => &mut self.serialize,
+            Phase::Write => &mut self.write,
+        }
+    }
+    /// The time spent in every phase together.
+    pub fn sum(&self) -> Duration {
+        self.read + self.signature + self.parse + self.crc + self.serialize + self.write
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    timings: Timings,
+    /// Time spent in phases nested in the one running now.
+    nested: Duration,
+    /// When the edit running now began, and the time spent in phases by then.
+    edit: Option<(Instant, Duration)>,
+}
+
+thread_local! {
+    static RECORDER: RefCell<Recorder> = RefCell::default();
+}
+
+/// Starts recording, for the whole process.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Runs `f`, counting the time it takes, less any nested phases, toward
+/// `phase`.
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return f();
+    }
+    let outer = RECORDER.with_borrow_mut(|r| std::mem::take(&mut r.nested));
+    let start = Instant::now();
+    let value = f();
+    let elapsed = start.elapsed();
+    RECORDER.with_borrow_mut(|r| {
+        *r.timings.phase(phase) += elapsed.saturating_sub(r.nested);
+        r.nested = outer + elapsed;
+    });
+    value
+}
+
+/// Counts `count` more parsed chunks.
+pub fn count_chunks(count: usize) {
+    if is_enabled() {
+        RECORDER.with_borrow_mut(|r| r.timings.chunks += count);
+    }
+}
+
+/// Starts timing an edit of a file that has been read and parsed.
+pub fn begin_edit() {
+    if is_enabled() {
+        RECORDER.with_borrow_mut(|r| r.edit = Some((Instant::now(), r.timings.sum())));
+    }
+}
+
+/// Stops timing the edit `begin_edit` started, if there is one, as the
+/// edited file is about to be written.
+pub fn end_edit() {
+    if !is_enabled() {
+        return;
+    }
+    RECORDER.with_borrow_mut(|r| {
+        if let Some((started, phases)) = r.edit.take() {
+            let elapsed = started.elapsed().saturating_sub(r.timings.sum() - phases);
+            *r.timings.mutation.get_or_insert_default() += elapsed;
+        }
+    });
+}
+
+/// What this thread has recorded, starting afresh.
+pub fn take() -> Timings {
+    RECORDER.with_borrow_mut(|r| std::mem::take(&mut r.timings))
+}
+
+/// A reader whose reads count as `Phase::Read`.
+pub struct Timed<R>(pub R);
+
+impl<R: Read> Read for Timed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        time(Phase::Read, || self.0.read(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_nested_phases_are_exclusive() {
+        enable();
+        take();
+        let value = time(Phase::Parse, || {
+            sleep(Duration::from_millis(20));
+            time(Phase::Read, || sleep(Duration::from_millis(30)));
+            time(Phase::Crc, || 7)
+        });
+        count_chunks(3);
+        assert_eq!(value, 7);
+        let timings = take();
+        assert!(timings.read >= Duration::from_millis(30));
+        assert!(timings.parse >= Duration::from_millis(20));
+        // The nested read isn't counted again
+        assert!(timings.parse < Duration::from_millis(45), "{timings:?}");
+        assert_eq!(timings.chunks, 3);
+        assert_eq!(take(), Timings::default());
+    }
+
+    #[test]
+    fn test_edit_excludes_phases() {
+        enable();
+        take();
+        end_edit();
+        assert_eq!(take().mutation, None);
+        begin_edit();
+        sleep(Duration::from_millis(20));
+        time(Phase::Serialize, || sleep(Duration::from_millis(30)));
+        end_edit();
+        // Only the first end counts
+        sleep(Duration::from_millis(10));
+        end_edit();
+        let mutation = take().mutation.unwrap();
+        assert!(mutation >= Duration::from_millis(20));
+        assert!(mutation < Duration::from_millis(45), "{mutation:?}");
+    }
+}
@@ -0,0 +1,278 @@
+//! Several chunk edits made all together or not at all.
+//!
+//! ```
+//! use pngme::builder::PngBuilder;
+//! use pngme::chunk::Chunk;
+//! use pngme::transaction::TransactionError;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut png = PngBuilder::new(1, 1).build()?;
+//! png.transaction(|tx| {
+//!     tx.append(Chunk::new("ruSt".parse()?, b"new tag".to_vec()));
+//!     // There is no tIME, so this fails and the tag isn't added either
+//!     tx.replace("tIME", vec![0; 7])?;
+//!     Ok::<_, TransactionError>(())
+//! })
+//! .unwrap_err();
+//! assert!(png.chunk_by_type("ruSt").is_none());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The closure works on a copy of the file. Each call changes the copy
+//! straight away, in the order the calls are made, so later calls see
+//! what earlier ones did: indices refer to the copy as it is at the time
+//! of the call, and `Transaction::png` shows it. Only if the closure
+//! returns `Ok` does the copy replace the file; otherwise the file is as
+//! it was, `Png::is_modified` included.
+
+use crate::chunk::Chunk;
+use crate::chunk_type::{ChunkType, ChunkTypeError};
+use crate::png::Png;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    InvalidType(ChunkTypeError),
+    NotFound(ChunkType),
+    OutOfRange { index: usize, count: usize },
+}
+
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::InvalidType(e) => write!(f, "Invalid chunk type: {e}"),
+            TransactionError::NotFound(chunk_type) => {
+                write!(f, "{chunk_type} wasn't found in the png")
+            }
+            TransactionError::OutOfRange { index, count } => {
+                write!(f, "Chunk index {index} is out of range for {count} chunks")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransactionError::InvalidType(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ChunkTypeError> for TransactionError {
+    fn from(e: ChunkTypeError) -> Self {
+        TransactionError::InvalidType(e)
+    }
+}
+
+/// One edit a transaction made, with the index it was made at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added { index: usize, chunk_type: ChunkType },
+    Removed { index: usize, chunk_type: ChunkType },
+    Replaced { index: usize, chunk_type: ChunkType },
+}
+
+/// The edits of a `Png::transaction` in progress.
+pub struct Transaction {
+    png: Png,
+    changes: Vec<Change>,
+}
+
+impl Transaction {
+    /// The file with the edits so far.
+    pub fn png(&self) -> &Png {
+        &self.png
+    }
+    /// The edits so far, in order.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+    /// Adds `chunk` before IEND, as `Png::insert_before_iend` does.
+    pub fn append(&mut self, chunk: Chunk) {
+        let index = self
+            .find(&ChunkType::IEND)
+            .unwrap_or(self.png.chunk_count());
+        let chunk_type = *chunk.chunk_type();
+        self.png.insert_before_iend(chunk);
+        self.changes.push(Change::Added { index, chunk_type });
+    }
+    /// Adds `chunk` at `index`, which may be one past the last chunk.
+    pub fn insert(&mut self, index: usize, chunk: Chunk) -> Result<(), TransactionError> {
+        let count = self.png.chunk_count();
+        if index > count {
+            return Err(TransactionError::OutOfRange { index, count });
+        }
+        let chunk_type = *chunk.chunk_type();
+        self.png.insert_chunk(index, chunk);
+        self.changes.push(Change::Added { index, chunk_type });
+        Ok(())
+    }
+    /// Removes the first chunk of `chunk_type`, if there is one.
+    pub fn remove_first(&mut self, chunk_type: &str) -> Result<Option<Chunk>, TransactionError> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let Some(index) = self.find(&chunk_type) else {
+            return Ok(None);
+        };
+        Ok(self.remove_at(index).ok())
+    }
+    pub fn remove_at(&mut self, index: usize) -> Result<Chunk, TransactionError> {
+        let count = self.png.chunk_count();
+        let chunk = self
+            .png
+            .remove_chunk_at(index)
+            .ok_or(TransactionError::OutOfRange { index, count })?;
+        self.changes.push(Change::Removed {
+            index,
+            chunk_type: *chunk.chunk_type(),
+        });
+        Ok(chunk)
+    }
+    /// Gives the first chunk of `chunk_type` new data, keeping its place.
+    pub fn replace(
+        &mut self,
+        chunk_type: &str,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<Chunk, TransactionError> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let index = self
+            .find(&chunk_type)
+            .ok_or(TransactionError::NotFound(chunk_type))?;
+        let old = self
+            .png
+            .replace_chunk_at(index, Chunk::new(chunk_type, data.into()))
+            .expect("index is in range");
+        self.changes.push(Change::Replaced { index, chunk_type });
+        Ok(old)
+    }
+    fn find(&self, chunk_type: &ChunkType) -> Option<usize> {
+        self.png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type() == chunk_type)
+    }
+}
+
+impl Png {
+    /// Runs `edit` on a copy of the file and keeps the result only if it
+    /// returns `Ok`, returning the edits it made. See the module docs.
+    pub fn transaction<E>(
+        &mut self,
+        edit: impl FnOnce(&mut Transaction) -> Result<(), E>,
+    ) -> Result<Vec<Change>, E> {
+        let mut tx = Transaction {
+            png: self.clone(),
+            changes: Vec::new(),
+        };
+        edit(&mut tx)?;
+        *self = tx.png;
+        Ok(tx.changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.into())
+    }
+
+    fn png() -> Png {
+        let mut png = PngBuilder::new(1, 1)
+            .with_chunk(chunk("ruSt", "old tag"))
+            .with_chunk(chunk("tIME", "before!"))
+            .build()
+            .unwrap();
+        png.mark_clean();
+        png
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_failed_transaction_leaves_png_untouched() {
+        let mut png = png();
+        let before = png.as_bytes();
+        let error = png
+            .transaction(|tx| {
+                tx.remove_first("ruSt")?;
+                tx.append(chunk("ruSt", "new tag"));
+                tx.replace("tIME", "after!!")?;
+                assert_eq!(types(tx.png()), ["IHDR", "IDAT", "tIME", "ruSt", "IEND"]);
+                tx.remove_at(9)?;
+                Ok::<_, TransactionError>(())
+            })
+            .unwrap_err();
+        assert_eq!(error, TransactionError::OutOfRange { index: 9, count: 5 });
+        assert_eq!(png.as_bytes(), before);
+        assert!(!png.is_modified());
+
+        let error = png
+            .transaction(|tx| tx.replace("zzZz", "x").map(drop))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            TransactionError::NotFound(ChunkType::from_str("zzZz").unwrap())
+        );
+        assert!(matches!(
+            png.transaction(|tx| tx.remove_first("ru5t").map(drop)),
+            Err(TransactionError::InvalidType(_))
+        ));
+        assert_eq!(png.as_bytes(), before);
+    }
+
+    #[test]
+    fn test_transaction_applies_changes_in_order() {
+        let mut png = png();
+        let changes = png
+            .transaction(|tx| {
+                assert_eq!(tx.remove_first("ruSt")?.unwrap().data(), b"old tag");
+                tx.append(chunk("ruSt", "new tag"));
+                tx.replace("tIME", "after!!")?;
+                // Sees the chunk appended above
+                tx.insert(0, chunk("ruSa", "first"))?;
+                assert_eq!(tx.remove_first("nOne")?, None);
+                assert_eq!(tx.changes().len(), 4);
+                Ok::<_, TransactionError>(())
+            })
+            .unwrap();
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        assert_eq!(
+            changes,
+            [
+                Change::Removed {
+                    index: 2,
+                    chunk_type: rust
+                },
+                Change::Added {
+                    index: 3,
+                    chunk_type: rust
+                },
+                Change::Replaced {
+                    index: 2,
+                    chunk_type: ChunkType::from_str("tIME").unwrap()
+                },
+                Change::Added {
+                    index: 0,
+                    chunk_type: ChunkType::from_str("ruSa").unwrap()
+                },
+            ]
+        );
+        assert_eq!(
+            types(&png),
+            ["ruSa", "IHDR", "IDAT", "tIME", "ruSt", "IEND"]
+        );
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"new tag");
+        assert_eq!(png.chunk_by_type("tIME").unwrap().data(), b"after!!");
+        assert!(png.is_modified());
+    }
+}
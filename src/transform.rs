@@ -0,0 +1,404 @@
+//! Steps applied to a payload before it's stored, such as compression, and
+//! the envelope that records them so they can be undone when it's read.
+//!
+//! ```
+//! use pngme::transform::{Registry, DEFLATE, BASE64};
+//! # fn main() -> Result<(), pngme::transform::TransformError> {
+//! let registry = Registry::default();
+//! // Compressed, then the compressed bytes base64-encoded
+//! let stored = registry.wrap(b"hello hello hello", &[DEFLATE, BASE64])?;
+//! assert_eq!(registry.unwrap(&stored)?, b"hello hello hello");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! An envelope is the 4 bytes of `MAGIC`, a count, the ids of the
+//! transforms in the order they were applied, and the transformed payload.
+//! `unwrap` undoes them last to first. Ids below 128 are kept for the ones
+//! built in here; a transform of your own, registered with
+//! `Registry::register`, should take one from 128 up.
+
+use crate::base64;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::fmt::{self, Display};
+use std::io::{Read, Write};
+
+/// What every envelope starts with.
+pub const MAGIC: [u8; 4] = *b"\x89pmE";
+
+pub const DEFLATE: u8 = 1;
+pub const BASE64: u8 = 2;
+
+/// The least `Registry::wrap_auto` must save by compressing to bother.
+pub const AUTO_MIN_SAVING: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransformError {
+    /// No transform with this id is registered.
+    UnknownId(u8),
+    /// No transform with this name is registered.
+    UnknownName(String),
+    /// A second transform registered with an id that's taken.
+    DuplicateId(u8),
+    /// More transforms than an envelope can record.
+    TooMany(usize),
+    /// Data that starts like an envelope but is cut off in its header.
+    Truncated,
+    /// A transform couldn't process the data.
+    Failed { name: String, reason: String },
+}
+
+impl Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransformError::UnknownId(id) => write!(f, "Unknown transform id {id}"),
+            TransformError::UnknownName(name) => write!(f, "Unknown transform '{name}'"),
+            TransformError::DuplicateId(id) => {
+                write!(f, "A transform with id {id} is already registered")
+            }
+            TransformError::TooMany(count) => {
+                write!(f, "{count} transforms is more than an envelope can record")
+            }
+            TransformError::Truncated => write!(f, "The envelope header is cut short"),
+            TransformError::Failed { name, reason } => write!(f, "{name} failed: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+/// One reversible step: bytes in, bytes out.
+pub trait Transform {
+    /// What an envelope records for this transform. Must be unique in a
+    /// registry.
+    fn id(&self) -> u8;
+    /// How the CLI and error messages refer to it.
+    fn name(&self) -> &str;
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError>;
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError>;
+}
+
+/// zlib compression, as in zTXt.
+pub struct Deflate;
+
+impl Deflate {
+    /// The most `decode` inflates to, so a small payload can't expand
+    /// without bound.
+    pub const MAX_INFLATED: u64 = 256 * 1024 * 1024;
+
+    fn failed(e: impl Display) -> TransformError {
+        TransformError::Failed {
+            name: "deflate".to_string(),
+            reason: e.to_string(),
+        }
+    }
+}
+
+impl Transform for Deflate {
+    fn id(&self) -> u8 {
+        DEFLATE
+    }
+    fn name(&self) -> &str {
+        "deflate"
+    }
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data).map_err(Self::failed)?;
+        encoder.finish().map_err(Self::failed)
+    }
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError> {
+        let mut inflated = Vec::new();
+        ZlibDecoder::new(data)
+            .take(Self::MAX_INFLATED + 1)
+            .read_to_end(&mut inflated)
+            .map_err(Self::failed)?;
+        if inflated.len() as u64 > Self::MAX_INFLATED {
+            return Err(Self::failed(format!(
+                "inflates to more than {} bytes",
+                Self::MAX_INFLATED
+            )));
+        }
+        Ok(inflated)
+    }
+}
+
+/// Base64, for payloads that have to stay printable.
+pub struct Base64;
+
+impl Transform for Base64 {
+    fn id(&self) -> u8 {
+        BASE64
+    }
+    fn name(&self) -> &str {
+        "base64"
+    }
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError> {
+        Ok(base64::encode(data).into_bytes())
+    }
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError> {
+        let text = std::str::from_utf8(data).map_err(|e| TransformError::Failed {
+            name: "base64".to_string(),
+            reason: e.to_string(),
+        })?;
+        base64::decode(text).map_err(|e| TransformError::Failed {
+            name: "base64".to_string(),
+            reason: e.to_string(),
+        })
+    }
+}
+
+/// The transforms an envelope may name. `Registry::default()` has the
+/// built-in ones; `Registry::new()` has none.
+pub struct Registry {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            transforms: vec![Box::new(Deflate), Box::new(Base64)],
+        }
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            transforms: Vec::new(),
+        }
+    }
+    pub fn register(&mut self, transform: Box<dyn Transform>) -> Result<(), TransformError> {
+        if self.get(transform.id()).is_some() {
+            return Err(TransformError::DuplicateId(transform.id()));
+        }
+        self.transforms.push(transform);
+        Ok(())
+    }
+    pub fn get(&self, id: u8) -> Option<&dyn Transform> {
+        self.transforms
+            .iter()
+            .find(|t| t.id() == id)
+            .map(|t| t.as_ref())
+    }
+    /// The names of the transforms, in the order they were registered.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.transforms.iter().map(|t| t.name())
+    }
+    /// The id of the transform called `name`.
+    pub fn id(&self, name: &str) -> Result<u8, TransformError> {
+        self.transforms
+            .iter()
+            .find(|t| t.name() == name)
+            .map(|t| t.id())
+            .ok_or_else(|| TransformError::UnknownName(name.to_string()))
+    }
+    /// Applies the transforms in `chain`, first to last, and wraps the
+    /// result in an envelope that records them.
+    pub fn wrap(&self, payload: &[u8], chain: &[u8]) -> Result<Vec<u8>, TransformError> {
+        let count = u8::try_from(chain.len()).map_err(|_| TransformError::TooMany(chain.len()))?;
+        let mut data = payload.to_vec();
+        for &id in chain {
+            data = self
+                .get(id)
+                .ok_or(TransformError::UnknownId(id))?
+                .encode(&data)?;
+        }
+        let mut envelope = MAGIC.to_vec();
+        envelope.push(count);
+        envelope.extend_from_slice(chain);
+        envelope.extend_from_slice(&data);
+        Ok(envelope)
+    }
+    /// Wraps `payload` in the envelope `chain` makes, or, if it's at least
+    /// `AUTO_MIN_SAVING` bytes smaller, the one deflate then `chain` makes.
+    /// Compressing first is what lets it save anything, as whatever comes
+    /// after, such as encryption, leaves nothing to compress.
+    pub fn wrap_auto(&self, payload: &[u8], chain: &[u8]) -> Result<AutoWrapped, TransformError> {
+        let plain = self.wrap(payload, chain)?;
+        let deflated = self.wrap(payload, &[&[DEFLATE], chain].concat())?;
+        let (plain_len, deflated_len) = (plain.len(), deflated.len());
+        let compressed = plain_len.saturating_sub(deflated_len) >= AUTO_MIN_SAVING;
+        Ok(AutoWrapped {
+            envelope: if compressed { deflated } else { plain },
+            compressed,
+            plain_len,
+            deflated_len,
+        })
+    }
+    /// Undoes the transforms an envelope records, last to first.
+    pub fn unwrap(&self, envelope: &[u8]) -> Result<Vec<u8>, TransformError> {
+        let (chain, data) = split(envelope)?;
+        let mut data = data.to_vec();
+        for &id in chain.iter().rev() {
+            data = self
+                .get(id)
+                .ok_or(TransformError::UnknownId(id))?
+                .decode(&data)?;
+        }
+        Ok(data)
+    }
+}
+
+/// What `Registry::wrap_auto` chose.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoWrapped {
+    pub envelope: Vec<u8>,
+    /// Whether deflate was applied.
+    pub compressed: bool,
+    /// How long the envelope is without compression, and with it.
+    pub plain_len: usize,
+    pub deflated_len: usize,
+}
+
+impl AutoWrapped {
+    /// How many bytes compressing saves, or would have saved; negative if
+    /// it makes the envelope longer.
+    pub fn saved(&self) -> i64 {
+        self.plain_len as i64 - self.deflated_len as i64
+    }
+}
+
+/// Whether `data` starts like an envelope.
+pub fn is_envelope(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// The ids an envelope records, in the order they were applied, and the
+/// transformed payload.
+pub fn split(envelope: &[u8]) -> Result<(&[u8], &[u8]), TransformError> {
+    let rest = envelope
+        .strip_prefix(&MAGIC)
+        .ok_or(TransformError::Truncated)?;
+    let (&count, rest) = rest.split_first().ok_or(TransformError::Truncated)?;
+    if rest.len() < count as usize {
+        return Err(TransformError::Truncated);
+    }
+    Ok(rest.split_at(count as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reverses the bytes; its own inverse, so order mistakes show up.
+    struct Reverse;
+
+    impl Transform for Reverse {
+        fn id(&self) -> u8 {
+            200
+        }
+        fn name(&self) -> &str {
+            "reverse"
+        }
+        fn encode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError> {
+            Ok(data.iter().rev().copied().collect())
+        }
+        fn decode(&self, data: &[u8]) -> Result<Vec<u8>, TransformError> {
+            self.encode(data)
+        }
+    }
+
+    fn registry() -> Registry {
+        let mut registry = Registry::default();
+        registry.register(Box::new(Reverse)).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_chains_round_trip() {
+        let registry = registry();
+        let payload = b"hello hello hello \0\xff binary too";
+        let chains: [&[u8]; 6] = [
+            &[],
+            &[DEFLATE],
+            &[BASE64, 200],
+            &[200, BASE64],
+            &[DEFLATE, 200, BASE64],
+            &[BASE64, DEFLATE, BASE64],
+        ];
+        for chain in chains {
+            let envelope = registry.wrap(payload, chain).unwrap();
+            assert!(is_envelope(&envelope));
+            assert_eq!(split(&envelope).unwrap().0, chain);
+            assert_eq!(registry.unwrap(&envelope).unwrap(), payload, "{chain:?}");
+        }
+        // The order is kept: base64 last leaves printable bytes
+        let envelope = registry.wrap(payload, &[DEFLATE, BASE64]).unwrap();
+        let (_, data) = split(&envelope).unwrap();
+        assert!(data.is_ascii());
+        let envelope = registry.wrap(payload, &[BASE64, DEFLATE]).unwrap();
+        assert_eq!(split(&envelope).unwrap().1[0], 0x78);
+    }
+
+    #[test]
+    fn test_unknown_and_duplicate_ids() {
+        let envelope = registry().wrap(b"data", &[200, BASE64]).unwrap();
+        assert_eq!(
+            Registry::default().unwrap(&envelope),
+            Err(TransformError::UnknownId(200))
+        );
+        assert_eq!(
+            Registry::new().wrap(b"data", &[DEFLATE]),
+            Err(TransformError::UnknownId(DEFLATE))
+        );
+        let mut registry = registry();
+        assert_eq!(
+            registry.register(Box::new(Reverse)),
+            Err(TransformError::DuplicateId(200))
+        );
+        assert_eq!(registry.id("reverse"), Ok(200));
+        assert!(registry.id("rot13").is_err());
+    }
+
+    #[test]
+    fn test_auto_compresses_only_when_worth_it() {
+        let registry = registry();
+        let chosen = |payload: &[u8], chain: &[u8]| {
+            let auto = registry.wrap_auto(payload, chain).unwrap();
+            assert_eq!(registry.unwrap(&auto.envelope).unwrap(), payload);
+            let (recorded, _) = split(&auto.envelope).unwrap();
+            assert_eq!(recorded.first() == Some(&DEFLATE), auto.compressed);
+            recorded.to_vec()
+        };
+        let compressible = b"all work and no play ".repeat(50);
+        assert_eq!(chosen(&compressible, &[]), [DEFLATE]);
+        // Compression comes before the other transforms
+        assert_eq!(
+            chosen(&compressible, &[200, BASE64]),
+            [DEFLATE, 200, BASE64]
+        );
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..1000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(chosen(&noise, &[]), [0u8; 0]);
+        // Compression saves a little here, but not enough to bother
+        let tiny = b"aaaaaaaaaaaaaaaaaaaa";
+        let auto = registry.wrap_auto(tiny, &[]).unwrap();
+        assert!(
+            (1..AUTO_MIN_SAVING as i64).contains(&auto.saved()),
+            "{auto:?}"
+        );
+        assert_eq!(chosen(tiny, &[]), [0u8; 0]);
+    }
+
+    #[test]
+    fn test_bad_envelopes() {
+        let registry = Registry::default();
+        let truncated = [&MAGIC[..], &[2, DEFLATE]].concat();
+        assert_eq!(registry.unwrap(&truncated), Err(TransformError::Truncated));
+        assert_eq!(registry.unwrap(b"plain"), Err(TransformError::Truncated));
+        let not_zlib = [&MAGIC[..], &[1, DEFLATE], b"not zlib"].concat();
+        assert!(matches!(
+            registry.unwrap(&not_zlib),
+            Err(TransformError::Failed { .. })
+        ));
+    }
+}
@@ -0,0 +1,323 @@
+//! The undo log behind `--undo-log` and `pngme undo`: for every edit, the
+//! chunks it removed or overwrote and where the ones it added went, so the
+//! file can be put back the way it was, one edit at a time.
+//!
+//! Each file gets a JSON-lines file in the log directory, one record per
+//! edit, newest last.
+
+use crate::Result;
+use pngme::chunk::Chunk;
+use pngme::png::Png;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FILE_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// A chunk the edit took out, and where it was before the edit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Removed {
+    index: usize,
+    /// `Chunk::as_bytes`, hex encoded.
+    chunk: String,
+}
+
+/// Length and CRC-32 of the whole file as the edit left it, to tell whether
+/// it was changed since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    length: usize,
+    crc: u32,
+}
+
+impl Fingerprint {
+    fn of(bytes: &[u8]) -> Self {
+        Fingerprint {
+            length: bytes.len(),
+            crc: FILE_CRC.checksum(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// When the edit was made, as seconds and nanoseconds since the epoch.
+    pub id: String,
+    pub file: PathBuf,
+    pub command: String,
+    removed: Vec<Removed>,
+    /// Positions of the chunks the edit added, after the edit.
+    added: Vec<usize>,
+    after: Fingerprint,
+}
+
+impl Record {
+    pub fn restored(&self) -> usize {
+        self.removed.len()
+    }
+    pub fn dropped(&self) -> usize {
+        self.added.len()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Which chunks of `before` aren't in `after` and which of `after` are new,
+/// as positions in each. Everything else is the longest run of chunks the
+/// two have in common, in order.
+fn diff(before: &[Chunk], after: &[Chunk]) -> (Vec<usize>, Vec<usize>) {
+    // Edits touch a few chunks, so skip the common ends before comparing
+    // what's left pairwise
+    let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &before[prefix..before.len() - suffix];
+    let new = &after[prefix..after.len() - suffix];
+    // common[i][j] is the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            removed.push(prefix + i);
+            i += 1;
+        } else {
+            added.push(prefix + j);
+            j += 1;
+        }
+    }
+    (removed, added)
+}
+
+pub struct UndoLog {
+    dir: PathBuf,
+}
+
+impl UndoLog {
+    pub fn new(dir: &Path) -> Self {
+        UndoLog {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// The file's full path, so the same file reached by different paths
+    /// shares one history.
+    fn key(file: &Path) -> Result<PathBuf> {
+        Ok(fs::canonicalize(file).map_err(|e| format!("{}: {e}", file.display()))?)
+    }
+
+    fn log_path(&self, file: &Path) -> PathBuf {
+        let name = FILE_CRC.checksum(file.as_os_str().as_encoded_bytes());
+        self.dir.join(format!("{name:08x}.jsonl"))
+    }
+
+    /// Every record in `file`'s log, oldest first. The log is shared by any
+    /// files whose names hash alike, so records for other files come too.
+    fn read_log(&self, file: &Path) -> Result<Vec<Record>> {
+        let path = self.log_path(file);
+        let log = match File::open(&path) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("{}: {e}", path.display()).into()),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(log).lines() {
+            let line = line?;
+            let record = serde_json::from_str(&line)
+                .map_err(|e| format!("{}: corrupt undo record: {e}", path.display()))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Logs the difference between `before` and `after`, made to `file` by
+    /// `command`. Done before `after` is written, so an edit can't land
+    /// without a record, and undone with `pop` if the write fails.
+    pub fn push(&self, file: &Path, command: &str, before: &Png, after: &Png) -> Result<()> {
+        let (removed, added) = diff(before.chunks(), after.chunks());
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let record = Record {
+            id: format!("{}.{:09}", time.as_secs(), time.subsec_nanos()),
+            file: Self::key(file)?,
+            command: command.to_string(),
+            removed: removed
+                .into_iter()
+                .map(|index| Removed {
+                    index,
+                    chunk: to_hex(&before.chunks()[index].as_bytes()),
+                })
+                .collect(),
+            added,
+            after: Fingerprint::of(&after.as_bytes()),
+        };
+        fs::create_dir_all(&self.dir).map_err(|e| format!("{}: {e}", self.dir.display()))?;
+        let path = self.log_path(&record.file);
+        let mut log = File::options()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        writeln!(log, "{}", serde_json::to_string(&record)?)?;
+        Ok(())
+    }
+
+    /// Drops the newest record for `file` from the log.
+    pub fn pop(&self, file: &Path) -> Result<()> {
+        let key = Self::key(file)?;
+        let mut records = self.read_log(&key)?;
+        if let Some(last) = records.iter().rposition(|r| r.file == key) {
+            records.remove(last);
+        }
+        let path = self.log_path(&key);
+        if records.is_empty() {
+            fs::remove_file(&path)?;
+        } else {
+            let mut lines = String::new();
+            for record in &records {
+                lines += &serde_json::to_string(record)?;
+                lines.push('\n');
+            }
+            fs::write(&path, lines)?;
+        }
+        Ok(())
+    }
+
+    /// Reverts the newest edit logged for `file` on `png`, parsed from the
+    /// file's current `bytes`. Refuses if the file changed since that edit,
+    /// as its positions would no longer line up. Returns the record, which
+    /// is still in the log until `pop` is called.
+    pub fn undo(&self, file: &Path, bytes: &[u8], png: &mut Png) -> Result<Record> {
+        let key = Self::key(file)?;
+        let Some(record) = self.read_log(&key)?.into_iter().rfind(|r| r.file == key) else {
+            return Err(format!("{}: nothing to undo", file.display()).into());
+        };
+        if Fingerprint::of(bytes) != record.after {
+            return Err(format!(
+                "{} has changed since the {} being undone, so it can't be reverted",
+                file.display(),
+                record.command
+            )
+            .into());
+        }
+        for &index in record.added.iter().rev() {
+            png.remove_chunk_at(index)
+                .ok_or("Undo record doesn't match the file")?;
+        }
+        for removed in &record.removed {
+            let bytes = from_hex(&removed.chunk).ok_or("Corrupt chunk in undo record")?;
+            let chunk = Chunk::try_from(bytes.as_slice())?;
+            if removed.index > png.chunk_count() {
+                return Err("Undo record doesn't match the file".into());
+            }
+            png.insert_chunk(removed.index, chunk);
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::builder::PngBuilder;
+    use pngme::chunk_type::ChunkType;
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    fn chunk(data: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            data.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_diff() {
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(chunk);
+        let before = [a.clone(), b.clone(), c.clone()];
+        assert_eq!(diff(&before, &before), (vec![], vec![]));
+        assert_eq!(diff(&before, &[a.clone(), c.clone()]), (vec![1], vec![]));
+        assert_eq!(
+            diff(&before, &[a.clone(), b.clone(), d.clone(), c.clone()]),
+            (vec![], vec![2])
+        );
+        // Overwriting in place is a removal and an addition at one position
+        assert_eq!(diff(&before, &[a, d, c]), (vec![1], vec![1]));
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = chunk("payload \x00\x7f").as_bytes();
+        assert_eq!(from_hex(&to_hex(&bytes)), Some(bytes));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_undo_levels() {
+        let dir = TempDir::new().unwrap();
+        let log = UndoLog::new(&dir.path().join("log"));
+        let path = dir.path().join("a.png");
+        let original = PngBuilder::new(1, 1)
+            .with_chunk(chunk("one"))
+            .build()
+            .unwrap();
+        fs::write(&path, original.as_bytes()).unwrap();
+
+        // Two edits, each logged before being written
+        let mut versions = vec![original];
+        for data in ["two", "three"] {
+            let before = versions.last().unwrap().clone();
+            let mut after = before.clone();
+            after.remove_chunk_at(1);
+            after.append_chunk(chunk(data));
+            log.push(&path, "edit", &before, &after).unwrap();
+            fs::write(&path, after.as_bytes()).unwrap();
+            versions.push(after);
+        }
+
+        while versions.len() > 1 {
+            let bytes = fs::read(&path).unwrap();
+            let mut png = Png::try_from(bytes.as_slice()).unwrap();
+            let record = log.undo(&path, &bytes, &mut png).unwrap();
+            assert_eq!((record.restored(), record.dropped()), (1, 1));
+            versions.pop();
+            assert_eq!(&png, versions.last().unwrap());
+            fs::write(&path, png.as_bytes()).unwrap();
+            log.pop(&path).unwrap();
+        }
+        let bytes = fs::read(&path).unwrap();
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        let err = log.undo(&path, &bytes, &mut png).unwrap_err();
+        assert!(err.to_string().contains("nothing to undo"));
+    }
+}
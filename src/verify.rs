@@ -0,0 +1,702 @@
+use crate::chunk::{ChunkRef, InvalidChunk};
+use crate::chunk_type::ChunkType;
+use crate::format::format_crc;
+use crate::idat;
+use crate::ihdr::Ihdr;
+use crate::palette::{self, PLTE, TRNS};
+use crate::png::{self, ParseOptions};
+use crate::standard;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Every kind of problem `verify` can report. The codes returned by `code`
+/// are part of the CLI's JSON output, so existing ones must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    BadSignature,
+    CrcMismatch,
+    TruncatedChunk,
+    BadChunkType,
+    ChunkTooLarge,
+    StrayBytes,
+    MissingIhdr,
+    MissingIdat,
+    MissingIend,
+    BadOrder,
+    ChunkAfterIend,
+    TrailingData,
+    BadPalette,
+    LengthMismatch,
+    LimitExceeded,
+    DuplicateChunk,
+    BadImageData,
+}
+
+impl FindingKind {
+    /// Every kind, in the order they're declared.
+    pub const ALL: [FindingKind; 17] = [
+        FindingKind::BadSignature,
+        FindingKind::CrcMismatch,
+        FindingKind::TruncatedChunk,
+        FindingKind::BadChunkType,
+        FindingKind::ChunkTooLarge,
+        FindingKind::StrayBytes,
+        FindingKind::MissingIhdr,
+        FindingKind::MissingIdat,
+        FindingKind::MissingIend,
+        FindingKind::BadOrder,
+        FindingKind::ChunkAfterIend,
+        FindingKind::TrailingData,
+        FindingKind::BadPalette,
+        FindingKind::LengthMismatch,
+        FindingKind::LimitExceeded,
+        FindingKind::DuplicateChunk,
+        FindingKind::BadImageData,
+    ];
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.code() == code)
+    }
+    pub fn code(&self) -> &'static str {
+        match self {
+            FindingKind::BadSignature => "bad-signature",
+            FindingKind::CrcMismatch => "crc-mismatch",
+            FindingKind::TruncatedChunk => "truncated-chunk",
+            FindingKind::BadChunkType => "bad-chunk-type",
+            FindingKind::ChunkTooLarge => "chunk-too-large",
+            FindingKind::StrayBytes => "stray-bytes",
+            FindingKind::MissingIhdr => "missing-ihdr",
+            FindingKind::MissingIdat => "missing-idat",
+            FindingKind::MissingIend => "missing-iend",
+            FindingKind::BadOrder => "bad-order",
+            FindingKind::ChunkAfterIend => "chunk-after-iend",
+            FindingKind::TrailingData => "trailing-data",
+            FindingKind::BadPalette => "bad-palette",
+            FindingKind::LengthMismatch => "length-mismatch",
+            FindingKind::LimitExceeded => "limit-exceeded",
+            FindingKind::DuplicateChunk => "duplicate-chunk",
+            FindingKind::BadImageData => "bad-image-data",
+        }
+    }
+    /// What the finding means, what usually causes it and which command
+    /// deals with it, as `pngme explain` prints it.
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            FindingKind::BadSignature => {
+                "The file doesn't start with the 8-byte PNG signature, so it isn't a PNG \
+                 or its first bytes are damaged. Most often it's another format saved with \
+                 a .png name, which the message names when it recognizes it, or a file sent \
+                 in text mode, which mangles the line-ending bytes the signature contains. \
+                 pngme can't fix it; get the file again or convert it with an image tool."
+            }
+            FindingKind::CrcMismatch => {
+                "A chunk's stored CRC doesn't match the CRC of its type and data, so one \
+                 of them changed after the chunk was written: a bit flipped in storage or \
+                 transfer, or a tool edited the data without updating the CRC. If the data \
+                 is known to be good, `pngme repair` recomputes the CRC; `--lenient` reads \
+                 past the chunk instead."
+            }
+            FindingKind::TruncatedChunk => {
+                "A chunk needs more bytes than are left in the file, nearly always because \
+                 the file was cut off by an interrupted download or copy. `pngme salvage` \
+                 reports how much of the file is intact and can write the chunks before \
+                 the cut to a file of their own with --write-recovered."
+            }
+            FindingKind::BadChunkType => {
+                "A chunk type isn't four ASCII letters, as the spec requires. Either the \
+                 chunk header is corrupt or a tool wrote the type on purpose, as `encode \
+                 --type-hex` does for testing other parsers. `decode` and `remove` reach \
+                 such a chunk with --type-hex, and `pngme remove --type-hex` takes it out."
+            }
+            FindingKind::ChunkTooLarge => {
+                "A chunk declares a length above the limit, 2^31 - 1 bytes by the spec or \
+                 less with --max-chunk-size. Usually the length field is damaged and points \
+                 far past the end of the file. `pngme repair --fix-lengths` looks for the \
+                 real length where the stored CRC matches."
+            }
+            FindingKind::StrayBytes => {
+                "A lenient parse skipped bytes between chunks that don't form a valid \
+                 chunk, usually the remains of a damaged one or data another tool spliced \
+                 in. Other decoders stop there. If only a CRC or a length is wrong, `pngme \
+                 repair` or `repair --fix-lengths` restores the chunk; otherwise `pngme \
+                 salvage --write-recovered` keeps the chunks before it."
+            }
+            FindingKind::MissingIhdr => {
+                "There is no IHDR chunk, which holds the image size and format, so no \
+                 decoder can display the file. It usually means the start of the file was \
+                 damaged or a tool removed it; `remove` refuses to without --force. \
+                 pngme can't recreate it, as the image parameters are lost."
+            }
+            FindingKind::MissingIdat => {
+                "There is no IDAT chunk, so the file has no image data. This comes from \
+                 removing the IDATs, or from a truncated file whose image data never made \
+                 it. pngme can't restore the pixels; `pngme salvage` shows where a \
+                 truncated file stops."
+            }
+            FindingKind::MissingIend => {
+                "The file ends without the IEND chunk that marks its end. Many decoders \
+                 still show the image, but it's the usual sign of a truncated file. \
+                 `pngme salvage --write-recovered` writes the intact chunks with an IEND \
+                 added."
+            }
+            FindingKind::BadOrder => {
+                "IHDR isn't the first chunk. The spec requires it there, and strict \
+                 decoders reject the file otherwise. It comes from a tool that inserted a \
+                 chunk at index 0; `pngme remove --index` takes out a chunk that doesn't \
+                 belong, and `encode --conventional` puts new chunks where they go."
+            }
+            FindingKind::ChunkAfterIend => {
+                "A valid chunk comes after IEND, where decoders stop reading, so it's \
+                 ignored by everything but tools that look for it. Appending tools and \
+                 hiding data after the image both cause it. `--relocate-post-iend` moves \
+                 such chunks before IEND when a command writes the file, and `pngme \
+                 optimize` removes them."
+            }
+            FindingKind::TrailingData => {
+                "There are bytes after IEND that aren't a chunk. Decoders ignore them, so \
+                 this is only a warning, but they make the file larger and may be data \
+                 someone appended, such as an archive. `pngme optimize` removes them."
+            }
+            FindingKind::BadPalette => {
+                "PLTE or tRNS doesn't fit the image: a palette image without a palette, a \
+                 palette longer than the bit depth can index or not a multiple of 3 bytes, \
+                 or more tRNS entries than palette colors. It's usually a bug in the tool \
+                 that wrote the file. `pngme info --palette` shows what's there; pngme \
+                 can't make a correct palette up."
+            }
+            FindingKind::LengthMismatch => {
+                "A chunk's length field is wrong, but its stored CRC matches at a \
+                 different length, so the data is most likely intact. A tool that edited \
+                 the data without updating the length causes it. `pngme repair \
+                 --fix-lengths` rewrites the length."
+            }
+            FindingKind::LimitExceeded => {
+                "The file went over a limit set by --max-file-size, --max-chunks or \
+                 --max-decompressed, so it wasn't read further. The file itself may be \
+                 valid; raise the limit if you trust it. Files built to exhaust memory, \
+                 such as compressed text that inflates enormously, are what the limits \
+                 are for."
+            }
+            FindingKind::DuplicateChunk => {
+                "A chunk type the spec allows only once, such as tIME or gAMA, appears \
+                 again. Decoders differ over which copy they use. It comes from tools that \
+                 add a chunk without checking for an existing one; `encode` refuses to \
+                 without --force. `pngme remove --index` takes out the extra copy."
+            }
+            FindingKind::BadImageData => {
+                "The IDAT chunks together don't inflate to the size IHDR's dimensions, \
+                 bit depth and color type call for, or don't inflate at all. An IDAT went \
+                 missing or was edited, or IHDR was changed without re-encoding the \
+                 pixels. Decoders show a partial image or none. `pngme info \
+                 --inflate-check` shows both sizes; pngme can't restore the pixels."
+            }
+        }
+    }
+    /// Decoders ignore anything after IEND, so those findings are only warnings.
+    pub fn severity(&self) -> Severity {
+        match self {
+            FindingKind::ChunkAfterIend | FindingKind::TrailingData => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+    fn from_invalid_chunk(kind: &InvalidChunk) -> Self {
+        match kind {
+            InvalidChunk::Header | InvalidChunk::InvalidSignature { .. } => {
+                FindingKind::BadSignature
+            }
+            InvalidChunk::Crc { .. } => FindingKind::CrcMismatch,
+            // Parsing never decodes text, so NotUtf8 doesn't come up
+            InvalidChunk::Length { .. }
+            | InvalidChunk::Truncated { .. }
+            | InvalidChunk::NotUtf8 { .. } => FindingKind::TruncatedChunk,
+            InvalidChunk::Type { .. } => FindingKind::BadChunkType,
+            InvalidChunk::TooLarge { .. } => FindingKind::ChunkTooLarge,
+            InvalidChunk::FileTooLarge { .. }
+            | InvalidChunk::TooManyChunks { .. }
+            | InvalidChunk::TooMuchDecompressed { .. } => FindingKind::LimitExceeded,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Index of the chunk involved, as shown by `list`.
+    pub chunk_index: Option<usize>,
+    pub chunk_type: Option<ChunkType>,
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Finding {
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+    fn for_chunk(kind: FindingKind, index: usize, chunk: &ChunkRef, message: String) -> Self {
+        Self {
+            kind,
+            chunk_index: Some(index),
+            chunk_type: Some(*chunk.chunk_type()),
+            offset: chunk.offset(),
+            message,
+        }
+    }
+}
+
+/// The chunk type in the header at `offset`, if there is a readable one.
+fn type_at(value: &[u8], offset: usize) -> Option<ChunkType> {
+    let bytes: [u8; 4] = value.get(offset + 4..offset + 8)?.try_into().ok()?;
+    ChunkType::try_from(bytes).ok()
+}
+
+/// Describes the chunk at `offset` whose stored CRC doesn't match its
+/// contents, with both values.
+fn crc_mismatch_message(value: &[u8], offset: usize) -> Option<String> {
+    let length: [u8; 4] = value.get(offset..offset + 4)?.try_into().ok()?;
+    let end = offset + 12 + u32::from_be_bytes(length) as usize;
+    let chunk = ChunkRef::parse_unverified(value.get(offset..end)?, offset).ok()?;
+    let mismatch = chunk.verify_crc().err()?;
+    Some(format!(
+        "{} at offset {offset} (0x{offset:x}) has CRC {} but its contents give {}",
+        chunk.chunk_type(),
+        format_crc(mismatch.stored),
+        format_crc(mismatch.computed)
+    ))
+}
+
+fn length_mismatch(index: usize, mismatch: png::LengthMismatch) -> Finding {
+    Finding {
+        kind: FindingKind::LengthMismatch,
+        chunk_index: Some(index),
+        chunk_type: Some(mismatch.chunk_type),
+        offset: mismatch.offset,
+        message: mismatch.to_string(),
+    }
+}
+
+/// Checks a whole file. A strict parse reports where it stops; a lenient one
+/// reports every run of bytes it had to skip. The chunk layout is checked in
+/// both cases.
+pub fn verify(value: &[u8], options: &ParseOptions) -> Vec<Finding> {
+    let lenient = ParseOptions {
+        lenient: true,
+        ..options.clone()
+    };
+    let png::Scanned { chunks, stray, .. } = match png::scan(value, &lenient) {
+        Ok(scanned) => scanned,
+        Err(e) => {
+            return vec![Finding {
+                kind: FindingKind::from_invalid_chunk(e.kind()),
+                chunk_index: None,
+                chunk_type: None,
+                offset: e.offset(),
+                message: e.to_string(),
+            }];
+        }
+    };
+    let iend = chunks
+        .iter()
+        .position(|c| *c.chunk_type() == ChunkType::IEND);
+    let iend_end = iend.map(|i| chunks[i].offset() + chunks[i].length() as usize + 12);
+    let after_iend = |offset: usize| iend_end.is_some_and(|end| offset >= end);
+
+    let mut findings = Vec::new();
+    if options.lenient {
+        for range in &stray {
+            let len = range.end - range.offset;
+            if !after_iend(range.offset)
+                && let Some(mismatch) = png::recover_length(value, range.offset, &lenient)
+            {
+                findings.push(length_mismatch(range.index, mismatch));
+                continue;
+            }
+            let kind = if after_iend(range.offset) {
+                FindingKind::TrailingData
+            } else if matches!(
+                png::expected_chunk_at(value, range.offset, &lenient),
+                Err(InvalidChunk::Crc { .. })
+            ) {
+                FindingKind::CrcMismatch
+            } else {
+                FindingKind::StrayBytes
+            };
+            findings.push(Finding {
+                kind,
+                chunk_index: Some(range.index),
+                chunk_type: type_at(value, range.offset)
+                    .filter(|_| kind == FindingKind::CrcMismatch),
+                offset: range.offset,
+                message: crc_mismatch_message(value, range.offset)
+                    .filter(|_| kind == FindingKind::CrcMismatch)
+                    .unwrap_or_else(|| {
+                        format!(
+                            "{len} stray bytes at offset {} (0x{:x})",
+                            range.offset, range.offset
+                        )
+                    }),
+            });
+        }
+    } else if let Err(e) = png::scan(value, options) {
+        let index = chunks.iter().filter(|c| c.offset() < e.offset()).count();
+        if !after_iend(e.offset())
+            && let Some(mismatch) = png::recover_length(value, e.offset(), options)
+        {
+            findings.push(length_mismatch(index, mismatch));
+        } else {
+            let kind = if after_iend(e.offset()) {
+                FindingKind::TrailingData
+            } else {
+                FindingKind::from_invalid_chunk(e.kind())
+            };
+            findings.push(Finding {
+                kind,
+                chunk_index: Some(index),
+                chunk_type: type_at(value, e.offset()),
+                offset: e.offset(),
+                message: crc_mismatch_message(value, e.offset())
+                    .filter(|_| kind == FindingKind::CrcMismatch)
+                    .unwrap_or_else(|| e.to_string()),
+            });
+        }
+    }
+
+    // Kept with `any_chunk_type`, or by the lenient scan when the CRC is
+    // right, in which case the strict parse may have stopped at it already
+    for (index, chunk) in chunks.iter().enumerate() {
+        let reported = findings
+            .iter()
+            .any(|f| f.kind == FindingKind::BadChunkType && f.offset == chunk.offset());
+        if !chunk.chunk_type().is_ascii_letters() && !reported {
+            findings.push(Finding::for_chunk(
+                FindingKind::BadChunkType,
+                index,
+                chunk,
+                format!("Chunk type {} isn't four ASCII letters", chunk.chunk_type()),
+            ));
+        }
+    }
+    match chunks
+        .iter()
+        .position(|c| *c.chunk_type() == ChunkType::IHDR)
+    {
+        None => findings.push(Finding {
+            kind: FindingKind::MissingIhdr,
+            chunk_index: None,
+            chunk_type: None,
+            offset: png::Png::STANDARD_HEADER.len(),
+            message: "There is no IHDR chunk".to_string(),
+        }),
+        Some(0) => {}
+        Some(index) => findings.push(Finding::for_chunk(
+            FindingKind::BadOrder,
+            index,
+            &chunks[index],
+            format!("IHDR is chunk {index} but must come first"),
+        )),
+    }
+    if !chunks.iter().any(|c| *c.chunk_type() == ChunkType::IDAT) {
+        findings.push(Finding {
+            kind: FindingKind::MissingIdat,
+            chunk_index: None,
+            chunk_type: None,
+            offset: png::Png::STANDARD_HEADER.len(),
+            message: "There is no IDAT chunk".to_string(),
+        });
+    }
+    match iend {
+        None => findings.push(Finding {
+            kind: FindingKind::MissingIend,
+            chunk_index: None,
+            chunk_type: None,
+            offset: value.len(),
+            message: "The file ends without an IEND chunk".to_string(),
+        }),
+        Some(iend) => {
+            for (index, chunk) in chunks.iter().enumerate().skip(iend + 1) {
+                findings.push(Finding::for_chunk(
+                    FindingKind::ChunkAfterIend,
+                    index,
+                    chunk,
+                    format!(
+                        "{} comes after IEND and is ignored by decoders",
+                        chunk.chunk_type()
+                    ),
+                ));
+            }
+        }
+    }
+    for (index, chunk) in chunks.iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        if !standard::is_unique(chunk_type) {
+            continue;
+        }
+        if let Some(first) = chunks[..index]
+            .iter()
+            .position(|c| c.chunk_type() == chunk_type)
+        {
+            findings.push(Finding::for_chunk(
+                FindingKind::DuplicateChunk,
+                index,
+                chunk,
+                format!(
+                    "{chunk_type} at chunk {index} repeats chunk {first}, but there may be only one"
+                ),
+            ));
+        }
+    }
+    let find = |chunk_type: ChunkType| {
+        chunks
+            .iter()
+            .enumerate()
+            .find(|(_, c)| *c.chunk_type() == chunk_type)
+    };
+    if let Some(Ok(ihdr)) = find(ChunkType::IHDR).map(|(_, c)| Ihdr::parse(c.data())) {
+        let idats = chunks
+            .iter()
+            .filter(|c| *c.chunk_type() == ChunkType::IDAT)
+            .map(|c| c.data());
+        if let Some((index, chunk)) = find(ChunkType::IDAT)
+            && let Some(message) = idat::check(&ihdr, idats)
+        {
+            findings.push(Finding::for_chunk(
+                FindingKind::BadImageData,
+                index,
+                chunk,
+                message,
+            ));
+        }
+        let plte = find(PLTE);
+        let trns = find(TRNS);
+        for message in palette::check(
+            &ihdr,
+            plte.map(|(_, c)| c.data()),
+            trns.map(|(_, c)| c.data()),
+        ) {
+            let (index, chunk) = plte.unwrap_or_else(|| find(ChunkType::IHDR).unwrap());
+            findings.push(Finding::for_chunk(
+                FindingKind::BadPalette,
+                index,
+                chunk,
+                message,
+            ));
+        }
+    }
+    findings.sort_by_key(|f| f.offset);
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::PngBuilder;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn fixture() -> Vec<u8> {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+        PngBuilder::new(1, 1)
+            .with_chunk(chunk)
+            .build()
+            .unwrap()
+            .as_bytes()
+    }
+
+    fn kinds(findings: &[Finding]) -> Vec<FindingKind> {
+        findings.iter().map(|f| f.kind).collect()
+    }
+
+    #[test]
+    fn test_clean_file() {
+        assert!(verify(&fixture(), &ParseOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = fixture();
+        let rust = png::Png::parse_borrowed(&bytes).unwrap()[2];
+        let (offset, rust_type) = (rust.offset(), *rust.chunk_type());
+        // Flip a data byte in ruSt
+        bytes[offset + 8] ^= 1;
+
+        for lenient in [false, true] {
+            let options = ParseOptions {
+                lenient,
+                ..Default::default()
+            };
+            let findings = verify(&bytes, &options);
+            assert_eq!(kinds(&findings), [FindingKind::CrcMismatch], "{lenient}");
+            assert_eq!(findings[0].chunk_index, Some(2));
+            assert_eq!(findings[0].chunk_type, Some(rust_type));
+            assert_eq!(findings[0].offset, offset);
+        }
+    }
+
+    #[test]
+    fn test_length_mismatch() {
+        let mut bytes = fixture();
+        let rust = png::Png::parse_borrowed(&bytes).unwrap()[2];
+        let offset = rust.offset();
+        // Declares 3 bytes of "hello"
+        bytes[offset + 3] = 3;
+
+        for lenient in [false, true] {
+            let options = ParseOptions {
+                lenient,
+                ..Default::default()
+            };
+            let findings = verify(&bytes, &options);
+            assert_eq!(kinds(&findings), [FindingKind::LengthMismatch], "{lenient}");
+            assert_eq!(findings[0].chunk_index, Some(2));
+            assert_eq!(findings[0].offset, offset);
+            assert!(findings[0].message.ends_with("declared length 3, actual 5"));
+        }
+    }
+
+    #[test]
+    fn test_trailing_data_is_a_warning() {
+        let mut bytes = fixture();
+        bytes.extend_from_slice(b"trailing junk");
+        let findings = verify(&bytes, &ParseOptions::default());
+        assert_eq!(kinds(&findings), [FindingKind::TrailingData]);
+        assert_eq!(findings[0].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn test_layout_checks() {
+        let png = png::Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new()),
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::IEND, Vec::new()),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new()),
+        ]);
+        let findings = verify(&png.as_bytes(), &ParseOptions::default());
+        assert_eq!(
+            kinds(&findings),
+            [
+                FindingKind::MissingIdat,
+                FindingKind::BadOrder,
+                FindingKind::ChunkAfterIend
+            ]
+        );
+        assert_eq!(findings[2].chunk_index, Some(3));
+
+        let bytes = fixture();
+        let findings = verify(&bytes[..bytes.len() - 12], &ParseOptions::default());
+        assert_eq!(kinds(&findings), [FindingKind::MissingIend]);
+    }
+
+    #[test]
+    fn test_bad_palette() {
+        let mut chunks = png::Png::try_from(fixture().as_slice())
+            .unwrap()
+            .chunks()
+            .to_vec();
+        chunks.insert(1, Chunk::new(PLTE, vec![0; 4]));
+        let bytes = png::Png::from_chunks(chunks).as_bytes();
+        let findings = verify(&bytes, &ParseOptions::default());
+        assert_eq!(kinds(&findings), [FindingKind::BadPalette]);
+        assert_eq!(findings[0].chunk_index, Some(1));
+    }
+
+    #[test]
+    fn test_unchecked_chunk_type_is_flagged() {
+        let odd = ChunkType::from_bytes_unchecked([0xab, b'B', b'C', b'D']);
+        let bytes = PngBuilder::new(1, 1)
+            .with_chunk(Chunk::new(odd, b"hello".to_vec()))
+            .build()
+            .unwrap()
+            .as_bytes();
+        let findings = verify(&bytes, &ParseOptions::default());
+        assert_eq!(kinds(&findings), [FindingKind::BadChunkType]);
+        // Still flagged when the parse lets the type through
+        let any_type = ParseOptions {
+            any_chunk_type: true,
+            ..Default::default()
+        };
+        let findings = verify(&bytes, &any_type);
+        assert_eq!(kinds(&findings), [FindingKind::BadChunkType]);
+        assert_eq!(findings[0].chunk_type, Some(odd));
+        assert_eq!(
+            findings[0].message,
+            "Chunk type \\xabBCD isn't four ASCII letters"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_unique_chunks() {
+        let time = ChunkType::from_str("tIME").unwrap();
+        let text = ChunkType::from_str("tEXt").unwrap();
+        let bytes = PngBuilder::new(1, 1)
+            .with_chunk(Chunk::new(time, vec![0; 7]))
+            .with_chunk(Chunk::new(text, b"a\0b".to_vec()))
+            .with_chunk(Chunk::new(text, b"a\0b".to_vec()))
+            .with_chunk(Chunk::new(time, vec![1; 7]))
+            .with_chunk(Chunk::new(time, vec![2; 7]))
+            .build()
+            .unwrap()
+            .as_bytes();
+        let findings = verify(&bytes, &ParseOptions::default());
+        // Repeated text chunks are fine, the second and third tIME aren't
+        assert_eq!(
+            kinds(&findings),
+            [FindingKind::DuplicateChunk, FindingKind::DuplicateChunk]
+        );
+        assert_eq!(findings[0].chunk_index, Some(5));
+        assert_eq!(findings[0].chunk_type, Some(time));
+        assert_eq!(
+            findings[1].message,
+            "tIME at chunk 6 repeats chunk 2, but there may be only one"
+        );
+        assert_eq!(findings[1].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn test_image_data_must_fit_ihdr() {
+        let mut png = PngBuilder::new(3, 2).build().unwrap();
+        // Taller than the pixels stored
+        let mut ihdr = png.chunks()[0].data().to_vec();
+        ihdr[7] = 3;
+        png.remove_chunk_at(0);
+        png.insert_chunk(0, Chunk::new(ChunkType::IHDR, ihdr));
+        let findings = verify(&png.as_bytes(), &ParseOptions::default());
+        assert_eq!(kinds(&findings), [FindingKind::BadImageData]);
+        assert_eq!(findings[0].chunk_type, Some(ChunkType::IDAT));
+        assert_eq!(
+            findings[0].message,
+            "The image data inflates to 20 bytes, but a 3x3 8-bit truecolor image needs 30"
+        );
+    }
+
+    #[test]
+    fn test_every_kind_is_explained() {
+        // Declared in order, so the last one's discriminant gives the count
+        assert_eq!(
+            FindingKind::ALL.len(),
+            FindingKind::BadImageData as usize + 1
+        );
+        for (i, kind) in FindingKind::ALL.into_iter().enumerate() {
+            assert_eq!(kind as usize, i);
+            assert_eq!(FindingKind::from_code(kind.code()), Some(kind));
+            assert!(!kind.explanation().is_empty(), "{kind:?}");
+        }
+        assert_eq!(FindingKind::from_code("no-such-code"), None);
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let findings = verify(b"GIF89a", &ParseOptions::default());
+        assert_eq!(kinds(&findings), [FindingKind::BadSignature]);
+    }
+}
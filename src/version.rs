@@ -0,0 +1,40 @@
+//! `pngme --version-json`: what this build can do, for scripts that need to
+//! check before relying on it. Everything comes from the tables the code
+//! itself uses, or from the build script for the features and commit, so
+//! it can't drift from what the binary actually does.
+
+use pngme::hex;
+use pngme::redundant;
+use pngme::transform::{self, Registry};
+use pngme::verify::FindingKind;
+
+/// The cargo features this was built with, as the build script found them.
+pub fn features() -> Vec<&'static str> {
+    env!("PNGME_FEATURES")
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect()
+}
+
+pub fn version_json() -> serde_json::Value {
+    let registry = Registry::default();
+    let transforms: Vec<&str> = registry.names().collect();
+    let codes: Vec<&str> = FindingKind::ALL.iter().map(|kind| kind.code()).collect();
+    serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": option_env!("PNGME_GIT_HASH"),
+        "features": features(),
+        "formats": {
+            "transform_envelope": {
+                "magic": hex::encode(&transform::MAGIC),
+                "transforms": transforms,
+            },
+            "redundant": {
+                "magic": String::from_utf8_lossy(&redundant::MAGIC),
+                "versions": [redundant::VERSION],
+            },
+        },
+        "finding_codes": codes,
+    })
+}
@@ -0,0 +1,286 @@
+//! `pngme watch`: keeps a chunk in every PNG under a directory, putting it
+//! back whenever a file turns up without it.
+
+use crate::{Editable, EngineOptions, Output, Result, context, lock_png_file};
+use notify::{EventKind, RecursiveMode, Watcher};
+use pngme::chunk::Chunk;
+use pngme::message::EncodeOptions;
+use pngme::png::ParseOptions;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// How often the watcher checks for files that have settled and for ctrl-C.
+const TICK: Duration = Duration::from_millis(100);
+
+/// A file that still fails to parse after this many tries, one debounce
+/// period apart, is reported and left alone until it changes again.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The chunk to keep in every file, and how to read and write them.
+pub struct Rule {
+    pub chunk: Chunk,
+    pub options: ParseOptions,
+    pub engine_options: EngineOptions,
+    pub lock_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Added,
+    /// The file already has a chunk of the rule's type, whatever its contents.
+    AlreadyPresent,
+}
+
+impl Rule {
+    /// Appends the rule's chunk to `file` unless it already has one of that type.
+    pub fn apply_if_missing(&self, file: &Path) -> Result<Outcome> {
+        let lock = lock_png_file(file, self.lock_timeout)?;
+        let mut png = Editable::from_file(lock.try_clone()?, &self.options, false)?;
+        if png.contains(self.chunk.chunk_type()) {
+            return Ok(Outcome::AlreadyPresent);
+        }
+        png.encode(vec![self.chunk.clone()], EncodeOptions::default())?;
+        png.write(
+            &Output::InPlace(file.to_path_buf()),
+            &self.engine_options,
+            None,
+        )?;
+        Ok(Outcome::Added)
+    }
+    fn apply_and_log(&self, file: &Path) -> Result<Outcome> {
+        let outcome = self.apply_if_missing(file)?;
+        if outcome == Outcome::Added {
+            println!("{}: added {}", file.display(), self.chunk.chunk_type());
+        }
+        Ok(outcome)
+    }
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+}
+
+/// Every `.png` file under `dir`, sorted. Symlinked directories aren't
+/// followed, so a link loop can't make this run forever.
+pub fn find_pngs(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if is_png(&path) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Applies `rule` to every PNG under `dir` once, reporting failures and
+/// carrying on. Returns how many files were changed and how many failed.
+pub fn run_once(dir: &Path, rule: &Rule) -> Result<(usize, usize)> {
+    let (mut added, mut failed) = (0, 0);
+    for path in find_pngs(dir)? {
+        match rule.apply_and_log(&path) {
+            Ok(Outcome::Added) => added += 1,
+            Ok(Outcome::AlreadyPresent) => {}
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), context::one_line(e.as_ref()));
+                failed += 1;
+            }
+        }
+    }
+    Ok((added, failed))
+}
+
+struct Pending {
+    due: Instant,
+    attempts: u32,
+}
+
+/// Applies `rule` to every PNG under `dir`, then again to each one that is
+/// created or modified, until `stop` is set. A file is only touched once it
+/// has gone `debounce` without changing, and one that doesn't parse is
+/// retried in case it was still being written.
+pub fn watch(dir: &Path, rule: &Rule, debounce: Duration, stop: &AtomicBool) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+    println!(
+        "Watching {} for PNGs without {}",
+        dir.display(),
+        rule.chunk.chunk_type()
+    );
+
+    let now = Instant::now();
+    let mut pending: HashMap<PathBuf, Pending> = find_pngs(dir)?
+        .into_iter()
+        .map(|path| {
+            (
+                path,
+                Pending {
+                    due: now,
+                    attempts: 0,
+                },
+            )
+        })
+        .collect();
+    while !stop.load(Ordering::Relaxed) {
+        match rx.recv_timeout(TICK) {
+            Ok(Ok(event)) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                let due = Instant::now() + debounce;
+                for path in event.paths.into_iter().filter(|p| is_png(p)) {
+                    // Another event restarts the wait and the retry count
+                    pending.insert(path, Pending { due, attempts: 0 });
+                }
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => eprintln!("Watch error: {e}"),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let now = Instant::now();
+        let mut settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, p)| p.due <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        settled.sort();
+        for path in settled {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let Pending { attempts, .. } = pending.remove(&path).unwrap();
+            // Deleted or renamed away before it settled
+            if !path.is_file() {
+                continue;
+            }
+            match rule.apply_and_log(&path) {
+                Ok(_) => {}
+                Err(_) if attempts + 1 < MAX_ATTEMPTS => {
+                    let due = Instant::now() + debounce;
+                    let attempts = attempts + 1;
+                    pending.insert(path, Pending { due, attempts });
+                }
+                Err(e) => eprintln!(
+                    "{}: {}, giving up until it changes",
+                    path.display(),
+                    context::one_line(e.as_ref())
+                ),
+            }
+        }
+    }
+    println!("Stopped watching {}", dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::builder::PngBuilder;
+    use pngme::chunk_type::ChunkType;
+    use pngme::png::Png;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn rule() -> Rule {
+        Rule {
+            chunk: Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"tag".to_vec()),
+            options: ParseOptions::default(),
+            engine_options: EngineOptions::default(),
+            lock_timeout: Duration::ZERO,
+        }
+    }
+
+    fn write_png(path: &Path) {
+        fs::write(path, PngBuilder::new(1, 1).build().unwrap().as_bytes()).unwrap();
+    }
+
+    /// Zero for a file that isn't readable yet, as it may be mid-write.
+    fn tag_count(path: &Path) -> usize {
+        let Ok(png) = Png::try_from(fs::read(path).unwrap_or_default().as_slice()) else {
+            return 0;
+        };
+        png.chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "ruSt")
+            .count()
+    }
+
+    #[test]
+    fn test_apply_if_missing() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.png");
+        write_png(&path);
+        assert_eq!(rule().apply_if_missing(&path).unwrap(), Outcome::Added);
+        assert_eq!(
+            rule().apply_if_missing(&path).unwrap(),
+            Outcome::AlreadyPresent
+        );
+        assert_eq!(tag_count(&path), 1);
+    }
+
+    #[test]
+    fn test_partial_file_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.png");
+        let bytes = PngBuilder::new(1, 1).build().unwrap().as_bytes();
+        fs::write(&path, &bytes[..bytes.len() - 6]).unwrap();
+        assert!(rule().apply_if_missing(&path).is_err());
+        assert!(
+            rule()
+                .apply_if_missing(&dir.path().join("gone.png"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_run_once_recurses() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let nested = dir.path().join("sub/b.PNG");
+        write_png(&dir.path().join("a.png"));
+        write_png(&nested);
+        fs::write(dir.path().join("notes.txt"), b"not a png").unwrap();
+        fs::write(dir.path().join("broken.png"), b"not a png").unwrap();
+        assert_eq!(run_once(dir.path(), &rule()).unwrap(), (2, 1));
+        assert_eq!(tag_count(&nested), 1);
+        assert_eq!(run_once(dir.path(), &rule()).unwrap(), (0, 1));
+    }
+
+    #[test]
+    fn test_watch_smoke() {
+        let dir = TempDir::new().unwrap();
+        let existing = dir.path().join("existing.png");
+        write_png(&existing);
+        let stop = Arc::new(AtomicBool::new(false));
+        let watcher = {
+            let (dir, stop) = (dir.path().to_path_buf(), stop.clone());
+            std::thread::spawn(move || {
+                watch(&dir, &rule(), Duration::from_millis(50), &stop).unwrap()
+            })
+        };
+
+        let created = dir.path().join("created.png");
+        // Give the watcher time to start before making the new file
+        std::thread::sleep(Duration::from_millis(200));
+        write_png(&created);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline && tag_count(&created) == 0 {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        stop.store(true, Ordering::Relaxed);
+        watcher.join().unwrap();
+        assert_eq!(tag_count(&existing), 1);
+        assert_eq!(tag_count(&created), 1);
+    }
+}
@@ -0,0 +1,153 @@
+use pngme::builder::PngBuilder;
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::png::{ParseOptions, Png};
+use pngme::rewrite::Rewrite;
+use pngme::stamp::PngTemplate;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// Bytes allocated and not yet freed.
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The counters are global, so tests measuring them take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn allocations_during<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let result = f();
+    let after = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(result);
+    after - before
+}
+
+#[test]
+fn borrowed_parse_does_not_copy_chunk_data() {
+    let _serial = SERIAL.lock().unwrap();
+    let mut builder = PngBuilder::new(16, 16);
+    for i in 0..100 {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        builder = builder.with_chunk(Chunk::new(chunk_type, format!("payload {i}").into_bytes()));
+    }
+    let bytes = builder.build().unwrap().as_bytes();
+
+    let owned = allocations_during(|| Png::try_from(bytes.as_slice()).unwrap());
+    let borrowed = allocations_during(|| Png::parse_borrowed(&bytes).unwrap());
+
+    // One allocation per chunk payload at the very least
+    assert!(owned >= 103, "owned parse made {owned} allocations");
+    // Only the growth of the result Vec itself
+    assert!(borrowed <= 10, "borrowed parse made {borrowed} allocations");
+}
+
+fn bytes_allocated_during<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let result = f();
+    let after = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    drop(result);
+    after - before
+}
+
+#[test]
+fn copy_through_rewrite_does_not_hold_image_data() {
+    let _serial = SERIAL.lock().unwrap();
+    let idat = Chunk::new(ChunkType::IDAT, vec![0x5a; 16 * 1024 * 1024]);
+    let bytes = Png::from_chunks(vec![idat, Chunk::new(ChunkType::IEND, Vec::new())]).as_bytes();
+
+    let allocated = bytes_allocated_during(|| {
+        let mut rewrite = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap();
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        rewrite.insert_before_iend(Chunk::new(chunk_type, b"hello".to_vec()));
+        rewrite
+            .write_to(&mut std::io::Cursor::new(&bytes), &mut std::io::sink())
+            .unwrap();
+    });
+    assert!(
+        allocated < 1024 * 1024,
+        "rewrite allocated {allocated} bytes"
+    );
+}
+
+#[test]
+fn chunk_data_reader_streams_from_the_input() {
+    let _serial = SERIAL.lock().unwrap();
+    let chunk_type = ChunkType::from_str("ruSt").unwrap();
+    let payload = Chunk::new(chunk_type, vec![0x5a; 16 * 1024 * 1024]);
+    let bytes = PngBuilder::new(1, 1)
+        .with_chunk(payload)
+        .build()
+        .unwrap()
+        .as_bytes();
+
+    let allocated = bytes_allocated_during(|| {
+        let rewrite = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap();
+        let mut input = std::io::Cursor::new(&bytes);
+        let mut reader = rewrite.chunk_data_reader(2, &mut input).unwrap().unwrap();
+        let copied = std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(copied, 16 * 1024 * 1024);
+    });
+    assert!(
+        allocated < 1024 * 1024,
+        "reading the payload allocated {allocated} bytes"
+    );
+}
+
+#[test]
+fn stamping_does_not_copy_the_template() {
+    let _serial = SERIAL.lock().unwrap();
+    let idat = Chunk::new(ChunkType::IDAT, vec![0x5a; 1024 * 1024]);
+    let png = Png::from_chunks(vec![idat, Chunk::new(ChunkType::IEND, Vec::new())]);
+    let template = PngTemplate::new(&png);
+    drop(png);
+    let chunk_type = ChunkType::from_str("usEr").unwrap();
+    let extra = [Chunk::new(chunk_type, b"user 1".to_vec())];
+    let mut naive = Png::try_from(template.as_bytes()).unwrap();
+    naive.append_chunks(extra.to_vec());
+    let expected = naive.as_bytes();
+    drop(naive);
+
+    // Written straight out, nothing the size of the image is allocated
+    let allocated = bytes_allocated_during(|| {
+        for _ in 0..1000 {
+            template.stamp_to(&extra, &mut std::io::sink()).unwrap();
+        }
+    });
+    assert!(
+        allocated < 64 * 1024,
+        "1000 stamps allocated {allocated} bytes"
+    );
+
+    // Each stamp allocates its output and the new chunk's bytes, no more,
+    // and none of it outlives the output
+    let live = LIVE_BYTES.load(Ordering::Relaxed);
+    let allocated = bytes_allocated_during(|| {
+        for _ in 0..1000 {
+            assert_eq!(template.stamp(&extra), expected);
+        }
+    });
+    let per_stamp = expected.len() + extra[0].serialized_len();
+    assert_eq!(allocated, 1000 * per_stamp);
+    assert_eq!(LIVE_BYTES.load(Ordering::Relaxed), live);
+}
@@ -0,0 +1,3751 @@
+use pngme::builder::PngBuilder;
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::format::format_crc;
+use pngme::png::Png;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::str::FromStr;
+use tempfile::TempDir;
+
+fn pngme(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn pngme_with_stdin(args: &[&str], stdin: &[u8]) -> Output {
+    use std::io::Write;
+    use std::process::Stdio;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn write_fixture(dir: &TempDir, name: &str, chunks: &[(&str, &str)]) -> PathBuf {
+    let mut builder = PngBuilder::new(2, 2);
+    for (chunk_type, data) in chunks {
+        let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+        builder = builder.with_chunk(Chunk::new(chunk_type, data.as_bytes().to_vec()));
+    }
+    let path = dir.path().join(name);
+    fs::write(&path, builder.build().unwrap().as_bytes()).unwrap();
+    path
+}
+
+fn read_png(path: &Path) -> Png {
+    Png::try_from(fs::read(path).unwrap().as_slice()).unwrap()
+}
+
+fn chunk_summary(png: &Png) -> Vec<(String, String)> {
+    png.chunks()
+        .iter()
+        .map(|c| {
+            (
+                c.chunk_type().to_string(),
+                String::from_utf8_lossy(c.data()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Reads back comma- or tab-separated records, undoing the quoting.
+fn parse_delimited(text: &str, separator: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let (mut record, mut field) = (Vec::new(), String::new());
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => record.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    records
+}
+
+#[test]
+fn remove_by_index_keeps_order() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("ruSt", "one"), ("ruSt", "two"), ("ruSt", "three")],
+    );
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["remove", file, "--index", "3"]);
+    assert!(output.status.success());
+
+    let summary = chunk_summary(&read_png(&path));
+    let private: Vec<&str> = summary
+        .iter()
+        .filter(|(t, _)| t == "ruSt")
+        .map(|(_, d)| d.as_str())
+        .collect();
+    assert_eq!(private, ["one", "three"]);
+    assert_eq!(summary.last().unwrap().0, "IEND");
+}
+
+#[test]
+fn remove_by_index_out_of_range() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let before = fs::read(&path).unwrap();
+
+    let output = pngme(&["remove", path.to_str().unwrap(), "--index", "3"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of range"));
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn remove_critical_by_index_needs_force() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    assert!(!pngme(&["remove", file, "--index", "2"]).status.success());
+    assert_eq!(read_png(&path).chunks().len(), 3);
+
+    assert!(
+        pngme(&["remove", file, "--index", "2", "--force"])
+            .status
+            .success()
+    );
+    assert_eq!(read_png(&path).chunks().len(), 2);
+}
+
+#[test]
+fn files_without_ihdr_or_iend_need_allow_invalid() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let out = dir.path().join("out.png");
+    let out = out.to_str().unwrap();
+    // IEND is the last 12 bytes
+    let bytes = fs::read(&path).unwrap();
+    fs::write(&path, &bytes[..bytes.len() - 12]).unwrap();
+
+    for output in ["-", out, file] {
+        let result = pngme(&["encode", file, "ruSt", "hi", "--output", output]);
+        assert!(!result.status.success());
+        let stderr = String::from_utf8(result.stderr).unwrap();
+        assert!(stderr.contains("pass --allow-invalid"), "{stderr}");
+        assert!(stderr.contains("There is no IEND chunk"), "{stderr}");
+    }
+    assert_eq!(fs::read(&path).unwrap(), &bytes[..bytes.len() - 12]);
+    assert!(!Path::new(out).exists());
+
+    let result = pngme(&["encode", file, "ruSt", "hi", "--allow-invalid"]);
+    assert!(result.status.success(), "{result:?}");
+    assert!(read_png(&path).chunk_by_type("ruSt").is_some());
+
+    // Removing IHDR with --force means to break the file
+    let path = write_fixture(&dir, "b.png", &[]);
+    let file = path.to_str().unwrap();
+    let result = pngme(&["remove", file, "--index", "0", "--force"]);
+    assert!(result.status.success(), "{result:?}");
+    let result = pngme(&["encode", file, "ruSt", "hi"]);
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(
+        stderr.contains("The first chunk is IDAT, not IHDR"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn unquoted_messages_are_refused_not_split() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let before = fs::read(&path).unwrap();
+
+    // Spare words used to be taken as the output path, or failed in clap
+    for args in [
+        &["encode", file, "ruSt", "my", "message", "with", "spaces"][..],
+        &["encode", file, "--redundant", "2", "my", "message"],
+        &["encode", file, "--type-hex", "0xab424344", "my", "message"],
+    ] {
+        let output = pngme(args);
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("caused by: Too many arguments"), "{stderr}");
+        assert!(stderr.contains("quote it, as in \"my message"), "{stderr}");
+    }
+    assert_eq!(fs::read(&path).unwrap(), before);
+    assert!(!dir.path().join("message").exists());
+
+    // The positional output still works, with a warning
+    let out = dir.path().join("out.png");
+    let output = pngme(&["encode", file, "ruSt", "hi", out.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Warning: giving the output after the message is deprecated; use -o"),
+        "{stderr}"
+    );
+    assert!(read_png(&out).chunk_by_type("ruSt").is_some());
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    let output = pngme(&["encode", file, "ruSt", "hi", "-o", out.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn remove_index_conflicts_with_chunktype() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let output = pngme(&["remove", path.to_str().unwrap(), "ruSt", "--index", "1"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn encode_multiple_chunks_in_one_write() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        "msg1",
+        "--chunk",
+        "teXt=msg2",
+        "--chunk",
+        "abCd=a=b",
+    ]);
+    assert!(output.status.success());
+
+    let summary = chunk_summary(&read_png(&path));
+    let added: Vec<(&str, &str)> = summary
+        .iter()
+        .filter(|(t, _)| !["IHDR", "IDAT", "IEND"].contains(&t.as_str()))
+        .map(|(t, d)| (t.as_str(), d.as_str()))
+        .collect();
+    assert_eq!(added, [("ruSt", "msg1"), ("teXt", "msg2"), ("abCd", "a=b")]);
+    let entries: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+    assert_eq!(entries.len(), 1, "temporary files were left behind");
+}
+
+#[test]
+fn encode_conventional_places_chunks_by_type() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        "hidden",
+        "--chunk",
+        "tEXt=Comment",
+        "--chunk",
+        "fdAT=frame",
+        "--chunk",
+        "gAMA=gama",
+        "--conventional",
+    ]);
+    assert!(output.status.success());
+    let types: Vec<String> = chunk_summary(&read_png(&path))
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect();
+    assert_eq!(
+        types,
+        ["IHDR", "gAMA", "ruSt", "tEXt", "IDAT", "fdAT", "IEND"]
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("+ ruSt (6 bytes) at index 2\n"), "{stdout}");
+    assert!(
+        stdout.contains("+ fdAT (5 bytes) at index 5 (before IEND)\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn wildcard_messages_are_kept_even_when_files_match() {
+    let dir = TempDir::new().unwrap();
+    write_fixture(&dir, "a.png", &[]);
+    write_fixture(&dir, "b.png", &[]);
+    let output = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .current_dir(dir.path())
+        .args(["encode", "a.png", "ruSt", "*", "--kv", "ruSu:?.png"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let png = read_png(&dir.path().join("a.png"));
+    assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"*");
+    assert_eq!(png.chunk_by_type("ruSu").unwrap().data(), b"?.png");
+    assert_eq!(read_png(&dir.path().join("b.png")).chunk_count(), 3);
+}
+
+#[test]
+fn encode_type_and_message_as_one_argument() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "--kv",
+        "ruSt:hello world",
+        "--kv",
+        "teXt:at 12:30: lunch",
+    ]);
+    assert!(output.status.success());
+    let summary = chunk_summary(&read_png(&path));
+    assert_eq!(summary[2], ("ruSt".into(), "hello world".into()));
+    assert_eq!(summary[3], ("teXt".into(), "at 12:30: lunch".into()));
+
+    let output = pngme(&["encode", file, "--kv", "ruSt hello"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'ruSt hello'"), "{stderr}");
+    assert_eq!(read_png(&path).chunk_count(), 5);
+}
+
+#[test]
+fn encode_files_listed_on_stdin() {
+    let dir = TempDir::new().unwrap();
+    let a = write_fixture(&dir, "a.png", &[]);
+    let b = write_fixture(&dir, "b c.png", &[]);
+    let c = write_fixture(&dir, "c.png", &[]);
+    let missing = dir.path().join("missing.png");
+    let list = format!(
+        "# generated\n{}\n{}\r\n\n{}\n{}\n",
+        a.display(),
+        b.display(),
+        missing.display(),
+        c.display()
+    );
+
+    let args = ["encode", "--kv", "ruSt:tag", "--files-from", "-"];
+    let output = pngme_with_stdin(&args, list.as_bytes());
+    assert!(!output.status.success());
+    for path in [&a, &b, &c] {
+        let summary = chunk_summary(&read_png(path));
+        assert_eq!(
+            summary[2],
+            ("ruSt".into(), "tag".into()),
+            "{}",
+            path.display()
+        );
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("{}: No such file", missing.display())),
+        "{stderr}"
+    );
+    assert!(stderr.contains("1 of 4 files failed"), "{stderr}");
+
+    let list = format!("{}\0{}\0", a.display(), b.display());
+    let output = pngme_with_stdin(&["verify", "--files-from", "-", "--null"], list.as_bytes());
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches(": OK").count(), 2, "{stdout}");
+}
+
+#[test]
+fn encode_files_from_takes_the_chunk_type_first() {
+    let dir = TempDir::new().unwrap();
+    let a = write_fixture(&dir, "a.png", &[]);
+    let b = write_fixture(&dir, "b.png", &[]);
+    let list = format!("{}\n{}\n", a.display(), b.display());
+
+    let output = pngme_with_stdin(
+        &["encode", "--files-from", "-", "ruSt", "hi"],
+        list.as_bytes(),
+    );
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for path in [&a, &b] {
+        assert_eq!(
+            chunk_summary(&read_png(path))[2],
+            ("ruSt".into(), "hi".into())
+        );
+        assert!(
+            stdout.contains(&format!("{}: + ruSt", path.display())),
+            "{stdout}"
+        );
+        assert!(
+            stdout.contains(&format!("{}: Size: ", path.display())),
+            "{stdout}"
+        );
+    }
+
+    let args = [
+        "encode",
+        "--files-from",
+        "-",
+        "--type-hex",
+        "0xab424344",
+        "odd",
+    ];
+    let output = pngme_with_stdin(&args, list.as_bytes());
+    assert!(output.status.success(), "{output:?}");
+    let output = pngme(&["decode", a.to_str().unwrap(), "--type-hex", "0xab424344"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "odd\n");
+
+    let output = pngme_with_stdin(&["encode", "--files-from", "-", "ruSt"], list.as_bytes());
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Give the chunk type and the message"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn encode_invalid_chunk_aborts_before_writing() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let before = fs::read(&path).unwrap();
+
+    let output = pngme(&[
+        "encode",
+        path.to_str().unwrap(),
+        "--chunk",
+        "ruSt=fine",
+        "--chunk",
+        "r1St=broken",
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn encode_in_place_failure_leaves_original() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let before = fs::read(&path).unwrap();
+    // A directory squatting on the temp file's name makes creating it fail
+    fs::create_dir(dir.path().join(".a.png.pngme-tmp")).unwrap();
+
+    let output = pngme(&["encode", path.to_str().unwrap(), "ruSt", "hello"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("create temporary file"), "{stderr}");
+    assert!(stderr.contains(".a.png.pngme-tmp"), "{stderr}");
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[cfg(unix)]
+#[test]
+fn write_failure_prints_the_cause_chain() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let shots = dir.path().join("shots");
+    fs::create_dir(&shots).unwrap();
+    fs::set_permissions(&shots, fs::Permissions::from_mode(0o555)).unwrap();
+    // Root ignores the permissions, so squat on the temporary file's name too
+    let enforced = fs::write(shots.join("probe"), b"").is_err();
+    if !enforced {
+        fs::create_dir(shots.join(".out.png.pngme-tmp")).unwrap();
+    }
+    let out = shots.join("out.png");
+
+    let file = path.to_str().unwrap();
+    let output = pngme(&["encode", file, "ruSt", "hi", "-o", out.to_str().unwrap()]);
+    fs::set_permissions(&shots, fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 4, "{stderr}");
+    assert_eq!(
+        lines[0],
+        format!(
+            "error: failed to encode chunk 'ruSt' into \"{}\"",
+            path.display()
+        )
+    );
+    assert_eq!(
+        lines[1],
+        format!("caused by: failed to write output \"{}\"", out.display())
+    );
+    assert_eq!(
+        lines[2],
+        format!(
+            "caused by: failed to create temporary file \"{}\"",
+            shots.join(".out.png.pngme-tmp").display()
+        )
+    );
+    if enforced {
+        assert!(
+            lines[3].starts_with("caused by: Permission denied"),
+            "{stderr}"
+        );
+    } else {
+        assert!(lines[3].starts_with("caused by: "), "{stderr}");
+    }
+}
+
+#[test]
+fn backup_keeps_the_file_being_replaced() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let original = fs::read(&path).unwrap();
+    let file = path.to_str().unwrap();
+    assert!(
+        pngme(&["encode", file, "ruSt", "hi", "--backup", ".bak"])
+            .status
+            .success()
+    );
+    assert_eq!(fs::read(dir.path().join("a.png.bak")).unwrap(), original);
+    assert_eq!(read_png(&path).chunk_count(), 4);
+}
+
+#[test]
+fn list_and_stats_as_csv_and_tsv() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "one"), ("ruSt", "three")]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["list", file, "--format", "csv"]);
+    assert!(output.status.success());
+    let records = parse_delimited(&String::from_utf8(output.stdout).unwrap(), ',');
+    assert_eq!(
+        records[0],
+        [
+            "index",
+            "type",
+            "length",
+            "crc_hex",
+            "offset",
+            "critical",
+            "safe_to_copy"
+        ]
+    );
+    assert_eq!(records.len(), 6);
+    let png = read_png(&path);
+    let rust = &png.chunks()[3];
+    assert_eq!(
+        records[4],
+        [
+            "3".to_string(),
+            "ruSt".into(),
+            "5".into(),
+            format!("0x{:08x}", rust.crc()),
+            png.chunk_offsets()[3].to_string(),
+            "false".into(),
+            "true".into(),
+        ]
+    );
+
+    let output = pngme(&["stats", file, "--format", "tsv", "--no-header"]);
+    assert!(output.status.success());
+    let records = parse_delimited(&String::from_utf8(output.stdout).unwrap(), '\t');
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[2][..3], ["ruSt", "2", "32"]);
+    let percent: f64 = records.iter().map(|r| r[3].parse::<f64>().unwrap()).sum();
+    let expected = (png.total_size() - 8) as f64 * 100.0 / png.total_size() as f64;
+    assert!((percent - expected).abs() < 0.05, "{percent}");
+
+    let output = pngme(&["stats", file, "--json", "--format", "csv"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
+
+#[test]
+fn list_digests_match_across_files() {
+    let dir = TempDir::new().unwrap();
+    let a = write_fixture(&dir, "a.png", &[("ruSt", "abc"), ("tEXt", "a")]);
+    let b = write_fixture(&dir, "b.png", &[("ruSt", "abc"), ("tEXt", "b")]);
+    let digests = |path: &Path, args: &[&str]| -> Vec<String> {
+        let mut all = vec!["list", path.to_str().unwrap(), "--format", "csv"];
+        all.extend(args);
+        let output = pngme(&all);
+        assert!(output.status.success());
+        let records = parse_delimited(&String::from_utf8(output.stdout).unwrap(), ',');
+        records.iter().map(|r| r.last().unwrap().clone()).collect()
+    };
+
+    let column = digests(&a, &["--digest", "sha256"]);
+    assert_eq!(column[0], "sha256");
+    assert_eq!(column[3], "ba7816bf");
+    let column = digests(&a, &["--digest", "sha256", "--long-digest"]);
+    assert_eq!(
+        column[3],
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(digests(&a, &["--digest", "blake3"])[3], "6437b3ac");
+    assert_eq!(digests(&a, &["--digest", "crc32"])[3], "352441c2");
+
+    // Everything but the tEXt chunk holds the same data in both
+    let (a, b) = (
+        digests(&a, &["--digest", "blake3"]),
+        digests(&b, &["--digest", "blake3"]),
+    );
+    let same: Vec<bool> = a.iter().zip(&b).map(|(a, b)| a == b).collect();
+    assert_eq!(same, [true, true, true, true, false, true]);
+
+    let output = pngme(&["list", "x.png", "--long-digest"]);
+    assert!(!output.status.success());
+    let output = pngme(&["list", "x.png", "--digest", "md5"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected sha256"));
+}
+
+#[test]
+fn select_chunks_by_combined_predicates() {
+    let dir = TempDir::new().unwrap();
+    let large = "x".repeat(5000);
+    let chunks = [
+        ("ruSt", "small"),
+        ("ruSt", large.as_str()),
+        ("tEXt", "k\0v"),
+    ];
+    let path = write_fixture(&dir, "a.png", &chunks);
+    let file = path.to_str().unwrap();
+    let listed = |select: &str| -> Vec<String> {
+        let output = pngme(&["list", file, "--format", "{index}", "--select", select]);
+        assert!(output.status.success(), "{select}");
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    };
+    assert_eq!(listed("private"), ["2", "3"]);
+    assert_eq!(listed("private,larger-than=4096"), ["3"]);
+    assert_eq!(listed("ancillary, public"), ["4"]);
+    assert_eq!(listed("type=ruSt,index=2"), ["2"]);
+    assert!(listed("critical,private").is_empty());
+
+    let output = pngme(&["decode", file, "--select", "private,larger-than=10"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, format!("{large}\n").as_bytes());
+
+    let output = pngme(&["list", file, "--select", "private,larger-than=big"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("'big' isn't a number"), "{stderr}");
+    assert!(
+        stderr.contains("  private,larger-than=big\n          ^^^^^^^^^^^^^^^"),
+        "{stderr}"
+    );
+
+    // Critical chunks need --force, as with any other way of removing them
+    let output = pngme(&["remove", file, "--select", "larger-than=4"]);
+    assert!(!output.status.success());
+    assert_eq!(read_png(&path).chunk_count(), 6);
+
+    let output = pngme(&["strip", file, "--select", "larger-than=4"]);
+    assert!(output.status.success());
+    let types: Vec<String> = chunk_summary(&read_png(&path))
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect();
+    assert_eq!(types, ["IHDR", "IDAT", "tEXt", "IEND"]);
+
+    let output = pngme(&["remove", file, "--select", "public,ancillary"]);
+    assert!(output.status.success());
+    assert_eq!(read_png(&path).chunk_count(), 3);
+}
+
+#[test]
+fn non_png_input_names_its_format() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("photo.png");
+    fs::write(
+        &path,
+        [0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10, b'J', b'F', b'I', b'F'],
+    )
+    .unwrap();
+    let output = pngme(&["list", path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("input is a JPEG (FF D8 FF), not a PNG"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn piped_decode_keeps_control_characters() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "\x1b[31mred\x1b[0m\rover")]);
+    for args in [
+        vec!["decode", path.to_str().unwrap(), "ruSt"],
+        vec!["decode", path.to_str().unwrap(), "ruSt", "--no-sanitize"],
+    ] {
+        let output = pngme(&args);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"\x1b[31mred\x1b[0m\rover\n");
+        assert!(output.stderr.is_empty());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn rewrite_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    for mode in [0o644, 0o640, 0o600] {
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        let output = pngme(&["encode", path.to_str().unwrap(), "ruSt", "hi"]);
+        assert!(output.status.success());
+        let kept = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(kept, mode, "{mode:o}");
+    }
+}
+
+#[test]
+fn rewrite_keeps_mtime_with_preserve_timestamps() {
+    use std::time::{Duration, SystemTime};
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let set_mtime = || {
+        let f = fs::File::options().write(true).open(&path).unwrap();
+        f.set_modified(old).unwrap();
+    };
+    let mtime = || fs::metadata(&path).unwrap().modified().unwrap();
+
+    set_mtime();
+    assert!(
+        pngme(&["encode", file, "ruSt", "hi", "--preserve-timestamps"])
+            .status
+            .success()
+    );
+    assert_eq!(mtime(), old);
+    assert_eq!(read_png(&path).chunk_count(), 4);
+
+    assert!(pngme(&["encode", file, "ruSt", "hi"]).status.success());
+    assert_ne!(mtime(), old);
+}
+
+fn insert_junk(path: &Path, at: usize, junk: &[u8]) {
+    let mut bytes = fs::read(path).unwrap();
+    bytes.splice(at..at, junk.iter().copied());
+    fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn lenient_verify_reports_stray_bytes() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    insert_junk(&path, 8, &[1, 2, 3]);
+    let file = path.to_str().unwrap();
+
+    let strict = pngme(&["verify", file]);
+    assert!(!strict.status.success());
+
+    let lenient = pngme(&["verify", file, "--lenient"]);
+    assert!(!lenient.status.success());
+    let stdout = String::from_utf8_lossy(&lenient.stdout);
+    assert!(stdout.contains("3 stray bytes at offset 8"), "{stdout}");
+
+    let decoded = pngme(&["--lenient", "decode", file, "ruSt"]);
+    assert!(decoded.status.success());
+    assert_eq!(String::from_utf8_lossy(&decoded.stdout), "hello\n");
+}
+
+#[test]
+fn lenient_repair_drops_stray_bytes() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let good = fs::read(&path).unwrap();
+    insert_junk(&path, 8, &[0xee; 8]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["--lenient", "repair", file]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Dropped 8 stray bytes at offset 8"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("no CRC errors found"), "{stdout}");
+    assert_eq!(fs::read(&path).unwrap(), good);
+    assert!(pngme(&["verify", file, "--lenient"]).status.success());
+}
+
+#[test]
+fn garbled_chunk_types_fail_cleanly_or_list_leniently() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let garbled = ChunkType::from_bytes_unchecked([0x00, 0x41, 0x42, 0x43]);
+    let at = fs::read(&path).unwrap().len() - 12;
+    insert_junk(&path, at, &Chunk::new(garbled, b"abc".to_vec()).as_bytes());
+    let file = path.to_str().unwrap();
+
+    let strict = pngme(&["list", file]);
+    assert_eq!(strict.status.code(), Some(1));
+    let stderr = String::from_utf8(strict.stderr).unwrap();
+    assert!(
+        stderr.contains("Invalid chunk type 00 41 42 43: Byte 0 of the chunk type is 0x00"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("panicked"), "{stderr}");
+
+    let lenient = pngme(&["--lenient", "list", file]);
+    assert!(lenient.status.success(), "{lenient:?}");
+    let stdout = String::from_utf8(lenient.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 5, "{stdout}");
+    assert!(stdout.contains("\\x00ABC"), "{stdout}");
+}
+
+#[test]
+fn max_chunk_size_rejects_large_chunks() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "a fairly long message")]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["list", file, "--max-chunk-size", "16"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("more than the maximum of 16"), "{stderr}");
+
+    assert!(pngme(&["list", file]).status.success());
+}
+
+#[test]
+fn empty_message_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    assert!(pngme(&["encode", file, "ruSt", ""]).status.success());
+    let chunk = read_png(&path)
+        .chunks()
+        .iter()
+        .find(|c| c.chunk_type().to_string() == "ruSt")
+        .cloned()
+        .unwrap();
+    assert_eq!(chunk.length(), 0);
+    assert_eq!(chunk.as_bytes().len(), 12);
+
+    let list = pngme(&["list", file]);
+    assert!(String::from_utf8_lossy(&list.stdout).contains("ruSt           0"));
+
+    let decoded = pngme(&["decode", file, "ruSt"]);
+    assert!(decoded.status.success());
+    assert_eq!(decoded.stdout, b"\n");
+
+    let raw = pngme(&["decode", file, "ruSt", "--raw"]);
+    assert!(raw.status.success());
+    assert!(raw.stdout.is_empty());
+
+    assert!(pngme(&["print", file]).status.success());
+    assert!(pngme(&["remove", file, "ruSt"]).status.success());
+    assert!(
+        read_png(&path)
+            .chunks()
+            .iter()
+            .all(|c| c.chunk_type().to_string() != "ruSt")
+    );
+}
+
+#[test]
+fn list_shows_offsets() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let output = pngme(&["list", path.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    // Signature, then IHDR with 13 bytes of data
+    assert!(
+        lines[0].contains("         8  0x00000008  IHDR"),
+        "{stdout}"
+    );
+    assert!(
+        lines[1].contains("        33  0x00000021  IDAT"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn strip_unsafe_only() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("ruSt", "safe"), ("ruST", "unsafe"), ("tEXt", "text")],
+    );
+    let file = path.to_str().unwrap();
+
+    assert!(pngme(&["strip", file, "--unsafe-only"]).status.success());
+    let types: Vec<String> = chunk_summary(&read_png(&path))
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect();
+    assert_eq!(types, ["IHDR", "IDAT", "ruSt", "tEXt", "IEND"]);
+
+    assert!(pngme(&["strip", file]).status.success());
+    assert_eq!(read_png(&path).chunks().len(), 3);
+}
+
+#[test]
+fn init_creates_decodable_png() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("carrier.png");
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&[
+        "init",
+        file,
+        "--width",
+        "3",
+        "--height",
+        "2",
+        "--color",
+        "8bit-rgba",
+        "--fill",
+        "ff000080",
+    ]);
+    assert!(output.status.success());
+
+    let decoder = png::Decoder::new(std::io::Cursor::new(fs::read(&path).unwrap()));
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!((info.width, info.height), (3, 2));
+    assert_eq!(info.color_type, png::ColorType::Rgba);
+    assert_eq!(&buf[..info.buffer_size()], [255, 0, 0, 128].repeat(6));
+
+    assert!(pngme(&["verify", file]).status.success());
+    assert!(pngme(&["encode", file, "ruSt", "hello"]).status.success());
+    // Refuses to clobber the carrier without --force
+    assert!(!pngme(&["init", file]).status.success());
+}
+
+#[test]
+fn init_rejects_bad_options() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("a.png");
+    let file = file.to_str().unwrap();
+    assert!(!pngme(&["init", file, "--width", "0"]).status.success());
+    assert!(!pngme(&["init", file, "--width", "5000"]).status.success());
+    let output = pngme(&["init", file, "--color", "8bit-rgb", "--fill", "ff000080"]);
+    assert!(!output.status.success());
+    assert!(!Path::new(file).exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn in_place_encode_writes_through_symlink() {
+    let dir = TempDir::new().unwrap();
+    let target = write_fixture(&dir, "target.png", &[]);
+    let link = dir.path().join("link.png");
+    std::os::unix::fs::symlink("target.png", &link).unwrap();
+
+    let output = pngme(&["encode", link.to_str().unwrap(), "ruSt", "hello"]);
+    assert!(output.status.success());
+
+    assert!(
+        fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink()
+    );
+    assert!(chunk_summary(&read_png(&target)).contains(&("ruSt".into(), "hello".into())));
+}
+
+#[cfg(unix)]
+#[test]
+fn no_follow_symlinks_replaces_link() {
+    let dir = TempDir::new().unwrap();
+    let target = write_fixture(&dir, "target.png", &[]);
+    let before = fs::read(&target).unwrap();
+    let link = dir.path().join("link.png");
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let link_arg = link.to_str().unwrap();
+    let output = pngme(&["encode", link_arg, "ruSt", "hello", "--no-follow-symlinks"]);
+    assert!(output.status.success());
+
+    assert!(fs::symlink_metadata(&link).unwrap().is_file());
+    assert_eq!(fs::read(&target).unwrap(), before);
+}
+
+#[cfg(unix)]
+#[test]
+fn special_and_missing_inputs_are_rejected() {
+    let dir = TempDir::new().unwrap();
+    let dangling = dir.path().join("dangling.png");
+    std::os::unix::fs::symlink("missing.png", &dangling).unwrap();
+    let fifo = dir.path().join("fifo.png");
+    assert!(
+        Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    for (path, reason) in [
+        (&dangling, "No such file"),
+        (&fifo, "Not a regular file"),
+        (&dir.path().to_path_buf(), "Is a directory"),
+    ] {
+        let output = pngme(&["list", path.to_str().unwrap()]);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(reason),
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn encode_reports_size_as_json() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let before = fs::metadata(&path).unwrap().len();
+
+    let output = pngme(&["encode", path.to_str().unwrap(), "ruSt", "hi", "--json"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["before"], before);
+    assert_eq!(report["after"], fs::metadata(&path).unwrap().len());
+    assert_eq!(report["payload"], 2);
+    assert!(report["growth_percent"].as_f64().unwrap() > 0.0);
+    assert!(report["payload_percent"].as_f64().unwrap() > 0.0);
+}
+
+#[test]
+fn encode_growth_threshold() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let before = fs::read(&path).unwrap();
+    let big = "x".repeat(before.len());
+
+    // Over the warning threshold, but under the hard limit
+    let output = pngme(&["encode", file, "ruSt", &big, "--max-growth", "500"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning"));
+
+    let output = pngme(&["encode", file, "ruSt", &big, "--max-growth", "10"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--max-growth"));
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        &big,
+        "--max-growth",
+        "10",
+        "--force",
+    ]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn verify_json_findings() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let mut bytes = fs::read(&path).unwrap();
+    let offset = Png::parse_borrowed(&bytes).unwrap()[2].offset();
+    // Corrupt ruSt's data, "hello" -> "iello", so its CRC no longer matches
+    bytes[offset + 8] ^= 1;
+    let crc_of = |data: &str| {
+        format_crc(Chunk::new(ChunkType::from_str("ruSt").unwrap(), data.into()).crc())
+    };
+    fs::write(&path, bytes).unwrap();
+
+    let output = pngme(&["verify", path.to_str().unwrap(), "--json"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        report["findings"],
+        serde_json::json!([{
+            "severity": "error",
+            "code": "crc-mismatch",
+            "chunk_index": 2,
+            "chunk_type": "ruSt",
+            "offset": offset,
+            "message": format!(
+                "ruSt at offset {offset} (0x{offset:x}) has CRC {} but its contents give {}",
+                crc_of("hello"),
+                crc_of("iello"),
+            ),
+        }])
+    );
+    assert_eq!(
+        report["summary"],
+        serde_json::json!({"errors": 1, "warnings": 0, "ok": false})
+    );
+}
+
+#[test]
+fn verify_strict_fails_on_warnings() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let mut bytes = fs::read(&path).unwrap();
+    bytes.extend_from_slice(b"appended");
+    fs::write(&path, bytes).unwrap();
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["verify", file]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("warning[trailing-data]"));
+    assert!(!pngme(&["verify", file, "--strict"]).status.success());
+}
+
+#[test]
+fn tags_lists_and_filters() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[
+            ("tEXt", "Software\0pngme"),
+            ("tEXt", "Comment\0first"),
+            ("iTXt", "Comment\0\0\0\0\0second ✓"),
+        ],
+    );
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["tags", file]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Software: pngme  (tEXt)\nComment: first  (tEXt)\nComment: second ✓  (iTXt)\n"
+    );
+
+    let output = pngme(&["tags", file, "--keyword", "Comment"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "first\nsecond ✓\n");
+
+    let output = pngme(&["tags", file, "--json", "--keyword", "Software"]);
+    let entries: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(entries[0]["chunk_type"], "tEXt");
+    assert_eq!(entries[0]["chunk_index"], 2);
+    assert_eq!(entries[0]["compressed"], false);
+
+    assert!(
+        !pngme(&["tags", file, "--keyword", "Author"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn tags_set_and_remove() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[
+            ("ruSt", "before"),
+            ("tEXt", "Author\0Jane"),
+            ("tEXt", "Comment\0bye"),
+        ],
+    );
+    let file = path.to_str().unwrap();
+
+    assert!(
+        pngme(&["tags", "set", file, "Author", "Zoë"])
+            .status
+            .success()
+    );
+    assert!(pngme(&["tags", "set", file, "Title", "☃"]).status.success());
+    assert!(pngme(&["tags", "remove", file, "Comment"]).status.success());
+
+    let summary = chunk_summary(&read_png(&path));
+    let types: Vec<&str> = summary.iter().map(|(t, _)| t.as_str()).collect();
+    // Title is new and goes before IDAT, Author is rewritten where it was
+    assert_eq!(types, ["IHDR", "iTXt", "IDAT", "ruSt", "tEXt", "IEND"]);
+    assert_eq!(summary[1].1, "Title\0\0\0\0\0☃");
+    assert_eq!(summary[3].1, "before");
+    let output = pngme(&["tags", file, "--keyword", "Author"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Zoë\n");
+
+    assert!(!pngme(&["tags", "remove", file, "Comment"]).status.success());
+    assert!(
+        !pngme(&["tags", "set", file, "bad  keyword", "x"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn tags_set_leaves_duplicates_alone() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("tEXt", "Author\0Jane"), ("tEXt", "Author\0Joe")],
+    );
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["tags", "set", file, "Author", "Zoë"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Set the first Author entry; 1 later duplicates keep their old values"),
+        "{stdout}"
+    );
+    let output = pngme(&["tags", file, "--keyword", "Author"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Zoë\nJoe\n");
+}
+
+fn write_indexed_fixture(path: &Path) {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, 2, 2);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Two);
+    encoder.set_palette(vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+    encoder.set_trns(vec![0, 128]);
+    let mut writer = encoder.write_header().unwrap();
+    writer
+        .write_image_data(&[0b0001_0000, 0b1011_0000])
+        .unwrap();
+    writer.finish().unwrap();
+    fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn info_palette() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("indexed.png");
+    write_indexed_fixture(&path);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["info", file, "--palette"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Color type: 3 (indexed)"), "{stdout}");
+    assert!(stdout.contains("Bit depth: 2"), "{stdout}");
+    let palette: Vec<&str> = stdout.lines().skip(6).collect();
+    assert_eq!(
+        palette,
+        [
+            "    0  #ff0000  alpha   0",
+            "    1  #00ff00  alpha 128",
+            "    2  #0000ff  alpha 255",
+            "    3  #ffffff  alpha 255",
+        ]
+    );
+    assert!(pngme(&["verify", file]).status.success());
+
+    let output = pngme(&["info", file, "--palette", "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["palette"][1], serde_json::json!([0, 255, 0, 128]));
+
+    let plain = write_fixture(&dir, "plain.png", &[]);
+    assert!(
+        !pngme(&["info", plain.to_str().unwrap(), "--palette"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn frames_extract_writes_each_frame() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("anim.png");
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, 4, 3);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_palette(vec![0, 0, 0, 255, 0, 0, 0, 255, 0]);
+    encoder.set_trns(vec![255, 128, 0]);
+    encoder.set_animated(3, 0).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&[0; 4 * 3]).unwrap();
+    for (size, value) in [((3, 2), 1), ((1, 1), 2)] {
+        writer.set_frame_dimension(size.0, size.1).unwrap();
+        writer
+            .write_image_data(&vec![value; (size.0 * size.1) as usize])
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    fs::write(&path, bytes).unwrap();
+    let file = path.to_str().unwrap();
+    let frames = dir.path().join("frames");
+
+    let output = pngme(&["frames", "extract", file, "--dir", frames.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 3, "{stdout}");
+    for (n, size, value) in [(1, (4, 3), 0), (2, (3, 2), 1), (3, (1, 1), 2)] {
+        let frame = frames.join(format!("anim-00{n}.png"));
+        assert!(pngme(&["verify", frame.to_str().unwrap()]).status.success());
+        let decoder = png::Decoder::new(std::io::Cursor::new(fs::read(&frame).unwrap()));
+        let mut reader = decoder.read_info().unwrap();
+        assert!(reader.info().animation_control.is_none());
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), size);
+        // The palette and tRNS come along
+        assert_eq!(reader.info().trns.as_deref(), Some(&[255, 128, 0][..]));
+        assert!(pixels.iter().all(|&p| p == value));
+    }
+
+    let still = write_fixture(&dir, "still.png", &[]);
+    let output = pngme(&[
+        "frames",
+        "extract",
+        still.to_str().unwrap(),
+        "--dir",
+        frames.to_str().unwrap(),
+    ]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("isn't animated")
+    );
+}
+
+#[test]
+fn info_inflate_check_compares_with_ihdr() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("indexed.png");
+    write_indexed_fixture(&path);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["info", file, "--inflate-check"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Image data: 1 IDAT chunk, "), "{stdout}");
+    // Two rows of a filter byte and one byte of 2-bit pixels
+    assert!(
+        stdout.contains("Inflated: 4 bytes, as IHDR calls for 4 bytes"),
+        "{stdout}"
+    );
+
+    // Claim a third row that was never stored
+    let mut png = read_png(&path);
+    let mut ihdr = png.chunks()[0].data().to_vec();
+    ihdr[7] = 3;
+    png.remove_chunk_at(0);
+    png.insert_chunk(0, Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr));
+    fs::write(&path, png.as_bytes()).unwrap();
+    let output = pngme(&["info", file, "--inflate-check", "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["image_data"]["inflated"], 4);
+    assert_eq!(info["image_data"]["expected_inflated"], 6);
+    assert_eq!(info["image_data"]["zlib"]["method"], 8);
+
+    let output = pngme(&["verify", file]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("bad-image-data"), "{stdout}");
+}
+
+#[test]
+fn info_and_print_decode_known_chunks() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "known.png",
+        &[
+            ("tEXt", "Author\0ferris"),
+            ("tIME", "\x07\x68\x05\x01\x0c\x1e\x00"),
+            ("gAMA", "\0\0\x7f\x7f"),
+            ("gAMA", "short"),
+        ],
+    );
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["info", file]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let known: Vec<&str> = stdout.lines().skip(6).collect();
+    assert_eq!(
+        known,
+        [
+            "Text: Author = \"ferris\"",
+            "Last modified: 1896-05-01T12:30:00Z",
+            "Gamma: 0.32639",
+            "Unreadable: gAMA: Data is 5 bytes, not 4",
+        ]
+    );
+
+    let output = pngme(&["info", file, "--json"]);
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["known_chunks"][1]["chunk_type"], "tIME");
+    assert_eq!(info["known_chunks"][1]["data"]["minute"], 30);
+
+    let output = pngme(&["print", file, "--data"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Chunk 3: Text: Author = \"ferris\""),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Chunk 5: Gamma: 0.32639"), "{stdout}");
+
+    let output = pngme(&["tags", file]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("ferris"));
+}
+
+#[test]
+fn inspect_text_payload() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "just some plain text")]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["inspect", file, "ruSt"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Length: 20 bytes"), "{stdout}");
+    assert!(stdout.contains("Guess: looks like text"), "{stdout}");
+
+    let output = pngme(&["inspect", file, "IDAT", "--json"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["magic"], "zlib stream");
+    assert_eq!(report["histogram"].as_array().unwrap().len(), 16);
+
+    assert!(!pngme(&["inspect", file, "miSs"]).status.success());
+}
+
+#[test]
+fn corrupt_crc_then_repair() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["encode", file, "ruSt", "broken", "--corrupt-crc", "+1"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("deliberately wrong CRC"));
+
+    let output = pngme(&["verify", file, "--json"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["findings"][0]["code"], "crc-mismatch");
+    assert_eq!(report["findings"][0]["chunk_type"], "ruSt");
+
+    let output = pngme(&["repair", file]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Fixed CRC of ruSt at index 2"));
+    assert!(pngme(&["verify", file]).status.success());
+    assert!(chunk_summary(&read_png(&path)).contains(&("ruSt".into(), "broken".into())));
+
+    let output = pngme(&["repair", file]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("no CRC errors found"));
+}
+
+#[test]
+fn repair_fix_lengths() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("ruSt", "forty-two bytes, give or take a few extra!")],
+    );
+    let file = path.to_str().unwrap();
+    let good = fs::read(&path).unwrap();
+    let offset = Png::parse_borrowed(&good).unwrap()[2].offset();
+    let mut bytes = good.clone();
+    bytes[offset..offset + 4].copy_from_slice(&40u32.to_be_bytes());
+    fs::write(&path, &bytes).unwrap();
+
+    let output = pngme(&["verify", file, "--json"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["findings"][0]["code"], "length-mismatch");
+    let message = report["findings"][0]["message"].as_str().unwrap();
+    assert!(
+        message.ends_with("declared length 40, actual 42"),
+        "{message}"
+    );
+
+    // Plain repair can only see the CRC it lands on as wrong
+    assert!(!pngme(&["repair", file]).status.success());
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+
+    let output = pngme(&["repair", file, "--fix-lengths"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!(
+        "Fixed length of ruSt at offset {offset}: declared 40 -> 42"
+    )));
+    assert_eq!(fs::read(&path).unwrap(), good);
+    assert!(pngme(&["verify", file]).status.success());
+}
+
+#[cfg(feature = "render-check")]
+#[test]
+fn verify_render_catches_changed_pixels() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["encode", file, "ruSt", "hello", "--verify-render"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let copy = dir.path().join("b.png");
+    let output = pngme(&[
+        "strip",
+        file,
+        "-o",
+        copy.to_str().unwrap(),
+        "--verify-render",
+    ]);
+    assert!(output.status.success());
+
+    // Overwrite the zlib header of the image data, which no longer decodes
+    let output = pngme(&[
+        "patch",
+        file,
+        "IDAT",
+        "--offset",
+        "0",
+        "--bytes",
+        "0000",
+        "--verify-render",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("shows a different image than before the edit"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("the output doesn't decode"), "{stderr}");
+}
+
+#[cfg(feature = "render-check")]
+#[test]
+fn compare_pixels_ignores_chunks_but_not_pixels() {
+    let dir = TempDir::new().unwrap();
+    let write = |name: &str, edit: Option<(usize, u8)>| {
+        let mut pixels: Vec<u8> = (0..4 * 3 * 3).map(|i| i as u8 * 7).collect();
+        if let Some((index, value)) = edit {
+            pixels[index] = value;
+        }
+        let path = dir.path().join(name);
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 4, 3);
+        encoder.set_color(png::ColorType::Rgb);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&pixels).unwrap();
+        writer.finish().unwrap();
+        fs::write(&path, bytes).unwrap();
+        path
+    };
+    let original = write("a.png", None);
+    let a = original.to_str().unwrap();
+    assert!(pngme(&["tags", "set", a, "Comment", "hi"]).status.success());
+    let stripped = dir.path().join("stripped.png");
+    let output = pngme(&["strip", a, "-o", stripped.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    assert_ne!(fs::read(&original).unwrap(), fs::read(&stripped).unwrap());
+
+    let output = pngme(&["compare-pixels", a, stripped.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"identical\n");
+
+    // The green of pixel (2, 1), 4 * 3 + 2 * 3 + 1 bytes in, from 133 to 135
+    let edited = write("edited.png", Some((19, 135)));
+    let b = edited.to_str().unwrap();
+    let output = pngme(&["compare-pixels", a, b]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        format!(
+            "different at pixel (2, 1): {a} has rgba(126, 133, 140, 255) and {b} has rgba(126, 135, 140, 255)\n1 pixel differs\n"
+        )
+    );
+    assert!(
+        pngme(&["compare-pixels", a, b, "--tolerance", "2"])
+            .status
+            .success()
+    );
+
+    let output = pngme(&["compare-pixels", a, b, "--json"]);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["first_difference"]["x"], 2);
+    assert_eq!(json["first_difference"]["y"], 1);
+}
+
+#[test]
+fn audit_log_records_each_edit() {
+    use pngme::digest::sha256_hex;
+
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("tEXt", "Comment\0hi")]);
+    let file = path.to_str().unwrap();
+    let log = dir.path().join("audit.jsonl");
+    let copy = dir.path().join("copy.png");
+    let audited = |args: &[&str]| {
+        let mut all = args.to_vec();
+        all.extend(["--audit-log", log.to_str().unwrap()]);
+        let output = pngme(&all);
+        assert!(output.status.success(), "{args:?}");
+    };
+    let original = fs::read(&path).unwrap();
+    audited(&["encode", file, "ruSt", "hello"]);
+    audited(&["remove", file, "tEXt"]);
+    // Nothing to strip, so nothing written and nothing logged
+    audited(&["strip", file, "--unsafe-only"]);
+    audited(&["strip", file, "-o", copy.to_str().unwrap()]);
+    // The environment variable does the same as the flag
+    let output = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(["encode", file, "ruSt", "again"])
+        .env("PNGME_LOG_FILE", &log)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let lines: Vec<serde_json::Value> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let field = |line: &serde_json::Value, key: &str| line[key].as_str().unwrap().to_string();
+    let commands: Vec<String> = lines.iter().map(|l| field(l, "command")).collect();
+    assert_eq!(commands, ["encode", "remove", "strip", "encode"]);
+    let types: Vec<&serde_json::Value> = lines.iter().map(|l| &l["chunk_types"]).collect();
+    assert_eq!(
+        types,
+        [
+            &serde_json::json!(["ruSt"]),
+            &serde_json::json!(["tEXt"]),
+            &serde_json::json!(["ruSt"]),
+            &serde_json::json!(["ruSt"]),
+        ]
+    );
+    assert_eq!(field(&lines[0], "sha256_before"), sha256_hex(&original));
+    assert_eq!(lines[0]["size_before"], original.len());
+    // Each in-place edit starts from where the one before it left the file
+    assert_eq!(
+        field(&lines[0], "sha256_after"),
+        field(&lines[1], "sha256_before")
+    );
+    assert_eq!(
+        field(&lines[1], "sha256_after"),
+        field(&lines[3], "sha256_before")
+    );
+    let copied = fs::read(&copy).unwrap();
+    assert_eq!(field(&lines[2], "sha256_after"), sha256_hex(&copied));
+    assert_eq!(lines[2]["size_after"], copied.len());
+    assert!(field(&lines[2], "output").ends_with("copy.png"));
+    let now = fs::read(&path).unwrap();
+    assert_eq!(field(&lines[3], "sha256_after"), sha256_hex(&now));
+    for line in &lines {
+        assert!(Path::new(&field(line, "input")).ends_with("a.png"));
+        assert!(field(line, "timestamp").ends_with('Z'));
+    }
+
+    // A log that can't be written stops the edit, unless best effort will do
+    let unwritable = dir.path().join("missing").join("audit.jsonl");
+    let unwritable = unwritable.to_str().unwrap();
+    let output = pngme(&["encode", file, "ruSt", "x", "--audit-log", unwritable]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("failed to open audit log"), "{stderr}");
+    assert_eq!(fs::read(&path).unwrap(), now);
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        "x",
+        "--audit-log",
+        unwritable,
+        "--audit-best-effort",
+    ]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: failed to open audit log"));
+    assert_ne!(fs::read(&path).unwrap(), now);
+
+    // Written to stdout, there's no file after to describe
+    let now = fs::read(&path).unwrap();
+    let log_path = log.to_str().unwrap();
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        "y",
+        "-o",
+        "-",
+        "--audit-log",
+        log_path,
+    ]);
+    assert!(output.status.success());
+    let log = fs::read_to_string(&log).unwrap();
+    let last: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+    assert_eq!(last["output"], "-");
+    assert_eq!(last["sha256_before"], sha256_hex(&now));
+    assert_eq!(last["sha256_after"], serde_json::Value::Null);
+    assert_eq!(last["chunk_types"], serde_json::json!([]));
+}
+
+#[test]
+fn patch_edits_chunk_data() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello world")]);
+    let file = path.to_str().unwrap();
+
+    // "world" -> "WORLD", then "hello" -> "hi" with one byte left over
+    let output = pngme(&[
+        "patch",
+        file,
+        "ruSt",
+        "--offset",
+        "6",
+        "--bytes",
+        "574f524c44",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Patched ruSt at index 2"), "{stdout}");
+    let output = pngme(&[
+        "patch",
+        file,
+        "ruSt",
+        "--offset",
+        "0",
+        "--bytes",
+        "6869",
+        "--replace",
+        "5",
+    ]);
+    assert!(output.status.success());
+
+    let decoded = pngme(&["decode", file, "ruSt"]);
+    assert_eq!(String::from_utf8_lossy(&decoded.stdout), "hi WORLD\n");
+    assert!(pngme(&["verify", file]).status.success());
+
+    let before = fs::read(&path).unwrap();
+    let output = pngme(&["patch", file, "ruSt", "--offset", "8", "--bytes", "0000"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("outside the chunk"));
+    assert!(
+        !pngme(&["patch", file, "ruSt", "--offset", "0", "--bytes", "abc"])
+            .status
+            .success()
+    );
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn patching_a_critical_chunk_drops_unsafe_to_copy_chunks() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "in.png", &[("ruSX", "depends"), ("ruSt", "safe")]);
+    let file = path.to_str().unwrap();
+
+    // Width 2 becomes 3
+    let args = ["patch", file, "IHDR", "--offset", "3", "--bytes", "03"];
+    let output = pngme(&args);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Warning: dropped ruSX, which isn't safe to copy once IHDR changes"),
+        "{stderr}"
+    );
+    let png = read_png(&path);
+    assert!(png.chunk_by_type("ruSX").is_none());
+    assert!(png.chunk_by_type("ruSt").is_some());
+}
+
+#[test]
+fn redact_keeps_the_layout() {
+    let dir = TempDir::new().unwrap();
+    let secrets = [
+        ("tEXt", "Author\0someone"),
+        ("ruSt", "secret"),
+        ("tEXt", "a\0b"),
+    ];
+    let path = write_fixture(&dir, "a.png", &secrets);
+    let file = path.to_str().unwrap();
+    let before = fs::read(&path).unwrap();
+    let offsets = read_png(&path).chunk_offsets();
+
+    let redacted = |png: &Png, chunk_type: &str| -> Vec<Vec<u8>> {
+        png.chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == chunk_type)
+            .map(|c| {
+                assert_eq!(c.crc(), c.computed_crc());
+                c.data().to_vec()
+            })
+            .collect()
+    };
+
+    let output = pngme(&["redact", file, "tEXt"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Redacted tEXt at index 2: 14 bytes overwritten"),
+        "{stdout}"
+    );
+    assert_eq!(fs::read(&path).unwrap().len(), before.len());
+    let png = read_png(&path);
+    assert_eq!(png.chunk_offsets(), offsets);
+    assert_eq!(redacted(&png, "tEXt"), [vec![0; 14], vec![0; 3]]);
+    assert_eq!(redacted(&png, "ruSt"), [b"secret"]);
+    assert!(pngme(&["verify", file]).status.success());
+
+    let out = dir.path().join("out.png");
+    let out_arg = out.to_str().unwrap();
+    let output = pngme(&["redact", file, "ruSt", "--fill", "0xff", "-o", out_arg]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(redacted(&read_png(&out), "ruSt"), [[0xff; 6]]);
+    // "REDACTED", repeated and cut off
+    let pattern = "5245444143544544";
+    let output = pngme(&["redact", file, "ruSt", "--pattern", pattern, "-o", out_arg]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(redacted(&read_png(&out), "ruSt"), [b"REDACT"]);
+    assert_eq!(fs::read(&out).unwrap().len(), before.len());
+
+    // Critical chunks need --force, and a missing one is an error
+    let before = fs::read(&path).unwrap();
+    let output = pngme(&["redact", file, "IDAT"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Refusing to redact IDAT without --force"),
+        "{stderr}"
+    );
+    assert!(!pngme(&["redact", file, "zzZz"]).status.success());
+    assert!(
+        !pngme(&["redact", file, "ruSt", "--pattern", ""])
+            .status
+            .success()
+    );
+    assert_eq!(fs::read(&path).unwrap(), before);
+    assert!(pngme(&["redact", file, "IDAT", "--force"]).status.success());
+    assert_eq!(fs::read(&path).unwrap().len(), before.len());
+}
+
+#[test]
+fn refusals_are_reported_as_errors() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "in.png", &[("ruSt", "hi")]);
+    let file = path.to_str().unwrap();
+    for (args, message) in [
+        (
+            &["inspect", file, "zzZz"][..],
+            "zzZz wasnt found in the png",
+        ),
+        (
+            &["patch", file, "zzZz", "--offset", "0", "--bytes", "00"],
+            "zzZz wasnt found in the png",
+        ),
+        (
+            &["redact", file, "IDAT"],
+            "Refusing to redact IDAT without --force",
+        ),
+        (
+            &["remove", file, "--index", "9"],
+            "Index 9 is out of range, the png has 4 chunks",
+        ),
+        (
+            &["init", file],
+            "already exists, pass --force to overwrite it",
+        ),
+        (&["undo", file], "undo needs --undo-log DIR"),
+    ] {
+        let output = pngme(args);
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with("error: "), "{stderr}");
+        assert!(stderr.contains(message), "{stderr}");
+    }
+}
+
+#[test]
+fn redacting_a_critical_chunk_drops_unsafe_to_copy_chunks() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "in.png", &[("ruSX", "depends"), ("ruSt", "safe")]);
+    let file = path.to_str().unwrap();
+
+    // An ancillary chunk changing leaves the rest alone
+    let output = pngme(&["redact", file, "ruSt"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(read_png(&path).chunk_by_type("ruSX").is_some());
+
+    let output = pngme(&["redact", file, "IDAT", "--force"]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Warning: dropped ruSX, which isn't safe to copy once IDAT changes"),
+        "{stderr}"
+    );
+    let png = read_png(&path);
+    assert!(png.chunk_by_type("ruSX").is_none());
+    assert!(png.chunk_by_type("ruSt").is_some());
+}
+
+#[test]
+fn corrupt_crc_explicit_value() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["encode", file, "ruSt", "x", "--corrupt-crc", "0xdeadbeef"]);
+    assert!(output.status.success());
+    let bytes = fs::read(&path).unwrap();
+    let options = pngme::png::ParseOptions {
+        ignore_crc: true,
+        ..Default::default()
+    };
+    let png = Png::parse_with(&bytes, &options).unwrap();
+    let chunk = png.chunk_by_type("ruSt").unwrap();
+    assert_eq!(chunk.crc(), 0xdeadbeef);
+}
+
+#[test]
+fn remove_types_from_list() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/text-chunks.png");
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("a.png");
+    fs::copy(&fixture, &path).unwrap();
+    let file = path.to_str().unwrap();
+    let list = dir.path().join("purge.txt");
+    let list_arg = list.to_str().unwrap();
+
+    fs::write(
+        &list,
+        "# metadata to purge\ntEXt\nzTXt  # compressed too\n\nsPLT\n",
+    )
+    .unwrap();
+    let output = pngme(&["remove", file, "--types-file", list_arg]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "- tEXt (16 bytes) from index 1\n\
+         - zTXt (35 bytes) from index 2\n\
+         Size: 218 bytes -> 143 bytes\n\
+         tEXt: 1 removed\nzTXt: 1 removed\nsPLT: 0 removed\n"
+    );
+    let types: Vec<String> = chunk_summary(&read_png(&path))
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect();
+    assert_eq!(types, ["IHDR", "iTXt", "pHYs", "IDAT", "tIME", "IEND"]);
+
+    // Every bad line is reported and nothing is removed
+    let before = fs::read(&path).unwrap();
+    fs::write(&list, "iTXt\nab\ntIME\n12cd\n").unwrap();
+    let output = pngme(&["remove", file, "--types-file", list_arg]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("purge.txt: line 2: 'ab'"), "{stderr}");
+    assert!(stderr.contains("purge.txt: line 4: '12cd'"), "{stderr}");
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    fs::write(&list, "iTXt\nIDAT\n").unwrap();
+    let output = pngme(&["remove", file, "--types-file", list_arg]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("without --force"));
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn edits_summarize_their_changes() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/text-chunks.png");
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("a.png");
+    fs::copy(&fixture, &path).unwrap();
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["encode", file, "ruSt", "hello"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "+ ruSt (5 bytes) at index 7 (before IEND)\n\
+         Size: 218 bytes -> 235 bytes (+7.8%), payload is 2.1% of the file\n"
+    );
+    let output = pngme(&["remove", file, "tEXt"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "- tEXt (16 bytes) from index 1\nSize: 235 bytes -> 207 bytes\n"
+    );
+
+    let output = pngme(&["remove", file, "--index", "6", "--json"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "changes": [{"change": "removed", "index": 6, "chunk_type": "ruSt", "length": 5}],
+            "before": 207,
+            "after": 190,
+        })
+    );
+    let output = pngme(&["--quiet", "encode", file, "ruSt", "hello"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn remove_output_leaves_input_untouched() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "one"), ("ruSt", "two")]);
+    let file = path.to_str().unwrap();
+    let out = dir.path().join("out.png");
+    let before = fs::read(&path).unwrap();
+
+    let output = pngme(&["remove", file, "ruSt", "--output", out.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(&path).unwrap(), before);
+    assert_eq!(
+        chunk_summary(&read_png(&out))
+            .iter()
+            .filter(|(t, _)| t == "ruSt")
+            .count(),
+        1
+    );
+
+    // To stdout, with the status line moved to stderr
+    let output = pngme(&["remove", file, "--index", "2", "-o", "-"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(&path).unwrap(), before);
+    let png = Png::try_from(output.stdout.as_slice()).unwrap();
+    assert_eq!(chunk_summary(&png)[2], ("ruSt".into(), "two".into()));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("- ruSt (3 bytes) from index 2"));
+}
+
+#[test]
+fn every_edit_accepts_output() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello"), ("tEXt", "Title\0old")]);
+    let file = path.to_str().unwrap();
+    let before = fs::read(&path).unwrap();
+    let commands: [&[&str]; 8] = [
+        &["encode", file, "ruSt", "more"],
+        &["strip", file],
+        &["patch", file, "ruSt", "--offset", "0", "--bytes", "48"],
+        &["repair", file],
+        &["optimize", file],
+        &["tags", "set", file, "Title", "new"],
+        &["tags", "remove", file, "Title"],
+        &["remove", file, "tEXt"],
+    ];
+    for (i, command) in commands.iter().enumerate() {
+        let out = dir.path().join(format!("out{i}.png"));
+        let mut args = command.to_vec();
+        args.extend(["-o", out.to_str().unwrap()]);
+        let output = pngme(&args);
+        assert!(output.status.success(), "{command:?}");
+        assert_eq!(fs::read(&path).unwrap(), before, "{command:?}");
+        assert!(pngme(&["verify", out.to_str().unwrap()]).status.success());
+    }
+    // Naming the input itself is an in-place edit
+    let same = dir.path().join(".").join("a.png");
+    let output = pngme(&["strip", file, "-o", same.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_ne!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn read_only_commands_leave_the_file_alone() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/text-chunks.png");
+    let dir = TempDir::new().unwrap();
+    let copy = dir.path().join("a.png");
+    fs::copy(&path, &copy).unwrap();
+    let file = copy.to_str().unwrap();
+    let before = fs::read(&copy).unwrap();
+    let modified = fs::metadata(&copy).unwrap().modified().unwrap();
+
+    for args in [
+        &["print", file][..],
+        &["list", file],
+        &["decode", file, "tEXt"],
+        &["verify", file],
+        &["tags", file],
+        &["info", file],
+        &["inspect", file, "IDAT"],
+    ] {
+        assert!(pngme(args).status.success(), "{args:?}");
+    }
+    assert_eq!(fs::read(&copy).unwrap(), before);
+    assert_eq!(fs::metadata(&copy).unwrap().modified().unwrap(), modified);
+}
+
+fn chunk_type_output(chunk_type: &str) -> String {
+    let output = pngme(&["chunk-type", chunk_type]);
+    assert!(output.status.success(), "{chunk_type}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn chunk_type_explains_private_chunk() {
+    assert_eq!(
+        chunk_type_output("ruSt"),
+        "\
+ruSt
+  r  0x72  bit 5 = 1  ancillary
+  u  0x75  bit 5 = 1  private
+  S  0x53  bit 5 = 0  reserved bit valid
+  t  0x74  bit 5 = 1  safe to copy
+  not a registered chunk type
+"
+    );
+}
+
+#[test]
+fn chunk_type_explains_standard_chunk() {
+    assert_eq!(
+        chunk_type_output("IDAT"),
+        "\
+IDAT
+  I  0x49  bit 5 = 0  critical
+  D  0x44  bit 5 = 0  public
+  A  0x41  bit 5 = 0  reserved bit valid
+  T  0x54  bit 5 = 0  unsafe to copy
+  standard: Compressed image data
+"
+    );
+}
+
+#[test]
+fn chunk_type_flags_reserved_bit() {
+    assert_eq!(
+        chunk_type_output("Rust"),
+        "\
+Rust
+  R  0x52  bit 5 = 0  critical
+  u  0x75  bit 5 = 1  private
+  s  0x73  bit 5 = 1  reserved bit invalid
+  t  0x74  bit 5 = 1  safe to copy
+  not a registered chunk type
+"
+    );
+}
+
+#[test]
+fn chunk_type_rejects_invalid_bytes() {
+    let output = pngme(&["chunk-type", "IDAT", "Ru1t"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("IDAT\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Byte 2 of the chunk type is 0x31 ('1')"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn list_matches_library_chunks() {
+    let dir = TempDir::new().unwrap();
+    // A chunk whose payload contains newlines and chunk-like text shouldn't
+    // confuse the listing
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "1 IEND\n2 IDAT\n")]);
+    let png = read_png(&path);
+    let output = pngme(&["list", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let types: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.split_whitespace().nth(3).unwrap())
+        .collect();
+    let expected: Vec<String> = png
+        .chunks()
+        .iter()
+        .map(|c| c.chunk_type().to_string())
+        .collect();
+    assert_eq!(types.len(), png.chunk_count());
+    assert_eq!(types, expected);
+    assert_eq!(types.first(), Some(&"IHDR"));
+    assert_eq!(types.last(), Some(&"IEND"));
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_paths_are_accepted() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = TempDir::new().unwrap();
+    let source = write_fixture(&dir, "a.png", &[]);
+    let path = dir.path().join(OsStr::from_bytes(b"caf\xe9.png"));
+    fs::rename(&source, &path).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .arg("encode")
+        .arg(&path)
+        .args(["ruSt", "hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let png = read_png(&path);
+    assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"hello");
+}
+
+#[test]
+fn encode_and_remove_match_in_memory_edits() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let dir = TempDir::new().unwrap();
+    for entry in fs::read_dir(fixtures).unwrap() {
+        let source = entry.unwrap().path();
+        let bytes = fs::read(&source).unwrap();
+        let copy = dir.path().join("a.png");
+        fs::write(&copy, &bytes).unwrap();
+        let file = copy.to_str().unwrap();
+
+        let mut expected = Png::try_from(bytes.as_slice()).unwrap();
+        expected.insert_before_iend(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            b"hello".to_vec(),
+        ));
+        assert!(pngme(&["encode", file, "ruSt", "hello"]).status.success());
+        assert!(
+            fs::read(&copy).unwrap() == expected.as_bytes(),
+            "{}",
+            source.display()
+        );
+
+        expected.remove_chunk_at(0);
+        assert!(
+            pngme(&["remove", file, "--index", "0", "--force"])
+                .status
+                .success()
+        );
+        assert!(
+            fs::read(&copy).unwrap() == expected.as_bytes(),
+            "{}",
+            source.display()
+        );
+    }
+}
+
+#[test]
+fn apply_rules_file() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("eXIf", "exif"), ("ruSt", "x"), ("ruSt", "x")],
+    );
+    let rules = dir.path().join("rules.toml");
+    fs::write(
+        &rules,
+        r#"
+[[step]]
+remove = ["eXIf"]
+
+[[step]]
+add = [{ type = "tEXt", keyword = "Software", value = "mytool" }]
+
+[[step]]
+dedup = true
+"#,
+    )
+    .unwrap();
+    let (rules, file) = (rules.to_str().unwrap(), path.to_str().unwrap());
+    let before = fs::read(&path).unwrap();
+
+    let output = pngme(&["apply", "--rules", rules, file, "--dry-run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("1. remove eXIf: 1 removed, 0 added"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("3. remove duplicate ancillary chunks: 1 removed"),
+        "{stdout}"
+    );
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    assert!(pngme(&["apply", "--rules", rules, file]).status.success());
+    let summary = chunk_summary(&read_png(&path));
+    assert!(!summary.iter().any(|(t, _)| t == "eXIf"));
+    assert_eq!(summary.iter().filter(|(t, _)| t == "ruSt").count(), 1);
+    assert!(summary.contains(&("tEXt".to_string(), "Software\0mytool".to_string())));
+}
+
+#[test]
+fn apply_with_nothing_to_do_leaves_the_file_alone() {
+    use std::time::{Duration, SystemTime};
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let rules = dir.path().join("rules.toml");
+    fs::write(&rules, "[[step]]\nremove = [\"eXIf\"]\n").unwrap();
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let f = fs::File::options().write(true).open(&path).unwrap();
+    f.set_modified(old).unwrap();
+    drop(f);
+
+    let file = path.to_str().unwrap();
+    let output = pngme(&["apply", "--rules", rules.to_str().unwrap(), file]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("{file}: no changes, file not rewritten")),
+        "{stdout}"
+    );
+    assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), old);
+
+    let output = pngme(&["strip", file]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("no changes"));
+    assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), old);
+}
+
+#[cfg(unix)]
+#[test]
+fn streamed_remove_with_nothing_to_do_leaves_the_file_alone() {
+    use std::os::unix::fs::MetadataExt;
+    use std::time::{Duration, SystemTime};
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "kept")]);
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let f = fs::File::options().write(true).open(&path).unwrap();
+    f.set_modified(old).unwrap();
+    drop(f);
+    let inode = fs::metadata(&path).unwrap().ino();
+
+    let file = path.to_str().unwrap();
+    for args in [
+        &["remove", file, "zzZz"][..],
+        &["remove", file, "--select", "type=zzZz"],
+    ] {
+        let output = pngme(args);
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("no changes, file not rewritten"),
+            "{stdout}"
+        );
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.ino(), inode, "{args:?}");
+        assert_eq!(metadata.modified().unwrap(), old, "{args:?}");
+    }
+}
+
+#[test]
+fn apply_rejects_unknown_keys() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let rules = dir.path().join("rules.toml");
+    fs::write(
+        &rules,
+        "[[step]]\nremove = [\"eXIf\"]\n[[step]]\nshrink = true\n",
+    )
+    .unwrap();
+    let before = fs::read(&path).unwrap();
+    let output = pngme(&[
+        "apply",
+        "--rules",
+        rules.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 4"), "{stderr}");
+    assert!(stderr.contains("shrink"), "{stderr}");
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn decode_by_keyword() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/text-chunks.png");
+    let file = fixture.to_str().unwrap();
+    let decode = |args: &[&str]| {
+        let output = pngme(&[&["decode", file][..], args].concat());
+        (
+            output.status.success(),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+        )
+    };
+    assert_eq!(
+        decode(&["--keyword", "Comment"]),
+        (true, "compressed comment\n".to_string())
+    );
+    assert_eq!(
+        decode(&["--keyword", "Software"]),
+        (true, "fixture\n".to_string())
+    );
+    assert!(!decode(&["--keyword", "software"]).0);
+    assert_eq!(
+        decode(&["--keyword", "software", "--ignore-case"]),
+        (true, "fixture\n".to_string())
+    );
+    assert_eq!(
+        decode(&["--keyword", "Comment", "--raw"]),
+        (true, "compressed comment".to_string())
+    );
+    assert!(!decode(&["tEXt", "--keyword", "Comment"]).0);
+}
+
+#[test]
+fn decode_keyword_with_several_entries() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("tEXt", "Comment\0one"), ("tEXt", "Comment\0two")],
+    );
+    let file = path.to_str().unwrap();
+    let output = pngme(&["decode", file, "--keyword", "Comment"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\n");
+    let output = pngme(&["decode", file, "--keyword", "Comment", "--nth", "2"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "two\n");
+    assert!(
+        !pngme(&["decode", file, "--keyword", "Comment", "--nth", "3"])
+            .status
+            .success()
+    );
+    assert!(
+        !pngme(&["decode", file, "--keyword", "Comment", "--raw"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn self_test_passes() {
+    let output = pngme(&["self-test"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("PASS  crc"));
+    assert!(!stdout.contains("FAIL"));
+}
+
+#[test]
+fn version_json_lists_the_build() {
+    let output = pngme(&["--version-json"]);
+    assert!(output.status.success(), "{output:?}");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    let features = json["features"].as_array().unwrap();
+    assert_eq!(
+        features.contains(&"render-check".into()),
+        cfg!(feature = "render-check")
+    );
+    let transforms = &json["formats"]["transform_envelope"]["transforms"];
+    assert_eq!(*transforms, serde_json::json!(["deflate", "base64"]));
+    let codes = json["finding_codes"].as_array().unwrap();
+    assert_eq!(codes.len(), pngme::verify::FindingKind::ALL.len());
+    assert!(codes.contains(&"crc-mismatch".into()));
+
+    assert!(!pngme(&["--version-json", "--json"]).status.success());
+}
+
+#[test]
+fn output_is_reproducible() {
+    // Nothing pngme writes depends on the clock, the file name or randomness,
+    // except encode --template's {now} and {filename}
+    let run = || {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.png");
+        let file = path.to_str().unwrap();
+        assert!(pngme(&["init", file, "--width", "3"]).status.success());
+        let encode = ["encode", file, "ruSt", "hello", "--chunk", "teSt=again"];
+        assert!(pngme(&encode).status.success());
+        fs::read(&path).unwrap()
+    };
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn list_formats_sizes_and_crcs() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", &"x".repeat(1536))]);
+    let file = path.to_str().unwrap();
+    let lines = |args: &[&str]| -> Vec<String> {
+        let output = pngme(args);
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    let exact = lines(&["list", file]);
+    assert!(exact[2].contains("1536 bytes  0x"), "{exact:?}");
+    assert!(exact[3].ends_with("0 bytes  0xae426082"), "{exact:?}");
+    let human = lines(&["list", file, "--human-readable"]);
+    assert!(human[2].contains("1.5 KiB  0x"), "{human:?}");
+    assert!(human[0].contains("13 bytes"), "{human:?}");
+}
+
+#[test]
+fn list_with_a_format_template() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "abc")]);
+    let file = path.to_str().unwrap();
+    let output = pngme(&[
+        "list",
+        file,
+        "--format",
+        "{index}\\t{type}\\t{length}\\t{crc:x}",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[3], "3\tIEND\t0\tae426082");
+    assert!(lines[2].starts_with("2\truSt\t3\t"), "{lines:?}");
+
+    let output = pngme(&["list", file, "--format", "{index} {colour}"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown field '{colour}'"));
+}
+
+#[test]
+fn decode_pretty_prints_json_and_falls_back() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("ruSt", r#"{"b":1,"a":2}"#), ("ruSx", r#"{"b":"#)],
+    );
+    let file = path.to_str().unwrap();
+    let output = pngme(&["decode", file, "ruSt", "--pretty"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"{\n  \"a\": 2,\n  \"b\": 1\n}\n");
+
+    let output = pngme(&["decode", file, "ruSx", "--pretty"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"{\"b\":\n");
+
+    let output = pngme(&["print", file, "--data"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Chunk 3: JSON object with keys: a, b"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Chunk 4: looks like text"), "{stdout}");
+
+    let mut png = read_png(&path);
+    png.insert_before_iend(Chunk::new(
+        ChunkType::from_str("ruSb").unwrap(),
+        vec![0xff, b'{'],
+    ));
+    fs::write(&path, png.as_bytes()).unwrap();
+    let output = pngme(&["decode", file, "ruSb", "--pretty"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("non-UTF-8 data"));
+}
+
+#[test]
+fn edits_wait_for_the_lock() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let before = fs::read(&path).unwrap();
+    let held = pngme::lock::lock_exclusive(
+        fs::File::open(&path).unwrap(),
+        &path,
+        std::time::Duration::ZERO,
+    )
+    .unwrap();
+
+    let output = pngme(&["encode", file, "ruSt", "hi", "--lock-timeout", "0"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("locked by another process"), "{stderr}");
+    assert_eq!(fs::read(&path).unwrap(), before);
+    // Reading doesn't take the lock (Windows enforces it, so only elsewhere)
+    if cfg!(not(windows)) {
+        assert!(pngme(&["list", file]).status.success());
+    }
+
+    drop(held);
+    assert!(pngme(&["encode", file, "ruSt", "hi"]).status.success());
+}
+
+#[test]
+fn remove_and_replace_can_be_undone() {
+    let dir = TempDir::new().unwrap();
+    let log = dir.path().join("undo");
+    let log = log.to_str().unwrap();
+    let path = write_fixture(
+        &dir,
+        "a.png",
+        &[("ruSt", "one"), ("tEXt", "Comment\0old"), ("ruSt", "two")],
+    );
+    let file = path.to_str().unwrap();
+    let original = fs::read(&path).unwrap();
+
+    let edit = |args: &[&str]| {
+        let output = pngme(&[args, &["--undo-log", log]].concat());
+        assert!(output.status.success(), "{output:?}");
+    };
+    edit(&["remove", file, "--index", "2"]);
+    let removed = fs::read(&path).unwrap();
+    // tags set overwrites the Comment entry in place
+    edit(&["tags", "set", file, "Comment", "new"]);
+    edit(&["encode", file, "ruSt", "three"]);
+    assert_ne!(fs::read(&path).unwrap(), removed);
+
+    edit(&["undo", file]);
+    edit(&["undo", file]);
+    assert_eq!(fs::read(&path).unwrap(), removed);
+    edit(&["undo", file]);
+    assert_eq!(fs::read(&path).unwrap(), original);
+
+    let output = pngme(&["undo", file, "--undo-log", log]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("nothing to undo"));
+}
+
+#[test]
+fn undo_refuses_a_changed_file() {
+    let dir = TempDir::new().unwrap();
+    let log = dir.path().join("undo");
+    let log = log.to_str().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "one")]);
+    let file = path.to_str().unwrap();
+    assert!(
+        pngme(&["remove", file, "ruSt", "--undo-log", log])
+            .status
+            .success()
+    );
+    // An edit the log doesn't know about
+    assert!(pngme(&["encode", file, "ruSt", "two"]).status.success());
+    let before = fs::read(&path).unwrap();
+
+    let output = pngme(&["undo", file, "--undo-log", log]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("has changed since the remove"), "{stderr}");
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    let output = pngme(&["undo", file]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--undo-log"));
+}
+
+#[test]
+fn redundant_copies_survive_stripping() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let run = |args: &[&str]| {
+        let output = pngme(args);
+        assert!(output.status.success(), "{output:?}");
+        output
+    };
+    run(&["encode", file, "--redundant", "3", "keep me"]);
+    let types: Vec<String> = chunk_summary(&read_png(&path))
+        .into_iter()
+        .map(|(t, _)| t)
+        .filter(|t| t.starts_with("pmR"))
+        .collect();
+    assert_eq!(types, ["pmRa", "pmRb", "pmRc"]);
+
+    // Stand in for an optimizer that drops one of the copies
+    run(&["remove", file, "pmRb"]);
+    let output = run(&["decode", file, "--redundant"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "keep me\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2/3"), "{stderr}");
+
+    run(&["remove", file, "--redundant"]);
+    assert!(
+        !chunk_summary(&read_png(&path))
+            .iter()
+            .any(|(t, _)| t.starts_with("pmR"))
+    );
+    assert!(!pngme(&["decode", file, "--redundant"]).status.success());
+}
+
+#[test]
+fn redundant_with_listed_types() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let encode = |types: &[&str]| {
+        let mut args = vec!["encode", file, "--redundant", "2", "hi"];
+        for t in types {
+            args.extend(["--type", t]);
+        }
+        pngme(&args)
+    };
+    assert!(encode(&["abCd", "efGh"]).status.success());
+    let output = pngme(&["decode", file, "--redundant"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n");
+    assert!(output.stderr.is_empty());
+    // Wrong count, an unsafe-to-copy type and a repeated one
+    for types in [&["abCd"][..], &["abCd", "ruST"], &["abCd", "abCd"]] {
+        assert!(!encode(types).status.success(), "{types:?}");
+    }
+}
+
+#[test]
+fn optimize_reports_savings() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("split.png");
+    fs::copy(root.join("tests/fixtures/split-idat.png"), &path).unwrap();
+    let file = path.to_str().unwrap();
+    let before = fs::read(&path).unwrap();
+
+    let output = pngme(&["optimize", file, "--dry-run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("merge adjacent IDAT chunks: "), "{stdout}");
+    assert!(stdout.contains("Total: "), "{stdout}");
+    assert_eq!(fs::read(&path).unwrap(), before);
+
+    let output = pngme(&["optimize", file, "--json", "--no-merge-idat"]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(
+        !report["passes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|p| p["pass"] == "merge adjacent IDAT chunks")
+    );
+
+    assert!(pngme(&["optimize", file]).status.success());
+    let idats = chunk_summary(&read_png(&path))
+        .iter()
+        .filter(|(t, _)| t == "IDAT")
+        .count();
+    assert_eq!(idats, 1);
+    assert!(fs::read(&path).unwrap().len() < before.len());
+    assert!(
+        !pngme(&["optimize", file, "--remove", "IDAT"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn advise_reports_each_tool() {
+    let output = pngme(&["advise", "--type", "zTXt"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("zTXt after IDAT:\n"));
+    assert!(stdout.contains("  ImageMagick: may be altered - zTXt may be recompressed"));
+
+    let output = pngme(&[
+        "advise",
+        "--type",
+        "ruSt",
+        "--position",
+        "after-iend",
+        "--json",
+    ]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["position"], "after IEND");
+    assert_eq!(report["worst"], "dropped");
+    assert!(
+        !pngme(&["advise", "--type", "ruSt", "--position", "middle"])
+            .status
+            .success()
+    );
+
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let output = pngme(&["encode", path.to_str().unwrap(), "ruSt", "hi", "-v"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ruSt after IDAT:\n"));
+    assert!(stdout.contains("  social media re-encoding: dropped"));
+}
+
+/// A fixture with `count` valid chunks appended after IEND.
+fn write_post_iend_fixture(dir: &TempDir, name: &str, count: usize) -> PathBuf {
+    let path = write_fixture(dir, name, &[]);
+    let mut bytes = fs::read(&path).unwrap();
+    for i in 0..count {
+        let chunk = Chunk::new(
+            ChunkType::from_str("wmRk").unwrap(),
+            format!("mark {i}").into_bytes(),
+        );
+        bytes.extend_from_slice(&chunk.as_bytes());
+    }
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+#[test]
+fn post_iend_chunks_are_kept_and_listed() {
+    let dir = TempDir::new().unwrap();
+    for count in [1, 3] {
+        let path = write_post_iend_fixture(&dir, "a.png", count);
+        let file = path.to_str().unwrap();
+
+        let output = pngme(&["list", file]);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let flagged = stdout.lines().filter(|l| l.ends_with("after IEND")).count();
+        assert_eq!(flagged, count);
+
+        let output = pngme(&["decode", file, "wmRk"]);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "mark 0\n");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("after IEND"));
+
+        let output = pngme(&["verify", file]);
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(stdout.matches("chunk-after-iend").count(), count);
+
+        // Edits leave them where they were, byte for byte
+        let before = fs::read(&path).unwrap();
+        assert!(pngme(&["encode", file, "ruSt", "hi"]).status.success());
+        assert!(pngme(&["remove", file, "ruSt"]).status.success());
+        assert_eq!(fs::read(&path).unwrap(), before);
+    }
+}
+
+#[test]
+fn relocate_post_iend_moves_chunks_before_iend() {
+    let dir = TempDir::new().unwrap();
+    let path = write_post_iend_fixture(&dir, "a.png", 2);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["encode", file, "ruSt", "hi", "--relocate-post-iend"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Moved 2 chunks from after IEND to before it"));
+
+    let png = read_png(&path);
+    assert!(png.post_iend_chunks().is_empty());
+    let summary = chunk_summary(&png);
+    let types: Vec<&str> = summary.iter().map(|(t, _)| t.as_str()).collect();
+    assert_eq!(types, ["IHDR", "IDAT", "ruSt", "wmRk", "wmRk", "IEND"]);
+    assert_eq!(summary[4].1, "mark 1");
+}
+
+#[test]
+fn scan_baseline_reports_the_changed_chunk() {
+    let dir = TempDir::new().unwrap();
+    let images = dir.path().join("images");
+    fs::create_dir(&images).unwrap();
+    let images_dir = TempDir::new_in(&images).unwrap();
+    write_fixture(&images_dir, "a.png", &[("tEXt", "Author\0me")]);
+    let b = write_fixture(&images_dir, "b.png", &[("tEXt", "Author\0me")]);
+    let manifest = dir.path().join("baseline.json");
+    let (images, manifest) = (images.to_str().unwrap(), manifest.to_str().unwrap());
+
+    assert!(
+        pngme(&["scan", images, "--write-baseline", manifest])
+            .status
+            .success()
+    );
+    let output = pngme(&["scan", images, "--baseline", manifest]);
+    assert!(output.status.success());
+
+    assert!(
+        pngme(&["tags", "set", b.to_str().unwrap(), "Author", "someone else"])
+            .status
+            .success()
+    );
+    let output = pngme(&["scan", images, "--baseline", manifest, "--json"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let findings = report["findings"].as_array().unwrap();
+    assert_eq!(findings.len(), 1);
+    assert!(findings[0]["path"].as_str().unwrap().ends_with("b.png"));
+    assert_eq!(findings[0]["status"], "changed");
+    assert_eq!(
+        findings[0]["changes"],
+        serde_json::json!([
+            {"change": "modified", "index": 2, "type": "tEXt", "pixel_data": false}
+        ])
+    );
+}
+
+#[test]
+fn decode_streams_to_output_file() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let file = path.to_str().unwrap();
+    let out = dir.path().join("payload.bin");
+
+    let output = pngme(&["decode", file, "ruSt", "-o", out.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(fs::read(&out).unwrap(), b"hello");
+    assert_eq!(pngme(&["decode", file, "ruSt"]).stdout, b"hello\n");
+    assert_eq!(pngme(&["decode", file, "ruSt", "--raw"]).stdout, b"hello");
+
+    let mut png = read_png(&path);
+    png.insert_before_iend(Chunk::new(
+        ChunkType::from_str("biNy").unwrap(),
+        vec![0xff, 0xfe],
+    ));
+    fs::write(&path, png.as_bytes()).unwrap();
+    let output = pngme(&["decode", file, "biNy"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("non-UTF-8 data"));
+    assert_eq!(
+        pngme(&["decode", file, "biNy", "--raw"]).stdout,
+        [0xff, 0xfe]
+    );
+}
+
+#[test]
+fn decode_exit_codes_tell_missing_from_binary() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let mut png = read_png(&path);
+    png.insert_before_iend(Chunk::new(
+        ChunkType::from_str("biNy").unwrap(),
+        (0..58).map(|i| 0x80 + i).collect(),
+    ));
+    fs::write(&path, png.as_bytes()).unwrap();
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["decode", file, "ruSt"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"hello\n");
+    assert!(output.stderr.is_empty());
+
+    let output = pngme(&["decode", file, "ruSx"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "error: failed to decode chunk 'ruSx' from \"{file}\"\n\
+             caused by: ruSx wasn't found in the png\n"
+        )
+    );
+    for missing in [
+        &["decode", file, "--select", "type=ruSx"][..],
+        &["decode", file, "--keyword", "Comment"],
+    ] {
+        assert_eq!(pngme(missing).status.code(), Some(3), "{missing:?}");
+    }
+
+    let output = pngme(&["decode", file, "biNy"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.ends_with(
+            "caused by: the biNy payload is 58 bytes of non-UTF-8 data; use --raw or --output\n"
+        ),
+        "{stderr}"
+    );
+    assert_eq!(pngme(&["decode", file, "biNy", "--raw"]).stdout.len(), 58);
+}
+
+#[test]
+fn decode_max_output_bytes_truncates_stdout() {
+    let dir = TempDir::new().unwrap();
+    // Cut after 5 bytes, the second snowman would be split
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "ab☃☃cd")]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["decode", file, "ruSt", "--max-output-bytes", "6"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "ab☃\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "[truncated, 10 bytes total]\n"
+    );
+
+    let output = pngme(&["decode", file, "ruSt", "--raw", "--max-output-bytes", "6"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(output.stdout, &"ab☃☃".as_bytes()[..6]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(["decode", file, "ruSt"])
+        .env("PNGME_MAX_OUTPUT", "2")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(output.stdout, b"ab\n");
+
+    // Enough room, or a file instead of stdout, and nothing is cut
+    let output = pngme(&["decode", file, "ruSt", "--max-output-bytes", "10"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "ab☃☃cd\n");
+    let out = dir.path().join("payload.txt");
+    let output = pngme(&[
+        "decode",
+        file,
+        "ruSt",
+        "--max-output-bytes",
+        "2",
+        "-o",
+        out.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read_to_string(&out).unwrap(), "ab☃☃cd");
+}
+
+#[test]
+#[ignore = "writes a 100 MB file"]
+fn decode_huge_payload() {
+    let dir = TempDir::new().unwrap();
+    let payload: Vec<u8> = (0..100 * 1024 * 1024)
+        .map(|i| b'a' + (i % 26) as u8)
+        .collect();
+    let png = PngBuilder::new(1, 1)
+        .with_chunk(Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            payload.clone(),
+        ))
+        .build()
+        .unwrap();
+    let path = dir.path().join("huge.png");
+    fs::write(&path, png.as_bytes()).unwrap();
+    drop(png);
+    let out = dir.path().join("payload.bin");
+
+    let file = path.to_str().unwrap();
+    assert!(
+        pngme(&["decode", file, "ruSt", "-o", out.to_str().unwrap()])
+            .status
+            .success()
+    );
+    assert!(fs::read(&out).unwrap() == payload);
+    let output = pngme(&["decode", file, "ruSt"]);
+    assert_eq!(output.stdout.len(), payload.len() + 1);
+}
+
+#[test]
+fn timings_break_down_the_command() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let file = path.to_str().unwrap();
+
+    // With --json the timings are part of the command's output
+    let output = pngme(&["--timings", "--json", "encode", file, "ruSa", "hi"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let line: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    assert_eq!(line["changes"][0]["chunk_type"], "ruSa", "{line}");
+    let timings = &line["timings"];
+    assert_eq!(timings["chunks"], 4);
+    let phases = [
+        "read_ms",
+        "signature_ms",
+        "parse_ms",
+        "crc_ms",
+        "mutation_ms",
+        "serialize_ms",
+        "write_ms",
+    ];
+    let mut sum = 0.0;
+    for phase in phases {
+        let ms = timings[phase].as_f64().unwrap_or_else(|| panic!("{phase}"));
+        assert!(ms >= 0.0, "{phase}");
+        sum += ms;
+    }
+    let total = timings["total_ms"].as_f64().unwrap();
+    assert!(total > 0.0);
+    // Allow for rounding in the conversion to milliseconds
+    assert!(sum <= total + 1e-6, "{timings}");
+    // Writing the file took some time, and it had to be parsed
+    assert!(timings["write_ms"].as_f64().unwrap() > 0.0);
+    assert!(timings["parse_ms"].as_f64().unwrap() > 0.0);
+
+    let output = pngme(&["--timings", "decode", file, "ruSa"]);
+    assert_eq!(output.stdout, b"hi\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let labels: Vec<&str> = stderr
+        .lines()
+        .skip(1)
+        .map(|l| l.split_whitespace().next().unwrap())
+        .collect();
+    assert!(stderr.starts_with("Timings (ms):\n"));
+    assert_eq!(
+        labels,
+        [
+            "read",
+            "signature",
+            "parse",
+            "crc",
+            "mutation",
+            "serialize",
+            "write",
+            "total"
+        ]
+    );
+    assert!(stderr.contains("(5 chunks)"), "{stderr}");
+
+    // Nothing is edited, and output that isn't an object is wrapped
+    let output = pngme(&["--timings", "--json", "list", file]);
+    let line: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(line["output"].as_array().unwrap().len(), 5, "{line}");
+    assert_eq!(line["timings"]["mutation_ms"], serde_json::Value::Null);
+    assert!(output.stderr.is_empty());
+
+    // A command that fails still says where the time went
+    let output = pngme(&["--timings", "inspect", file, "zzZz"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Timings (ms):\n"), "{stderr}");
+    assert!(
+        stderr.contains("caused by: zzZz wasnt found in the png"),
+        "{stderr}"
+    );
+
+    // Nothing extra without the flag
+    let output = pngme(&["decode", file, "ruSa"]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn malformed_chunk_types_are_rejected_before_reading() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let file = path.to_str().unwrap();
+    let before = fs::read(&path).unwrap();
+    let cases = [
+        ("ruSty", "Chunk type must be 4 bytes, not 5"),
+        ("ruS", "Chunk type must be 4 bytes, not 3"),
+        (
+            "ru5t",
+            "Byte 2 of the chunk type is 0x35 ('5'), but only ASCII letters are allowed",
+        ),
+    ];
+    for (chunk_type, reason) in cases {
+        for args in [
+            vec!["encode", file, chunk_type, "hi"],
+            vec!["decode", file, chunk_type],
+            vec!["remove", file, chunk_type],
+        ] {
+            let output = pngme(&args);
+            assert!(!output.status.success(), "{args:?}");
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains(reason), "{args:?}: {stderr}");
+            assert!(!stderr.contains("wasn't found"), "{args:?}: {stderr}");
+        }
+        // Caught by the argument parser, so a missing file doesn't matter
+        let output = pngme(&["decode", "missing.png", chunk_type]);
+        assert_eq!(output.status.code(), Some(2));
+    }
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn type_hex_round_trips_a_non_letter_chunk_type() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hello")]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["encode", file, "--type-hex", "0xab424344", "odd type"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("\\xabBCD")
+    );
+    let png = Png::parse_with(
+        &fs::read(&path).unwrap(),
+        &pngme::png::ParseOptions {
+            any_chunk_type: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        png.chunks()[3].chunk_type().bytes(),
+        [0xab, b'B', b'C', b'D']
+    );
+
+    let output = pngme(&["decode", file, "--type-hex", "ab:42:43:44"]);
+    assert_eq!(output.stdout, b"odd type\n");
+    // Without --type-hex the file doesn't parse, and verify says why
+    let output = pngme(&["decode", file, "ruSt"]);
+    assert!(!output.status.success());
+    let output = pngme(&["verify", file]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("bad-chunk-type")
+    );
+
+    let output = pngme(&["remove", file, "--type-hex", "0xAB424344"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        chunk_summary(&read_png(&path)),
+        chunk_summary(&read_png(&write_fixture(
+            &dir,
+            "b.png",
+            &[("ruSt", "hello")]
+        )))
+    );
+
+    let output = pngme(&["decode", file, "--type-hex", "0xab42"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn salvage_recovers_the_intact_prefix() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("ruSt", "hidden"), ("ruSu", "later")]);
+    let bytes = fs::read(&path).unwrap();
+    let offsets = read_png(&path).chunk_offsets();
+    // IHDR, IDAT, ruSt, ruSu, IEND
+    let cut = dir.path().join("cut.png");
+    let recovered = dir.path().join("recovered.png");
+
+    // In the middle of ruSu
+    fs::write(&cut, &bytes[..offsets[3] + 10]).unwrap();
+    let output = pngme(&[
+        "salvage",
+        cut.to_str().unwrap(),
+        "--decode",
+        "ruSt",
+        "--write-recovered",
+        recovered.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"hidden\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Recovered 3 chunks"), "{stderr}");
+    assert!(
+        stderr.contains(&format!("Stopped at offset {} ", offsets[3])),
+        "{stderr}"
+    );
+    assert!(stderr.contains("cut short"), "{stderr}");
+    let png = read_png(&recovered);
+    let types: Vec<String> = png
+        .chunks()
+        .iter()
+        .map(|c| c.chunk_type().to_string())
+        .collect();
+    assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+    assert_eq!(&png.as_bytes()[..offsets[3]], &bytes[..offsets[3]]);
+
+    // Between ruSu and IEND: everything parses, but IEND is missing
+    fs::write(&cut, &bytes[..offsets[4]]).unwrap();
+    let output = pngme(&["--json", "salvage", cut.to_str().unwrap()]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["chunks"], 4);
+    assert_eq!(report["recovered_bytes"], offsets[4]);
+    assert_eq!(report["stopped"]["reason"], "the file ends without IEND");
+
+    let output = pngme(&["salvage", path.to_str().unwrap()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Recovered 5 chunks"), "{stdout}");
+    assert!(stdout.contains("The whole file is intact"), "{stdout}");
+
+    // A chunk past the cut can't be decoded
+    fs::write(&cut, &bytes[..offsets[3] + 10]).unwrap();
+    let output = pngme(&["salvage", cut.to_str().unwrap(), "--decode", "ruSu"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn transforms_are_undone_on_decode() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let message = "squeeze me ".repeat(20);
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        &message,
+        "--transform",
+        "deflate",
+        "--transform",
+        "base64",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let png = read_png(&path);
+    let stored = png.chunk_by_type("ruSt").unwrap().data().to_vec();
+    assert!(pngme::transform::is_envelope(&stored));
+    assert!(stored.len() < message.len());
+
+    let output = pngme(&["decode", file, "ruSt"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), message + "\n");
+    let output = pngme(&["decode", file, "ruSt", "--raw", "--keep-envelope"]);
+    assert_eq!(output.stdout, stored);
+
+    let output = pngme(&["encode", file, "ruSu", "x", "--transform", "rot13"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Unknown transform 'rot13'")
+    );
+}
+
+#[test]
+fn template_fills_in_the_environment_and_file() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "build.png", &[]);
+    let file = path.to_str().unwrap();
+    let original = fs::read(&path).unwrap();
+    let encode = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pngme"))
+            .args(["encode", file])
+            .args(args)
+            .env("BUILD_ID", "1234")
+            .env("GIT_SHA", "abc123")
+            .env_remove("PNGME_UNSET_FOR_TEST")
+            .output()
+            .unwrap()
+    };
+    let decode = |chunk_type: &str| {
+        let output = pngme(&["decode", file, chunk_type]);
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let template = "build={env:BUILD_ID} sha={env:GIT_SHA} file={filename} size={filesize}";
+    let output = encode(&["ruSt", template, "--template"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        decode("ruSt"),
+        format!(
+            "build=1234 sha=abc123 file=build.png size={}\n",
+            original.len()
+        )
+    );
+
+    // The digest and size are of the file before this edit, not after
+    let before = fs::read(&path).unwrap();
+    let output = encode(&["--template", "--kv", "ruSu:{sha256} {filesize} {{literal}}"]);
+    assert!(output.status.success(), "{output:?}");
+    let hash = pngme::digest::sha256_hex(&before);
+    assert_eq!(
+        decode("ruSu"),
+        format!("{hash} {} {{literal}}\n", before.len())
+    );
+    let output = encode(&["ruSv", "at {now:%Y-%m-%dT%H:%M:%SZ}", "--template"]);
+    assert!(output.status.success(), "{output:?}");
+    let stamp = decode("ruSv");
+    assert_eq!(stamp.len(), "at 2024-01-01T00:00:00Z\n".len(), "{stamp}");
+    // Without --template the braces are kept as they are
+    assert!(encode(&["ruSw", "{env:BUILD_ID}"]).status.success());
+    assert_eq!(decode("ruSw"), "{env:BUILD_ID}\n");
+
+    let before = fs::read(&path).unwrap();
+    for (template, error) in [
+        (
+            "{env:PNGME_UNSET_FOR_TEST}",
+            "PNGME_UNSET_FOR_TEST isn't set",
+        ),
+        ("{build}", "Unknown placeholder '{build}'"),
+        ("{now:%Q}", "'%Q' isn't a time format"),
+    ] {
+        let output = encode(&["ruSx", template, "--template"]);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(error), "{stderr}");
+    }
+    assert_eq!(fs::read(&path).unwrap(), before);
+    let output = encode(&[
+        "ruSx",
+        "[{env:PNGME_UNSET_FOR_TEST}]",
+        "--template",
+        "--allow-missing",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode("ruSx"), "[]\n");
+    assert!(!encode(&["ruSy", "x", "--allow-missing"]).status.success());
+}
+
+#[test]
+fn template_now_is_source_date_epoch_when_set() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let encode = |epoch: &str| {
+        Command::new(env!("CARGO_BIN_EXE_pngme"))
+            .args(["encode", file, "ruSt", "at {now}", "--template"])
+            .env("SOURCE_DATE_EPOCH", epoch)
+            .output()
+            .unwrap()
+    };
+    let output = encode("1700000000");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        pngme(&["decode", file, "ruSt"]).stdout,
+        b"at 2023-11-14T22:13:20Z\n"
+    );
+
+    let before = fs::read(&path).unwrap();
+    let output = encode("yesterday");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("SOURCE_DATE_EPOCH is 'yesterday'"),
+        "{stderr}"
+    );
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn auto_compresses_only_when_it_pays() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let compressible = "squeeze me ".repeat(20);
+    let mut state = 0x2545_f491_u32;
+    let noise: String = (0..300)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            format!("{:02x}", state as u8)
+        })
+        .collect();
+
+    // The transforms an envelope records follow the magic and their count
+    let recorded = |chunk_type: &str| {
+        let png = read_png(&path);
+        let stored = png.chunk_by_type(chunk_type).unwrap().data().to_vec();
+        assert!(pngme::transform::is_envelope(&stored));
+        stored[5..5 + stored[4] as usize].to_vec()
+    };
+    let cases = [
+        (
+            "ruSt",
+            &compressible[..],
+            &[][..],
+            "Compressed ruSt: ",
+            &[1][..],
+        ),
+        ("ruSu", "tiny", &[], "Stored ruSu uncompressed", &[]),
+        (
+            "ruSv",
+            &noise,
+            &["--input-encoding", "hex"],
+            "Stored ruSv uncompressed",
+            &[],
+        ),
+        // Compression comes before any other transform
+        (
+            "ruSw",
+            &compressible,
+            &["--transform", "base64"],
+            "Compressed ruSw",
+            &[1, 2],
+        ),
+    ];
+    for (chunk_type, message, extra, line, chain) in cases {
+        let output = pngme(&[&["encode", file, chunk_type, message, "--auto"], extra].concat());
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains(line), "{stdout}");
+        assert_eq!(recorded(chunk_type), chain, "{chunk_type}");
+    }
+
+    let output = pngme(&["decode", file, "ruSt"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        compressible + "\n"
+    );
+    let output = pngme(&["decode", file, "ruSv", "--raw", "--output-encoding", "hex"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), noise);
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSx",
+        "x",
+        "--auto",
+        "--transform",
+        "deflate",
+    ]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn data_urls_are_read_and_written() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let original = fs::read(&path).unwrap();
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        "pasted",
+        "--output-format",
+        "base64",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    // The edit goes to stdout as a data URL, the report to stderr
+    let url = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        url.starts_with("data:image/png;base64,iVBORw0KGgo"),
+        "{url}"
+    );
+    assert!(String::from_utf8(output.stderr).unwrap().contains("+ ruSt"));
+    assert_eq!(fs::read(&path).unwrap(), original);
+
+    let output = pngme(&["decode", url.trim(), "ruSt"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"pasted\n");
+    let output = pngme(&["list", url.trim()]);
+    assert!(String::from_utf8(output.stdout).unwrap().contains("ruSt"));
+
+    // To a file, read back as text, with the URL or as bare base64
+    let text = dir.path().join("a.txt");
+    let text = text.to_str().unwrap();
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSu",
+        "again",
+        "--output-format",
+        "base64",
+        "-o",
+        text,
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let output = pngme(&["decode", text, "ruSu", "--input-format", "base64"]);
+    assert_eq!(output.stdout, b"again\n");
+    let bare = &url.trim()["data:image/png;base64,".len()..];
+    let output = pngme(&["decode", bare, "ruSt", "--input-format", "base64"]);
+    assert_eq!(output.stdout, b"pasted\n");
+
+    let fails = |args: &[&str], reason: &str| {
+        let output = pngme(args);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(reason), "{stderr}");
+    };
+    fails(
+        &["list", "data:image/jpeg;base64,/9j/4AAQ"],
+        "is image/jpeg, not image/png",
+    );
+    fails(&["list", &url.trim()[..51]], "4-byte groups");
+    fails(
+        &["list", file, "--input-format", "base64"],
+        "failed to read",
+    );
+    fails(&["encode", url.trim(), "ruSv", "x"], "can only be read");
+}
+
+#[test]
+fn binary_payloads_round_trip_through_base64_and_hex() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let payload = b"\0key\0\xff\x01\0";
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSt",
+        "AGtleQD/AQA=",
+        "--input-encoding",
+        "base64",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let output = pngme(&[
+        "encode",
+        file,
+        "--kv",
+        "ruSu:006B657900FF0100",
+        "--input-encoding",
+        "hex",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let png = read_png(&path);
+    assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), payload);
+    assert_eq!(png.chunk_by_type("ruSu").unwrap().data(), payload);
+
+    for (chunk_type, encoding, text) in [
+        ("ruSt", "base64", "AGtleQD/AQA=\n"),
+        ("ruSu", "hex", "006b657900ff0100\n"),
+    ] {
+        let output = pngme(&["decode", file, chunk_type, "--output-encoding", encoding]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), text);
+    }
+
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSv",
+        "Zm9v!mFy",
+        "--input-encoding",
+        "base64",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("ruSv: Invalid base64 byte 0x21 ('!') at offset 4"),
+        "{stderr}"
+    );
+    let output = pngme(&["encode", file, "ruSv", "00fg", "--input-encoding", "hex"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Invalid hex byte 0x67 ('g') at offset 3"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn auto_inflate_decodes_compressed_payloads() {
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    let dir = TempDir::new().unwrap();
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(b"squeezed ".repeat(50).as_slice()).unwrap();
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(b"gzipped").unwrap();
+    let mut bomb = ZlibEncoder::new(Vec::new(), Compression::best());
+    bomb.write_all(&vec![0; 10 * 1024 * 1024]).unwrap();
+    let bomb = bomb.finish().unwrap();
+    let mut builder = PngBuilder::new(1, 1);
+    for (chunk_type, data) in [
+        ("zlIb", zlib.finish().unwrap()),
+        ("gzIp", gzip.finish().unwrap()),
+        ("boMb", bomb.clone()),
+        // A zlib header by chance
+        ("noPe", b"x^ marks the spot".to_vec()),
+    ] {
+        builder = builder.with_chunk(Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data));
+    }
+    let path = dir.path().join("a.png");
+    fs::write(&path, builder.build().unwrap().as_bytes()).unwrap();
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["decode", file, "zlIb", "--auto-inflate"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        output.stdout,
+        [b"squeezed ".repeat(50).as_slice(), b"\n"].concat()
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("inflated zlIb from a zlib stream of"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("to 450 bytes"), "{stderr}");
+
+    let output = pngme(&["decode", file, "gzIp", "--auto-inflate"]);
+    assert_eq!(output.stdout, b"gzipped\n");
+
+    // Over the cap, the payload is printed as stored
+    let output = pngme(&[
+        "decode",
+        file,
+        "boMb",
+        "--auto-inflate",
+        "--max-inflate",
+        "1048576",
+        "--raw",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, bomb);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("but it inflates to more than 1048576 bytes"),
+        "{stderr}"
+    );
+    let output = pngme(&["decode", file, "boMb", "--auto-inflate", "--raw"]);
+    assert_eq!(output.stdout.len(), 10 * 1024 * 1024);
+
+    let output = pngme(&["decode", file, "noPe", "--auto-inflate"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"x^ marks the spot\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Warning: noPe looks like a zlib stream but it doesn't inflate"),
+        "{stderr}"
+    );
+
+    // Without the flag nothing is inflated
+    let output = pngme(&["decode", file, "gzIp", "--raw"]);
+    assert_ne!(output.stdout, b"gzipped");
+}
+
+#[test]
+fn encode_refuses_a_second_unique_chunk_without_force() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[("tIME", "1234567")]);
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["encode", file, "tIME", "7654321"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("There may be only one tIME and the file already has one at chunk 2")
+    );
+    let output = pngme(&["encode", file, "--chunk", "gAMA=1", "--chunk", "gAMA=2"]);
+    assert!(!output.status.success());
+    assert_eq!(read_png(&path).chunk_count(), 4);
+    // Chunks that may repeat need no --force
+    let output = pngme(&["encode", file, "tEXt", "a", "--chunk", "tEXt=c"]);
+    assert!(output.status.success(), "{output:?}");
+
+    let output = pngme(&["encode", file, "tIME", "7654321", "--force"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Warning: adding a second tIME")
+    );
+    let output = pngme(&["verify", file]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("error[duplicate-chunk]: tIME at chunk 5 repeats chunk 2")
+    );
+}
+
+#[test]
+fn list_and_stats_skip_crc_checks() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir, "a.png", &[]);
+    let file = path.to_str().unwrap();
+    let output = pngme(&["encode", file, "ruSt", "hello", "--corrupt-crc", "+1"]);
+    assert!(output.status.success(), "{output:?}");
+
+    let output = pngme(&["list", file]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8(output.stdout).unwrap().contains("ruSt"));
+    assert!(pngme(&["stats", file]).status.success());
+    // Checked where it matters
+    let output = pngme(&["verify", file]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("error[crc-mismatch]: ruSt at offset")
+    );
+    assert!(!pngme(&["decode", file, "ruSt"]).status.success());
+}
+
+#[test]
+fn explain_describes_finding_codes() {
+    let output = pngme(&["explain", "crc-mismatch"]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("crc-mismatch (error)\n\n"));
+    assert!(stdout.contains("pngme repair"));
+
+    let output = pngme(&["--json", "explain", "trailing-data"]);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["severity"], "warning");
+    assert!(json["explanation"].as_str().unwrap().contains("optimize"));
+
+    let output = pngme(&["explain", "crc-wrong"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Unknown code 'crc-wrong'"));
+    assert!(stderr.contains("  bad-order\n"));
+}
+
+#[test]
+fn max_chunks_is_capped_by_default() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("many.png");
+    let empty = Chunk::new(ChunkType::from_str("ruSt").unwrap(), Vec::new()).as_bytes();
+    let mut bytes = Png::STANDARD_HEADER.to_vec();
+    for _ in 0..100_001 {
+        bytes.extend_from_slice(&empty);
+    }
+    fs::write(&path, &bytes).unwrap();
+    let file = path.to_str().unwrap();
+
+    let output = pngme(&["list", file]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("more than the maximum of 100000 chunks")
+    );
+    let output = pngme(&["--max-chunks", "200000", "stats", file]);
+    assert!(output.status.success(), "{output:?}");
+}
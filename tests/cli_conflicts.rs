@@ -0,0 +1,473 @@
+//! Flags that can't be given together are refused as usage errors, naming
+//! both, rather than one quietly winning; flags that can are applied in
+//! the order their help gives.
+
+use pngme::builder::PngBuilder;
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::transform::{BASE64, DEFLATE, Registry};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::str::FromStr;
+use tempfile::TempDir;
+
+fn pngme(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn write_fixture(dir: &TempDir) -> PathBuf {
+    let path = dir.path().join("a.png");
+    fs::write(&path, PngBuilder::new(2, 2).build().unwrap().as_bytes()).unwrap();
+    path
+}
+
+/// Runs `args` on a fresh file, put in for `FILE`, and checks clap refused
+/// them with a usage error mentioning each of `flags`.
+fn assert_usage_error(args: &[&str], flags: &[&str], reason: &str) {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir);
+    let original = fs::read(&path).unwrap();
+    let args: Vec<&str> = args
+        .iter()
+        .map(|arg| {
+            if *arg == "FILE" {
+                path.to_str().unwrap()
+            } else {
+                arg
+            }
+        })
+        .collect();
+    let output = pngme(&args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{args:?}: {stderr}");
+    assert!(stderr.contains(reason), "{args:?}: {stderr}");
+    for flag in flags {
+        assert!(
+            stderr.contains(flag),
+            "{args:?} doesn't name {flag}: {stderr}"
+        );
+    }
+    assert_eq!(fs::read(&path).unwrap(), original, "{args:?}");
+}
+
+#[test]
+fn conflicting_flags_are_usage_errors() {
+    let conflicts: &[(&[&str], [&str; 2])] = &[
+        // What to decode is one of a chunk type, --type-hex, --select,
+        // --keyword or --redundant
+        (
+            &["decode", "FILE", "ruSt", "--keyword", "k"],
+            ["[CHUNKTYPE]", "--keyword"],
+        ),
+        (
+            &["decode", "FILE", "ruSt", "--redundant"],
+            ["[CHUNKTYPE]", "--redundant"],
+        ),
+        (
+            &["decode", "FILE", "ruSt", "--select", "private"],
+            ["[CHUNKTYPE]", "--select"],
+        ),
+        (
+            &["decode", "FILE", "ruSt", "--type-hex", "0x72755374"],
+            ["[CHUNKTYPE]", "--type-hex"],
+        ),
+        (
+            &["decode", "FILE", "--keyword", "k", "--redundant"],
+            ["--keyword", "--redundant"],
+        ),
+        (
+            &["decode", "FILE", "--keyword", "k", "--select", "private"],
+            ["--keyword", "--select"],
+        ),
+        (
+            &["decode", "FILE", "--select", "private", "--redundant"],
+            ["--select", "--redundant"],
+        ),
+        (
+            &[
+                "decode",
+                "FILE",
+                "--type-hex",
+                "0x72755374",
+                "--keyword",
+                "k",
+            ],
+            ["--type-hex", "--keyword"],
+        ),
+        // --nth and --ignore-case pick out keyword entries
+        (
+            &["decode", "FILE", "ruSt", "--nth", "2"],
+            ["--nth", "[CHUNKTYPE]"],
+        ),
+        (
+            &["decode", "FILE", "--select", "private", "--ignore-case"],
+            ["--ignore-case", "--select"],
+        ),
+        (
+            &["decode", "FILE", "--redundant", "--nth", "1"],
+            ["--nth", "--redundant"],
+        ),
+        // How to print it
+        (
+            &["decode", "FILE", "ruSt", "--pretty", "--raw"],
+            ["--pretty", "--raw"],
+        ),
+        (
+            &["decode", "FILE", "ruSt", "--pretty", "-o", "out"],
+            ["--pretty", "--output"],
+        ),
+        (
+            &[
+                "decode",
+                "FILE",
+                "ruSt",
+                "--pretty",
+                "--output-encoding",
+                "hex",
+            ],
+            ["--pretty", "--output-encoding"],
+        ),
+        (
+            &["decode", "FILE", "--keyword", "k", "-o", "out"],
+            ["--keyword", "--output"],
+        ),
+        (
+            &["decode", "FILE", "--redundant", "-o", "out"],
+            ["--output", "--redundant"],
+        ),
+        // Envelopes and compression are only found in chunks
+        (
+            &["decode", "FILE", "--keyword", "k", "--auto-inflate"],
+            ["--keyword", "--auto-inflate"],
+        ),
+        (
+            &["decode", "FILE", "--redundant", "--auto-inflate"],
+            ["--redundant", "--auto-inflate"],
+        ),
+        (
+            &["decode", "FILE", "--keyword", "k", "--keep-envelope"],
+            ["--keyword", "--keep-envelope"],
+        ),
+        (
+            &["decode", "FILE", "--redundant", "--keep-envelope"],
+            ["--redundant", "--keep-envelope"],
+        ),
+        // What to remove is one of a chunk type, --type-hex, --index,
+        // --select, --redundant or --types-file
+        (
+            &["remove", "FILE", "ruSt", "--index", "1"],
+            ["[CHUNKTYPE]", "--index"],
+        ),
+        (
+            &["remove", "FILE", "ruSt", "--redundant"],
+            ["[CHUNKTYPE]", "--redundant"],
+        ),
+        (
+            &["remove", "FILE", "--index", "1", "--select", "private"],
+            ["--index", "--select"],
+        ),
+        (
+            &["remove", "FILE", "--index", "1", "--types-file", "t"],
+            ["--index", "--types-file"],
+        ),
+        (
+            &["remove", "FILE", "--redundant", "--types-file", "t"],
+            ["--redundant", "--types-file"],
+        ),
+        (
+            &["remove", "FILE", "--select", "private", "--redundant"],
+            ["--select", "--redundant"],
+        ),
+        (
+            &["remove", "FILE", "--type-hex", "0x72755374", "--index", "1"],
+            ["--type-hex", "--index"],
+        ),
+        // Encode
+        (
+            &["encode", "FILE", "ruSt", "hi", "out.png", "-o", "b.png"],
+            ["--output", "[OUTPUT_PATH]"],
+        ),
+        (
+            &[
+                "encode",
+                "FILE",
+                "hi",
+                "--redundant",
+                "2",
+                "--type-hex",
+                "0x72755374",
+            ],
+            ["--redundant", "--type-hex"],
+        ),
+        (
+            &[
+                "encode",
+                "FILE",
+                "hi",
+                "--redundant",
+                "2",
+                "--transform",
+                "base64",
+            ],
+            ["--redundant", "--transform"],
+        ),
+        (
+            &["encode", "FILE", "hi", "--redundant", "2", "--auto"],
+            ["--redundant", "--auto"],
+        ),
+        (
+            &["encode", "FILE", "ruSt", "hi", "-v", "--quiet"],
+            ["--verbose", "--quiet"],
+        ),
+        (
+            &["encode", "FILE", "ruSt", "hi", "-v", "--json"],
+            ["--verbose", "--json"],
+        ),
+        // A dry run writes nothing, so has nowhere to write it
+        (
+            &["optimize", "FILE", "--dry-run", "-o", "out.png"],
+            ["--dry-run", "--output"],
+        ),
+        (
+            &[
+                "apply",
+                "--rules",
+                "r.toml",
+                "FILE",
+                "--dry-run",
+                "-o",
+                "out.png",
+            ],
+            ["--dry-run", "--output"],
+        ),
+        // Others
+        (
+            &["redact", "FILE", "ruSt", "--fill", "1", "--pattern", "ff"],
+            ["--fill", "--pattern"],
+        ),
+        (
+            &["scan", "dir", "--write-baseline", "a", "--baseline", "b"],
+            ["--write-baseline", "--baseline"],
+        ),
+        (
+            &["list", "FILE", "--json", "--format", "csv"],
+            ["--json", "--format"],
+        ),
+        (
+            &["stats", "FILE", "--json", "--format", "csv"],
+            ["--json", "--format"],
+        ),
+        (
+            &["--version-json", "info", "FILE"],
+            ["--version-json", "info"],
+        ),
+    ];
+    for (args, flags) in conflicts {
+        assert_usage_error(args, flags, "cannot be used with");
+    }
+}
+
+#[test]
+fn flags_that_only_qualify_another_need_it() {
+    let requirements: &[(&[&str], [&str; 2])] = &[
+        (
+            &["decode", "FILE", "ruSt", "--max-inflate", "10"],
+            ["--max-inflate", "--auto-inflate"],
+        ),
+        (
+            &["encode", "FILE", "ruSt", "hi", "--type", "pmRz"],
+            ["--type", "--redundant"],
+        ),
+        (
+            &["encode", "FILE", "ruSt", "hi", "--allow-missing"],
+            ["--allow-missing", "--template"],
+        ),
+        (
+            &["encode", "FILE", "ruSt", "hi", "--null"],
+            ["--null", "--files-from"],
+        ),
+        (
+            &["list", "FILE", "--no-header"],
+            ["--no-header", "--format"],
+        ),
+        (
+            &["list", "FILE", "--long-digest"],
+            ["--long-digest", "--digest"],
+        ),
+        (
+            &["stats", "FILE", "--no-header"],
+            ["--no-header", "--format"],
+        ),
+    ];
+    for (args, flags) in requirements {
+        assert_usage_error(args, flags, "required arguments were not provided");
+    }
+    // And the choice of what to decode or remove must be made
+    assert_usage_error(&["decode", "FILE"], &["<CHUNKTYPE|--type-hex"], "required");
+    assert_usage_error(&["remove", "FILE"], &["<CHUNKTYPE|--type-hex"], "required");
+    assert_usage_error(
+        &["scan", "dir"],
+        &["--write-baseline", "--baseline"],
+        "required",
+    );
+}
+
+#[test]
+fn conflicts_that_depend_on_values_are_refused() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir);
+    let file = path.to_str().unwrap();
+    let original = fs::read(&path).unwrap();
+    let refused = |args: &[&str], expected: &str| {
+        let output = pngme(&[&["encode", file], args].concat());
+        assert!(!output.status.success(), "{args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+        assert_eq!(fs::read(&path).unwrap(), original, "{args:?}");
+    };
+
+    refused(
+        &["ruSt", "x", "--auto", "--transform", "deflate"],
+        "--auto decides whether to deflate, so don't also pass --transform deflate",
+    );
+    refused(
+        &["x", "--redundant", "2", "--type", "pmRz", "--type", "pmRz"],
+        "--type pmRz is given more than once",
+    );
+    refused(
+        &[
+            "x",
+            "--redundant",
+            "2",
+            "--type",
+            "pmRz",
+            "--type",
+            "pmRz",
+            "--type",
+            "pmRy",
+        ],
+        "--type pmRz is given more than once",
+    );
+    refused(
+        &["x", "--redundant", "3", "--type", "pmRz"],
+        "--redundant 3 needs 3 --type values, not 1",
+    );
+}
+
+#[test]
+fn encode_applies_template_encoding_transforms_then_crc() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir);
+    let file = path.to_str().unwrap();
+
+    // The placeholder is filled in before --input-encoding decodes it:
+    // "aGk=" is base64 for "hi"
+    let output = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(["encode", file, "ruSt", "{env:PAYLOAD}", "--template"])
+        .args(["--input-encoding", "base64"])
+        .env("PAYLOAD", "aGk=")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let output = pngme(&["decode", file, "ruSt"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hi\n");
+
+    // --auto deflates before the --transform chain, which is then
+    // recorded in the envelope in that order
+    let message = "squeeze me ".repeat(20);
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSu",
+        &message,
+        "--auto",
+        "--transform",
+        "base64",
+        "--corrupt-crc",
+        "+1",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let stored = Registry::default()
+        .wrap(message.as_bytes(), &[DEFLATE, BASE64])
+        .unwrap();
+    // The CRC is broken last, so it's the stored envelope's CRC plus one
+    let chunk = Chunk::new(ChunkType::from_str("ruSu").unwrap(), stored);
+    let mut expected = chunk.as_bytes();
+    let crc_at = expected.len() - 4;
+    expected[crc_at..].copy_from_slice(&(chunk.crc().wrapping_add(1)).to_be_bytes());
+    let bytes = fs::read(&path).unwrap();
+    assert!(
+        bytes.windows(expected.len()).any(|w| w == expected),
+        "the stored chunk isn't the deflated, base64-encoded message with CRC + 1"
+    );
+}
+
+#[test]
+fn decode_undoes_envelopes_inflates_then_encodes() {
+    let dir = TempDir::new().unwrap();
+    let path = write_fixture(&dir);
+    let file = path.to_str().unwrap();
+    let message = "squeeze me ".repeat(20);
+    let output = pngme(&["encode", file, "ruSt", &message, "--transform", "deflate"]);
+    assert!(output.status.success(), "{output:?}");
+    let envelope = Registry::default()
+        .wrap(message.as_bytes(), &[DEFLATE])
+        .unwrap();
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() };
+    let decode = |args: &[&str]| {
+        let output = pngme(&[&["decode", file, "ruSt"], args].concat());
+        assert!(output.status.success(), "{args:?}: {output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // --raw with --output-encoding writes the encoded text without a newline
+    assert_eq!(
+        decode(&["--raw", "--output-encoding", "hex"]),
+        hex(message.as_bytes())
+    );
+    assert_eq!(
+        decode(&["--output-encoding", "hex"]),
+        hex(message.as_bytes()) + "\n"
+    );
+    // An envelope kept is printed as stored, as --auto-inflate only looks
+    // at what's inside one
+    assert_eq!(
+        decode(&[
+            "--keep-envelope",
+            "--auto-inflate",
+            "--output-encoding",
+            "hex"
+        ]),
+        hex(&envelope) + "\n"
+    );
+
+    // A bare zlib stream is inflated before it's encoded
+    let zlib = &envelope[6..];
+    let output = pngme(&[
+        "encode",
+        file,
+        "ruSz",
+        &hex(zlib),
+        "--input-encoding",
+        "hex",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    let output = pngme(&[
+        "decode",
+        file,
+        "ruSz",
+        "--auto-inflate",
+        "--output-encoding",
+        "hex",
+        "--raw",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        hex(message.as_bytes())
+    );
+}
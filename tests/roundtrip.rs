@@ -0,0 +1,169 @@
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::png::ParseOptions;
+use pngme::png::Png;
+use pngme::rewrite::Rewrite;
+use proptest::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Small PNGs covering layouts the serializer must not normalize: odd
+/// bit depths and color types, interlacing, split and empty IDATs,
+/// duplicate and pre-IDAT private chunks, and bytes left after IEND.
+fn fixtures() -> Vec<PathBuf> {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut paths: Vec<PathBuf> = fs::read_dir(root.join("tests/fixtures"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "png"))
+        .collect();
+    paths.push(root.join("sliding-window.png"));
+    paths.sort();
+    paths
+}
+
+#[test]
+fn fixtures_round_trip_byte_for_byte() {
+    let paths = fixtures();
+    assert!(paths.len() > 5);
+    for path in paths {
+        let bytes = fs::read(&path).unwrap();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert!(png.as_bytes() == bytes, "{}", path.display());
+        let png = Png::from_reader(bytes.as_slice()).unwrap();
+        assert!(png.as_bytes() == bytes, "{}", path.display());
+    }
+}
+
+#[test]
+fn fixtures_are_real_images() {
+    for path in fixtures() {
+        let decoder = png::Decoder::new(std::io::Cursor::new(fs::read(&path).unwrap()));
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        reader
+            .next_frame(&mut buf)
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    }
+}
+
+fn chunk_type() -> impl Strategy<Value = ChunkType> {
+    "[a-zA-Z]{4}".prop_map(|s| ChunkType::from_str(&s).unwrap())
+}
+
+fn chunk() -> impl Strategy<Value = Chunk> {
+    (chunk_type(), prop::collection::vec(any::<u8>(), 0..512))
+        .prop_map(|(chunk_type, data)| Chunk::new(chunk_type, data))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn chunk_round_trips(chunk_type in chunk_type(), data in prop::collection::vec(any::<u8>(), 0..1024)) {
+        let chunk = Chunk::new(chunk_type, data.clone());
+        let parsed = Chunk::try_from(chunk.as_bytes().as_slice()).unwrap();
+        prop_assert_eq!(parsed.data(), data.as_slice());
+        prop_assert_eq!(parsed, chunk);
+    }
+
+    #[test]
+    fn png_round_trips(chunks in prop::collection::vec(chunk(), 0..16)) {
+        let png = Png::from_chunks(chunks);
+        let parsed = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        prop_assert_eq!(parsed.chunks(), png.chunks());
+        prop_assert_eq!(parsed, png);
+    }
+
+    #[test]
+    fn accepted_files_round_trip_byte_for_byte(
+        chunks in prop::collection::vec(chunk(), 0..16),
+        crc_flips in prop::collection::vec(any::<bool>(), 16),
+        trailing in prop::collection::vec(any::<u8>(), 0..4),
+    ) {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        for (chunk, flip) in chunks.iter().zip(crc_flips) {
+            let mut serialized = chunk.as_bytes();
+            if flip {
+                *serialized.last_mut().unwrap() ^= 1;
+            }
+            bytes.extend(serialized);
+        }
+        bytes.extend(trailing);
+        let options = ParseOptions { ignore_crc: true, ..Default::default() };
+        for options in [ParseOptions::default(), options] {
+            if let Ok(png) = Png::parse_with(&bytes, &options) {
+                prop_assert_eq!(png.as_bytes(), bytes.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn encode_then_decode_returns_payload(
+        chunks in prop::collection::vec(chunk(), 0..8),
+        payload in prop::collection::vec(any::<u8>(), 0..1024),
+    ) {
+        let mut png = Png::from_chunks(chunks);
+        png.append_chunk(Chunk::new(ChunkType::from_str("zzZz").unwrap(), payload.clone()));
+        let parsed = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        let decoded = parsed
+            .chunks()
+            .iter()
+            .rev()
+            .find(|c| c.chunk_type().to_string() == "zzZz")
+            .unwrap();
+        prop_assert_eq!(decoded.data(), payload.as_slice());
+    }
+
+    #[test]
+    fn copy_through_rewrite_matches_in_memory_edit(
+        chunks in prop::collection::vec(chunk(), 0..12),
+        with_iend in any::<bool>(),
+        added in prop::collection::vec(chunk(), 0..4),
+        removals in prop::collection::vec(0..16usize, 0..4),
+        trailing in prop::collection::vec(any::<u8>(), 0..4),
+    ) {
+        let mut chunks = chunks;
+        if with_iend {
+            chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+        }
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        bytes.extend(trailing);
+
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        let mut rewrite = Rewrite::index(bytes.as_slice(), &ParseOptions::default()).unwrap();
+        for index in removals {
+            prop_assert_eq!(
+                png.remove_chunk_at(index).map(|c| *c.chunk_type()),
+                rewrite.remove_chunk_at(index)
+            );
+        }
+        prop_assert_eq!(png.append_chunks(added.clone()), rewrite.append_chunks(added));
+
+        let mut output = Vec::new();
+        rewrite.write_to(&mut std::io::Cursor::new(&bytes), &mut output).unwrap();
+        prop_assert_eq!(output, png.as_bytes());
+    }
+}
+
+#[cfg(feature = "render-check")]
+#[test]
+fn optimized_fixtures_decode_identically() {
+    use pngme::optimize::{OptimizeOptions, optimize};
+
+    let lenient = ParseOptions {
+        lenient: true,
+        ..ParseOptions::default()
+    };
+    for path in fixtures() {
+        let bytes = fs::read(&path).unwrap();
+        let mut png = Png::parse_with(&bytes, &lenient).unwrap();
+        optimize(&mut png, &OptimizeOptions::default()).unwrap();
+        let optimized = png.as_bytes();
+        assert!(optimized.len() <= bytes.len(), "{}", path.display());
+        if let Err(e) = pngme::render::compare(&bytes, &optimized) {
+            panic!("{}: {e}", path.display());
+        }
+    }
+}